@@ -1,6 +1,11 @@
 use get_port::Ops;
 
-use crate::{env::EnvPointer, eval::ScrapeResultsPtr, EnvVars, Interpreter};
+use crate::{
+    env::EnvPointer,
+    eval::{SchemaPolicy, ScrapeResultsPtr},
+    limits::{CancellationToken, Limits},
+    Engine, EnvVars, Interpreter,
+};
 
 #[derive(Debug)]
 pub enum BuilderError {
@@ -13,6 +18,11 @@ pub struct InterpreterBuilder {
     env: Option<EnvPointer>,
     crawler: Option<fantoccini::Client>,
     results: Option<ScrapeResultsPtr>,
+    schema_policy: Option<SchemaPolicy>,
+    engine: Option<Engine>,
+    optimize: Option<bool>,
+    limits: Option<Limits>,
+    cancel: Option<CancellationToken>,
 }
 
 impl InterpreterBuilder {
@@ -31,6 +41,38 @@ impl InterpreterBuilder {
         self
     }
 
+    /// Sets how `scrape Type { ... }` handles maps that do not match their
+    /// type. Overrides `SCOUT_SCHEMA_POLICY`.
+    pub fn with_schema_policy(mut self, policy: SchemaPolicy) -> Self {
+        self.schema_policy = Some(policy);
+        self
+    }
+
+    /// Sets the engine programs are run with. Overrides `SCOUT_ENGINE`.
+    pub fn with_engine(mut self, engine: Engine) -> Self {
+        self.engine = Some(engine);
+        self
+    }
+
+    /// Sets whether programs are optimized before they are run.
+    /// Overrides `SCOUT_OPTIMIZE`.
+    pub fn with_optimize(mut self, optimize: bool) -> Self {
+        self.optimize = Some(optimize);
+        self
+    }
+
+    /// Sets the limits each evaluation runs under. Unlimited by default.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Sets a token that stops evaluation when it is cancelled.
+    pub fn with_cancel_token(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
     pub async fn build(self) -> Result<Interpreter, BuilderError> {
         let env_vars =
             envy::from_env::<EnvVars>().map_err(|e| BuilderError::EnvError(e.to_string()))?;
@@ -43,12 +85,23 @@ impl InterpreterBuilder {
             None => new_crawler(&env_vars, port).await,
         }?;
 
-        let interpreter = Interpreter::new(
-            self.env.unwrap_or_default(),
-            self.results.unwrap_or_default(),
-            crawler,
-            child,
-        );
+        let results: ScrapeResultsPtr = self.results.unwrap_or_default();
+        if let Some(policy) = self.schema_policy.or(env_vars.scout_schema_policy) {
+            results.lock().await.set_schema_policy(policy);
+        }
+
+        let mut interpreter =
+            Interpreter::new(self.env.unwrap_or_default(), results, crawler, child);
+        if let Some(engine) = self.engine.or(env_vars.scout_engine) {
+            interpreter.set_engine(engine);
+        }
+        interpreter.set_optimize(self.optimize.unwrap_or(env_vars.scout_optimize));
+        if let Some(limits) = self.limits {
+            interpreter.set_limits(limits);
+        }
+        if let Some(cancel) = self.cancel {
+            interpreter.set_cancel_token(cancel);
+        }
 
         Ok(interpreter)
     }
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, env, str::FromStr, sync::Arc, thread::sleep, time::Duration};
+use std::{env, str::FromStr, sync::Arc, thread::sleep, time::Duration};
 
 use fantoccini::{
     actions::{InputSource, KeyAction, KeyActions},
@@ -7,6 +7,7 @@ use fantoccini::{
     key::Key,
 };
 use futures::{future::BoxFuture, lock::Mutex, FutureExt, TryFutureExt};
+use indexmap::IndexMap;
 use reqwest::{
     header::{HeaderMap, HeaderName, HeaderValue},
     Method,
@@ -27,6 +28,30 @@ macro_rules! assert_param_len {
     };
 }
 
+/// Keyword arguments passed to a fn call, in the order they were given.
+pub type Kwargs = IndexMap<Identifier, Arc<Object>>;
+
+/// The static types of a builtin's positional params and return value,
+/// written like annotations. `a|b` accepts either type, and a trailing
+/// `?` marks a param that may be null or left out.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Signature {
+    pub params: &'static [&'static str],
+    /// Whether any number of args of the last param type may be passed.
+    pub variadic: bool,
+    pub ret: &'static str,
+}
+
+impl Signature {
+    const fn new(params: &'static [&'static str], ret: &'static str) -> Self {
+        Self {
+            params,
+            variadic: false,
+            ret,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum BuiltinKind {
     Args,
@@ -45,6 +70,7 @@ pub enum BuiltinKind {
     Sleep,
     IsWhitespace,
     List,
+    Enumerate,
     Push,
     Cookies,
     SetCookies,
@@ -54,32 +80,139 @@ pub enum BuiltinKind {
 }
 
 impl BuiltinKind {
+    pub const ALL: [BuiltinKind; 23] = {
+        use BuiltinKind::*;
+        [
+            Args,
+            Print,
+            TextContent,
+            Href,
+            Click,
+            Results,
+            Len,
+            Input,
+            Contains,
+            Type,
+            KeyPress,
+            Number,
+            Url,
+            Sleep,
+            IsWhitespace,
+            List,
+            Enumerate,
+            Push,
+            Cookies,
+            SetCookies,
+            ToJson,
+            HttpRequest,
+            SetViewport,
+        ]
+    };
+
     pub fn is_from(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|b| b.name() == s)
+    }
+
+    /// The name the builtin is called by.
+    pub fn name(&self) -> &'static str {
         use BuiltinKind::*;
-        match s {
-            "isWhitespace" => Some(IsWhitespace),
-            "url" => Some(Url),
-            "number" => Some(Number),
-            "args" => Some(Args),
-            "print" => Some(Print),
-            "textContent" => Some(TextContent),
-            "href" => Some(Href),
-            "click" => Some(Click),
-            "results" => Some(Results),
-            "len" => Some(Len),
-            "input" => Some(Input),
-            "contains" => Some(Contains),
-            "type" => Some(Type),
-            "keyAction" => Some(KeyPress),
-            "sleep" => Some(Sleep),
-            "list" => Some(List),
-            "push" => Some(Push),
-            "cookies" => Some(Cookies),
-            "setCookies" => Some(SetCookies),
-            "toJson" => Some(ToJson),
-            "httpRequest" => Some(HttpRequest),
-            "setViewport" => Some(SetViewport),
-            _ => None,
+        match self {
+            IsWhitespace => "isWhitespace",
+            Url => "url",
+            Number => "number",
+            Args => "args",
+            Print => "print",
+            TextContent => "textContent",
+            Href => "href",
+            Click => "click",
+            Results => "results",
+            Len => "len",
+            Input => "input",
+            Contains => "contains",
+            Type => "type",
+            KeyPress => "keyAction",
+            Sleep => "sleep",
+            List => "list",
+            Enumerate => "enumerate",
+            Push => "push",
+            Cookies => "cookies",
+            SetCookies => "setCookies",
+            ToJson => "toJson",
+            HttpRequest => "httpRequest",
+            SetViewport => "setViewport",
+        }
+    }
+
+    /// A short description of what the builtin does.
+    pub fn doc(&self) -> &'static str {
+        use BuiltinKind::*;
+        match self {
+            Args => "Returns the command line args the script was run with.",
+            Print => "Prints each arg on its own line.",
+            TextContent => "Returns the text of a node, or of each node in a list.",
+            Href => "Returns the `href` of a node, or of each node in a list.",
+            Click => "Clicks a node.",
+            Results => "Prints the results scraped so far as JSON.",
+            Len => "Returns the length of a list, str or map.",
+            Input => "Types a str into a node. Pass `submit = true` to press enter after.",
+            Contains => "Returns whether a str contains a substring, a list contains an item or a map contains a key.",
+            Type => "Returns the name of the type of a value.",
+            KeyPress => "Presses the key for the first char of a str.",
+            Number => "Parses a str into a number.",
+            Url => "Returns the url of the current page.",
+            Sleep => "Pauses for the given number of milliseconds.",
+            IsWhitespace => "Returns whether a str is only whitespace.",
+            List => "Collects the items of a list, str or map into a new list.",
+            Enumerate => "Returns a list of `[index, item]` pairs.",
+            Push => "Appends an item to the end of a list.",
+            Cookies => "Returns the cookies of the current page as a map.",
+            SetCookies => "Replaces the cookies of the current page with a map of names to values.",
+            ToJson => "Serializes a value to a JSON str.",
+            HttpRequest => "Sends an HTTP request and returns a map of its `statusCode`, `url` and `content`, read as `format`: `\"text\"`, `\"json\"` or `null` to skip it.",
+            SetViewport => "Resizes the browser window to a width and height.",
+        }
+    }
+
+    pub fn signature(&self) -> Signature {
+        use BuiltinKind::*;
+        const ITERABLE: &str = "list|str|map";
+        match self {
+            Args => Signature::new(&[], "list"),
+            Print => Signature {
+                params: &["any"],
+                variadic: true,
+                ret: "null",
+            },
+            // Node fns map over lists of nodes.
+            TextContent | Href => Signature::new(&["node|list"], "any"),
+            Click => Signature::new(&["node"], "null"),
+            Results => Signature::new(&[], "null"),
+            Len => Signature::new(&[ITERABLE], "number"),
+            Input => Signature::new(&["node", "str", "any?"], "null"),
+            Contains => Signature::new(&["str|list|map", "any"], "bool"),
+            Type => Signature::new(&["any"], "str"),
+            KeyPress => Signature::new(&["str"], "null"),
+            Number => Signature::new(&["str"], "number"),
+            Url => Signature::new(&[], "str"),
+            Sleep => Signature::new(&["number"], "null"),
+            IsWhitespace => Signature::new(&["str"], "bool"),
+            List | Enumerate => Signature::new(&[ITERABLE], "list"),
+            Push => Signature::new(&["list", "any"], "null"),
+            Cookies => Signature::new(&[], "map"),
+            SetCookies => Signature::new(&["map"], "null"),
+            ToJson => Signature::new(&["any"], "str"),
+            HttpRequest => Signature::new(&["str", "str", "any?", "map?", "str?"], "map"),
+            SetViewport => Signature::new(&["number", "number"], "null"),
+        }
+    }
+
+    /// The names of the kwargs the builtin accepts.
+    fn kwargs(&self) -> &'static [&'static str] {
+        use BuiltinKind::*;
+        match self {
+            Input => &["submit"],
+            HttpRequest => &["body", "headers", "format", "timeout"],
+            _ => &[],
         }
     }
 
@@ -88,13 +221,22 @@ impl BuiltinKind {
         crawler: &fantoccini::Client,
         results: ScrapeResultsPtr,
         args: Vec<Arc<Object>>,
+        kwargs: Kwargs,
     ) -> EvalResult {
+        if let Some(unknown) = kwargs
+            .keys()
+            .find(|k| !self.kwargs().contains(&k.name.as_str()))
+        {
+            return Err(EvalError::UnknownKwarg(unknown.clone()));
+        }
+
         use BuiltinKind::*;
         match self {
             HttpRequest => {
-                if args.len() < 5 {
-                    return Err(EvalError::InvalidFnParams);
-                }
+                assert_param_len!(args, 2);
+                let body = opt_arg(&args, &kwargs, 2, "body")?;
+                let headers = opt_arg(&args, &kwargs, 3, "headers")?;
+                let format = opt_arg(&args, &kwargs, 4, "format")?;
 
                 match (&*args[0], &*args[1]) {
                     (Object::Str(method), Object::Str(url)) => {
@@ -104,13 +246,23 @@ impl BuiltinKind {
                         let mut req_builder = client.request(method, url);
 
                         // Check for an optional body
-                        if args[2].is_truthy().await {
-                            let body = args[2].to_string();
-                            req_builder = req_builder.body(body);
+                        if let Some(body) = body.filter(|b| !matches!(***b, Object::Null)) {
+                            req_builder = req_builder.body(body.to_string());
+                        }
+
+                        if let Some(timeout) = kwargs.get(&Identifier::new("timeout".into())) {
+                            match &**timeout {
+                                Object::Number(ms) => {
+                                    let ms = Duration::from_millis(ms.round() as u64);
+                                    req_builder = req_builder.timeout(ms);
+                                }
+                                Object::Null => {}
+                                _ => return Err(EvalError::InvalidFnParams),
+                            }
                         }
 
                         // Check for an optional headers map
-                        if let Object::Map(map) = &*args[3] {
+                        if let Some(Object::Map(map)) = headers.map(|h| &**h) {
                             let mut headers = HeaderMap::default();
                             let inner = map.lock().await;
                             for (k, v) in inner.iter() {
@@ -129,7 +281,7 @@ impl BuiltinKind {
                         let status = Object::Number(res.status().as_u16() as f64);
                         let url = Object::Str(res.url().to_string());
                         let mut content = Arc::new(Object::Null);
-                        if let Object::Str(s) = &*args[4] {
+                        if let Some(Object::Str(s)) = format.map(|f| &**f) {
                             match s.as_str() {
                                 "json" => {
                                     let json = res.json::<Value>().await?;
@@ -177,7 +329,7 @@ impl BuiltinKind {
                             Arc::new(Object::Str(c.value().to_string())),
                         )
                     })
-                    .collect::<HashMap<Identifier, Arc<Object>>>();
+                    .collect::<IndexMap<Identifier, Arc<Object>>>();
 
                 Ok(Arc::new(Object::Map(Mutex::new(cookies))))
             }
@@ -208,7 +360,7 @@ impl BuiltinKind {
             }
             List => {
                 assert_param_len!(args, 1);
-                if let Some(iterable) = args[0].into_iterable().await {
+                if let Some(iterable) = args[0].into_iterable() {
                     Ok(Arc::new(Object::List(Mutex::new(
                         iterable.into_iter().collect(),
                     ))))
@@ -216,6 +368,22 @@ impl BuiltinKind {
                     Err(EvalError::InvalidFnParams)
                 }
             }
+            Enumerate => {
+                assert_param_len!(args, 1);
+                if let Some(iterable) = args[0].into_iterable() {
+                    let pairs = iterable
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, obj)| {
+                            let pair = vec![Arc::new(Object::Number(i as f64)), obj];
+                            Arc::new(Object::List(Mutex::new(pair)))
+                        })
+                        .collect();
+                    Ok(Arc::new(Object::List(Mutex::new(pairs))))
+                } else {
+                    Err(EvalError::InvalidFnParams)
+                }
+            }
             IsWhitespace => {
                 assert_param_len!(args, 1);
                 if let Object::Str(s) = &*args[0] {
@@ -257,8 +425,8 @@ impl BuiltinKind {
                 let env_args = env::args().collect::<Vec<String>>();
                 let mut out = Vec::new();
                 // start at 1 because idx 0 is the executable location
-                for idx in 1..env_args.len() {
-                    out.push(Arc::new(Object::Str(env_args[idx].clone())));
+                for arg in env_args.iter().skip(1) {
+                    out.push(Arc::new(Object::Str(arg.clone())));
                 }
                 Ok(Arc::new(Object::List(Mutex::new(out))))
             }
@@ -268,7 +436,7 @@ impl BuiltinKind {
             }
             Print => {
                 for obj in args {
-                    let display = obj.to_display().await;
+                    let display = obj.to_display();
                     println!("{display}");
                 }
                 Ok(Arc::new(Object::Null))
@@ -314,6 +482,7 @@ impl BuiltinKind {
                 let len = match &*args[0] {
                     Object::List(v) => Ok(v.lock().await.len() as f64),
                     Object::Str(s) => Ok(s.len() as f64),
+                    Object::Map(m) => Ok(m.lock().await.len() as f64),
                     _ => Err(EvalError::InvalidFnParams),
                 }?;
 
@@ -334,11 +503,15 @@ impl BuiltinKind {
             }
             Input => {
                 assert_param_len!(args, 2);
+                let submit = match opt_arg(&args, &kwargs, 2, "submit")? {
+                    Some(submit) => submit.is_truthy(),
+                    None => false,
+                };
                 match (&*args[0], &*args[1]) {
                     (Object::Node(elem), Object::Str(s)) => {
                         elem.send_keys(s).map_err(EvalError::BrowserError).await?;
 
-                        if args.len() > 2 && args[2].is_truthy().await {
+                        if submit {
                             let actions =
                                 KeyActions::new("enter".to_owned()).then(KeyAction::Down {
                                     value: Key::Return.into(),
@@ -364,7 +537,7 @@ impl BuiltinKind {
                         let inner = v.lock().await;
                         let mut contains = false;
                         for obj in inner.iter() {
-                            if obj.eq(&args[1]).await {
+                            if obj.eq(&args[1]) {
                                 contains = true;
                                 break;
                             }
@@ -388,6 +561,20 @@ impl BuiltinKind {
     }
 }
 
+/// An optional arg that can be passed either by position or by name.
+fn opt_arg<'a>(
+    args: &'a [Arc<Object>],
+    kwargs: &'a Kwargs,
+    idx: usize,
+    name: &str,
+) -> Result<Option<&'a Arc<Object>>, EvalError> {
+    let ident = Identifier::new(name.into());
+    match (args.get(idx), kwargs.get(&ident)) {
+        (Some(_), Some(_)) => Err(EvalError::DuplicateKwarg(ident)),
+        (positional, named) => Ok(positional.or(named)),
+    }
+}
+
 async fn apply_elem_fn(
     arg: &Object,
     f: impl Fn(&'_ Element) -> BoxFuture<'_, Object>,
@@ -0,0 +1,683 @@
+//! A static pass that reports type errors without running a script.
+//!
+//! Types come from annotations, literals, record declarations and builtin
+//! signatures. Anything the checker can't see through, such as map values
+//! or fns from other modules, is `any` and never reported.
+
+use std::collections::HashMap;
+
+use scout_lexer::{Span, TokenKind};
+use scout_parser::ast::{
+    Block, CallLiteral, Expr, ExprKind, FnParam, HashLiteral, Identifier, MapKey, MatchLiteral,
+    ParamKind, Pattern, Program, Stmt, StmtKind, TypeDecl, TypeExpr,
+};
+use scout_parser::diagnostic::Diagnostic;
+
+use crate::builtin::BuiltinKind;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ty {
+    Any,
+    Null,
+    Str,
+    Number,
+    Bool,
+    List,
+    Map,
+    Node,
+    Fn,
+    /// A map declared with `type Name { ... }`.
+    Record(String),
+    /// `a|b`, only used by builtin signatures.
+    Union(Vec<Ty>),
+    /// `T?`
+    Optional(Box<Ty>),
+}
+
+impl Ty {
+    /// Whether a value of type `found` can be used where `self` is expected.
+    /// Optional values are accepted anywhere their inner type is, since
+    /// null checks can't be followed statically.
+    fn accepts(&self, found: &Ty) -> bool {
+        match (self, found) {
+            (Ty::Any, _) | (_, Ty::Any) => true,
+            (_, Ty::Union(tys)) => tys.iter().any(|ty| self.accepts(ty)),
+            (Ty::Union(tys), _) => tys.iter().any(|ty| ty.accepts(found)),
+            (Ty::Optional(_), Ty::Null) => true,
+            (Ty::Optional(inner), _) => inner.accepts(found),
+            (_, Ty::Optional(inner)) => self.accepts(inner),
+            (Ty::Record(_), Ty::Map) | (Ty::Map, Ty::Record(_)) => true,
+            _ => self == found,
+        }
+    }
+
+    fn optional(self) -> Ty {
+        match self {
+            Ty::Any | Ty::Null | Ty::Optional(_) => self,
+            _ => Ty::Optional(Box::new(self)),
+        }
+    }
+
+    /// Joins the types a name may have after two branches.
+    fn join(self, other: Ty) -> Ty {
+        if self == other {
+            self
+        } else {
+            Ty::Any
+        }
+    }
+}
+
+impl std::fmt::Display for Ty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ty::Any => write!(f, "any"),
+            Ty::Null => write!(f, "null"),
+            Ty::Str => write!(f, "str"),
+            Ty::Number => write!(f, "number"),
+            Ty::Bool => write!(f, "bool"),
+            Ty::List => write!(f, "list"),
+            Ty::Map => write!(f, "map"),
+            Ty::Node => write!(f, "node"),
+            Ty::Fn => write!(f, "fn"),
+            Ty::Record(name) => write!(f, "{name}"),
+            Ty::Union(tys) => {
+                let names = tys.iter().map(|ty| ty.to_string()).collect::<Vec<_>>();
+                write!(f, "{}", names.join("|"))
+            }
+            Ty::Optional(inner) => write!(f, "{inner}?"),
+        }
+    }
+}
+
+/// The types of a user defined fn, from its annotations.
+#[derive(Debug, Clone)]
+struct FnSig {
+    params: Vec<(Identifier, ParamKind, Ty)>,
+    ret: Ty,
+}
+
+/// Checks a program, returning a diagnostic for each type error found.
+pub fn check(program: &Program) -> Vec<Diagnostic> {
+    let mut checker = Checker::default();
+    checker.hoist(&program.stmts);
+    for stmt in &program.stmts {
+        checker.check_stmt(stmt);
+    }
+    checker.diagnostics
+}
+
+#[derive(Default)]
+struct Checker {
+    types: HashMap<String, TypeDecl>,
+    fns: HashMap<String, FnSig>,
+    /// The inferred type of each name assigned so far.
+    vars: HashMap<String, Ty>,
+    /// Names with a type annotation, which every assignment must match.
+    declared: HashMap<String, Ty>,
+    /// The name and return type of the fn being checked, if any.
+    ret: Option<(String, Ty)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn error(&mut self, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic::new(message, span));
+    }
+
+    /// Registers the types and fns declared in a block, so they can be
+    /// used by fns defined before them.
+    fn hoist(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            if let StmtKind::TypeDecl(decl) = &stmt.kind {
+                self.types.insert(decl.ident.name.clone(), decl.clone());
+            }
+        }
+        for stmt in stmts {
+            if let StmtKind::Func(def, _) = &stmt.kind {
+                let sig = self.fn_sig(&def.params, &def.ret, stmt.span);
+                self.fns.insert(def.ident.name.clone(), sig);
+            }
+        }
+    }
+
+    fn resolve(&mut self, ty: &TypeExpr, span: Span) -> Ty {
+        let base = match ty.name.name.as_str() {
+            "any" => Ty::Any,
+            "null" => Ty::Null,
+            "str" | "string" => Ty::Str,
+            "number" => Ty::Number,
+            "bool" => Ty::Bool,
+            "list" => Ty::List,
+            "map" => Ty::Map,
+            "node" => Ty::Node,
+            "fn" => Ty::Fn,
+            name if self.types.contains_key(name) => Ty::Record(name.to_owned()),
+            name => {
+                self.error(format!("unknown type `{name}`"), span);
+                Ty::Any
+            }
+        };
+        if ty.optional {
+            base.optional()
+        } else {
+            base
+        }
+    }
+
+    /// Resolves a type written in a builtin signature, like `node|list`.
+    fn resolve_builtin(&mut self, name: &str) -> Ty {
+        let (name, optional) = match name.strip_suffix('?') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let mut tys = name
+            .split('|')
+            .map(|name| {
+                let ty = TypeExpr::new(Identifier::new(name.to_owned()), false);
+                self.resolve(&ty, Span::default())
+            })
+            .collect::<Vec<Ty>>();
+        let ty = match tys.len() {
+            1 => tys.remove(0),
+            _ => Ty::Union(tys),
+        };
+        if optional {
+            ty.optional()
+        } else {
+            ty
+        }
+    }
+
+    fn fn_sig(&mut self, params: &[FnParam], ret: &Option<TypeExpr>, span: Span) -> FnSig {
+        let params = params
+            .iter()
+            .map(|p| {
+                let ty = match &p.ty {
+                    Some(ty) => self.resolve(ty, span),
+                    None => Ty::Any,
+                };
+                (p.ident.clone(), p.kind, ty)
+            })
+            .collect();
+        let ret = match ret {
+            Some(ty) => self.resolve(ty, span),
+            None => Ty::Any,
+        };
+        FnSig { params, ret }
+    }
+
+    fn lookup(&self, ident: &Identifier) -> Ty {
+        self.declared
+            .get(&ident.name)
+            .or_else(|| self.vars.get(&ident.name))
+            .cloned()
+            .unwrap_or(Ty::Any)
+    }
+
+    /// Records an assignment of `found` to `ident`, checking it against the
+    /// annotation given here or when the name was first declared.
+    fn assign(&mut self, ident: &Identifier, ty: &Option<TypeExpr>, found: Ty, span: Span) {
+        if let Some(ty) = ty {
+            let ty = self.resolve(ty, span);
+            self.declared.insert(ident.name.clone(), ty);
+        }
+        if let Some(expected) = self.declared.get(&ident.name).cloned() {
+            if !expected.accepts(&found) {
+                self.error(
+                    format!("`{ident}` is declared as {expected}, found {found}"),
+                    span,
+                );
+            }
+        }
+        self.vars.insert(ident.name.clone(), found);
+    }
+
+    /// Binds a name whose type can't be known, such as a loop variable.
+    fn bind_any(&mut self, ident: &Identifier) {
+        self.declared.remove(&ident.name);
+        self.vars.insert(ident.name.clone(), Ty::Any);
+    }
+
+    /// Checks a block that may or may not run, joining any names it
+    /// assigns with their types from before it.
+    fn check_branch(&mut self, block: &Block) {
+        let before = self.vars.clone();
+        self.check_block(block);
+        for (name, ty) in self.vars.iter_mut() {
+            if let Some(prev) = before.get(name) {
+                *ty = std::mem::replace(ty, Ty::Any).join(prev.clone());
+            }
+        }
+    }
+
+    fn check_block(&mut self, block: &Block) {
+        self.hoist(&block.stmts);
+        for stmt in &block.stmts {
+            self.check_stmt(stmt);
+        }
+    }
+
+    /// Checks a fn body in its own scope. Names from outside the fn may be
+    /// reassigned before it is called, so only their annotations are kept.
+    fn check_fn(&mut self, name: String, params: &[FnParam], sig: FnSig, body: &Block) {
+        let vars = std::mem::take(&mut self.vars);
+        let declared = self.declared.clone();
+        let ret = self.ret.replace((name, sig.ret));
+        for (param, (_, kind, ty)) in params.iter().zip(sig.params) {
+            if let Some(default) = &param.default {
+                let found = self.infer(default);
+                if !ty.accepts(&found) {
+                    self.error(
+                        format!("`{}` is declared as {ty}, found {found}", param.ident),
+                        default.span,
+                    );
+                }
+            }
+            let ty = match kind {
+                ParamKind::Positional => ty,
+                ParamKind::Rest => Ty::List,
+                ParamKind::Kwargs => Ty::Map,
+            };
+            self.declared.insert(param.ident.name.clone(), ty);
+        }
+        self.check_block(body);
+        self.vars = vars;
+        self.declared = declared;
+        self.ret = ret;
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Assign(lhs, rhs, _, ty) | StmtKind::Let(lhs, rhs, _, ty) => {
+                let found = self.infer(rhs);
+                match &lhs.kind {
+                    ExprKind::Ident(ident) => self.assign(ident, ty, found, rhs.span),
+                    ExprKind::List(items) => {
+                        for item in items {
+                            if let ExprKind::Ident(ident) = &item.kind {
+                                self.bind_any(ident);
+                            }
+                        }
+                    }
+                    _ => {
+                        self.infer(lhs);
+                    }
+                }
+            }
+            StmtKind::Func(def, _) => {
+                // Every def was hoisted along with the rest of its block.
+                let sig = match self.fns.get(&def.ident.name) {
+                    Some(sig) => sig.clone(),
+                    None => self.fn_sig(&def.params, &def.ret, stmt.span),
+                };
+                self.check_fn(def.ident.name.clone(), &def.params, sig, &def.body);
+            }
+            StmtKind::Expr(expr) | StmtKind::Goto(expr) | StmtKind::Throw(expr) => {
+                self.infer(expr);
+            }
+            StmtKind::IfElse(lit) => {
+                self.infer(&lit.if_lit.cond);
+                self.check_branch(&lit.if_lit.block);
+                for elif in &lit.elifs {
+                    self.infer(&elif.cond);
+                    self.check_branch(&elif.block);
+                }
+                if let Some(else_lit) = &lit.else_lit {
+                    self.check_branch(&else_lit.block);
+                }
+            }
+            StmtKind::ForLoop(floop) => {
+                self.infer(&floop.iterable);
+                for ident in &floop.idents {
+                    self.bind_any(ident);
+                }
+                self.check_branch(&floop.block);
+            }
+            StmtKind::WhileLoop(cond, block) => {
+                self.infer(cond);
+                self.check_branch(block);
+            }
+            StmtKind::Crawl(lit) => {
+                if let Some(bindings) = &lit.bindings {
+                    self.vars.insert(bindings.link.name.clone(), Ty::Str);
+                    self.vars.insert(bindings.depth.name.clone(), Ty::Number);
+                }
+                if let Some(filter) = &lit.filter {
+                    self.infer(filter);
+                }
+                self.check_branch(&lit.body);
+            }
+            StmtKind::TryCatch(block, catch, finally) => {
+                self.check_branch(block);
+                if let Some(catch) = catch {
+                    if let Some(binding) = &catch.binding {
+                        self.vars.insert(binding.name.clone(), Ty::Map);
+                    }
+                    self.check_branch(&catch.block);
+                }
+                if let Some(finally) = finally {
+                    self.check_branch(finally);
+                }
+            }
+            StmtKind::Return(expr) => {
+                let (found, span) = match expr {
+                    Some(expr) => (self.infer(expr), expr.span),
+                    None => (Ty::Null, stmt.span),
+                };
+                if let Some((name, ret)) = self.ret.clone() {
+                    if !ret.accepts(&found) {
+                        self.error(format!("`{name}` should return {ret}, found {found}"), span);
+                    }
+                }
+            }
+            StmtKind::Scrape(ty, hash) => match ty {
+                Some(ty) => {
+                    self.check_record(ty, hash, stmt.span);
+                }
+                None => self.infer_hash(hash),
+            },
+            StmtKind::TypeDecl(decl) => {
+                self.types.insert(decl.ident.name.clone(), decl.clone());
+                for (_, ty) in &decl.fields {
+                    self.resolve(ty, stmt.span);
+                }
+            }
+            StmtKind::Use(_) | StmtKind::Screenshot(_) | StmtKind::Break | StmtKind::Continue => {}
+        }
+    }
+
+    fn infer_hash(&mut self, hash: &HashLiteral) {
+        for (key, val) in &hash.pairs {
+            if let MapKey::Computed(expr) = key {
+                self.infer(expr);
+            }
+            self.infer(val);
+        }
+    }
+
+    /// Checks the fields of a record literal against its declared type.
+    fn check_record(&mut self, ident: &Identifier, hash: &HashLiteral, span: Span) -> Ty {
+        let Some(decl) = self.types.get(&ident.name).cloned() else {
+            self.error(format!("unknown type `{ident}`"), span);
+            self.infer_hash(hash);
+            return Ty::Any;
+        };
+
+        let mut computed = false;
+        let mut seen = Vec::new();
+        for (key, val) in &hash.pairs {
+            let found = self.infer(val);
+            let field = match key {
+                MapKey::Ident(field) => field,
+                MapKey::Computed(expr) => {
+                    self.infer(expr);
+                    computed = true;
+                    continue;
+                }
+            };
+            seen.push(field);
+            match decl.fields.iter().find(|(name, _)| name == field) {
+                Some((_, ty)) => {
+                    let expected = self.resolve(ty, span);
+                    if !expected.accepts(&found) {
+                        self.error(
+                            format!(
+                                "invalid `{ident}`: field `{field}` should be {expected}, found {found}"
+                            ),
+                            val.span,
+                        );
+                    }
+                }
+                None => self.error(format!("invalid `{ident}`: unknown field `{field}`"), span),
+            }
+        }
+
+        // Computed keys may fill in any field.
+        if !computed {
+            for (field, ty) in &decl.fields {
+                if !ty.optional && !seen.contains(&field) {
+                    self.error(format!("invalid `{ident}`: missing field `{field}`"), span);
+                }
+            }
+        }
+        Ty::Record(ident.name.clone())
+    }
+
+    /// Checks a call against the signature of the fn it calls, with `prev`
+    /// being the value piped into it.
+    fn check_call(&mut self, call: &CallLiteral, prev: Option<(Ty, Span)>) -> Ty {
+        let mut args: Vec<(Ty, Span)> = prev.into_iter().collect();
+        for arg in &call.args {
+            args.push((self.infer(arg), arg.span));
+        }
+        let mut kwargs = Vec::new();
+        for kwarg in &call.kwargs {
+            kwargs.push((&kwarg.ident, self.infer(&kwarg.expr), kwarg.expr.span));
+        }
+
+        let name = &call.ident;
+        if let Some(sig) = self.fns.get(&name.name).cloned() {
+            let positional = sig
+                .params
+                .iter()
+                .filter(|(_, kind, _)| *kind == ParamKind::Positional);
+            for (idx, ((_, _, expected), (found, span))) in positional.zip(&args).enumerate() {
+                if !expected.accepts(found) {
+                    let msg = format!(
+                        "`{name}` expects {expected} for argument {}, found {found}",
+                        idx + 1
+                    );
+                    self.error(msg, *span);
+                }
+            }
+            for (ident, found, span) in kwargs {
+                let param = sig
+                    .params
+                    .iter()
+                    .find(|(p, kind, _)| p == ident && *kind == ParamKind::Positional);
+                if let Some((_, _, expected)) = param {
+                    if !expected.accepts(&found) {
+                        let msg =
+                            format!("`{name}` expects {expected} for `{ident}`, found {found}");
+                        self.error(msg, span);
+                    }
+                }
+            }
+            return sig.ret;
+        }
+
+        // A variable holding a lambda, whose params can't be known.
+        if self.vars.contains_key(&name.name) || self.declared.contains_key(&name.name) {
+            return Ty::Any;
+        }
+
+        match BuiltinKind::is_from(&name.name) {
+            Some(builtin) => {
+                let sig = builtin.signature();
+                for (idx, (found, span)) in args.iter().enumerate() {
+                    let expected = match sig.params.get(idx) {
+                        Some(ty) => *ty,
+                        None if sig.variadic => sig.params[sig.params.len() - 1],
+                        None => break,
+                    };
+                    let expected = self.resolve_builtin(expected);
+                    if !expected.accepts(found) {
+                        let msg = format!(
+                            "`{name}` expects {expected} for argument {}, found {found}",
+                            idx + 1
+                        );
+                        self.error(msg, *span);
+                    }
+                }
+                self.resolve_builtin(sig.ret)
+            }
+            None => Ty::Any,
+        }
+    }
+
+    fn infer(&mut self, expr: &Expr) -> Ty {
+        match &expr.kind {
+            ExprKind::Str(_) => Ty::Str,
+            ExprKind::Number(_) => Ty::Number,
+            ExprKind::Boolean(_) => Ty::Bool,
+            ExprKind::Null => Ty::Null,
+            ExprKind::Ident(ident) => self.lookup(ident),
+            ExprKind::List(items) => {
+                for item in items {
+                    self.infer(item);
+                }
+                Ty::List
+            }
+            ExprKind::Interp(parts) => {
+                for part in parts {
+                    self.infer(part);
+                }
+                Ty::Str
+            }
+            ExprKind::Map(hash) => {
+                self.infer_hash(hash);
+                Ty::Map
+            }
+            ExprKind::Select(_, _) => Ty::Node.optional(),
+            ExprKind::SelectAll(_, _) => Ty::List,
+            ExprKind::Call(call) => self.check_call(call, None),
+            ExprKind::Lambda(lit) => {
+                let sig = self.fn_sig(&lit.params, &lit.ret, expr.span);
+                self.check_fn("fn".into(), &lit.params, sig, &lit.body);
+                Ty::Fn
+            }
+            ExprKind::Match(MatchLiteral { subject, arms }) => {
+                self.infer(subject);
+                for arm in arms {
+                    self.bind_pattern(&arm.pattern);
+                    if let Some(guard) = &arm.guard {
+                        self.infer(guard);
+                    }
+                    self.check_branch(&arm.block);
+                }
+                Ty::Any
+            }
+            ExprKind::Record(ident, hash) => self.check_record(ident, hash, expr.span),
+            ExprKind::Conditional(cond, then, other) => {
+                self.infer(cond);
+                let then = self.infer(then);
+                let other = self.infer(other);
+                then.join(other)
+            }
+            ExprKind::Field(obj, _) => {
+                self.infer(obj);
+                Ty::Any
+            }
+            ExprKind::MethodCall(recv, call) => {
+                let prev = self.infer(recv);
+                self.check_call(call, Some((prev, recv.span)))
+            }
+            ExprKind::Chain(exprs) | ExprKind::SafeChain(exprs) => {
+                let safe = matches!(expr.kind, ExprKind::SafeChain(_));
+                let mut prev: Option<(Ty, Span)> = None;
+                for link in exprs {
+                    let piped = prev.take().map(|(ty, span)| match ty {
+                        Ty::Optional(inner) if safe => (*inner, span),
+                        ty => (ty, span),
+                    });
+                    let ty = match &link.kind {
+                        ExprKind::Call(call) => self.check_call(call, piped),
+                        _ => self.infer(link),
+                    };
+                    prev = Some((ty, link.span));
+                }
+                let ty = prev.map(|(ty, _)| ty).unwrap_or(Ty::Any);
+                if safe {
+                    ty.optional()
+                } else {
+                    ty
+                }
+            }
+            ExprKind::Infix(lhs, op, rhs) => {
+                let lhs = self.infer(lhs);
+                let rhs = self.infer(rhs);
+                use TokenKind::*;
+                match (op.kind, lhs, rhs) {
+                    (EQ | NEQ | LT | GT | LTE | GTE, _, _) => Ty::Bool,
+                    (Plus, Ty::Str, Ty::Str) => Ty::Str,
+                    (
+                        Plus | Minus | Asterisk | DbAsterisk | Slash | TildeSlash | Percent,
+                        Ty::Number,
+                        Ty::Number,
+                    ) => Ty::Number,
+                    _ => Ty::Any,
+                }
+            }
+            ExprKind::Prefix(operand, op) => {
+                self.infer(operand);
+                match op.kind {
+                    TokenKind::Bang => Ty::Bool,
+                    TokenKind::Minus => Ty::Number,
+                    _ => Ty::Any,
+                }
+            }
+        }
+    }
+
+    fn bind_pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Binding(ident) => self.bind_any(ident),
+            Pattern::List(items) => {
+                for item in items {
+                    self.bind_pattern(item);
+                }
+            }
+            Pattern::Map(fields) => {
+                for (_, item) in fields {
+                    self.bind_pattern(item);
+                }
+            }
+            Pattern::Wildcard | Pattern::Literal(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use scout_lexer::Lexer;
+    use scout_parser::Parser;
+    use test_case::test_case;
+
+    use super::*;
+
+    fn check_str(input: &str) -> Vec<String> {
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        check(&program).into_iter().map(|d| d.message).collect()
+    }
+
+    #[test_case("click($$\"a\")", "`click` expects node for argument 1, found list"; "list into click")]
+    #[test_case("$$\"a\" |> click()", "`click` expects node for argument 1, found list"; "piped list into click")]
+    #[test_case("x = $\"a\" |> textContent()\nnumber(1)", "`number` expects str for argument 1, found number"; "number of number")]
+    #[test_case("x: number = \"a\"", "`x` is declared as number, found str"; "annotated assign")]
+    #[test_case("let x: str = \"a\"\nx = 1", "`x` is declared as str, found number"; "reassign annotated")]
+    #[test_case("def f(a: number) do end\nf(\"a\")", "`f` expects number for argument 1, found str"; "fn param")]
+    #[test_case("def f(a: number) do end\nf(a = [])", "`f` expects number for `a`, found list"; "fn kwarg")]
+    #[test_case("def f() -> str do\n  return 1\nend", "`f` should return str, found number"; "fn return")]
+    #[test_case("def f(a: Foo) do end", "unknown type `Foo`"; "unknown type")]
+    #[test_case("type P { a: str }\nx = P { a: 1 }", "invalid `P`: field `a` should be str, found number"; "record field")]
+    #[test_case("type P { a: str }\nscrape P {}", "invalid `P`: missing field `a`"; "scrape missing field")]
+    #[test_case("def apply(f: fn, x: number) do end\napply(1, 2)", "`apply` expects fn for argument 1, found number"; "fn param type")]
+    #[test_case("x: null = 1", "`x` is declared as null, found number"; "null annotation")]
+    fn test_check_error(input: &str, exp: &str) {
+        assert_eq!(check_str(input), vec![exp.to_string()]);
+    }
+
+    #[test_case("x = 1\nif a do\n  x = \"a\"\nend\nclick(x)"; "joined branches")]
+    #[test_case("def f(a) do\n  click(a)\nend\nf(1)"; "unannotated params")]
+    #[test_case("click($\"a\")"; "optional node")]
+    #[test_case("x: str? = null\nx = \"a\""; "optional annotation")]
+    #[test_case("type P { a: str, b: number? }\ny = P { a: \"x\" }\nz: P = y"; "record annotation")]
+    #[test_case("f = fn(x) do end\nf(1)\nprint(1, \"a\", [])"; "lambdas and variadics")]
+    #[test_case("def apply(f: fn, x: number) -> any do\n  return f(x)\nend\napply(fn(a) do a end, 1)"; "fn annotation")]
+    #[test_case("x: null = null"; "null annotation")]
+    #[test_case("m = {a: 1}\nx = contains(m, \"a\")\ny = len(m)"; "map builtins")]
+    fn test_check_ok(input: &str) {
+        assert_eq!(check_str(input), Vec::<String>::new());
+    }
+}
@@ -1,72 +1,288 @@
+use crate::eval::EvalError;
 use crate::object::Object;
-use futures::future::BoxFuture;
 use futures::lock::Mutex;
-use futures::FutureExt;
 use scout_parser::ast::Identifier;
-use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::{Arc, Weak};
 
 pub type EnvPointer = Arc<Mutex<Env>>;
 
-#[derive(Debug, Default)]
+#[derive(Default)]
 pub struct Env {
     pub store: HashMap<String, Arc<Object>>,
-    outer: Mutex<Weak<Mutex<Env>>>,
+    // Strong, because a block scope can be captured by a closure that
+    // outlives the fn call it was created in. Module scopes have none.
+    outer: Option<EnvPointer>,
     pub globals: Vec<Identifier>,
+    /// Names declared with `const` in this scope.
+    consts: HashSet<String>,
+    /// Block scopes only hold `let`/`const` declarations and loop
+    /// bindings. Assigning a new name in one defines it in the
+    /// enclosing fn or module scope instead.
+    block: bool,
+    /// How many fn calls and module loads deep code in this scope runs.
+    depth: usize,
+    /// The file of the module this is the top level scope of.
+    file: Option<PathBuf>,
+    /// The scope a module is being called from with `::`, which the names
+    /// it does not define resolve from. Weak, since the caller may well
+    /// hold the module.
+    caller: Option<Weak<Mutex<Env>>>,
+}
+
+/// The caller and depth of a module scope from before [`Env::enter`],
+/// restored by [`Env::exit`].
+pub struct Caller(Option<Weak<Mutex<Env>>>, usize);
+
+// Fns hold a pointer to the env they were defined in, which may in turn
+// hold the fn, so only print the names in scope.
+impl std::fmt::Debug for Env {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Env")
+            .field("store", &self.store.keys().collect::<Vec<_>>())
+            .field("globals", &self.globals)
+            .finish()
+    }
+}
+
+/// Empties `env` and every scope reachable from the values in it.
+///
+/// Fns hold the scope they were defined in, which usually holds the fns in
+/// turn, so the scopes of a program are never freed on their own. This
+/// breaks those cycles once nothing is going to run in them again.
+pub fn clear(env: &EnvPointer) {
+    let mut envs = vec![env.clone()];
+    let mut objs = Vec::new();
+    while !envs.is_empty() || !objs.is_empty() {
+        while let Some(env) = envs.pop() {
+            let Some(mut env) = env.try_lock() else {
+                continue;
+            };
+            objs.extend(std::mem::take(&mut env.store).into_values());
+            envs.extend(env.outer.take());
+        }
+        while let Some(obj) = objs.pop() {
+            match &*obj {
+                Object::Fn(_, _, env) | Object::Module(env) => envs.push(env.clone()),
+                Object::Closure(closure) => closure.clear(&mut envs, &mut objs),
+                Object::Return(obj) => objs.push(obj.clone()),
+                Object::List(items) => {
+                    if let Some(mut items) = items.try_lock() {
+                        objs.append(&mut items);
+                    }
+                }
+                Object::Map(map) => {
+                    if let Some(mut map) = map.try_lock() {
+                        objs.extend(std::mem::take(&mut *map).into_values());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
 }
 
 impl Env {
-    pub async fn add_outer(&mut self, env: EnvPointer) {
-        *self.outer.get_mut() = Arc::downgrade(&env);
+    /// Creates the scope for the body of a block nested in `outer`.
+    pub fn block(outer: EnvPointer) -> Self {
+        Self {
+            outer: Some(outer),
+            block: true,
+            ..Default::default()
+        }
+    }
+
+    /// Creates the top level scope of the module in `file`, or of the
+    /// directory imported as a module, loaded `depth` calls and loads deep.
+    pub fn module(file: PathBuf, depth: usize) -> Self {
+        Self {
+            depth,
+            file: Some(file),
+            ..Default::default()
+        }
+    }
+
+    pub fn add_outer(&mut self, env: EnvPointer) {
+        self.outer = Some(env);
+    }
+
+    /// Resolves names this module scope does not define from `caller`,
+    /// with calls from it running `depth` calls deep, until the returned
+    /// link is passed to [`Env::exit`].
+    pub fn enter(&mut self, caller: &EnvPointer, depth: usize) -> Caller {
+        let prev = Caller(self.caller.take(), self.depth);
+        self.caller = Some(Arc::downgrade(caller));
+        self.depth = depth;
+        prev
+    }
+
+    pub fn exit(&mut self, prev: Caller) {
+        self.caller = prev.0;
+        self.depth = prev.1;
+    }
+
+    /// The scope names not found in this one are looked up in.
+    fn parent(&self) -> Option<EnvPointer> {
+        match &self.outer {
+            Some(outer) => Some(outer.clone()),
+            None => self.caller.as_ref()?.upgrade(),
+        }
+    }
+
+    /// Block scopes run at the depth of the scope they are nested in.
+    pub fn depth(&self) -> usize {
+        match (&self.outer, self.block) {
+            (Some(outer), true) => outer.try_lock().map_or(self.depth, |o| o.depth()),
+            _ => self.depth,
+        }
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
     }
 
-    pub async fn inherit_globals(&mut self, other: EnvPointer) {
-        let inner = other.lock().await;
+    /// The file of the module this scope is nested in, or `None` for the
+    /// program being run.
+    pub fn module_path(&self) -> Option<PathBuf> {
+        match &self.file {
+            Some(file) => Some(file.clone()),
+            None => self.parent()?.try_lock()?.module_path(),
+        }
+    }
+
+    pub fn inherit_globals(&mut self, inner: &Env) {
         for global in &inner.globals {
             // This should be safe because we only add to globals
             // and store at the same time.
-            let val = inner.get(global).await.unwrap();
-            self.add_global(global, val.clone()).await;
+            let val = inner.get(global).unwrap();
+            self.store.insert(global.name.clone(), val);
+            self.globals.push(global.clone());
         }
     }
 
-    pub async fn add_global(&mut self, id: &Identifier, obj: Arc<Object>) {
-        self.set(id, obj).await;
+    pub fn add_global(&mut self, id: &Identifier, obj: Arc<Object>) -> Result<(), EvalError> {
+        self.set(id, obj)?;
         self.globals.push(id.clone());
+        Ok(())
     }
 
-    pub fn get<'a>(&'a self, id: &'a Identifier) -> BoxFuture<'a, Option<Arc<Object>>> {
-        async move {
-            match self.outer.borrow().lock().await.upgrade() {
-                None => self.store.get(&id.name).cloned(),
-                Some(env) => {
-                    if let Some(obj) = self.store.get(&id.name) {
-                        Some(obj.clone())
-                    } else {
-                        env.lock().await.get(id).await
-                    }
-                }
-            }
+    // Evaluation never waits while holding an env's lock, so the only lock
+    // that can fail below is on an env that has become its own outer.
+
+    pub fn get(&self, id: &Identifier) -> Option<Arc<Object>> {
+        match self.store.get(&id.name) {
+            Some(obj) => Some(obj.clone()),
+            None => self.parent()?.try_lock()?.get(id),
         }
-        .boxed()
     }
 
-    pub fn set<'a>(&'a mut self, id: &'a Identifier, obj: Arc<Object>) -> BoxFuture<'a, ()> {
-        async move {
-            match self.outer.lock().await.upgrade() {
-                None => {
-                    self.store.insert(id.name.clone(), obj);
-                }
-                Some(env) => {
-                    if env.lock().await.get(id).await.is_some() {
-                        env.lock().await.set(id, obj).await;
-                    } else {
-                        self.store.insert(id.name.clone(), obj);
-                    }
-                }
+    /// Binds `id` in this scope, shadowing any outer binding of the same
+    /// name. Used for fn params and loop variables.
+    pub fn bind(&mut self, id: &Identifier, obj: Arc<Object>) {
+        self.consts.remove(&id.name);
+        self.store.insert(id.name.clone(), obj);
+    }
+
+    /// Declares `id` in this scope with `let` or `const`. A name can only
+    /// be declared once per scope.
+    pub fn declare(
+        &mut self,
+        id: &Identifier,
+        obj: Arc<Object>,
+        constant: bool,
+    ) -> Result<(), EvalError> {
+        if self.store.contains_key(&id.name) {
+            return Err(EvalError::DuplicateDeclare(id.clone()));
+        }
+        if constant {
+            self.consts.insert(id.name.clone());
+        }
+        self.store.insert(id.name.clone(), obj);
+        Ok(())
+    }
+
+    /// Assigns to the nearest existing binding of `id`, or defines it in
+    /// the nearest fn or module scope if there is none.
+    pub fn set(&mut self, id: &Identifier, obj: Arc<Object>) -> Result<(), EvalError> {
+        if self.store.contains_key(&id.name) {
+            if self.consts.contains(&id.name) {
+                return Err(EvalError::ConstAssign(id.clone()));
             }
+            self.store.insert(id.name.clone(), obj);
+            return Ok(());
         }
-        .boxed()
+
+        if let Some(env) = self.parent() {
+            let mut outer = env.try_lock().ok_or(EvalError::LockError)?;
+            if self.block || outer.get(id).is_some() {
+                return outer.set(id, obj);
+            }
+        }
+        self.store.insert(id.name.clone(), obj);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::lock::Mutex;
+    use scout_parser::ast::{Block, Identifier};
+
+    use super::*;
+
+    #[test]
+    fn test_clear_frees_fn_cycles() {
+        let env = EnvPointer::default();
+        let module = EnvPointer::default();
+        let f = Arc::new(Object::Fn(vec![], Block::default(), env.clone()));
+        let g = Arc::new(Object::Fn(vec![], Block::default(), module.clone()));
+        let list = Arc::new(Object::List(Mutex::new(vec![f.clone()])));
+        {
+            let mut env = env.try_lock().unwrap();
+            env.bind(&Identifier::new("f".into()), f);
+            env.bind(&Identifier::new("fs".into()), list);
+            env.bind(
+                &Identifier::new("m".into()),
+                Arc::new(Object::Module(module.clone())),
+            );
+        }
+        module
+            .try_lock()
+            .unwrap()
+            .bind(&Identifier::new("g".into()), g);
+
+        let (weak_env, weak_module) = (Arc::downgrade(&env), Arc::downgrade(&module));
+        drop(module);
+        assert_eq!(Arc::strong_count(&env), 2);
+
+        clear(&env);
+        assert_eq!(Arc::strong_count(&env), 1);
+        drop(env);
+        assert!(weak_env.upgrade().is_none());
+        assert!(weak_module.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_module_caller_is_scoped() {
+        let module = Arc::new(Mutex::new(Env::module("m.sct".into(), 1)));
+        let caller = EnvPointer::default();
+        let x = Identifier::new("x".into());
+        caller.try_lock().unwrap().bind(&x, Arc::new(Object::Null));
+        caller.try_lock().unwrap().bind(
+            &Identifier::new("m".into()),
+            Arc::new(Object::Module(module.clone())),
+        );
+
+        let prev = module.try_lock().unwrap().enter(&caller, 3);
+        assert!(module.try_lock().unwrap().get(&x).is_some());
+        assert_eq!(module.try_lock().unwrap().depth(), 3);
+        assert_eq!(Arc::strong_count(&caller), 1);
+
+        module.try_lock().unwrap().exit(prev);
+        assert!(module.try_lock().unwrap().get(&x).is_none());
+        assert_eq!(module.try_lock().unwrap().depth(), 1);
     }
 }
@@ -1,51 +1,99 @@
 use std::collections::HashSet;
 use std::fs;
+use std::future::Future;
 use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
 use std::thread::sleep;
 use std::time::Duration;
-use std::{collections::HashMap, sync::Arc};
 
 use fantoccini::Locator;
 use futures::lock::Mutex;
 use futures::{future::BoxFuture, FutureExt};
+use indexmap::IndexMap;
 
-use scout_lexer::{Lexer, TokenKind};
+use scout_lexer::{Lexer, Span, TokenKind};
 use scout_parser::ast::{
-    Block, CallLiteral, CrawlLiteral, ExprKind, Identifier, IfElseLiteral, Kwarg, NodeKind,
-    Program, StmtKind,
+    Block, CallLiteral, CrawlLiteral, Expr, ExprKind, HashLiteral, Identifier, IfElseLiteral,
+    MapKey, MatchLiteral, NodeKind, ParamKind, Pattern, Program, Stmt, StmtKind, TypeDecl,
+    TypeExpr,
 };
+use scout_parser::diagnostic::Diagnostic;
 use scout_parser::{ParseError, Parser};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
 
 use crate::env::EnvPointer;
 use crate::import::resolve_module;
+use crate::limits::{Budget, Limit};
 use crate::object::{obj_map_to_json, Object};
-use crate::{builtin::BuiltinKind, env::Env};
+use crate::opt;
+use crate::{
+    builtin::{BuiltinKind, Kwargs},
+    env::Env,
+};
 
 pub type EvalResult = Result<Arc<Object>, EvalError>;
 pub type ScrapeResultsPtr = Arc<Mutex<ScrapeResults>>;
 
-const MAX_DEPTH: usize = 10;
+pub(crate) const MAX_DEPTH: usize = 10;
+
+/// How many fn calls and module loads can be nested before evaluation
+/// stops with [`EvalError::RecursionLimit`].
+pub const MAX_CALL_DEPTH: usize = 1000;
 
 /// Evaluates the block and early returns if the stmt evaluates
-/// to a Return
+/// to a Return, Break or Continue
 macro_rules! check_return_eval {
-    ($block:expr, $crawler:expr, $env:expr, $results:expr) => {
-        if let $crate::object::Object::Return(ret) =
-            &*eval_block($block, $crawler, $env, $results).await?
-        {
-            return Ok(Arc::new($crate::object::Object::Return(ret.clone())));
+    ($block:expr, $crawler:expr, $env:expr, $results:expr, $budget:expr) => {
+        let ev = eval_block($block, $crawler, block_scope($env), $results, $budget).await?;
+        if ev.is_control_flow() {
+            return Ok(ev);
         }
     };
 }
 
+/// What to do when `scrape Type { ... }` produces a map that does not
+/// match its declared type.
+#[derive(Default, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SchemaPolicy {
+    /// Raise an `invalid_record` error.
+    #[default]
+    Error,
+    /// Print a warning and record the map as scraped.
+    Warn,
+}
+
 #[derive(Default, Serialize, Deserialize, Debug)]
 pub struct ScrapeResults {
     results: Map<String, Value>,
+    #[serde(skip)]
+    schema_policy: SchemaPolicy,
+    #[serde(skip)]
+    optimize: bool,
 }
 
 impl ScrapeResults {
+    pub fn set_schema_policy(&mut self, policy: SchemaPolicy) {
+        self.schema_policy = policy;
+    }
+
+    pub fn schema_policy(&self) -> SchemaPolicy {
+        self.schema_policy
+    }
+
+    /// Sets whether modules imported with `use` are run through
+    /// [`opt::optimize`](crate::opt::optimize) as they are loaded.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    pub fn optimize(&self) -> bool {
+        self.optimize
+    }
+
     pub fn add_result(&mut self, res: Map<String, Value>, url: &str) {
         match self.results.get_mut(url) {
             None => {
@@ -67,11 +115,23 @@ impl ScrapeResults {
 
 #[derive(Debug)]
 pub enum ImportError {
-    ParseError(ParseError),
+    ParseError(Vec<ParseError>),
     PathError,
     UnknownModule,
 }
 
+/// A fn call or module load that an error unwound through.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// The fn that was called, or `None` for a module loaded by `use`.
+    pub name: Option<Identifier>,
+    /// The file of the module the fn was defined in or that was loaded,
+    /// or `None` for the program being run.
+    pub module: Option<PathBuf>,
+    /// Where the call or `use` is, in the module of the enclosing frame.
+    pub span: Span,
+}
+
 // TODO add parameters for better debugging.
 #[derive(Debug)]
 pub enum EvalError {
@@ -89,32 +149,295 @@ pub enum EvalError {
     InvalidJSONValue(serde_json::Value),
     HTTPError(reqwest::Error),
     IndexOutOfBounds,
+    /// The number of names being bound and the number of values found.
+    DestructureMismatch(usize, usize),
     NonFunction,
     UnknownIdent(Identifier),
     UnknownPrefixOp,
     UnknownInfixOp,
     UnknownKey(Identifier),
-    UncaughtException,
+    /// A value raised by a `throw` statement.
+    Throw(Arc<Object>),
     URLParseError(String),
-    DuplicateDeclare,
+    UnknownKwarg(Identifier),
+    /// A kwarg given twice, or for a param that was also passed by position.
+    DuplicateKwarg(Identifier),
+    /// A `let` or `const` of a name already declared in the same scope.
+    DuplicateDeclare(Identifier),
+    ConstAssign(Identifier),
+    /// A map that does not match the fields of a declared record type.
+    InvalidRecord(Identifier, String),
     NonIterable,
     ScreenshotError,
     BrowserError(fantoccini::error::CmdError),
     OSError(String),
     LockError,
+    /// More than [`MAX_CALL_DEPTH`] nested fn calls and module loads.
+    RecursionLimit,
+    /// Evaluation was stopped by one of the interpreter's [`Limits`].
+    /// Unlike other errors it cannot be caught by `try`.
+    ///
+    /// [`Limits`]: crate::limits::Limits
+    LimitExceeded(Limit),
+    /// An error along with the span of the innermost node it was raised from.
+    Spanned(Box<EvalError>, Span),
+    /// An error along with the frames it unwound through, innermost first.
+    Traced(Box<EvalError>, Vec<Frame>),
+}
+
+impl EvalError {
+    /// Attaches a span to the error unless it already has one, so the
+    /// innermost node that failed is the one that gets reported.
+    pub fn with_span(self, span: Span) -> Self {
+        match self {
+            Self::Spanned(_, _) | Self::Traced(_, _) => self,
+            _ => Self::Spanned(Box::new(self), span),
+        }
+    }
+
+    /// Records that the error unwound out of `frame`.
+    pub fn with_frame(self, frame: Frame) -> Self {
+        match self {
+            Self::Traced(e, mut frames) => {
+                frames.push(frame);
+                Self::Traced(e, frames)
+            }
+            _ => Self::Traced(Box::new(self), vec![frame]),
+        }
+    }
+
+    /// The span of the innermost node the error was raised from, which is
+    /// in the module of the innermost frame of its [`trace`](Self::trace).
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::Spanned(_, span) => Some(*span),
+            Self::Traced(e, _) => e.span(),
+            _ => None,
+        }
+    }
+
+    /// The fn calls and module loads the error unwound through, innermost
+    /// first.
+    pub fn trace(&self) -> &[Frame] {
+        match self {
+            Self::Traced(_, frames) => frames,
+            _ => &[],
+        }
+    }
+
+    /// Returns the underlying error with any span information stripped.
+    pub fn inner(&self) -> &EvalError {
+        match self {
+            Self::Spanned(e, _) | Self::Traced(e, _) => e.inner(),
+            _ => self,
+        }
+    }
+
+    /// The limit that stopped evaluation, if this error is from one.
+    pub fn limit(&self) -> Option<Limit> {
+        match self.inner() {
+            Self::LimitExceeded(limit) => Some(*limit),
+            _ => None,
+        }
+    }
+
+    /// A short, stable name for the kind of error, exposed to scripts
+    /// through the `kind` key of a caught error.
+    pub fn kind(&self) -> &'static str {
+        use EvalError::*;
+        match self.inner() {
+            TypeMismatch => "type_mismatch",
+            InvalidUsage(_) => "invalid_usage",
+            InvalidFnParams => "invalid_fn_params",
+            InvalidExpr => "invalid_expr",
+            InvalidUrl => "invalid_url",
+            InvalidImport(_) => "invalid_import",
+            InvalidIndex => "invalid_index",
+            InvalidAssign => "invalid_assign",
+            InvalidHTTPMethod(_) | InvalidHTTPHeaderKey(_) | InvalidHTTPHeaderValue(_) => {
+                "invalid_http_request"
+            }
+            InvalidJSONValue(_) => "invalid_json",
+            HTTPError(_) => "http_error",
+            IndexOutOfBounds => "index_out_of_bounds",
+            DestructureMismatch(_, _) => "destructure_mismatch",
+            NonFunction => "non_function",
+            UnknownIdent(_) => "unknown_ident",
+            UnknownPrefixOp => "unknown_prefix_op",
+            UnknownInfixOp => "unknown_infix_op",
+            UnknownKey(_) => "unknown_key",
+            Throw(_) => "throw",
+            URLParseError(_) => "url_parse_error",
+            UnknownKwarg(_) => "unknown_kwarg",
+            DuplicateKwarg(_) => "duplicate_kwarg",
+            DuplicateDeclare(_) => "duplicate_declare",
+            ConstAssign(_) => "const_assign",
+            InvalidRecord(_, _) => "invalid_record",
+            NonIterable => "non_iterable",
+            ScreenshotError => "screenshot_error",
+            BrowserError(_) => "browser_error",
+            OSError(_) => "os_error",
+            LockError => "lock_error",
+            RecursionLimit => "recursion_limit",
+            LimitExceeded(_) => "limit_exceeded",
+            Spanned(_, _) | Traced(_, _) => unreachable!("inner() strips spans and frames"),
+        }
+    }
+
+    /// Converts the error into the map bound by `catch e do`:
+    /// `kind`, `message`, the thrown `payload` (null for runtime errors)
+    /// and the `span` the error was raised from.
+    pub fn to_object(&self) -> Object {
+        let (message, payload) = match self.inner() {
+            EvalError::Throw(obj) => (thrown_message(obj), obj.clone()),
+            e => (e.to_string(), Arc::new(Object::Null)),
+        };
+        let span = match self.span() {
+            Some(span) => Object::Map(Mutex::new(IndexMap::from([
+                (
+                    Identifier::new("line".into()),
+                    Arc::new(Object::Number(span.line as f64)),
+                ),
+                (
+                    Identifier::new("col".into()),
+                    Arc::new(Object::Number(span.col as f64)),
+                ),
+            ]))),
+            None => Object::Null,
+        };
+        Object::Map(Mutex::new(IndexMap::from([
+            (
+                Identifier::new("kind".into()),
+                Arc::new(Object::Str(self.kind().to_owned())),
+            ),
+            (
+                Identifier::new("message".into()),
+                Arc::new(Object::Str(message)),
+            ),
+            (Identifier::new("payload".into()), payload),
+            (Identifier::new("span".into()), Arc::new(span)),
+        ])))
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        use EvalError::*;
+        match self.inner() {
+            UnknownIdent(_) => Some("variables must be assigned before they are used"),
+            InvalidFnParams => Some("check the number and types of the arguments"),
+            NonIterable => Some("only lists, maps, strings and modules can be looped over"),
+            InvalidIndex => Some("lists and strings are indexed by numbers, maps by strings"),
+            UnknownKwarg(_) => {
+                Some("kwargs must match a parameter name, or be collected by `**opts`")
+            }
+            DuplicateDeclare(_) => Some("assign without `let` to update an existing binding"),
+            ConstAssign(_) => Some("declare it with `let` if it needs to change"),
+            RecursionLimit => Some("check that recursive fns reach a base case and that modules do not `use` each other"),
+            _ => None,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Option<Diagnostic> {
+        let diag = Diagnostic::new(self.to_string(), self.span()?);
+        Some(match self.hint() {
+            Some(hint) => diag.with_hint(hint),
+            None => diag,
+        })
+    }
 }
 
+/// The message for a thrown value: strings are used as-is, and maps may
+/// provide one under a `message` key.
+fn thrown_message(obj: &Object) -> String {
+    match obj {
+        Object::Str(s) => s.clone(),
+        Object::Map(map) => match map.try_lock() {
+            Some(inner) => match inner.get(&Identifier::new("message".into())).map(|o| &**o) {
+                Some(Object::Str(s)) => s.clone(),
+                _ => obj.to_string(),
+            },
+            None => obj.to_string(),
+        },
+        _ => obj.to_string(),
+    }
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ParseError(errs) => match errs.first() {
+                Some(e) => write!(f, "failed to parse module: {e}"),
+                None => write!(f, "failed to parse module"),
+            },
+            Self::PathError => write!(f, "invalid module path"),
+            Self::UnknownModule => write!(f, "unknown module"),
+        }
+    }
+}
+
+impl std::fmt::Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use EvalError::*;
+        match self {
+            TypeMismatch => write!(f, "type mismatch"),
+            InvalidUsage(s) => write!(f, "invalid usage: {s}"),
+            InvalidFnParams => write!(f, "invalid function parameters"),
+            InvalidExpr => write!(f, "invalid expression"),
+            InvalidUrl => write!(f, "invalid url"),
+            InvalidImport(e) => write!(f, "invalid import: {e}"),
+            InvalidIndex => write!(f, "invalid index"),
+            InvalidAssign => write!(f, "invalid assignment target"),
+            InvalidHTTPMethod(m) => write!(f, "invalid HTTP method `{m}`"),
+            InvalidHTTPHeaderKey(k) => write!(f, "invalid HTTP header key `{k}`"),
+            InvalidHTTPHeaderValue(v) => write!(f, "invalid HTTP header value `{v}`"),
+            InvalidJSONValue(v) => write!(f, "invalid JSON value `{v}`"),
+            HTTPError(e) => write!(f, "HTTP error: {e}"),
+            IndexOutOfBounds => write!(f, "index out of bounds"),
+            DestructureMismatch(expected, found) => {
+                write!(f, "expected {expected} values to unpack, found {found}")
+            }
+            NonFunction => write!(f, "value is not a function"),
+            UnknownIdent(ident) => write!(f, "unknown identifier `{ident}`"),
+            UnknownPrefixOp => write!(f, "unknown prefix operator"),
+            UnknownInfixOp => write!(f, "unsupported operands for infix operator"),
+            UnknownKey(ident) => write!(f, "unknown key `{ident}`"),
+            Throw(obj) => write!(f, "uncaught error: {}", thrown_message(obj)),
+            URLParseError(s) => write!(f, "error parsing url: {s}"),
+            UnknownKwarg(ident) => write!(f, "unknown keyword argument `{ident}`"),
+            DuplicateKwarg(ident) => write!(f, "argument `{ident}` was given more than once"),
+            DuplicateDeclare(ident) => write!(f, "`{ident}` is already declared in this scope"),
+            ConstAssign(ident) => write!(f, "cannot assign to constant `{ident}`"),
+            InvalidRecord(ident, msg) => write!(f, "invalid `{ident}`: {msg}"),
+            NonIterable => write!(f, "value is not iterable"),
+            ScreenshotError => write!(f, "error taking screenshot"),
+            BrowserError(e) => write!(f, "browser error: {e}"),
+            OSError(s) => write!(f, "os error: {s}"),
+            LockError => write!(f, "lock error"),
+            RecursionLimit => {
+                write!(
+                    f,
+                    "more than {MAX_CALL_DEPTH} nested fn calls and module loads"
+                )
+            }
+            LimitExceeded(limit) => write!(f, "execution limit exceeded: {limit}"),
+            Spanned(e, _) | Traced(e, _) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
 pub async fn eval(
     node: NodeKind,
     crawler: &fantoccini::Client,
     env: EnvPointer,
     results: ScrapeResultsPtr,
+    budget: &Budget,
 ) -> EvalResult {
     use NodeKind::*;
     match node {
-        Program(p) => eval_program(p, crawler, env.clone(), results.clone()).await,
-        Stmt(s) => eval_statement(&s, crawler, env.clone(), results.clone()).await,
-        Expr(e) => eval_expression(&e, crawler, env.clone(), results.clone()).await,
+        Program(p) => eval_program(p, crawler, env.clone(), results.clone(), budget).await,
+        Stmt(s) => eval_statement(&s, crawler, env.clone(), results.clone(), budget).await,
+        Expr(e) => eval_expression(&e, crawler, env.clone(), results.clone(), budget).await,
     }
 }
 
@@ -123,23 +446,42 @@ async fn eval_program(
     crawler: &fantoccini::Client,
     env: EnvPointer,
     results: ScrapeResultsPtr,
+    budget: &Budget,
 ) -> EvalResult {
     let block = Block::new(prgm.stmts);
-    eval_block(&block, crawler, env.clone(), results.clone()).await
+    eval_block(&block, crawler, env.clone(), results.clone(), budget).await
 }
 
 fn eval_statement<'a>(
+    stmt: &'a Stmt,
+    crawler: &'a fantoccini::Client,
+    env: EnvPointer,
+    results: ScrapeResultsPtr,
+    budget: &'a Budget,
+) -> BoxFuture<'a, EvalResult> {
+    async move {
+        eval_stmt_kind(&stmt.kind, stmt.span, crawler, env, results, budget)
+            .await
+            .map_err(|e| e.with_span(stmt.span))
+    }
+    .boxed()
+}
+
+fn eval_stmt_kind<'a>(
     stmt: &'a StmtKind,
+    span: Span,
     crawler: &'a fantoccini::Client,
     env: EnvPointer,
     results: ScrapeResultsPtr,
+    budget: &'a Budget,
 ) -> BoxFuture<'a, EvalResult> {
     async move {
         match stmt {
             StmtKind::Goto(expr) => {
                 if let Object::Str(url) =
-                    &*eval_expression(expr, crawler, env.clone(), results.clone()).await?
+                    &*eval_expression(expr, crawler, env.clone(), results.clone(), budget).await?
                 {
+                    budget.visit()?;
                     if crawler.goto(url.as_str()).await.is_err() {
                         return Err(EvalError::InvalidUrl);
                     };
@@ -151,52 +493,99 @@ fn eval_statement<'a>(
 
                 Ok(Arc::new(Object::Null))
             }
-            StmtKind::TryCatch(try_block, catch_block) => {
-                match eval_block(try_block, crawler, env.clone(), results.clone())
-                    .await
-                    .as_deref()
-                {
-                    Ok(Object::Return(ret)) => return Ok(Arc::new(Object::Return(ret.clone()))),
-                    // if it was successful but not a return, do nothing
-                    Ok(_) => {}
-                    Err(_) if catch_block.is_some() => {
-                        let block = catch_block.as_ref().unwrap();
-                        check_return_eval!(block, crawler, env.clone(), results.clone());
+            StmtKind::TryCatch(try_block, catch_lit, finally_block) => {
+                let res = match (
+                    eval_block(
+                        try_block,
+                        crawler,
+                        block_scope(env.clone()),
+                        results.clone(),
+                        budget,
+                    )
+                    .await,
+                    catch_lit,
+                ) {
+                    (Err(e), Some(catch_lit)) if e.limit().is_none() => {
+                        let mut scope = Env::block(env.clone());
+                        if let Some(binding) = &catch_lit.binding {
+                            scope.bind(binding, Arc::new(e.to_object()));
+                        }
+                        let scope = Arc::new(Mutex::new(scope));
+                        eval_block(&catch_lit.block, crawler, scope, results.clone(), budget).await
                     }
-                    Err(_) => return Err(EvalError::UncaughtException),
+                    (res, _) => res,
                 };
 
-                Ok(Arc::new(Object::Null))
+                // The finally block always runs, and any error or early
+                // exit from it takes precedence over the try/catch result.
+                if let Some(block) = finally_block {
+                    check_return_eval!(block, crawler, env.clone(), results.clone(), budget);
+                }
+
+                match res {
+                    Ok(ev) if ev.is_control_flow() => Ok(ev),
+                    Ok(_) => Ok(Arc::new(Object::Null)),
+                    Err(e) => Err(e),
+                }
+            }
+            StmtKind::Throw(expr) => {
+                let obj =
+                    eval_expression(expr, crawler, env.clone(), results.clone(), budget).await?;
+                Err(EvalError::Throw(obj))
             }
-            StmtKind::Scrape(defs) => {
-                let mut res = HashMap::new();
-                for (id, def) in &defs.pairs {
-                    let val = eval_expression(def, crawler, env.clone(), results.clone()).await?;
-                    res.insert(id.clone(), val);
+            StmtKind::Scrape(ty, defs) => {
+                let mut res =
+                    eval_hash_literal(defs, crawler, env.clone(), results.clone(), budget).await?;
+                if let Some(ty) = ty {
+                    let lookup = env_lookup(&env);
+                    let decl = lookup_type(ty, &lookup)?;
+                    match check_record(decl, res.clone(), &lookup) {
+                        Ok(checked) => res = checked,
+                        Err(e @ EvalError::InvalidRecord(..))
+                            if results.lock().await.schema_policy == SchemaPolicy::Warn =>
+                        {
+                            eprintln!("warning: {e}");
+                        }
+                        Err(e) => return Err(e),
+                    }
                 }
-                results.lock().await.add_result(
-                    obj_map_to_json(&res).await,
-                    crawler.current_url().await.unwrap().as_str(),
-                );
+                let json = obj_map_to_json(&res).await;
+                let url = crawler.current_url().await.unwrap();
+                budget.record()?;
+                results.lock().await.add_result(json, url.as_str());
                 Ok(Arc::new(Object::Null))
             }
             StmtKind::Expr(expr) => {
-                eval_expression(expr, crawler, env.clone(), results.clone()).await
+                eval_expression(expr, crawler, env.clone(), results.clone(), budget).await
             }
             StmtKind::ForLoop(floop) => {
-                let items =
-                    eval_expression(&floop.iterable, crawler, env.clone(), results.clone()).await?;
-                if let Some(iterable) = items.into_iterable().await {
+                let items = eval_expression(
+                    &floop.iterable,
+                    crawler,
+                    env.clone(),
+                    results.clone(),
+                    budget,
+                )
+                .await?;
+                if let Some(iterable) = items.into_iterable() {
                     for obj in iterable.into_iter().collect::<Vec<Arc<Object>>>() {
-                        let mut scope = Env::default();
-                        scope.add_outer(env.clone()).await;
-                        scope.set(&floop.ident, obj.clone()).await;
-                        check_return_eval!(
+                        let mut scope = Env::block(env.clone());
+                        for (ident, val) in destructure(&floop.idents, obj)? {
+                            scope.bind(ident, val);
+                        }
+                        let ev = eval_block(
                             &floop.block,
                             crawler,
                             Arc::new(Mutex::new(scope)),
-                            results.clone()
-                        );
+                            results.clone(),
+                            budget,
+                        )
+                        .await?;
+                        match &*ev {
+                            Object::Break => break,
+                            Object::Return(_) => return Ok(ev),
+                            _ => {}
+                        }
                     }
                     Ok(Arc::new(Object::Null))
                 } else {
@@ -204,23 +593,40 @@ fn eval_statement<'a>(
                 }
             }
             StmtKind::WhileLoop(condition, block) => {
-                while eval_expression(condition, crawler, env.clone(), results.clone())
+                while eval_expression(condition, crawler, env.clone(), results.clone(), budget)
                     .await?
                     .is_truthy()
-                    .await
                 {
-                    check_return_eval!(block, crawler, env.clone(), results.clone());
+                    // Counted even when the body is empty, so that the
+                    // step limit also stops `while true do end`.
+                    budget.step()?;
+                    let ev = eval_block(
+                        block,
+                        crawler,
+                        block_scope(env.clone()),
+                        results.clone(),
+                        budget,
+                    )
+                    .await?;
+                    match &*ev {
+                        Object::Break => break,
+                        Object::Return(_) => return Ok(ev),
+                        _ => {}
+                    }
                 }
                 Ok(Arc::new(Object::Null))
             }
-            StmtKind::Assign(lhs, expr, global) => {
-                let val = eval_expression(expr, crawler, env.clone(), results.clone()).await?;
-                match lhs {
+            StmtKind::Assign(lhs, expr, global, _) => {
+                let val =
+                    eval_expression(expr, crawler, env.clone(), results.clone(), budget).await?;
+                match &lhs.kind {
                     ExprKind::Infix(lhs, t, rhs) if t.kind == TokenKind::LBracket => {
                         let r_obj =
-                            eval_expression(rhs, crawler, env.clone(), results.clone()).await?;
+                            eval_expression(rhs, crawler, env.clone(), results.clone(), budget)
+                                .await?;
                         let l_obj =
-                            &*eval_expression(lhs, crawler, env.clone(), results.clone()).await?;
+                            &*eval_expression(lhs, crawler, env.clone(), results.clone(), budget)
+                                .await?;
 
                         match (l_obj, &*r_obj) {
                             (Object::List(v), Object::Number(idx)) => {
@@ -242,17 +648,47 @@ fn eval_statement<'a>(
                             _ => Err(EvalError::InvalidIndex),
                         }
                     }
+                    ExprKind::Field(obj, field) => {
+                        match &*eval_expression(obj, crawler, env.clone(), results.clone(), budget)
+                            .await?
+                        {
+                            Object::Map(m) => {
+                                m.lock().await.insert(field.clone(), val);
+                                Ok(Arc::new(Object::Null))
+                            }
+                            _ => Err(EvalError::InvalidAssign),
+                        }
+                    }
                     ExprKind::Ident(ident) => {
                         if !global {
-                            env.lock().await.set(ident, val).await;
+                            env.lock().await.set(ident, val)?;
                         } else {
-                            env.lock().await.add_global(ident, val).await;
+                            env.lock().await.add_global(ident, val)?;
+                        }
+                        Ok(Arc::new(Object::Null))
+                    }
+                    // `[a, b] = pair`
+                    ExprKind::List(_) => {
+                        let idents = assign_targets(lhs)?;
+                        let mut inner = env.lock().await;
+                        for (ident, val) in destructure(&idents, val)? {
+                            inner.set(ident, val)?;
                         }
                         Ok(Arc::new(Object::Null))
                     }
                     _ => Err(EvalError::InvalidAssign),
                 }
             }
+            StmtKind::Let(lhs, expr, constant, _) => {
+                let val =
+                    eval_expression(expr, crawler, env.clone(), results.clone(), budget).await?;
+                let idents = assign_targets(lhs)?;
+                let mut inner = env.lock().await;
+                for (ident, val) in destructure(&idents, val)? {
+                    inner.declare(ident, val, *constant)?;
+                }
+                Ok(Arc::new(Object::Null))
+            }
             StmtKind::Screenshot(path) => {
                 let png = crawler.screenshot().await?;
                 let img = image::io::Reader::new(std::io::Cursor::new(png))
@@ -269,56 +705,311 @@ fn eval_statement<'a>(
                 else_lit,
             }) => {
                 let truth_check =
-                    eval_expression(&if_lit.cond, crawler, env.clone(), results.clone()).await?;
-                if truth_check.is_truthy().await {
-                    check_return_eval!(&if_lit.block, crawler, env.clone(), results.clone());
+                    eval_expression(&if_lit.cond, crawler, env.clone(), results.clone(), budget)
+                        .await?;
+                if truth_check.is_truthy() {
+                    check_return_eval!(
+                        &if_lit.block,
+                        crawler,
+                        env.clone(),
+                        results.clone(),
+                        budget
+                    );
                 } else {
                     for elif in elifs {
-                        if eval_expression(&elif.cond, crawler, env.clone(), results.clone())
-                            .await?
-                            .is_truthy()
-                            .await
+                        if eval_expression(
+                            &elif.cond,
+                            crawler,
+                            env.clone(),
+                            results.clone(),
+                            budget,
+                        )
+                        .await?
+                        .is_truthy()
                         {
-                            check_return_eval!(&elif.block, crawler, env.clone(), results.clone());
+                            check_return_eval!(
+                                &elif.block,
+                                crawler,
+                                env.clone(),
+                                results.clone(),
+                                budget
+                            );
                             return Ok(Arc::new(Object::Null));
                         }
                     }
 
                     if let Some(lit) = else_lit {
-                        check_return_eval!(&lit.block, crawler, env.clone(), results.clone());
+                        check_return_eval!(
+                            &lit.block,
+                            crawler,
+                            env.clone(),
+                            results.clone(),
+                            budget
+                        );
                     }
                 }
 
                 Ok(Arc::new(Object::Null))
             }
             StmtKind::Func(def, global) => {
-                let lit = Object::Fn(def.params.clone(), def.body.clone());
+                let lit = Object::Fn(def.params.clone(), def.body.clone(), env.clone());
                 if !global {
-                    env.lock().await.set(&def.ident, Arc::new(lit)).await;
+                    env.lock().await.set(&def.ident, Arc::new(lit))?;
                 } else {
-                    env.lock().await.add_global(&def.ident, Arc::new(lit)).await;
+                    env.lock().await.add_global(&def.ident, Arc::new(lit))?;
                 }
                 Ok(Arc::new(Object::Null))
             }
+            StmtKind::TypeDecl(decl) => {
+                env.lock()
+                    .await
+                    .set(&decl.ident, Arc::new(Object::Type(decl.clone())))?;
+                Ok(Arc::new(Object::Null))
+            }
+            StmtKind::Break => Ok(Arc::new(Object::Break)),
+            StmtKind::Continue => Ok(Arc::new(Object::Continue)),
             StmtKind::Return(rv) => match rv {
                 None => Ok(Arc::new(Object::Null)),
-                Some(expr) => eval_expression(expr, crawler, env.clone(), results.clone()).await,
+                Some(expr) => {
+                    eval_expression(expr, crawler, env.clone(), results.clone(), budget).await
+                }
             },
             StmtKind::Use(import) => {
                 let resolved = resolve_module(import)?;
                 let path = std::env::current_dir()
                     .map_err(|e| EvalError::OSError(e.to_string()))?
                     .join(&resolved.filepath);
-                eval_use_chain(path, &resolved.ident, crawler, env.clone(), results.clone()).await
+                eval_use_chain(
+                    path,
+                    &resolved.ident,
+                    span,
+                    crawler,
+                    env.clone(),
+                    results.clone(),
+                    budget,
+                )
+                .await
             }
             StmtKind::Crawl(lit) => {
                 let mut visited = HashSet::new();
 
-                eval_crawl(lit, crawler, env, results, &mut visited, 1).await?;
+                let ev = eval_crawl(lit, crawler, env, results, &mut visited, 1, budget).await?;
+                match &*ev {
+                    Object::Return(_) => Ok(ev),
+                    _ => Ok(Arc::new(Object::Null)),
+                }
+            }
+        }
+    }
+    .boxed()
+}
+
+/// Resolves the name of a record type to its declaration.
+pub(crate) type TypeLookup<'a> = &'a dyn Fn(&Identifier) -> Option<Arc<Object>>;
 
-                Ok(Arc::new(Object::Null))
+pub(crate) fn lookup_type(ident: &Identifier, lookup: TypeLookup) -> Result<TypeDecl, EvalError> {
+    match lookup(ident).as_deref() {
+        Some(Object::Type(decl)) => Ok(decl.clone()),
+        Some(_) => Err(EvalError::InvalidUsage(format!("`{ident}` is not a type"))),
+        None => Err(EvalError::UnknownIdent(ident.clone())),
+    }
+}
+
+/// Looks up type names in `env`.
+fn env_lookup(env: &EnvPointer) -> impl Fn(&Identifier) -> Option<Arc<Object>> + '_ {
+    |ident| env.try_lock()?.get(ident)
+}
+
+/// Checks `map` against a record type, returning its fields in declared
+/// order with any missing optional fields set to null.
+pub(crate) fn check_record(
+    decl: TypeDecl,
+    mut map: IndexMap<Identifier, Arc<Object>>,
+    lookup: TypeLookup,
+) -> Result<IndexMap<Identifier, Arc<Object>>, EvalError> {
+    let invalid = |msg: String| EvalError::InvalidRecord(decl.ident.clone(), msg);
+    let mut out = IndexMap::new();
+    for (field, ty) in &decl.fields {
+        let val = match map.shift_remove(field) {
+            Some(val) => val,
+            None if ty.optional => Arc::new(Object::Null),
+            None => return Err(invalid(format!("missing field `{field}`"))),
+        };
+        let found = val.type_str().to_owned();
+        match check_type(ty, val, lookup)? {
+            Some(val) => out.insert(field.clone(), val),
+            None => {
+                return Err(invalid(format!(
+                    "field `{field}` should be {ty}, found {found}"
+                )))
+            }
+        };
+    }
+    if let Some(extra) = map.keys().next() {
+        return Err(invalid(format!("unknown field `{extra}`")));
+    }
+    Ok(out)
+}
+
+/// Returns the value if it has type `ty`, or None if it does not. Maps
+/// checked against a record type come back in that type's field order.
+fn check_type(
+    ty: &TypeExpr,
+    val: Arc<Object>,
+    lookup: TypeLookup,
+) -> Result<Option<Arc<Object>>, EvalError> {
+    if ty.optional && matches!(*val, Object::Null) {
+        return Ok(Some(val));
+    }
+    let expected = match ty.name.name.as_str() {
+        "any" => return Ok(Some(val)),
+        "str" | "string" => "string",
+        name @ ("number" | "bool" | "list" | "map" | "node" | "fn" | "null") => name,
+        _ => {
+            let decl = lookup_type(&ty.name, lookup)?;
+            let Object::Map(map) = &*val else {
+                return Ok(None);
+            };
+            let inner = map.try_lock().ok_or(EvalError::LockError)?.clone();
+            let checked = check_record(decl, inner, lookup)?;
+            return Ok(Some(Arc::new(Object::Map(Mutex::new(checked)))));
+        }
+    };
+    Ok((val.type_str() == expected).then_some(val))
+}
+
+/// Evaluates the entries of a map or scrape literal in the order they
+/// were written.
+async fn eval_hash_literal(
+    lit: &HashLiteral,
+    crawler: &fantoccini::Client,
+    env: EnvPointer,
+    results: ScrapeResultsPtr,
+    budget: &Budget,
+) -> Result<IndexMap<Identifier, Arc<Object>>, EvalError> {
+    let mut out = IndexMap::new();
+    for (key, val) in lit.pairs.iter() {
+        let key = match key {
+            MapKey::Ident(ident) => ident.clone(),
+            MapKey::Computed(expr) => {
+                match &*eval_expression(expr, crawler, env.clone(), results.clone(), budget).await?
+                {
+                    Object::Str(s) => Identifier::new(s.clone()),
+                    _ => return Err(EvalError::InvalidIndex.with_span(expr.span)),
+                }
+            }
+        };
+        let obj_val = eval_expression(val, crawler, env.clone(), results.clone(), budget).await?;
+        out.insert(key, obj_val);
+    }
+    Ok(out)
+}
+
+/// The names bound by an assignment or declaration: a single identifier
+/// or a list of them to unpack into.
+pub(crate) fn assign_targets(lhs: &Expr) -> Result<Vec<Identifier>, EvalError> {
+    match &lhs.kind {
+        ExprKind::Ident(ident) => Ok(vec![ident.clone()]),
+        ExprKind::List(targets) => targets
+            .iter()
+            .map(|t| match &t.kind {
+                ExprKind::Ident(ident) => Ok(ident.clone()),
+                _ => Err(EvalError::InvalidAssign),
+            })
+            .collect(),
+        _ => Err(EvalError::InvalidAssign),
+    }
+}
+
+/// A new scope for the body of a block nested in `outer`.
+fn block_scope(outer: EnvPointer) -> EnvPointer {
+    Arc::new(Mutex::new(Env::block(outer)))
+}
+
+/// Identifiers paired with the values they are bound to.
+type Bindings<'a> = Vec<(&'a Identifier, Arc<Object>)>;
+
+/// Pairs each identifier with the value it binds to. A single identifier
+/// binds the whole value, while several unpack a list of the same length.
+pub(crate) fn destructure(
+    idents: &[Identifier],
+    obj: Arc<Object>,
+) -> Result<Bindings<'_>, EvalError> {
+    if let [ident] = idents {
+        return Ok(vec![(ident, obj)]);
+    }
+
+    match &*obj {
+        Object::List(items) => {
+            let items = items.try_lock().ok_or(EvalError::LockError)?;
+            if items.len() != idents.len() {
+                return Err(EvalError::DestructureMismatch(idents.len(), items.len()));
+            }
+            Ok(idents.iter().zip(items.iter().cloned()).collect())
+        }
+        _ => Err(EvalError::TypeMismatch),
+    }
+}
+
+/// Checks `obj` against `pattern`, returning the values bound by its
+/// names if it matches. Literals are compared with `==`.
+fn match_pattern<'a>(
+    pattern: &'a Pattern,
+    obj: Arc<Object>,
+    crawler: &'a fantoccini::Client,
+    env: EnvPointer,
+    results: ScrapeResultsPtr,
+    budget: &'a Budget,
+) -> BoxFuture<'a, Result<Option<Bindings<'a>>, EvalError>> {
+    async move {
+        let mut bindings = Vec::new();
+        match (pattern, &*obj) {
+            (Pattern::Wildcard, _) => {}
+            (Pattern::Binding(ident), _) => bindings.push((ident, obj.clone())),
+            (Pattern::Literal(expr), _) => {
+                let lit = eval_expression(expr, crawler, env, results, budget).await?;
+                if !obj.eq(&lit) {
+                    return Ok(None);
+                }
             }
+            (Pattern::List(pats), Object::List(items)) => {
+                let items = items.lock().await.clone();
+                if items.len() != pats.len() {
+                    return Ok(None);
+                }
+                for (pat, item) in pats.iter().zip(items) {
+                    match match_pattern(pat, item, crawler, env.clone(), results.clone(), budget)
+                        .await?
+                    {
+                        Some(inner) => bindings.extend(inner),
+                        None => return Ok(None),
+                    }
+                }
+            }
+            (Pattern::Map(pairs), Object::Map(map)) => {
+                let map = map.lock().await.clone();
+                for (key, pat) in pairs {
+                    let Some(val) = map.get(key) else {
+                        return Ok(None);
+                    };
+                    match match_pattern(
+                        pat,
+                        val.clone(),
+                        crawler,
+                        env.clone(),
+                        results.clone(),
+                        budget,
+                    )
+                    .await?
+                    {
+                        Some(inner) => bindings.extend(inner),
+                        None => return Ok(None),
+                    }
+                }
+            }
+            _ => return Ok(None),
         }
+        Ok(Some(bindings))
     }
     .boxed()
 }
@@ -326,32 +1017,52 @@ fn eval_statement<'a>(
 fn eval_use_chain<'a>(
     path: PathBuf,
     ident: &'a Identifier,
+    span: Span,
     crawler: &'a fantoccini::Client,
     env: EnvPointer,
     results: ScrapeResultsPtr,
+    budget: &'a Budget,
 ) -> BoxFuture<'a, EvalResult> {
     async move {
         if path.with_extension("sct").exists() {
-            let content = fs::read_to_string(path.with_extension("sct"))
-                .map_err(|e| EvalError::OSError(e.to_string()))?;
+            let file = path.with_extension("sct");
+            let content =
+                fs::read_to_string(&file).map_err(|e| EvalError::OSError(e.to_string()))?;
             let lex = Lexer::new(&content);
             let mut parser = Parser::new(lex);
             match parser.parse_program() {
                 Ok(prgm) => {
-                    let mut new_env = Env::default();
-                    new_env.inherit_globals(env.clone()).await;
+                    let prgm = match results.lock().await.optimize() {
+                        true => opt::optimize(prgm),
+                        false => prgm,
+                    };
+                    let depth = env.lock().await.depth();
+                    if depth >= MAX_CALL_DEPTH {
+                        return Err(EvalError::RecursionLimit);
+                    }
+                    let mut new_env = Env::module(file.clone(), depth + 1);
+                    new_env.inherit_globals(&*env.lock().await);
                     let module_env = Arc::new(Mutex::new(new_env));
-                    eval(
-                        NodeKind::Program(prgm),
-                        crawler,
-                        module_env.clone(),
-                        results.clone(),
+                    let frame = Frame {
+                        name: None,
+                        module: Some(file),
+                        span,
+                    };
+                    GrowStack(
+                        eval(
+                            NodeKind::Program(prgm),
+                            crawler,
+                            module_env.clone(),
+                            results.clone(),
+                            budget,
+                        )
+                        .boxed(),
                     )
-                    .await?;
+                    .await
+                    .map_err(|e| e.with_frame(frame))?;
                     env.lock()
                         .await
-                        .set(ident, Arc::new(Object::Module(module_env)))
-                        .await;
+                        .set(ident, Arc::new(Object::Module(module_env)))?;
                     Ok(Arc::new(Object::Null))
                 }
                 Err(e) => Err(EvalError::InvalidImport(ImportError::ParseError(e))),
@@ -369,34 +1080,42 @@ fn eval_use_chain<'a>(
             } else {
                 dir_name_raw
             };
-            let mut new_env = Env::default();
-            new_env.inherit_globals(env.clone()).await;
+            let depth = env.lock().await.depth();
+            if depth >= MAX_CALL_DEPTH {
+                return Err(EvalError::RecursionLimit);
+            }
+            let mut new_env = Env::module(path.clone(), depth + 1);
+            new_env.inherit_globals(&*env.lock().await);
             let mod_env = Arc::new(Mutex::new(new_env));
-            for entry in path.read_dir().unwrap() {
-                if let Ok(entry) = entry {
-                    let filename = entry
-                        .path()
-                        .file_stem()
-                        .ok_or(EvalError::InvalidImport(ImportError::PathError))?
-                        .to_str()
-                        .ok_or(EvalError::InvalidImport(ImportError::PathError))?
-                        .to_string();
-                    let sub_ident = Identifier::new(filename);
-                    let p = entry.path();
-
-                    // Ignore error because you could have files that arent valid
-                    // scout modules. But we dont care if they error.
-                    // @TODO: make errors more specific to ignore just these types of error.
-                    let _ =
-                        eval_use_chain(p, &sub_ident, crawler, mod_env.clone(), results.clone())
-                            .await;
-                }
+            for entry in path.read_dir().unwrap().flatten() {
+                let filename = entry
+                    .path()
+                    .file_stem()
+                    .ok_or(EvalError::InvalidImport(ImportError::PathError))?
+                    .to_str()
+                    .ok_or(EvalError::InvalidImport(ImportError::PathError))?
+                    .to_string();
+                let sub_ident = Identifier::new(filename);
+                let p = entry.path();
+
+                // Ignore error because you could have files that arent valid
+                // scout modules. But we dont care if they error.
+                // @TODO: make errors more specific to ignore just these types of error.
+                let _ = eval_use_chain(
+                    p,
+                    &sub_ident,
+                    span,
+                    crawler,
+                    mod_env.clone(),
+                    results.clone(),
+                    budget,
+                )
+                .await;
             }
             let mod_ident = Identifier::new(dir_name);
             env.lock()
                 .await
-                .set(&mod_ident, Arc::new(Object::Module(mod_env)))
-                .await;
+                .set(&mod_ident, Arc::new(Object::Module(mod_env)))?;
             Ok(Arc::new(Object::Null))
         } else if path
             .parent()
@@ -413,7 +1132,7 @@ fn eval_use_chain<'a>(
                 .ok_or(EvalError::InvalidImport(ImportError::PathError))?
                 .to_string();
             let parent_ident = Identifier::new(parent_module);
-            let mb_obj = env.lock().await.get(&parent_ident).await;
+            let mb_obj = env.lock().await.get(&parent_ident);
             match mb_obj {
                 Some(obj) => match &*obj {
                     // Parent module is already loaded, so simply get the object
@@ -426,9 +1145,9 @@ fn eval_use_chain<'a>(
                             .ok_or(EvalError::InvalidImport(ImportError::PathError))?
                             .to_string();
                         let final_ident = Identifier::new(final_name);
-                        let obj_exists = mod_env.lock().await.get(&final_ident).await;
+                        let obj_exists = mod_env.lock().await.get(&final_ident);
                         if let Some(obj) = obj_exists {
-                            env.lock().await.set(&final_ident, obj.clone()).await;
+                            env.lock().await.set(&final_ident, obj.clone())?;
                             Ok(Arc::new(Object::Null))
                         } else {
                             Err(EvalError::InvalidImport(ImportError::UnknownModule))
@@ -441,12 +1160,23 @@ fn eval_use_chain<'a>(
                     eval_use_chain(
                         parent_module_path.to_path_buf(),
                         &parent_ident,
+                        span,
                         crawler,
                         env.clone(),
                         results.clone(),
+                        budget,
+                    )
+                    .await?;
+                    eval_use_chain(
+                        path,
+                        ident,
+                        span,
+                        crawler,
+                        env.clone(),
+                        results.clone(),
+                        budget,
                     )
                     .await?;
-                    eval_use_chain(path, ident, crawler, env.clone(), results.clone()).await?;
                     Ok(Arc::new(Object::Null))
                 }
             }
@@ -464,7 +1194,8 @@ fn eval_crawl<'a>(
     results: ScrapeResultsPtr,
     visited: &'a mut HashSet<String>,
     depth: usize,
-) -> BoxFuture<'a, Result<(), EvalError>> {
+    budget: &'a Budget,
+) -> BoxFuture<'a, EvalResult> {
     async move {
         let start = crawler.window().await?;
         match crawler.find_all(Locator::Css("a[href]")).await {
@@ -472,37 +1203,30 @@ fn eval_crawl<'a>(
                 for elem in elems.iter() {
                     if let Ok(Some(link_str)) = elem.attr("href").await {
                         let curr_url = crawler.current_url().await?;
-                        let link = match url::Url::parse(&link_str) {
-                            Ok(l) => Ok(l.to_string()),
-                            Err(url::ParseError::RelativeUrlWithoutBase) => Ok(curr_url
-                                .join(&link_str)
-                                .map_err(|_| EvalError::InvalidUrl)?
-                                .to_string()),
-                            Err(_) => Err(EvalError::InvalidUrl),
-                        }?;
-
-                        let mut scope = Env::default();
-                        scope.add_outer(env.clone()).await;
+                        let link = resolve_link(&curr_url, &link_str)?;
 
+                        let mut scope = Env::block(env.clone());
                         if let Some(bindings) = &lit.bindings {
-                            scope
-                                .set(&bindings.link, Arc::new(Object::Str(link.clone())))
-                                .await;
-                            scope
-                                .set(&bindings.depth, Arc::new(Object::Number(depth as f64)))
-                                .await;
+                            scope.bind(&bindings.link, Arc::new(Object::Str(link.clone())));
+                            scope.bind(&bindings.depth, Arc::new(Object::Number(depth as f64)));
                         }
 
                         let new_env = Arc::new(Mutex::new(scope));
 
                         let mut truth_check = true;
                         if let Some(expr) = &lit.filter {
-                            let obj =
-                                eval_expression(expr, crawler, new_env.clone(), results.clone())
-                                    .await?;
-                            truth_check = obj.is_truthy().await;
+                            let obj = eval_expression(
+                                expr,
+                                crawler,
+                                new_env.clone(),
+                                results.clone(),
+                                budget,
+                            )
+                            .await?;
+                            truth_check = obj.is_truthy();
                         }
                         if !visited.contains(&link) && truth_check {
+                            budget.visit()?;
                             let new_tab = crawler.new_window(true).await?;
                             crawler.switch_to_window(new_tab.handle).await?;
                             let _ = crawler.goto(&link).await;
@@ -511,21 +1235,33 @@ fn eval_crawl<'a>(
                             visited.insert(link);
                             visited.insert(crawler.current_url().await?.to_string());
 
-                            eval_block(&lit.body, crawler, new_env.clone(), results.clone())
-                                .await?;
+                            let mut ev = eval_block(
+                                &lit.body,
+                                crawler,
+                                new_env.clone(),
+                                results.clone(),
+                                budget,
+                            )
+                            .await?;
 
-                            if depth < MAX_DEPTH {
-                                eval_crawl(
+                            if depth < MAX_DEPTH && !stops_crawl(&ev) {
+                                ev = eval_crawl(
                                     lit,
                                     crawler,
                                     env.clone(),
                                     results.clone(),
                                     visited,
                                     depth + 1,
+                                    budget,
                                 )
                                 .await?;
                             }
 
+                            if stops_crawl(&ev) {
+                                crawler.switch_to_window(start.clone()).await?;
+                                return Ok(ev);
+                            }
+
                             crawler.switch_to_window(start.clone()).await?;
                         }
                     }
@@ -534,12 +1270,30 @@ fn eval_crawl<'a>(
             Err(e) => return Err(EvalError::BrowserError(e)),
         };
 
-        Ok(())
+        Ok(Arc::new(Object::Null))
     }
     .boxed()
 }
 
-async fn wait_for_goto_ready() {
+/// Resolves the `href` of a link found on the page at `curr_url`.
+pub(crate) fn resolve_link(curr_url: &url::Url, link: &str) -> Result<String, EvalError> {
+    match url::Url::parse(link) {
+        Ok(l) => Ok(l.to_string()),
+        Err(url::ParseError::RelativeUrlWithoutBase) => Ok(curr_url
+            .join(link)
+            .map_err(|_| EvalError::InvalidUrl)?
+            .to_string()),
+        Err(_) => Err(EvalError::InvalidUrl),
+    }
+}
+
+/// A `break` anywhere in a crawl body ends the whole crawl, not just the
+/// current page. `continue` only skips the rest of the body.
+pub(crate) fn stops_crawl(ev: &Object) -> bool {
+    matches!(ev, Object::Break | Object::Return(_))
+}
+
+pub(crate) async fn wait_for_goto_ready() {
     // @TODO: Need a better way to determine that a page is "done"
     sleep(Duration::from_millis(50));
 }
@@ -549,48 +1303,71 @@ async fn eval_block(
     crawler: &fantoccini::Client,
     env: EnvPointer,
     results: ScrapeResultsPtr,
+    budget: &Budget,
 ) -> EvalResult {
     let mut res = Arc::new(Object::Null);
     for stmt in &block.stmts {
-        match stmt {
+        budget.step().map_err(|e| e.with_span(stmt.span))?;
+        match &stmt.kind {
             StmtKind::Return(rv) => {
                 return match rv {
                     None => Ok(Arc::new(Object::Return(Arc::new(Object::Null)))),
                     Some(expr) => {
                         let r =
-                            eval_expression(expr, crawler, env.clone(), results.clone()).await?;
+                            eval_expression(expr, crawler, env.clone(), results.clone(), budget)
+                                .await
+                                .map_err(|e| e.with_span(stmt.span))?;
                         Ok(Arc::new(Object::Return(r)))
                     }
                 };
             }
             _ => {
-                let temp = eval_statement(stmt, crawler, env.clone(), results.clone()).await?;
-                match &*temp {
-                    Object::Return(_) => return Ok(temp.clone()),
-                    _ => {
-                        res = temp.clone();
-                    }
+                let temp =
+                    eval_statement(stmt, crawler, env.clone(), results.clone(), budget).await?;
+                if temp.is_control_flow() {
+                    return Ok(temp);
                 }
+                res = temp;
             }
         }
     }
     Ok(res)
 }
 
+/// Polls a future on a newly allocated stack whenever the current one is
+/// nearly used up, so that deep recursion ends at [`MAX_CALL_DEPTH`]
+/// rather than by overflowing the native stack.
+pub(crate) struct GrowStack<'a, T>(pub(crate) BoxFuture<'a, T>);
+
+impl<T> Future for GrowStack<'_, T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        stacker::maybe_grow(1024 * 1024, 8 * 1024 * 1024, || self.0.poll_unpin(cx))
+    }
+}
+
 fn apply_call<'a>(
-    ident: &'a Identifier,
-    args: &'a [ExprKind],
-    kwargs: &'a [Kwarg],
+    call: &'a CallLiteral,
+    span: Span,
     crawler: &'a fantoccini::Client,
     prev: Option<Arc<Object>>,
     env: EnvPointer,
     results: ScrapeResultsPtr,
+    budget: &'a Budget,
 ) -> BoxFuture<'a, EvalResult> {
     async move {
+        let CallLiteral {
+            ident,
+            args,
+            kwargs,
+        } = call;
+
         // Evaluate the provided fn inputs
         let mut obj_params = Vec::new();
         for param in args.iter() {
-            let expr = eval_expression(param, crawler, env.clone(), results.clone()).await?;
+            let expr =
+                eval_expression(param, crawler, env.clone(), results.clone(), budget).await?;
             obj_params.push(expr);
         }
 
@@ -600,54 +1377,92 @@ fn apply_call<'a>(
             obj_params.insert(0, obj);
         }
 
+        let mut obj_kwargs = Kwargs::new();
+        for kwarg in kwargs.iter() {
+            let val =
+                eval_expression(&kwarg.expr, crawler, env.clone(), results.clone(), budget).await?;
+            if obj_kwargs.insert(kwarg.ident.clone(), val).is_some() {
+                return Err(EvalError::DuplicateKwarg(kwarg.ident.clone()));
+            }
+        }
+
         // Set var before match to avoid deadlock on env
-        let env_res = env.lock().await.get(ident).await;
+        let env_res = env.lock().await.get(ident);
         match env_res {
             // This is a user defined function
             Some(obj) => match &*obj {
                 // Only fn's are callable
-                Object::Fn(fn_params, block) => {
-                    // Create the scope that will be used within the fn body
+                Object::Fn(fn_params, block, closure) => {
+                    let depth = env.lock().await.depth();
+                    if depth >= MAX_CALL_DEPTH {
+                        return Err(EvalError::RecursionLimit);
+                    }
+
+                    // Create the scope that will be used within the fn body.
+                    // Its parent is the env the fn was defined in, not the caller.
                     let mut scope = Env::default();
-                    scope.add_outer(env.clone()).await;
-                    for (i, fn_param) in fn_params.iter().enumerate() {
+                    scope.add_outer(closure.clone());
+                    scope.set_depth(depth + 1);
+                    let mut provided = obj_params.into_iter();
+                    for fn_param in fn_params.iter() {
                         let id = &fn_param.ident;
-                        // check if the fn was provided this param or if
-                        // we should use a default
-                        match obj_params.get(i) {
-                            // Fn param was provided
-                            Some(provided) => {
-                                scope.set(id, provided.clone()).await;
+                        let val = match fn_param.kind {
+                            ParamKind::Rest => {
+                                let rest = provided.by_ref().collect();
+                                Arc::new(Object::List(Mutex::new(rest)))
                             }
-                            // Fn param was not provided, check for defaults
-                            None => match &fn_param.default {
-                                Some(def) => {
-                                    let obj_def =
-                                        eval_expression(def, crawler, env.clone(), results.clone())
-                                            .await?;
-                                    scope.set(id, obj_def).await;
-                                }
-                                None => {
-                                    return Err(EvalError::InvalidFnParams);
+                            ParamKind::Kwargs => {
+                                let opts = std::mem::take(&mut obj_kwargs);
+                                Arc::new(Object::Map(Mutex::new(opts)))
+                            }
+                            // A param can be passed by position or by name,
+                            // otherwise it falls back to its default.
+                            ParamKind::Positional => {
+                                match (provided.next(), obj_kwargs.shift_remove(id)) {
+                                    (Some(_), Some(_)) => {
+                                        return Err(EvalError::DuplicateKwarg(id.clone()))
+                                    }
+                                    (Some(val), None) | (None, Some(val)) => val,
+                                    (None, None) => match &fn_param.default {
+                                        Some(def) => {
+                                            eval_expression(
+                                                def,
+                                                crawler,
+                                                closure.clone(),
+                                                results.clone(),
+                                                budget,
+                                            )
+                                            .await?
+                                        }
+                                        None => return Err(EvalError::InvalidFnParams),
+                                    },
                                 }
-                            },
-                        }
+                            }
+                        };
+                        scope.bind(id, val);
                     }
 
-                    for kwarg in kwargs.iter() {
-                        let val =
-                            eval_expression(&kwarg.expr, crawler, env.clone(), results.clone())
-                                .await?;
-                        scope.set(&kwarg.ident, val).await;
+                    if provided.next().is_some() {
+                        return Err(EvalError::InvalidFnParams);
+                    }
+                    if let Some(unknown) = obj_kwargs.keys().next() {
+                        return Err(EvalError::UnknownKwarg(unknown.clone()));
                     }
 
-                    let ev = eval_block(
-                        &block,
+                    let body = eval_block(
+                        block,
                         crawler,
                         Arc::new(Mutex::new(scope)),
                         results.clone(),
-                    )
-                    .await?;
+                        budget,
+                    );
+                    let ev = GrowStack(body.boxed()).await.map_err(|e| {
+                        e.with_frame(Frame {
+                            name: Some(ident.clone()),
+                            module: closure.try_lock().and_then(|c| c.module_path()),
+                            span,
+                        })
+                    })?;
                     match &*ev {
                         Object::Return(ret) => Ok(ret.clone()),
                         _ => Ok(ev),
@@ -657,7 +1472,11 @@ fn apply_call<'a>(
             },
             // Not user defined, check if its a builtin
             None => match BuiltinKind::is_from(&ident.name) {
-                Some(builtin) => builtin.apply(crawler, results.clone(), obj_params).await,
+                Some(builtin) => {
+                    builtin
+                        .apply(crawler, results.clone(), obj_params, obj_kwargs)
+                        .await
+                }
                 None => Err(EvalError::UnknownIdent(ident.clone())),
             },
         }
@@ -665,7 +1484,7 @@ fn apply_call<'a>(
     .boxed()
 }
 
-async fn apply_debug_border(crawler: &fantoccini::Client, selector: &str) {
+pub(crate) async fn apply_debug_border(crawler: &fantoccini::Client, selector: &str) {
     let js = r#"
     const [selector] = arguments;
 
@@ -675,7 +1494,7 @@ async fn apply_debug_border(crawler: &fantoccini::Client, selector: &str) {
     let _ = crawler.execute(js, vec![json!(selector)]).await;
 }
 
-async fn apply_debug_border_all(crawler: &fantoccini::Client, selector: &str) {
+pub(crate) async fn apply_debug_border_all(crawler: &fantoccini::Client, selector: &str) {
     let js = r#"
     const [selector] = arguments;
 
@@ -686,15 +1505,32 @@ async fn apply_debug_border_all(crawler: &fantoccini::Client, selector: &str) {
 }
 
 fn eval_expression<'a>(
+    expr: &'a Expr,
+    crawler: &'a fantoccini::Client,
+    env: EnvPointer,
+    results: ScrapeResultsPtr,
+    budget: &'a Budget,
+) -> BoxFuture<'a, EvalResult> {
+    async move {
+        eval_expr_kind(&expr.kind, expr.span, crawler, env, results, budget)
+            .await
+            .map_err(|e| e.with_span(expr.span))
+    }
+    .boxed()
+}
+
+fn eval_expr_kind<'a>(
     expr: &'a ExprKind,
+    span: Span,
     crawler: &'a fantoccini::Client,
     env: EnvPointer,
     results: ScrapeResultsPtr,
+    budget: &'a Budget,
 ) -> BoxFuture<'a, EvalResult> {
     async move {
         match expr {
             ExprKind::Select(selector, scope) => match scope {
-                Some(ident) => match env.lock().await.get(ident).await.as_deref() {
+                Some(ident) => match env.lock().await.get(ident).as_deref() {
                     Some(Object::Node(elem)) => match elem.find(Locator::Css(selector)).await {
                         Ok(node) => {
                             // @TODO fix - applies borders outside scope
@@ -715,7 +1551,7 @@ fn eval_expression<'a>(
                 },
             },
             ExprKind::SelectAll(selector, scope) => match scope {
-                Some(ident) => match env.lock().await.get(ident).await.as_deref() {
+                Some(ident) => match env.lock().await.get(ident).as_deref() {
                     Some(Object::Node(elem)) => match elem.find_all(Locator::Css(selector)).await {
                         Ok(nodes) => {
                             // @TODO fix - applies borders outside scope
@@ -746,64 +1582,167 @@ fn eval_expression<'a>(
             ExprKind::Str(s) => Ok(Arc::new(Object::Str(s.to_owned()))),
             ExprKind::Number(n) => Ok(Arc::new(Object::Number(*n))),
             ExprKind::Map(map) => {
-                let mut out = HashMap::new();
-
-                for (key, val) in map.pairs.iter() {
-                    let obj_val =
-                        eval_expression(val, crawler, env.clone(), results.clone()).await?;
-                    out.insert(key.clone(), obj_val);
-                }
-
+                let out =
+                    eval_hash_literal(map, crawler, env.clone(), results.clone(), budget).await?;
                 Ok(Arc::new(Object::Map(Mutex::new(out))))
             }
-            ExprKind::Call(CallLiteral {
-                ident,
-                args,
-                kwargs,
-            }) => {
+            ExprKind::Call(call) => {
                 apply_call(
-                    ident,
-                    args,
-                    kwargs,
+                    call,
+                    span,
                     crawler,
                     None,
                     env.clone(),
                     results.clone(),
+                    budget,
                 )
                 .await
             }
-            ExprKind::Ident(ident) => match env.lock().await.get(ident).await {
+            ExprKind::Lambda(lit) => Ok(Arc::new(Object::Fn(
+                lit.params.clone(),
+                lit.body.clone(),
+                env.clone(),
+            ))),
+            ExprKind::Ident(ident) => match env.lock().await.get(ident) {
                 Some(obj) => Ok(obj.clone()),
                 None => Err(EvalError::UnknownIdent(ident.clone())),
             },
-            ExprKind::Chain(exprs) => {
+            ExprKind::Record(ty, lit) => {
+                let decl = lookup_type(ty, &env_lookup(&env))?;
+                let map =
+                    eval_hash_literal(lit, crawler, env.clone(), results.clone(), budget).await?;
+                let map = check_record(decl, map, &env_lookup(&env))?;
+                Ok(Arc::new(Object::Map(Mutex::new(map))))
+            }
+            ExprKind::Match(MatchLiteral { subject, arms }) => {
+                let subject =
+                    eval_expression(subject, crawler, env.clone(), results.clone(), budget).await?;
+                for arm in arms {
+                    let matched = match_pattern(
+                        &arm.pattern,
+                        subject.clone(),
+                        crawler,
+                        env.clone(),
+                        results.clone(),
+                        budget,
+                    )
+                    .await?;
+                    let Some(bindings) = matched else {
+                        continue;
+                    };
+
+                    let mut scope = Env::block(env.clone());
+                    for (ident, val) in bindings {
+                        scope.bind(ident, val);
+                    }
+                    let scope = Arc::new(Mutex::new(scope));
+
+                    if let Some(guard) = &arm.guard {
+                        let guard =
+                            eval_expression(guard, crawler, scope.clone(), results.clone(), budget)
+                                .await?;
+                        if !guard.is_truthy() {
+                            continue;
+                        }
+                    }
+                    return eval_block(&arm.block, crawler, scope, results.clone(), budget).await;
+                }
+                Ok(Arc::new(Object::Null))
+            }
+            ExprKind::Conditional(cond, then, otherwise) => {
+                let cond =
+                    eval_expression(cond, crawler, env.clone(), results.clone(), budget).await?;
+                match cond.is_truthy() {
+                    true => {
+                        eval_expression(then, crawler, env.clone(), results.clone(), budget).await
+                    }
+                    false => {
+                        eval_expression(otherwise, crawler, env.clone(), results.clone(), budget)
+                            .await
+                    }
+                }
+            }
+            ExprKind::Field(obj, field) => {
+                let obj =
+                    eval_expression(obj, crawler, env.clone(), results.clone(), budget).await?;
+                match &*obj {
+                    Object::Map(m) => match m.lock().await.get(field) {
+                        Some(val) => Ok(val.clone()),
+                        None => Err(EvalError::UnknownKey(field.clone())),
+                    },
+                    Object::Module(mod_env) => match mod_env.lock().await.get(field) {
+                        Some(val) => Ok(val.clone()),
+                        None => Err(EvalError::UnknownIdent(field.clone())),
+                    },
+                    _ => Err(EvalError::InvalidIndex),
+                }
+            }
+            ExprKind::MethodCall(obj, call) => {
+                let obj =
+                    eval_expression(obj, crawler, env.clone(), results.clone(), budget).await?;
+                match &*obj {
+                    // `mod.f(a)` is the same as `mod::f(a)`.
+                    Object::Module(mod_env) => {
+                        let call = apply_call(
+                            call,
+                            span,
+                            crawler,
+                            None,
+                            mod_env.clone(),
+                            results.clone(),
+                            budget,
+                        );
+                        in_module(mod_env, &env, call).await
+                    }
+                    // Otherwise the receiver is piped in as the first argument,
+                    // so `node.text()` is the same as `node |> text()`.
+                    _ => {
+                        apply_call(
+                            call,
+                            span,
+                            crawler,
+                            Some(obj.clone()),
+                            env.clone(),
+                            results.clone(),
+                            budget,
+                        )
+                        .await
+                    }
+                }
+            }
+            ExprKind::Chain(exprs) | ExprKind::SafeChain(exprs) => {
+                let null_safe = matches!(expr, ExprKind::SafeChain(_));
                 let mut prev: Option<Arc<Object>> = None;
                 for expr in exprs {
-                    let eval = match expr {
-                        ExprKind::Call(CallLiteral {
-                            ident,
-                            args,
-                            kwargs,
-                        }) => {
+                    if null_safe && matches!(prev.as_deref(), Some(Object::Null)) {
+                        break;
+                    }
+                    let eval = match &expr.kind {
+                        ExprKind::Call(call) => {
                             apply_call(
-                                ident,
-                                args,
-                                kwargs,
+                                call,
+                                expr.span,
                                 crawler,
                                 prev,
                                 env.clone(),
                                 results.clone(),
+                                budget,
                             )
-                            .await?
+                            .await
+                        }
+                        .map_err(|e| e.with_span(expr.span))?,
+                        _ => {
+                            eval_expression(expr, crawler, env.clone(), results.clone(), budget)
+                                .await?
                         }
-                        _ => eval_expression(expr, crawler, env.clone(), results.clone()).await?,
                     };
                     prev = Some(eval);
                 }
                 Ok(prev.unwrap())
             }
             ExprKind::Infix(lhs, op, rhs) => {
-                let l_obj = eval_expression(lhs, crawler, env.clone(), results.clone()).await?;
+                let l_obj =
+                    eval_expression(lhs, crawler, env.clone(), results.clone(), budget).await?;
                 let res = eval_infix(
                     l_obj.clone(),
                     &op.kind,
@@ -811,6 +1750,7 @@ fn eval_expression<'a>(
                     crawler,
                     env.clone(),
                     results.clone(),
+                    budget,
                 )
                 .await?;
                 Ok(res)
@@ -820,15 +1760,26 @@ fn eval_expression<'a>(
             ExprKind::List(vec) => {
                 let mut list_content = Vec::new();
                 for expr in vec {
-                    let obj = eval_expression(expr, crawler, env.clone(), results.clone()).await?;
+                    let obj = eval_expression(expr, crawler, env.clone(), results.clone(), budget)
+                        .await?;
                     list_content.push(obj);
                 }
 
                 Ok(Arc::new(Object::List(Mutex::new(list_content))))
             }
+            ExprKind::Interp(parts) => {
+                let mut out = String::new();
+                for part in parts {
+                    let obj = eval_expression(part, crawler, env.clone(), results.clone(), budget)
+                        .await?;
+                    out.push_str(&obj.to_display());
+                }
+                Ok(Arc::new(Object::Str(out)))
+            }
             ExprKind::Prefix(rhs, op) => {
-                let r_obj = eval_expression(rhs, crawler, env.clone(), results.clone()).await?;
-                let res = eval_prefix(r_obj, &op.kind).await?;
+                let r_obj =
+                    eval_expression(rhs, crawler, env.clone(), results.clone(), budget).await?;
+                let res = eval_prefix(r_obj, &op.kind)?;
                 Ok(res)
             }
         }
@@ -836,67 +1787,101 @@ fn eval_expression<'a>(
     .boxed()
 }
 
+/// Runs `eval` in a module's scope, resolving the names the module does
+/// not define from the caller's scope and nesting calls from it in the
+/// caller's until it is done.
+async fn in_module(
+    mod_env: &EnvPointer,
+    caller: &EnvPointer,
+    eval: impl Future<Output = EvalResult>,
+) -> EvalResult {
+    if Arc::ptr_eq(mod_env, caller) {
+        return eval.await;
+    }
+    let depth = caller.lock().await.depth();
+    let prev = mod_env.lock().await.enter(caller, depth);
+    let res = eval.await;
+    mod_env.lock().await.exit(prev);
+    res
+}
+
 async fn eval_infix(
     lhs: Arc<Object>,
     op: &TokenKind,
-    rhs: &ExprKind,
+    rhs: &Expr,
     crawler: &fantoccini::Client,
     env: EnvPointer,
     results: ScrapeResultsPtr,
+    budget: &Budget,
 ) -> EvalResult {
     match op {
+        // Only evaluated when needed, so `??` can guard a fallible default.
+        TokenKind::DbQuestion => match &*lhs {
+            Object::Null => {
+                eval_expression(rhs, crawler, env.clone(), results.clone(), budget).await
+            }
+            _ => Ok(lhs),
+        },
+        // `and`/`or` short-circuit and evaluate to the operand that decided them.
+        TokenKind::And => match lhs.is_truthy() {
+            true => eval_expression(rhs, crawler, env.clone(), results.clone(), budget).await,
+            false => Ok(lhs),
+        },
+        TokenKind::Or => match lhs.is_truthy() {
+            true => Ok(lhs),
+            false => eval_expression(rhs, crawler, env.clone(), results.clone(), budget).await,
+        },
         TokenKind::DbColon => match &*lhs {
             Object::Module(mod_env) => {
-                mod_env.lock().await.add_outer(env).await;
-                eval_expression(rhs, crawler, mod_env.clone(), results.clone()).await
+                let eval = eval_expression(rhs, crawler, mod_env.clone(), results.clone(), budget);
+                in_module(mod_env, &env, eval).await
             }
             _ => Err(EvalError::UnknownInfixOp),
         },
         _ => {
-            let rhs_obj = eval_expression(rhs, crawler, env.clone(), results.clone()).await?;
-            eval_infix_op(lhs, op, rhs_obj).await
+            let rhs_obj =
+                eval_expression(rhs, crawler, env.clone(), results.clone(), budget).await?;
+            eval_infix_op(lhs, op, rhs_obj)
         }
     }
 }
 
-async fn eval_infix_op(lhs: Arc<Object>, op: &TokenKind, rhs: Arc<Object>) -> EvalResult {
+pub(crate) fn eval_infix_op(lhs: Arc<Object>, op: &TokenKind, rhs: Arc<Object>) -> EvalResult {
     match op {
-        TokenKind::EQ => Ok(Arc::new(Object::Boolean(lhs.eq(&rhs).await))),
-        TokenKind::NEQ => Ok(Arc::new(Object::Boolean(!lhs.eq(&rhs).await))),
+        TokenKind::EQ => Ok(Arc::new(Object::Boolean(lhs.eq(&rhs)))),
+        TokenKind::NEQ => Ok(Arc::new(Object::Boolean(!lhs.eq(&rhs)))),
         TokenKind::Plus => eval_plus_op(lhs, rhs),
         TokenKind::Minus => eval_minus_op(lhs, rhs),
         TokenKind::Asterisk => eval_asterisk_op(lhs, rhs),
         TokenKind::Slash => eval_slash_op(lhs, rhs),
-        TokenKind::LBracket => eval_index(lhs, rhs).await,
+        TokenKind::TildeSlash => eval_number_op(lhs, rhs, |a, b| (a / b).trunc()),
+        TokenKind::Percent => eval_number_op(lhs, rhs, |a, b| a % b),
+        TokenKind::DbAsterisk => eval_number_op(lhs, rhs, f64::powf),
+        TokenKind::LBracket => eval_index(lhs, rhs),
         TokenKind::GT => eval_gt_op(lhs, rhs),
         TokenKind::LT => eval_lt_op(lhs, rhs),
         TokenKind::GTE => eval_gte_op(lhs, rhs),
         TokenKind::LTE => eval_lte_op(lhs, rhs),
-        TokenKind::And => Ok(Arc::new(Object::Boolean(
-            lhs.is_truthy().await && rhs.is_truthy().await,
-        ))),
-        TokenKind::Or => Ok(Arc::new(Object::Boolean(
-            lhs.is_truthy().await || rhs.is_truthy().await,
-        ))),
         _ => Err(EvalError::UnknownInfixOp),
     }
 }
 
-async fn eval_prefix(rhs: Arc<Object>, op: &TokenKind) -> EvalResult {
+pub(crate) fn eval_prefix(rhs: Arc<Object>, op: &TokenKind) -> EvalResult {
     match (&*rhs, op) {
         (_, TokenKind::Bang) => {
-            let truth = !rhs.is_truthy().await;
+            let truth = !rhs.is_truthy();
             Ok(Arc::new(Object::Boolean(truth)))
         }
+        (Object::Number(n), TokenKind::Minus) => Ok(Arc::new(Object::Number(-n))),
         _ => Err(EvalError::UnknownPrefixOp),
     }
 }
 
-async fn eval_index(lhs: Arc<Object>, idx: Arc<Object>) -> EvalResult {
+fn eval_index(lhs: Arc<Object>, idx: Arc<Object>) -> EvalResult {
     match (&*lhs, &*idx) {
         (Object::List(a), Object::Number(b)) => {
             let idx = *b as usize;
-            let inner = a.lock().await;
+            let inner = a.try_lock().ok_or(EvalError::LockError)?;
             if idx < inner.len() {
                 Ok(inner[idx].clone())
             } else {
@@ -904,7 +1889,7 @@ async fn eval_index(lhs: Arc<Object>, idx: Arc<Object>) -> EvalResult {
             }
         }
         (Object::Map(m), Object::Str(s)) => {
-            let inner = m.lock().await;
+            let inner = m.try_lock().ok_or(EvalError::LockError)?;
             let ident = Identifier::new(s.clone());
             let mb_val = inner.get(&ident);
             match mb_val {
@@ -980,6 +1965,13 @@ fn eval_slash_op(lhs: Arc<Object>, rhs: Arc<Object>) -> EvalResult {
     }
 }
 
+fn eval_number_op(lhs: Arc<Object>, rhs: Arc<Object>, op: fn(f64, f64) -> f64) -> EvalResult {
+    match (&*lhs, &*rhs) {
+        (Object::Number(a), Object::Number(b)) => Ok(Arc::new(Object::Number(op(*a, *b)))),
+        _ => Err(EvalError::UnknownInfixOp),
+    }
+}
+
 impl From<fantoccini::error::CmdError> for EvalError {
     fn from(e: fantoccini::error::CmdError) -> Self {
         Self::BrowserError(e)
@@ -991,3 +1983,239 @@ impl From<image::ImageError> for EvalError {
         Self::ScreenshotError
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use test_case::test_case;
+
+    use crate::testing::run;
+    use crate::Engine;
+
+    async fn eval_str(input: &str) -> Result<Value, String> {
+        run(input, Engine::Tree, false).await
+    }
+
+    #[test_case(r#"n = 2
+"page ${n} of ${null} ${[1, "a"]}""#, json!("page 2 of Null [1, a]"); "any value")]
+    #[test_case(r#""${"a"}${1 + 1}""#, json!("a2"); "no text")]
+    #[test_case(r#""a" + "b""#, json!("ab"); "str concat")]
+    #[tokio::test]
+    async fn test_interpolation(input: &str, exp: Value) {
+        assert_eq!(eval_str(input).await, Ok(exp));
+    }
+
+    // A directory that imports itself stops at the depth limit, and the
+    // error is skipped like any file in it that fails to load.
+    #[test_case(Engine::Tree; "tree")]
+    #[test_case(Engine::Vm; "vm")]
+    #[tokio::test]
+    async fn test_dir_import_depth(engine: Engine) {
+        let res = run("use tests::fixtures::cyc\ncyc::a::x", engine, false).await;
+        assert_eq!(res, Ok(json!(1.0)));
+    }
+
+    #[test_case(r#""x" + null"#; "str plus null")]
+    #[test_case(r#""n" + [1]"#; "str plus list")]
+    #[test_case(r#""n" + 1"#; "str plus number")]
+    #[tokio::test]
+    async fn test_str_plus_non_str(input: &str) {
+        let exp = Err("unsupported operands for infix operator".to_string());
+        assert_eq!(eval_str(input).await, exp);
+    }
+
+    #[test_case("def make(n) do\n  return fn(x) do\n    return x + n\n  end\nend\nadd = make(2)\nn = 10\nadd(1)", json!(3.0); "captures defining env")]
+    #[test_case("m = {f: fn(x) do\n  return x * 2\nend}\ng = m[\"f\"]\ng(3)", json!(6.0); "stored in map")]
+    #[test_case("def apply(f, x) do\n  return f(x)\nend\napply(fn(s) do\n  return s + \"!\"\nend, \"a\")", json!("a!"); "passed as arg")]
+    #[test_case("fs = []\nfor i in [1, 2] do\n  push(fs, fn() do\n    return i\n  end)\nend\nf = fs[0]\ng = fs[1]\nout = [f(), g()]\nout", json!([1.0, 2.0]); "captured in loop")]
+    #[tokio::test]
+    async fn test_lambda(input: &str, exp: Value) {
+        assert_eq!(eval_str(input).await, Ok(exp));
+    }
+
+    #[test_case("out = []\nfor x in [1, 2, 3, 4] do\n  if x == 3 do\n    break\n  end\n  push(out, x)\nend\nout", json!([1.0, 2.0]); "break for")]
+    #[test_case("out = []\nfor x in [1, 2, 3] do\n  if x == 2 do\n    continue\n  end\n  push(out, x)\nend\nout", json!([1.0, 3.0]); "continue for")]
+    #[test_case("i = 0\nwhile true do\n  i = i + 1\n  if i > 2 do\n    break\n  end\nend\ni", json!(3.0); "break while")]
+    #[test_case("i = 0\nn = 0\nwhile i < 4 do\n  i = i + 1\n  if i % 2 == 0 do\n    continue\n  end\n  n = n + i\nend\nn", json!(4.0); "continue while")]
+    #[test_case("out = []\nfor x in [1, 2] do\n  for y in [1, 2] do\n    if y == 2 do\n      break\n    end\n    push(out, [x, y])\n  end\nend\nout", json!([[1.0, 1.0], [2.0, 1.0]]); "break inner loop")]
+    #[tokio::test]
+    async fn test_loop_control(input: &str, exp: Value) {
+        assert_eq!(eval_str(input).await, Ok(exp));
+    }
+
+    #[test_case("try\n  throw \"boom\"\ncatch e do\n  x = e[\"message\"]\nend\nx", json!("boom"); "message")]
+    #[test_case("try\n  throw {code: 404}\ncatch e do\n  x = e[\"payload\"][\"code\"]\nend\nx", json!(404.0); "payload")]
+    #[test_case("try\n  x = y\ncatch e do\n  x = e[\"kind\"]\nend\nx", json!("unknown_ident"); "runtime error kind")]
+    #[test_case("out = []\ntry\n  throw \"a\"\ncatch e do\n  push(out, 1)\nfinally\n  push(out, 2)\nend\nout", json!([1.0, 2.0]); "finally after catch")]
+    #[test_case("out = []\ntry\n  push(out, 1)\nfinally\n  push(out, 2)\nend\nout", json!([1.0, 2.0]); "finally without error")]
+    #[tokio::test]
+    async fn test_try_catch(input: &str, exp: Value) {
+        assert_eq!(eval_str(input).await, Ok(exp));
+    }
+
+    #[test_case("throw \"boom\"", "uncaught error: boom"; "uncaught str")]
+    #[test_case("throw {message: \"bad\", code: 1}", "uncaught error: bad"; "uncaught map message")]
+    #[test_case("try\n  throw \"a\"\ncatch e do\n  throw \"b\"\nend", "uncaught error: b"; "rethrow from catch")]
+    #[tokio::test]
+    async fn test_throw_uncaught(input: &str, exp: &str) {
+        assert_eq!(eval_str(input).await, Err(exp.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_finally_runs_on_uncaught() {
+        let res = eval_str("x = 0\ndef f() do\n  try\n    throw \"a\"\n  finally\n    x = 1\n  end\nend\ntry\n  f()\ncatch e do\nend\nx").await;
+        assert_eq!(res, Ok(json!(1.0)));
+    }
+
+    #[test_case("x = 3\nx * -2", json!(-6.0); "unary minus")]
+    #[test_case("7 % 3", json!(1.0); "modulo")]
+    #[test_case("7 ~/ 2", json!(3.0); "integer division")]
+    #[test_case("2 ** 10", json!(1024.0); "exponent")]
+    #[test_case("0x10 + 1_000 + 1e2", json!(1116.0); "numeric literals")]
+    #[tokio::test]
+    async fn test_numeric_ops(input: &str, exp: Value) {
+        assert_eq!(eval_str(input).await, Ok(exp));
+    }
+
+    #[test_case(r#""a\tb\n\"c\" \u{41}""#, json!("a\tb\n\"c\" A"); "escapes")]
+    #[test_case("\"\"\"\nline 1\nline \"2\"\n\"\"\"", json!("line 1\nline \"2\"\n"); "triple quoted")]
+    #[test_case(r#""\${n}""#, json!("${n}"); "escaped interpolation")]
+    #[tokio::test]
+    async fn test_str_literals(input: &str, exp: Value) {
+        assert_eq!(eval_str(input).await, Ok(exp));
+    }
+
+    #[test_case("null ?|> len()", json!(null); "null safe pipe on null")]
+    #[test_case("\"abc\" ?|> len()", json!(3.0); "null safe pipe on value")]
+    #[test_case("null ?|> len() ?? \"n/a\"", json!("n/a"); "pipe then coalesce")]
+    #[test_case("0 ?? 1", json!(0.0); "coalesce keeps falsy")]
+    #[tokio::test]
+    async fn test_null_safe(input: &str, exp: Value) {
+        assert_eq!(eval_str(input).await, Ok(exp));
+    }
+
+    #[test_case("out = []\nfor k, v in {a: 1, b: 2} do\n  push(out, [k, v])\nend\nout", json!([["a", 1.0], ["b", 2.0]]); "map entries")]
+    #[test_case("out = []\nfor i, x in enumerate([\"a\", \"b\"]) do\n  push(out, [i, x])\nend\nout", json!([[0.0, "a"], [1.0, "b"]]); "enumerate")]
+    #[test_case("[a, b] = [1, 2]\nout = [b, a]\nout", json!([2.0, 1.0]); "list destructure")]
+    #[tokio::test]
+    async fn test_destructure(input: &str, exp: Value) {
+        assert_eq!(eval_str(input).await, Ok(exp));
+    }
+
+    #[tokio::test]
+    async fn test_destructure_mismatch() {
+        let res = eval_str("[a, b] = [1, 2, 3]").await;
+        assert_eq!(res, Err("expected 2 values to unpack, found 3".to_string()));
+    }
+
+    #[test_case("{z: 1, a: 2, m: 3}", r#"{"z":1.0,"a":2.0,"m":3.0}"#; "insertion order")]
+    #[test_case("{\"content-type\": \"json\", \"2024\": 1}", r#"{"content-type":"json","2024":1.0}"#; "quoted keys")]
+    #[test_case("k = \"b\"\n{a: 1, [k]: 2}", r#"{"a":1.0,"b":2.0}"#; "computed key")]
+    #[tokio::test]
+    async fn test_map_keys(input: &str, exp: &str) {
+        let res = eval_str(input).await.unwrap();
+        assert_eq!(serde_json::to_string(&res).unwrap(), exp);
+    }
+
+    #[test_case("m = {a: {b: 1}}\nm.a.b", json!(1.0); "field access")]
+    #[test_case("m = {a: {b: 1}}\nm.a.b = 2\nm[\"a\"][\"b\"]", json!(2.0); "field assign")]
+    #[test_case("\"abc\".len()", json!(3.0); "method call")]
+    #[test_case("xs = [1, 2]\nxs.contains(2)", json!(true); "method call with args")]
+    #[tokio::test]
+    async fn test_dot_access(input: &str, exp: Value) {
+        assert_eq!(eval_str(input).await, Ok(exp));
+    }
+
+    #[tokio::test]
+    async fn test_dot_access_unknown_key() {
+        let res = eval_str("m = {a: 1}\nm.b").await;
+        assert_eq!(res, Err("unknown key `b`".to_string()));
+    }
+
+    #[test_case("\"404\"", json!("not found"); "literal")]
+    #[test_case("[1, 5]", json!(5.0); "list with binding")]
+    #[test_case("[1, 0]", json!("other"); "failed guard")]
+    #[test_case("{kind: \"detail\", id: 7}", json!(7.0); "map pattern")]
+    #[test_case("3", json!("other"); "wildcard")]
+    #[tokio::test]
+    async fn test_match(value: &str, exp: Value) {
+        let input = format!(
+            "x = match {value}\ncase \"404\" do \"not found\"\ncase [1, a] if a > 1 do a\ncase {{ kind: \"detail\", id: i }} do i\ncase _ do \"other\"\nend\nx"
+        );
+        assert_eq!(eval_str(&input).await, Ok(exp));
+    }
+
+    #[test_case("node = null\nnode and len(node) > 0", json!(null); "and stops at falsy")]
+    #[test_case("\"a\" or len(null)", json!("a"); "or stops at truthy")]
+    #[test_case("0 or \"b\"", json!("b"); "or returns deciding operand")]
+    #[test_case("x = 5\ny = if x > 3 then \"big\" else \"small\"\ny", json!("big"); "if then else")]
+    #[test_case("y = if false then len(null) else 1\ny", json!(1.0); "untaken branch not run")]
+    #[tokio::test]
+    async fn test_short_circuit(input: &str, exp: Value) {
+        assert_eq!(eval_str(input).await, Ok(exp));
+    }
+
+    #[test_case("url = \"top\"\nfor x in [1] do\n  let url = \"loop\"\nend\nurl", json!("top"); "let is block local")]
+    #[test_case("x = 1\nif true do\n  x = 2\nend\nx", json!(2.0); "assign updates outer")]
+    #[test_case("let x = 1\nif true do\n  let x = 2\nend\nx", json!(1.0); "let shadows outer")]
+    #[test_case("const X = 1\ndef f() do\n  return X\nend\nf()", json!(1.0); "const read in fn")]
+    #[tokio::test]
+    async fn test_scoping(input: &str, exp: Value) {
+        assert_eq!(eval_str(input).await, Ok(exp));
+    }
+
+    #[test_case("const X = 1\nX = 2", "cannot assign to constant `X`"; "assign to const")]
+    #[test_case("let x = 1\nlet x = 2", "`x` is already declared in this scope"; "redeclare let")]
+    #[test_case("if true do\n  let y = 1\nend\ny", "unknown identifier `y`"; "let out of scope")]
+    #[tokio::test]
+    async fn test_scoping_errors(input: &str, exp: &str) {
+        assert_eq!(eval_str(input).await, Err(exp.to_string()));
+    }
+
+    #[test_case("def f(a, *rest) do\n  return rest\nend\nf(1, 2, 3)", json!([2.0, 3.0]); "rest")]
+    #[test_case("def f(a, **opts) do\n  return opts\nend\nf(1, b = 2)", json!({"b": 2.0}); "opts")]
+    #[test_case("def f(a, b = 1) do\n  return [a, b]\nend\nf(1, b = 5)", json!([1.0, 5.0]); "kwarg for default")]
+    #[tokio::test]
+    async fn test_variadic(input: &str, exp: Value) {
+        assert_eq!(eval_str(input).await, Ok(exp));
+    }
+
+    #[test_case("def f(a, b = 1) do\nend\nf(1, c = 2)", "unknown keyword argument `c`"; "unknown kwarg")]
+    #[test_case("def f(a, b = 1) do\nend\nf(1, b = 2, b = 3)", "argument `b` was given more than once"; "duplicate kwarg")]
+    #[test_case("def f(a) do\nend\nf(1, a = 2)", "argument `a` was given more than once"; "kwarg for positional")]
+    #[test_case("len(\"a\", x = 1)", "unknown keyword argument `x`"; "unknown builtin kwarg")]
+    #[tokio::test]
+    async fn test_kwarg_errors(input: &str, exp: &str) {
+        assert_eq!(eval_str(input).await, Err(exp.to_string()));
+    }
+
+    // The url is never valid, so only the kwargs are checked before the
+    // request fails.
+    #[tokio::test]
+    async fn test_http_format_kwarg() {
+        std::env::set_var("SCOUT_PATH", concat!(env!("CARGO_MANIFEST_DIR"), "/.."));
+        let res = eval_str("use std::http\nhttp::get(\"\", response = \"json\")").await;
+        assert_eq!(res, Err("unknown keyword argument `response`".to_string()));
+        let err = eval_str("use std::http\nhttp::get(\"\", format = \"json\")")
+            .await
+            .unwrap_err();
+        assert!(!err.starts_with("unknown keyword argument"), "{err}");
+    }
+
+    #[test_case("Product { name: \"a\", price: null }", json!({"name": "a", "price": null}); "constructor")]
+    #[test_case("Product { name: \"a\" }", json!({"name": "a", "price": null}); "optional field left out")]
+    #[tokio::test]
+    async fn test_record(fields: &str, exp: Value) {
+        let input = format!("type Product {{ name: str, price: number? }}\n{fields}");
+        assert_eq!(eval_str(&input).await, Ok(exp));
+    }
+
+    #[test_case("Product { price: 1 }"; "missing field")]
+    #[test_case("Product { name: 1 }"; "wrong type")]
+    #[tokio::test]
+    async fn test_record_invalid(fields: &str) {
+        let input = format!("type Product {{ name: str, price: number? }}\n{fields}");
+        let err = eval_str(&input).await.unwrap_err();
+        assert!(err.starts_with("invalid `Product`"), "{err}");
+    }
+}
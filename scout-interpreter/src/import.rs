@@ -4,7 +4,7 @@ use std::{
 };
 
 use scout_lexer::TokenKind;
-use scout_parser::ast::{ExprKind, Identifier};
+use scout_parser::ast::{Expr, ExprKind, Identifier};
 
 use crate::eval::{EvalError, ImportError};
 
@@ -14,10 +14,10 @@ pub struct ResolvedMod {
     pub filepath: String,
 }
 
-pub fn resolve_module(module: &ExprKind) -> Result<ResolvedMod, EvalError> {
-    let ident = match module {
+pub fn resolve_module(module: &Expr) -> Result<ResolvedMod, EvalError> {
+    let ident = match &module.kind {
         ExprKind::Ident(ident) => Ok(ident.clone()),
-        ExprKind::Infix(_, _, rhs) => match rhs.as_ref() {
+        ExprKind::Infix(_, _, rhs) => match &rhs.kind {
             ExprKind::Ident(ident) => Ok(ident.clone()),
             _ => Err(EvalError::InvalidImport(ImportError::UnknownModule)),
         },
@@ -28,17 +28,21 @@ pub fn resolve_module(module: &ExprKind) -> Result<ResolvedMod, EvalError> {
     Ok(ResolvedMod { filepath, ident })
 }
 
+/// The directory the `std` modules are installed in.
+pub fn std_dir() -> Result<PathBuf, EvalError> {
+    let scout_dir = match env::var("SCOUT_PATH") {
+        Ok(s) => Ok(Path::new(&s).to_path_buf()),
+        Err(_) => match env::var("HOME").or_else(|_| env::var("USERPROFILE")) {
+            Ok(s) => Ok(Path::new(&s).join("scout-lang")),
+            Err(e) => Err(EvalError::OSError(e.to_string())),
+        },
+    }?;
+    Ok(scout_dir.join("scout-lib"))
+}
+
 fn resolve_std_file(ident: &Identifier) -> Result<PathBuf, EvalError> {
     if *ident == Identifier::new("std".into()) {
-        let scout_dir = match env::var("SCOUT_PATH") {
-            Ok(s) => Ok(Path::new(&s).to_path_buf()),
-            Err(_) => match env::var("HOME").or_else(|_| env::var("USERPROFILE")) {
-                Ok(s) => Ok(Path::new(&s).join("scout-lang")),
-                Err(e) => Err(EvalError::OSError(e.to_string())),
-            },
-        }?;
-        let path = scout_dir.join("scout-lib").to_owned();
-        Ok(path)
+        std_dir()
     } else {
         Ok(Path::new(&ident.name).to_owned())
     }
@@ -52,19 +56,19 @@ fn convert_path_buf(buf: PathBuf) -> Result<String, EvalError> {
     Ok(res)
 }
 
-fn resolve_module_file(module: &ExprKind) -> Result<PathBuf, EvalError> {
-    match module {
+fn resolve_module_file(module: &Expr) -> Result<PathBuf, EvalError> {
+    match &module.kind {
         ExprKind::Ident(ident) => resolve_std_file(ident),
         ExprKind::Infix(lhs, t, rhs) if t.kind == TokenKind::DbColon => {
-            match (lhs.as_ref(), rhs.as_ref()) {
+            match (&lhs.kind, &rhs.kind) {
                 (ExprKind::Ident(base), ExprKind::Ident(file)) => {
                     let buf = resolve_std_file(base)?.join(&file.name);
                     Ok(buf)
                 }
-                (l @ ExprKind::Infix(_, t, _), ExprKind::Ident(file))
+                (ExprKind::Infix(_, t, _), ExprKind::Ident(file))
                     if t.kind == TokenKind::DbColon =>
                 {
-                    let base = resolve_module_file(l)?;
+                    let base = resolve_module_file(lhs)?;
                     let buf = base.join(&file.name);
                     Ok(buf)
                 }
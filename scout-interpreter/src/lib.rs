@@ -1,23 +1,46 @@
 use std::{
+    fs,
+    path::Path,
     process::{Child, Command},
     sync::Arc,
 };
 
 use env::EnvPointer;
-use eval::{eval, EvalError, ScrapeResultsPtr};
+use eval::{eval, EvalError, Frame, SchemaPolicy, ScrapeResultsPtr};
 use fantoccini::error::CmdError;
+use limits::{Budget, CancellationToken, Limit, Limits};
 use object::Object;
 use scout_json::ScoutJSON;
 use scout_lexer::Lexer;
-use scout_parser::{ast::NodeKind, ParseError, Parser};
+use scout_parser::{
+    ast::{NodeKind, Program},
+    ParseError, Parser,
+};
 use serde::Deserialize;
 
 pub mod builder;
 pub mod builtin;
+pub mod check;
 pub mod env;
 pub mod eval;
 pub mod import;
+pub mod limits;
 pub mod object;
+pub mod opt;
+#[cfg(test)]
+mod testing;
+pub mod vm;
+
+/// Which engine runs programs.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    /// Walk the AST directly.
+    #[default]
+    Tree,
+    /// Compile to bytecode and run it on the VM in [`vm`].
+    Vm,
+}
 
 #[derive(Deserialize, Debug)]
 pub struct EnvVars {
@@ -29,6 +52,15 @@ pub struct EnvVars {
 
     #[serde(default)]
     scout_proxy: Option<String>,
+
+    #[serde(default)]
+    scout_schema_policy: Option<SchemaPolicy>,
+
+    #[serde(default)]
+    scout_engine: Option<Engine>,
+
+    #[serde(default)]
+    scout_optimize: bool,
 }
 
 impl EnvVars {
@@ -43,15 +75,137 @@ impl EnvVars {
     pub fn proxy(&self) -> &Option<String> {
         &self.scout_proxy
     }
+
+    pub fn schema_policy(&self) -> Option<SchemaPolicy> {
+        self.scout_schema_policy
+    }
+
+    pub fn engine(&self) -> Option<Engine> {
+        self.scout_engine
+    }
+
+    pub fn optimize(&self) -> bool {
+        self.scout_optimize
+    }
 }
 
 #[derive(Debug)]
 pub enum InterpreterError {
     EvalError(EvalError),
-    ParserError(ParseError),
-    InvalidJson,
+    ParserError(Vec<ParseError>),
+    /// Input that failed to deserialize, with the serde error describing
+    /// what was wrong and where.
+    InvalidJson(String),
+    /// Evaluation was stopped by one of the interpreter's [`Limits`].
+    LimitExceeded(Limit),
+}
+
+impl InterpreterError {
+    /// Renders the error as a diagnostic against the source it came from,
+    /// falling back to the plain message when it carries no location.
+    /// Runtime errors raised inside fn calls or module loads are followed
+    /// by a traceback of them.
+    pub fn render(&self, path: &str, source: &str) -> String {
+        match self {
+            Self::EvalError(e) => {
+                let trace = e.trace();
+                // The error was raised in the module of the innermost frame.
+                let mut out = match (e.diagnostic(), trace.first().map(|f| &f.module)) {
+                    (Some(diag), Some(Some(file))) => diag.render(
+                        &display_path(file),
+                        &fs::read_to_string(file).unwrap_or_default(),
+                    ),
+                    (Some(diag), _) => diag.render(path, source),
+                    (None, _) => format!("error: {self}\n"),
+                };
+                out.push_str(&render_trace(trace, path));
+                out
+            }
+            Self::ParserError(errs) => errs
+                .iter()
+                .map(|e| e.diagnostic().render(path, source))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Self::InvalidJson(_) | Self::LimitExceeded(_) => format!("error: {self}\n"),
+        }
+    }
+}
+
+/// How many lines are kept from each end of a long traceback.
+const TRACE_EDGE: usize = 10;
+
+/// Lists the frames of a traceback outermost first, each at the call or
+/// `use` that entered it. Runs of the same call, as in a recursive fn,
+/// are collapsed into one line, and only the ends of a long traceback
+/// are kept.
+fn render_trace(trace: &[Frame], path: &str) -> String {
+    if trace.is_empty() {
+        return String::new();
+    }
+    let mut lines: Vec<(String, usize)> = Vec::new();
+    for (i, frame) in trace.iter().enumerate().rev() {
+        // Each frame is entered from the module of the one outside it.
+        let file = match trace.get(i + 1).map(|f| &f.module) {
+            Some(Some(file)) => display_path(file),
+            _ => path.to_owned(),
+        };
+        let line = match (&frame.name, &frame.module) {
+            (Some(name), _) => format!("{file}:{} calls `{name}`", frame.span),
+            (None, Some(module)) => format!("{file}:{} loads {}", frame.span, display_path(module)),
+            (None, None) => format!("{file}:{}", frame.span),
+        };
+        match lines.last_mut() {
+            Some((last, n)) if *last == line => *n += 1,
+            _ => lines.push((line, 0)),
+        }
+    }
+
+    let mut lines: Vec<String> = lines
+        .into_iter()
+        .flat_map(|(line, repeats)| match repeats {
+            0 => vec![line],
+            n => vec![line, format!("... repeated {n} more times")],
+        })
+        .collect();
+    if lines.len() > 2 * TRACE_EDGE {
+        let elided = lines.len() - 2 * TRACE_EDGE;
+        lines.splice(
+            TRACE_EDGE..lines.len() - TRACE_EDGE,
+            [format!("... {elided} more lines")],
+        );
+    }
+
+    let mut out = String::from("traceback, most recent call last:\n");
+    for line in lines {
+        out.push_str(&format!("  {line}\n"));
+    }
+    out
+}
+
+/// Shows a module's file relative to the working directory it was
+/// resolved from.
+fn display_path(file: &Path) -> String {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    file.strip_prefix(cwd).unwrap_or(file).display().to_string()
+}
+
+impl std::fmt::Display for InterpreterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EvalError(e) => write!(f, "{e}"),
+            Self::ParserError(errs) => match errs.as_slice() {
+                [e] => write!(f, "{e}"),
+                [e, rest @ ..] => write!(f, "{e} (and {} more errors)", rest.len()),
+                [] => write!(f, "parse error"),
+            },
+            Self::InvalidJson(e) => write!(f, "invalid json: {e}"),
+            Self::LimitExceeded(limit) => write!(f, "execution limit exceeded: {limit}"),
+        }
+    }
 }
 
+impl std::error::Error for InterpreterError {}
+
 pub struct GeckDriverProc(Child);
 
 impl GeckDriverProc {
@@ -74,6 +228,10 @@ pub struct Interpreter {
     env: EnvPointer,
     results: ScrapeResultsPtr,
     crawler: fantoccini::Client,
+    engine: Engine,
+    optimize: bool,
+    limits: Limits,
+    cancel: CancellationToken,
     _geckodriver_proc: GeckDriverProc,
 }
 
@@ -88,36 +246,118 @@ impl Interpreter {
             env,
             results,
             crawler,
+            engine: Engine::default(),
+            optimize: false,
+            limits: Limits::default(),
+            cancel: CancellationToken::new(),
             _geckodriver_proc: geckodriver_proc,
         }
     }
 
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+
+    /// Sets whether programs are run through [`opt::optimize`] first.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Sets the limits each evaluation runs under.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Sets the token that stops evaluation when it is cancelled. Once
+    /// cancelled, every later evaluation is stopped straight away too.
+    pub fn set_cancel_token(&mut self, cancel: CancellationToken) {
+        self.cancel = cancel;
+    }
+
+    /// The token that stops evaluation when it is cancelled.
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
     pub async fn eval(&self, content: &str) -> Result<Arc<Object>, InterpreterError> {
         let lexer = Lexer::new(content);
         let mut parser = Parser::new(lexer);
         match parser.parse_program() {
-            Ok(prgm) => Ok(eval(
-                NodeKind::Program(prgm),
-                &self.crawler,
-                self.env.clone(),
-                self.results.clone(),
-            )
-            .await?),
+            Ok(prgm) => self.eval_program(prgm).await,
             Err(e) => Err(InterpreterError::ParserError(e)),
         }
     }
 
+    /// Evaluates an already parsed program, such as one built by hand or
+    /// loaded back from `scout parse --emit ast-json`.
+    pub async fn eval_program(&self, program: Program) -> Result<Arc<Object>, InterpreterError> {
+        let program = match self.optimize {
+            true => opt::optimize(program),
+            false => program,
+        };
+        self.results.lock().await.set_optimize(self.optimize);
+        let budget = Budget::new(self.limits, self.cancel.clone());
+        let run = async {
+            match self.engine {
+                Engine::Tree => {
+                    eval(
+                        NodeKind::Program(program),
+                        &self.crawler,
+                        self.env.clone(),
+                        self.results.clone(),
+                        &budget,
+                    )
+                    .await
+                }
+                Engine::Vm => {
+                    vm::eval_program(
+                        &program,
+                        &self.crawler,
+                        self.env.clone(),
+                        self.results.clone(),
+                        &budget,
+                    )
+                    .await
+                }
+            }
+        };
+        // The budget checks the timeout and cancellation between
+        // statements, and these catch evaluation stuck waiting on the
+        // browser in the meantime.
+        let timeout = async {
+            match self.limits.timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        let res = tokio::select! {
+            res = run => res,
+            _ = self.cancel.cancelled() => Err(EvalError::LimitExceeded(Limit::Cancelled)),
+            _ = timeout => Err(EvalError::LimitExceeded(Limit::Timeout(
+                self.limits.timeout.unwrap_or_default(),
+            ))),
+        };
+        match res {
+            Err(e) => match e.limit() {
+                Some(limit) => Err(InterpreterError::LimitExceeded(limit)),
+                None => Err(e.into()),
+            },
+            Ok(obj) => Ok(obj),
+        }
+    }
+
+    /// Evaluates a program serialized as JSON by `scout parse --emit ast-json`.
+    pub async fn eval_ast_json(&self, content: &str) -> Result<Arc<Object>, InterpreterError> {
+        let program = serde_json::from_str::<Program>(content)
+            .map_err(|e| InterpreterError::InvalidJson(e.to_string()))?;
+        self.eval_program(program).await
+    }
+
     pub async fn eval_json(&self, content: &str) -> Result<Arc<Object>, InterpreterError> {
         let ast = serde_json::from_str::<ScoutJSON>(content)
-            .map_err(|_| InterpreterError::InvalidJson)?
+            .map_err(|e| InterpreterError::InvalidJson(e.to_string()))?
             .to_ast();
-        Ok(eval(
-            NodeKind::Program(ast),
-            &self.crawler,
-            self.env.clone(),
-            self.results.clone(),
-        )
-        .await?)
+        self.eval_program(ast).await
     }
 
     pub fn results(&self) -> ScrapeResultsPtr {
@@ -125,6 +365,7 @@ impl Interpreter {
     }
 
     pub fn reset(&mut self) {
+        env::clear(&self.env);
         self.env = EnvPointer::default();
         self.results = ScrapeResultsPtr::default();
     }
@@ -135,6 +376,7 @@ impl Interpreter {
     }
 
     pub async fn close(self) {
+        env::clear(&self.env);
         let _ = self.crawler.close().await;
     }
 }
@@ -174,3 +416,68 @@ impl Drop for GeckDriverProc {
         kill.wait().expect("error waiting for driver kill");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use scout_lexer::Span;
+    use scout_parser::ast::Identifier;
+
+    use super::*;
+
+    fn call(name: &str, module: Option<&str>, line: usize) -> Frame {
+        Frame {
+            name: Some(Identifier::new(name.into())),
+            module: module.map(PathBuf::from),
+            span: Span::new(0, 0, line, 1),
+        }
+    }
+
+    #[test]
+    fn test_render_trace() {
+        let trace = [
+            call("helper", Some("/lib/util.sct"), 7),
+            call("run", Some("/lib/util.sct"), 4),
+            Frame {
+                name: None,
+                module: Some(PathBuf::from("/lib/util.sct")),
+                span: Span::new(0, 0, 1, 1),
+            },
+        ];
+        let exp = "traceback, most recent call last:
+  main.sct:1:1 loads /lib/util.sct
+  /lib/util.sct:4:1 calls `run`
+  /lib/util.sct:7:1 calls `helper`
+";
+        assert_eq!(render_trace(&trace, "main.sct"), exp);
+    }
+
+    #[test]
+    fn test_render_trace_repeats() {
+        let mut trace = vec![call("f", None, 2); 5];
+        trace.push(call("f", None, 9));
+        let exp = "traceback, most recent call last:
+  main.sct:9:1 calls `f`
+  main.sct:2:1 calls `f`
+  ... repeated 4 more times
+";
+        assert_eq!(render_trace(&trace, "main.sct"), exp);
+    }
+
+    #[test]
+    fn test_render_trace_elided() {
+        let trace: Vec<Frame> = (1..=30).map(|i| call("f", None, i)).collect();
+        let rendered = render_trace(&trace, "main.sct");
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines.len(), 2 + 2 * TRACE_EDGE);
+        assert_eq!(lines[1], "  main.sct:30:1 calls `f`");
+        assert_eq!(lines[TRACE_EDGE + 1], "  ... 10 more lines");
+        assert_eq!(lines[2 * TRACE_EDGE + 1], "  main.sct:1:1 calls `f`");
+    }
+
+    #[test]
+    fn test_render_trace_empty() {
+        assert_eq!(render_trace(&[], "main.sct"), "");
+    }
+}
@@ -0,0 +1,234 @@
+//! Bounds on how much work a single [`Interpreter::eval`] may do, so a
+//! runaway loop or crawl stops with
+//! [`InterpreterError::LimitExceeded`] rather than running forever.
+//!
+//! [`Interpreter::eval`]: crate::Interpreter::eval
+//! [`InterpreterError::LimitExceeded`]: crate::InterpreterError::LimitExceeded
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+pub use tokio_util::sync::CancellationToken;
+
+use crate::eval::EvalError;
+
+/// The limits each evaluation runs under. Every limit is unbounded when
+/// it is `None`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// The most statements evaluated, counting every time a loop or fn
+    /// body runs them.
+    pub max_steps: Option<u64>,
+    /// The longest an evaluation may run for, including time spent
+    /// waiting on the browser.
+    pub timeout: Option<Duration>,
+    /// The most pages visited by `goto` and `crawl`.
+    pub max_pages: Option<usize>,
+    /// The most results recorded by `scrape`.
+    pub max_results: Option<usize>,
+}
+
+/// The limit an evaluation was stopped by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Steps(u64),
+    Timeout(Duration),
+    Pages(usize),
+    Results(usize),
+    /// The interpreter's [`CancellationToken`] was cancelled.
+    Cancelled,
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Steps(n) => write!(f, "evaluated more than {n} statements"),
+            Self::Timeout(d) => write!(f, "ran for longer than {}s", d.as_secs_f64()),
+            Self::Pages(n) => write!(f, "visited more than {n} pages"),
+            Self::Results(n) => write!(f, "scraped more than {n} results"),
+            Self::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// Counts the work an evaluation has done against its [`Limits`]. The
+/// counters are atomic, so the budget is shared by reference and counting
+/// a statement never waits on a lock.
+#[derive(Debug, Default)]
+pub struct Budget {
+    limits: Limits,
+    deadline: Option<Instant>,
+    cancel: CancellationToken,
+    steps: AtomicU64,
+    pages: AtomicUsize,
+    results: AtomicUsize,
+    /// The limit that was exceeded. It stays exceeded, so that evaluation
+    /// cannot carry on past a `use` of a directory that ignored the error.
+    exceeded: OnceLock<Limit>,
+}
+
+impl Budget {
+    pub fn new(limits: Limits, cancel: CancellationToken) -> Self {
+        Self {
+            limits,
+            deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+            cancel,
+            ..Default::default()
+        }
+    }
+
+    /// Counts a statement about to be evaluated. This is also where the
+    /// timeout and cancellation are checked, since a loop that never
+    /// awaits the browser gives nothing else the chance to stop it.
+    pub fn step(&self) -> Result<(), EvalError> {
+        let steps = self.steps.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(max) = self.limits.max_steps.filter(|max| steps > *max) {
+            self.exceeded.get_or_init(|| Limit::Steps(max));
+        }
+        if self.cancel.is_cancelled() {
+            self.exceeded.get_or_init(|| Limit::Cancelled);
+        }
+        if let Some(timeout) = self.limits.timeout {
+            if self
+                .deadline
+                .is_some_and(|deadline| Instant::now() > deadline)
+            {
+                self.exceeded.get_or_init(|| Limit::Timeout(timeout));
+            }
+        }
+        self.check()
+    }
+
+    /// Counts a page about to be visited.
+    pub fn visit(&self) -> Result<(), EvalError> {
+        let pages = self.pages.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(max) = self.limits.max_pages.filter(|max| pages > *max) {
+            self.exceeded.get_or_init(|| Limit::Pages(max));
+        }
+        self.check()
+    }
+
+    /// Counts a result about to be recorded.
+    pub fn record(&self) -> Result<(), EvalError> {
+        let results = self.results.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(max) = self.limits.max_results.filter(|max| results > *max) {
+            self.exceeded.get_or_init(|| Limit::Results(max));
+        }
+        self.check()
+    }
+
+    fn check(&self) -> Result<(), EvalError> {
+        match self.exceeded.get() {
+            Some(limit) => Err(EvalError::LimitExceeded(*limit)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unbounded() {
+        let budget = Budget::default();
+        for _ in 0..100 {
+            assert!(budget.step().is_ok());
+            assert!(budget.visit().is_ok());
+            assert!(budget.record().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_exceeded_stays_exceeded() {
+        let budget = Budget::new(
+            Limits {
+                max_pages: Some(2),
+                ..Default::default()
+            },
+            CancellationToken::new(),
+        );
+        assert!(budget.visit().is_ok());
+        assert!(budget.step().is_ok());
+        assert!(budget.visit().is_ok());
+        assert!(matches!(
+            budget.visit(),
+            Err(EvalError::LimitExceeded(Limit::Pages(2)))
+        ));
+        assert!(matches!(
+            budget.step(),
+            Err(EvalError::LimitExceeded(Limit::Pages(2)))
+        ));
+    }
+
+    #[test]
+    fn test_first_limit_is_kept() {
+        let budget = Budget::new(
+            Limits {
+                max_steps: Some(1),
+                max_results: Some(0),
+                ..Default::default()
+            },
+            CancellationToken::new(),
+        );
+        assert!(budget.step().is_ok());
+        assert!(matches!(
+            budget.record(),
+            Err(EvalError::LimitExceeded(Limit::Results(0)))
+        ));
+        assert!(matches!(
+            budget.step(),
+            Err(EvalError::LimitExceeded(Limit::Results(0)))
+        ));
+    }
+
+    #[test]
+    fn test_shared_steps() {
+        let budget = Budget::new(
+            Limits {
+                max_steps: Some(100),
+                ..Default::default()
+            },
+            CancellationToken::new(),
+        );
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| (0..25).for_each(|_| budget.step().unwrap()));
+            }
+        });
+        assert!(matches!(
+            budget.step(),
+            Err(EvalError::LimitExceeded(Limit::Steps(100)))
+        ));
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let cancel = CancellationToken::new();
+        let budget = Budget::new(Limits::default(), cancel.clone());
+        assert!(budget.step().is_ok());
+        cancel.cancel();
+        assert!(matches!(
+            budget.step(),
+            Err(EvalError::LimitExceeded(Limit::Cancelled))
+        ));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let timeout = Duration::from_millis(1);
+        let budget = Budget::new(
+            Limits {
+                timeout: Some(timeout),
+                ..Default::default()
+            },
+            CancellationToken::new(),
+        );
+        std::thread::sleep(timeout * 2);
+        assert!(matches!(
+            budget.step(),
+            Err(EvalError::LimitExceeded(Limit::Timeout(t))) if t == timeout
+        ));
+    }
+}
@@ -1,7 +1,8 @@
-use std::{collections::HashMap, fmt::Display, sync::Arc};
+use std::{fmt::Display, sync::Arc};
 
 use futures::{future::BoxFuture, lock::Mutex, FutureExt};
-use scout_parser::ast::{Block, FnParam, Identifier};
+use indexmap::IndexMap;
+use scout_parser::ast::{Block, ExprKind, FnParam, Identifier, TypeDecl};
 use serde_json::{json, Value};
 
 use crate::env::EnvPointer;
@@ -13,18 +14,42 @@ pub trait ParseObj<T> {
 #[derive(Debug)]
 pub enum Object {
     Null,
-    Map(Mutex<HashMap<Identifier, Arc<Object>>>),
+    Map(Mutex<IndexMap<Identifier, Arc<Object>>>),
     Str(String),
     Node(fantoccini::elements::Element),
     List(Mutex<Vec<Arc<Object>>>),
     Boolean(bool),
     Number(f64),
-    Fn(Vec<FnParam>, Block),
+    /// A function along with the environment it was defined in.
+    Fn(Vec<FnParam>, Block, EnvPointer),
+    /// A function compiled for the bytecode VM.
+    Closure(Arc<crate::vm::Closure>),
     Return(Arc<Object>),
+    Break,
+    Continue,
     Module(EnvPointer),
+    /// A record type declared with `type Name { ... }`.
+    Type(TypeDecl),
 }
 
 impl Object {
+    /// The value of a string, number, boolean or null literal.
+    pub fn from_literal(expr: &ExprKind) -> Option<Self> {
+        match expr {
+            ExprKind::Str(s) => Some(Object::Str(s.clone())),
+            ExprKind::Number(n) => Some(Object::Number(*n)),
+            ExprKind::Boolean(b) => Some(Object::Boolean(*b)),
+            ExprKind::Null => Some(Object::Null),
+            _ => None,
+        }
+    }
+
+    /// Returns true for the values that unwind blocks: `return`, `break`
+    /// and `continue`.
+    pub fn is_control_flow(&self) -> bool {
+        matches!(self, Object::Return(_) | Object::Break | Object::Continue)
+    }
+
     pub fn type_str(&self) -> &str {
         use Object::*;
         match self {
@@ -35,20 +60,19 @@ impl Object {
             List(_) => "list",
             Boolean(_) => "bool",
             Number(_) => "number",
-            Fn(_, _) => "fn",
+            Fn(..) | Closure(_) => "fn",
             Module(_) => "module",
+            Type(_) => "type",
             _ => "object",
         }
     }
 
-    pub async fn into_iterable(&self) -> Option<impl IntoIterator<Item = Arc<Object>>> {
+    /// The items a `for` loop visits, or None if the value is not
+    /// iterable.
+    pub fn into_iterable(&self) -> Option<Vec<Arc<Object>>> {
         use Object::*;
         match self {
-            List(v) => {
-                let inner = v.lock().await;
-
-                Some(inner.clone())
-            }
+            List(v) => Some(v.try_lock()?.clone()),
             Str(s) => {
                 let new_vec: Vec<Arc<Object>> = s
                     .chars()
@@ -57,8 +81,20 @@ impl Object {
 
                 Some(new_vec)
             }
+            // Maps iterate over `[key, value]` pairs.
+            Map(m) => {
+                let inner = m.try_lock()?;
+                let iterable = inner
+                    .iter()
+                    .map(|(k, v)| {
+                        let item = vec![Arc::new(Str(k.name.clone())), v.clone()];
+                        Arc::new(Object::List(Mutex::new(item)))
+                    })
+                    .collect();
+                Some(iterable)
+            }
             Module(ev) => {
-                let inner = ev.lock().await;
+                let inner = ev.try_lock()?;
                 let mut iterable = Vec::new();
                 for (id, obj) in &inner.store {
                     if obj.type_str() == "module" {
@@ -72,92 +108,51 @@ impl Object {
         }
     }
 
-    pub fn to_display(&self) -> BoxFuture<String> {
-        use Object::*;
-        async move {
-            match self {
-                Null => "Null".into(),
-                Map(hash) => {
-                    let inner = hash.lock().await;
-                    let mut out = "{ ".to_string();
-                    for (idx, (i, o)) in inner.iter().enumerate() {
-                        out.push_str(&format!("{}: {}", i, o.to_display().await));
-                        if idx != inner.len() - 1 {
-                            out.push_str(", ");
-                        }
-                    }
-                    out.push_str(" }");
-                    out
-                }
-                Str(s) => format!("{}", s),
-                Node(_) => "Node".into(),
-                List(mutex) => {
-                    let inner = mutex.lock().await;
+    // Lists and maps are only locked while they are read or written and
+    // evaluation never waits while holding one, so the only lock that can
+    // fail below is on a list or map that contains itself.
 
-                    let mut out: String = "[".into();
-                    for (i, obj) in inner.iter().enumerate() {
-                        out.push_str(obj.to_display().await.as_str());
-                        if i != inner.len() - 1 {
-                            out.push_str(", ");
-                        }
+    pub fn to_display(&self) -> String {
+        use Object::*;
+        match self {
+            Null => "Null".into(),
+            Map(hash) => {
+                let Some(inner) = hash.try_lock() else {
+                    return "{ ... }".into();
+                };
+                let mut out = "{ ".to_string();
+                for (idx, (i, o)) in inner.iter().enumerate() {
+                    out.push_str(&format!("{}: {}", i, o.to_display()));
+                    if idx != inner.len() - 1 {
+                        out.push_str(", ");
                     }
-                    out.push(']');
-
-                    out
                 }
-                Boolean(b) => format!("{}", b),
-                Number(n) => format!("{}", n),
-                _ => "object".into(),
+                out.push_str(" }");
+                out
             }
-        }
-        .boxed()
-    }
+            Str(s) => s.to_string(),
+            Node(_) => "Node".into(),
+            List(mutex) => {
+                let Some(inner) = mutex.try_lock() else {
+                    return "[...]".into();
+                };
 
-    pub fn eq<'a>(&'a self, other: &'a Self) -> BoxFuture<'a, bool> {
-        async move {
-            use Object::*;
-            match (self, other) {
-                (Null, Null) => true,
-                (Map(a), Map(b)) => {
-                    let a_i = a.lock().await;
-                    let b_i = b.lock().await;
-                    for key in a_i.keys() {
-                        match b_i.get(key) {
-                            Some(obj) => {
-                                if !a_i.get(key).unwrap().eq(obj).await {
-                                    return false;
-                                }
-                            }
-                            None => return false,
-                        }
+                let mut out: String = "[".into();
+                for (i, obj) in inner.iter().enumerate() {
+                    out.push_str(obj.to_display().as_str());
+                    if i != inner.len() - 1 {
+                        out.push_str(", ");
                     }
-                    true
                 }
-                (Str(a), Str(b)) => a == b,
-                // @TODO: check if this is even correct
-                (Node(a), Node(b)) => a.element_id() == b.element_id(),
-                (List(a), List(b)) => {
-                    let a_i = a.lock().await;
-                    let b_i = b.lock().await;
-
-                    if a_i.len() != b_i.len() {
-                        return false;
-                    }
+                out.push(']');
 
-                    for idx in 0..(a_i.len() - 1) {
-                        if !a_i[idx].eq(&b_i[idx]).await {
-                            return false;
-                        }
-                    }
-
-                    true
-                }
-                (Boolean(a), Boolean(b)) => a == b,
-                (Number(a), Number(b)) => a == b,
-                _ => false,
+                out
             }
+            Boolean(b) => format!("{}", b),
+            Number(n) => format!("{}", n),
+            Type(decl) => format!("{decl}"),
+            _ => "object".into(),
         }
-        .boxed()
     }
 
     pub async fn to_json(&self) -> Value {
@@ -174,18 +169,23 @@ impl Object {
             }
             Boolean(b) => Value::Bool(*b),
             Number(n) => json!(n),
-            Fn(_, _) => panic!("cant serialize func"),
-            _ => panic!("cant serialize object"),
+            // Values with no JSON form are kept as a placeholder, so that
+            // storing a callback in a scraped map does not lose the rest.
+            Fn(..) | Closure(_) => Value::String("Fn".to_owned()),
+            Module(_) => Value::String("Module".to_owned()),
+            Type(decl) => Value::String(decl.to_string()),
+            // Only ever unwind blocks, and are never stored as values.
+            Return(_) | Break | Continue => Value::Null,
         }
     }
 
-    pub async fn is_truthy(&self) -> bool {
+    pub fn is_truthy(&self) -> bool {
         use Object::*;
         match self {
             Null => false,
             Str(s) => !s.is_empty(),
-            Map(m) => !m.lock().await.is_empty(),
-            List(v) => !v.lock().await.is_empty(),
+            Map(m) => m.try_lock().is_none_or(|m| !m.is_empty()),
+            List(v) => v.try_lock().is_none_or(|v| !v.is_empty()),
             Boolean(b) => *b,
             // @TODO: Idk what truthiness of floats should be
             Number(n) => *n > 0.0,
@@ -194,6 +194,47 @@ impl Object {
     }
 }
 
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        use Object::*;
+        match (self, other) {
+            (Null, Null) => true,
+            (Map(a), Map(b)) => {
+                let (Some(a_i), Some(b_i)) = (a.try_lock(), b.try_lock()) else {
+                    return std::ptr::eq(a, b);
+                };
+                if a_i.len() != b_i.len() {
+                    return false;
+                }
+                for key in a_i.keys() {
+                    match b_i.get(key) {
+                        Some(obj) => {
+                            if !a_i.get(key).unwrap().eq(obj) {
+                                return false;
+                            }
+                        }
+                        None => return false,
+                    }
+                }
+                true
+            }
+            (Str(a), Str(b)) => a == b,
+            // @TODO: check if this is even correct
+            (Node(a), Node(b)) => a.element_id() == b.element_id(),
+            (List(a), List(b)) => {
+                let (Some(a_i), Some(b_i)) = (a.try_lock(), b.try_lock()) else {
+                    return std::ptr::eq(a, b);
+                };
+
+                a_i.len() == b_i.len() && a_i.iter().zip(b_i.iter()).all(|(a, b)| a.eq(b))
+            }
+            (Boolean(a), Boolean(b)) => a == b,
+            (Number(a), Number(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
 impl Display for Object {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use Object::*;
@@ -222,8 +263,8 @@ fn vec_to_json(v: &Mutex<Vec<Arc<Object>>>) -> BoxFuture<'_, Value> {
 }
 
 pub fn obj_map_to_json(
-    map: &HashMap<Identifier, Arc<Object>>,
-) -> BoxFuture<serde_json::Map<String, Value>> {
+    map: &IndexMap<Identifier, Arc<Object>>,
+) -> BoxFuture<'_, serde_json::Map<String, Value>> {
     async move {
         let mut out = serde_json::Map::new();
         for (ident, obj) in map.iter() {
@@ -243,7 +284,7 @@ pub fn json_to_obj(json: &Value) -> Arc<Object> {
             vals.iter().map(json_to_obj).collect(),
         ))),
         Value::Object(in_map) => {
-            let mut map = HashMap::new();
+            let mut map = IndexMap::new();
             for (k, v) in in_map.iter() {
                 let ident = Identifier::new(k.clone());
                 let val = json_to_obj(v);
@@ -275,3 +316,52 @@ impl ParseObj<String> for Object {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use scout_parser::ast::Block;
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test]
+    fn test_to_json_placeholders() {
+        let env = EnvPointer::default();
+        let map = IndexMap::from([
+            (
+                Identifier::new("cb".into()),
+                Arc::new(Object::Fn(vec![], Block::default(), env.clone())),
+            ),
+            (Identifier::new("mod".into()), Arc::new(Object::Module(env))),
+            (Identifier::new("n".into()), Arc::new(Object::Number(1.))),
+        ]);
+        let obj = Object::Map(Mutex::new(map));
+        let exp = json!({ "cb": "Fn", "mod": "Module", "n": 1.0 });
+        assert_eq!(block_on(obj.to_json()), exp);
+    }
+
+    fn list(items: &[f64]) -> Object {
+        let items = items.iter().map(|n| Arc::new(Object::Number(*n)));
+        Object::List(Mutex::new(items.collect()))
+    }
+
+    #[test_case(&[], &[], true; "empty")]
+    #[test_case(&[1., 2.], &[1., 2.], true; "same")]
+    #[test_case(&[1., 2.], &[1., 3.], false; "last differs")]
+    #[test_case(&[1.], &[1., 2.], false; "different len")]
+    fn test_list_eq(a: &[f64], b: &[f64], exp: bool) {
+        assert_eq!(list(a) == list(b), exp);
+    }
+
+    #[test]
+    fn test_map_eq_checks_len() {
+        let entry = (Identifier::new("a".into()), Arc::new(Object::Null));
+        let a = Object::Map(Mutex::new(IndexMap::from([entry.clone()])));
+        let b = Object::Map(Mutex::new(IndexMap::from([
+            entry,
+            (Identifier::new("b".into()), Arc::new(Object::Null)),
+        ])));
+        assert!(a != b);
+    }
+}
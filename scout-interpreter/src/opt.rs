@@ -0,0 +1,1001 @@
+//! Rewrites of a parsed [`Program`] that make it cheaper to run, applied
+//! before evaluation when the interpreter is built with optimizations on.
+//!
+//! Each pass only rewrites code it can show behaves the same, including
+//! which errors are raised and the spans they are reported at, so a
+//! program prints and scrapes the same with or without them. The
+//! exceptions are the traceback of an uncaught error, which leaves out
+//! calls to fns that [`Inline`] replaced with their body, and the number
+//! of statements counted against a step limit. Modules imported with
+//! `use` are optimized as they are loaded.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs, iter,
+    path::Path,
+    sync::Arc,
+};
+
+use scout_lexer::{Lexer, Span, Token, TokenKind};
+use scout_parser::{
+    ast::{
+        fold::{walk_call, walk_expr, walk_stmt},
+        visit, Block, CallLiteral, CatchLiteral, CrawlBindings, ElseLiteral, Expr, ExprKind,
+        FnParam, Fold, ForLoop, FuncDef, Identifier, IfElseLiteral, IfLiteral, LambdaLiteral,
+        ParamKind, Pattern, Program, Stmt, StmtKind, Visitor,
+    },
+    Parser,
+};
+
+use crate::{
+    builtin::BuiltinKind,
+    eval::{assign_targets, eval_infix_op, eval_prefix},
+    import::resolve_module,
+    object::Object,
+};
+
+/// Runs every pass over a program.
+pub fn optimize(program: Program) -> Program {
+    let program = ConstFold.fold_program(program);
+    let program = Prune.fold_program(program);
+    let program = Inline::new(&program).fold_program(program);
+    Hoist::new(&program).fold_program(program)
+}
+
+/// Evaluates operators whose operands are literals, joins the literal
+/// pieces of interpolated strings, and simplifies `and`, `or`, `??` and
+/// `if ... then ... else` with a literal on the side that decides them.
+/// Operations that would raise an error are left to raise it at runtime.
+pub struct ConstFold;
+
+impl Fold for ConstFold {
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        let Expr { kind, span } = walk_expr(self, expr);
+        match kind {
+            ExprKind::Infix(lhs, op, rhs) => fold_infix(*lhs, op, *rhs, span),
+            ExprKind::Interp(parts) => fold_interp(parts, span),
+            ExprKind::Prefix(rhs, op) => {
+                let folded = literal(&rhs).and_then(|obj| eval_prefix(obj, &op.kind).ok());
+                match folded.and_then(|obj| to_literal(&obj)) {
+                    Some(kind) => Expr::new(kind, span),
+                    None => Expr::new(ExprKind::Prefix(rhs, op), span),
+                }
+            }
+            ExprKind::Conditional(cond, then, otherwise) => match literal(&cond) {
+                Some(cond) if cond.is_truthy() => *then,
+                Some(_) => *otherwise,
+                None => Expr::new(ExprKind::Conditional(cond, then, otherwise), span),
+            },
+            kind => Expr::new(kind, span),
+        }
+    }
+}
+
+fn infix(lhs: Expr, op: Token, rhs: Expr, span: Span) -> Expr {
+    Expr::new(ExprKind::Infix(Box::new(lhs), op, Box::new(rhs)), span)
+}
+
+fn fold_infix(lhs: Expr, op: Token, rhs: Expr, span: Span) -> Expr {
+    let Some(l_obj) = literal(&lhs) else {
+        return match (lhs.kind, &rhs.kind) {
+            // `x + "a" + "b"` is `x + "ab"` when `x` is a str, and raises
+            // the same error from the same place when it is not.
+            (ExprKind::Infix(x, inner, a), ExprKind::Str(b))
+                if op.kind == TokenKind::Plus
+                    && inner.kind == TokenKind::Plus
+                    && matches!(a.kind, ExprKind::Str(_)) =>
+            {
+                let ExprKind::Str(a_str) = &a.kind else {
+                    unreachable!("matched a str");
+                };
+                let ab = Expr::new(ExprKind::Str(format!("{a_str}{b}")), a.span);
+                infix(*x, inner, ab, lhs.span)
+            }
+            // An interpolated string is always a str.
+            (ExprKind::Interp(mut parts), ExprKind::Str(_)) if op.kind == TokenKind::Plus => {
+                parts.push(rhs);
+                fold_interp(parts, span)
+            }
+            (kind, _) => infix(Expr::new(kind, lhs.span), op, rhs, span),
+        };
+    };
+
+    match op.kind {
+        TokenKind::And if l_obj.is_truthy() => rhs,
+        TokenKind::Or if !l_obj.is_truthy() => rhs,
+        TokenKind::And | TokenKind::Or => lhs,
+        TokenKind::DbQuestion if matches!(*l_obj, Object::Null) => rhs,
+        TokenKind::DbQuestion => lhs,
+        TokenKind::DbColon => infix(lhs, op, rhs, span),
+        _ => {
+            let folded = literal(&rhs).and_then(|r_obj| eval_infix_op(l_obj, &op.kind, r_obj).ok());
+            match folded.and_then(|obj| to_literal(&obj)) {
+                Some(kind) => Expr::new(kind, span),
+                None => infix(lhs, op, rhs, span),
+            }
+        }
+    }
+}
+
+/// Joins the pieces of an interpolated string that are literals, into a
+/// single str if they all are.
+fn fold_interp(parts: Vec<Expr>, span: Span) -> Expr {
+    let mut out: Vec<Expr> = Vec::new();
+    for part in parts {
+        let text = literal(&part)
+            .filter(|obj| to_literal(obj).is_some())
+            .map(|obj| obj.to_display());
+        match (text, out.last_mut()) {
+            (
+                Some(text),
+                Some(Expr {
+                    kind: ExprKind::Str(prev),
+                    span: prev_span,
+                }),
+            ) => {
+                prev.push_str(&text);
+                *prev_span = prev_span.to(part.span);
+            }
+            (Some(text), _) => out.push(Expr::new(ExprKind::Str(text), part.span)),
+            (None, _) => out.push(part),
+        }
+    }
+    match out.as_slice() {
+        [] => Expr::new(ExprKind::Str(String::new()), span),
+        [Expr {
+            kind: ExprKind::Str(_),
+            ..
+        }] => {
+            let Expr { kind, .. } = out.remove(0);
+            Expr::new(kind, span)
+        }
+        _ => Expr::new(ExprKind::Interp(out), span),
+    }
+}
+
+/// Removes code that can never run: branches with literal conditions,
+/// `while` loops with a falsy literal condition and statements after a
+/// `return`, `break`, `continue` or `throw`.
+pub struct Prune;
+
+impl Fold for Prune {
+    fn fold_program(&mut self, program: Program) -> Program {
+        Program {
+            stmts: self.prune(program.stmts),
+        }
+    }
+
+    fn fold_block(&mut self, block: Block) -> Block {
+        Block::new(self.prune(block.stmts))
+    }
+}
+
+impl Prune {
+    fn prune(&mut self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        let mut out = Vec::new();
+        for stmt in stmts {
+            let Stmt { kind, span } = walk_stmt(self, stmt);
+            match kind {
+                StmtKind::IfElse(lit) => out.extend(prune_if(lit, span)),
+                StmtKind::WhileLoop(cond, _) if literal(&cond).is_some_and(|c| !c.is_truthy()) => {
+                    out.push(null_stmt(span))
+                }
+                kind => out.push(Stmt::new(kind, span)),
+            }
+        }
+
+        let exit = out.iter().position(|s| {
+            matches!(
+                s.kind,
+                StmtKind::Return(_) | StmtKind::Break | StmtKind::Continue | StmtKind::Throw(_)
+            )
+        });
+        if let Some(exit) = exit {
+            out.truncate(exit + 1);
+        }
+
+        // Only the value of the last statement is used, as the value of
+        // the block.
+        let last = out.len().saturating_sub(1);
+        out.into_iter()
+            .enumerate()
+            .filter(|(i, stmt)| {
+                *i == last || !matches!(&stmt.kind, StmtKind::Expr(e) if literal(e).is_some())
+            })
+            .map(|(_, stmt)| stmt)
+            .collect()
+    }
+}
+
+/// Drops the arms of an `if` whose conditions are falsy literals, and the
+/// arms after one that is a truthy literal.
+fn prune_if(lit: IfElseLiteral, span: Span) -> Vec<Stmt> {
+    let mut arms = Vec::new();
+    let mut taken = None;
+    for arm in iter::once(lit.if_lit).chain(lit.elifs) {
+        match literal(&arm.cond) {
+            Some(cond) if cond.is_truthy() => {
+                taken = Some(arm.block);
+                break;
+            }
+            Some(_) => {}
+            None => arms.push(arm),
+        }
+    }
+    let taken = taken.or(lit.else_lit.map(|e| e.block));
+
+    let mut arms = arms.into_iter();
+    let Some(if_lit) = arms.next() else {
+        return match taken {
+            // An `if` is null when no branch is taken.
+            None => vec![null_stmt(span)],
+            Some(block) if binds_nothing(&block) => {
+                block.stmts.into_iter().chain([null_stmt(span)]).collect()
+            }
+            Some(block) => {
+                let lit = IfElseLiteral {
+                    if_lit: IfLiteral {
+                        cond: Expr::new(ExprKind::Boolean(true), span),
+                        block,
+                    },
+                    elifs: Vec::new(),
+                    else_lit: None,
+                };
+                vec![Stmt::new(StmtKind::IfElse(lit), span)]
+            }
+        };
+    };
+    let lit = IfElseLiteral {
+        if_lit,
+        elifs: arms.collect(),
+        else_lit: taken.map(|block| ElseLiteral { block }),
+    };
+    vec![Stmt::new(StmtKind::IfElse(lit), span)]
+}
+
+/// Whether a block binds no names in its own scope, so its statements can
+/// run in the enclosing one instead.
+fn binds_nothing(block: &Block) -> bool {
+    block.stmts.iter().all(|stmt| {
+        !matches!(
+            stmt.kind,
+            StmtKind::Assign(..)
+                | StmtKind::Let(..)
+                | StmtKind::Func(..)
+                | StmtKind::TypeDecl(_)
+                | StmtKind::Use(_)
+                | StmtKind::Crawl(_)
+        )
+    })
+}
+
+/// Replaces calls to fns like `def press(code) do keyAction(code) end`,
+/// which only pass their params on to a builtin, with the builtin call.
+///
+/// A fn is only inlined when it is defined at the top level and its name
+/// is not bound anywhere else, and only at the calls after its `def` that
+/// pass one arg for each param. The same goes for calls like
+/// `keys::press(k)` to the fns of a module file imported with `use` at the
+/// top level, as long as each arg is a literal or a name.
+pub struct Inline {
+    bound: HashMap<Identifier, usize>,
+    trivial: HashMap<Identifier, Trivial>,
+    modules: HashMap<Identifier, Module>,
+}
+
+/// The fns of an imported module that can be inlined, and the names bound
+/// in it, which the args of a call into the module are looked up in first.
+struct Module {
+    trivial: HashMap<Identifier, Trivial>,
+    bound: HashMap<Identifier, usize>,
+}
+
+/// The body of a fn that can be inlined.
+struct Trivial {
+    params: Vec<Identifier>,
+    call: CallLiteral,
+    span: Span,
+}
+
+impl Inline {
+    pub fn new(program: &Program) -> Self {
+        Self {
+            bound: Bindings::of(|b| b.visit_program(program)),
+            trivial: HashMap::new(),
+            modules: HashMap::new(),
+        }
+    }
+
+    /// Reads the module a top level `use` imports, if it is a file that
+    /// parses and nothing else binds its name.
+    fn module(&self, path: &Expr) -> Option<(Identifier, Module)> {
+        let resolved = resolve_module(path).ok()?;
+        if self.bound.get(&resolved.ident) != Some(&1) {
+            return None;
+        }
+        let file = Path::new(&resolved.filepath).with_extension("sct");
+        let content = fs::read_to_string(file).ok()?;
+        let program = Parser::new(Lexer::new(&content)).parse_program().ok()?;
+        let inner = Inline::new(&program);
+        let trivial = program
+            .stmts
+            .iter()
+            .filter_map(|stmt| match &stmt.kind {
+                StmtKind::Func(def, _) => Some((def.ident.clone(), inner.trivial(def)?)),
+                _ => None,
+            })
+            .collect();
+        let module = Module {
+            trivial,
+            bound: inner.bound,
+        };
+        Some((resolved.ident, module))
+    }
+
+    fn trivial(&self, def: &FuncDef) -> Option<Trivial> {
+        if self.bound.get(&def.ident) != Some(&1) {
+            return None;
+        }
+        if def
+            .params
+            .iter()
+            .any(|p| p.kind != ParamKind::Positional || p.default.is_some())
+        {
+            return None;
+        }
+        let [stmt] = def.body.stmts.as_slice() else {
+            return None;
+        };
+        let (StmtKind::Expr(expr) | StmtKind::Return(Some(expr))) = &stmt.kind else {
+            return None;
+        };
+        let ExprKind::Call(call) = &expr.kind else {
+            return None;
+        };
+        if !is_builtin(&call.ident, &self.bound) {
+            return None;
+        }
+
+        // The params must be passed on in order and once each, so that
+        // the args are evaluated just as they are for a call to the fn.
+        // Literals have no effects, so can be passed anywhere.
+        let params: Vec<Identifier> = def.params.iter().map(|p| p.ident.clone()).collect();
+        let mut passed = Vec::new();
+        for arg in call.args.iter().chain(call.kwargs.iter().map(|k| &k.expr)) {
+            match &arg.kind {
+                ExprKind::Ident(ident) if params.contains(ident) => passed.push(ident.clone()),
+                kind if Object::from_literal(kind).is_some() => {}
+                _ => return None,
+            }
+        }
+        if passed != params {
+            return None;
+        }
+
+        Some(Trivial {
+            params,
+            call: call.clone(),
+            span: expr.span,
+        })
+    }
+
+    fn inline(&mut self, call: CallLiteral, span: Span) -> Expr {
+        let call = walk_call(self, call);
+        let trivial = match self.trivial.get(&call.ident) {
+            Some(t) if t.params.len() == call.args.len() && call.kwargs.is_empty() => t,
+            _ => return Expr::new(ExprKind::Call(call), span),
+        };
+
+        let args: HashMap<&Identifier, Expr> = trivial.params.iter().zip(call.args).collect();
+        let subst = |expr: &mut Expr| {
+            if let ExprKind::Ident(ident) = &expr.kind {
+                *expr = args[ident].clone();
+            }
+        };
+        let mut body = trivial.call.clone();
+        body.args.iter_mut().for_each(&subst);
+        body.kwargs.iter_mut().for_each(|k| subst(&mut k.expr));
+        // Errors from the builtin are still reported in the body of the
+        // fn, as they are without inlining.
+        Expr::new(ExprKind::Call(body), trivial.span)
+    }
+
+    /// Inlines `module::f(args)`, with the args that name something in the
+    /// module read from it as they are without inlining.
+    fn inline_module(&self, module: &Identifier, call: &CallLiteral, span: Span) -> Option<Expr> {
+        let Module { trivial, bound } = self.modules.get(module)?;
+        let trivial = trivial.get(&call.ident)?;
+        if trivial.params.len() != call.args.len()
+            || !call.kwargs.is_empty()
+            || !is_builtin(&trivial.call.ident, &self.bound)
+        {
+            return None;
+        }
+        let mut args = HashMap::new();
+        for (param, arg) in trivial.params.iter().zip(&call.args) {
+            let arg = match &arg.kind {
+                ExprKind::Ident(ident) if bound.contains_key(ident) => {
+                    let op = Token::new(TokenKind::DbColon, "::".into());
+                    let lhs = Expr::new(ExprKind::Ident(module.clone()), arg.span);
+                    infix(lhs, op, arg.clone(), arg.span)
+                }
+                ExprKind::Ident(_) => arg.clone(),
+                kind if Object::from_literal(kind).is_some() => arg.clone(),
+                _ => return None,
+            };
+            args.insert(param, arg);
+        }
+        let mut body = trivial.call.clone();
+        let subst = |expr: &mut Expr| {
+            if let ExprKind::Ident(ident) = &expr.kind {
+                *expr = args[ident].clone();
+            }
+        };
+        body.args.iter_mut().for_each(subst);
+        body.kwargs.iter_mut().for_each(|k| subst(&mut k.expr));
+        // The body is in another file, so errors are reported at the call.
+        Some(Expr::new(ExprKind::Call(body), span))
+    }
+
+    fn fold_chain(&mut self, exprs: Vec<Expr>) -> Vec<Expr> {
+        exprs
+            .into_iter()
+            .enumerate()
+            .map(|(i, expr)| match expr.kind {
+                // Calls after the first are passed the piped value too.
+                ExprKind::Call(call) if i > 0 => {
+                    Expr::new(ExprKind::Call(walk_call(self, call)), expr.span)
+                }
+                kind => self.fold_expr(Expr::new(kind, expr.span)),
+            })
+            .collect()
+    }
+}
+
+impl Fold for Inline {
+    fn fold_program(&mut self, program: Program) -> Program {
+        let mut stmts = Vec::new();
+        for stmt in program.stmts {
+            let stmt = self.fold_stmt(stmt);
+            match &stmt.kind {
+                StmtKind::Func(def, _) => {
+                    if let Some(trivial) = self.trivial(def) {
+                        self.trivial.insert(def.ident.clone(), trivial);
+                    }
+                }
+                StmtKind::Use(path) => {
+                    if let Some((ident, module)) = self.module(path) {
+                        self.modules.insert(ident, module);
+                    }
+                }
+                _ => {}
+            }
+            stmts.push(stmt);
+        }
+        Program { stmts }
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        let span = expr.span;
+        match expr.kind {
+            ExprKind::Call(call) => self.inline(call, span),
+            // The right of `::` is evaluated in the module, where names
+            // may mean something else.
+            ExprKind::Infix(lhs, op, rhs) if op.kind == TokenKind::DbColon => {
+                if let (ExprKind::Ident(module), ExprKind::Call(call)) = (&lhs.kind, &rhs.kind) {
+                    if let Some(inlined) = self.inline_module(module, call, span) {
+                        return inlined;
+                    }
+                }
+                let lhs = Box::new(self.fold_expr(*lhs));
+                Expr::new(ExprKind::Infix(lhs, op, rhs), span)
+            }
+            // As is a method call on a module.
+            ExprKind::MethodCall(obj, call) => {
+                let obj = Box::new(self.fold_expr(*obj));
+                Expr::new(ExprKind::MethodCall(obj, call), span)
+            }
+            ExprKind::Chain(exprs) => Expr::new(ExprKind::Chain(self.fold_chain(exprs)), span),
+            ExprKind::SafeChain(exprs) => {
+                Expr::new(ExprKind::SafeChain(self.fold_chain(exprs)), span)
+            }
+            kind => walk_expr(self, Expr::new(kind, span)),
+        }
+    }
+}
+
+/// Computes calls to pure builtins whose args do not change in a loop
+/// once before the loop, instead of on every iteration.
+///
+/// Only loops that call no fns other than builtins, do not `push` and do
+/// not assign to an index or field are considered, as nothing else can
+/// change the value of an arg. The value is computed in a `try` before
+/// the loop, and the loop only uses it if that succeeded, so that an
+/// error is still raised where and when it would have been.
+pub struct Hoist {
+    bound: HashMap<Identifier, usize>,
+    next: usize,
+}
+
+/// Builtins that only compute a str, number or boolean from their args.
+const PURE_BUILTINS: [BuiltinKind; 5] = [
+    BuiltinKind::Len,
+    BuiltinKind::Contains,
+    BuiltinKind::Type,
+    BuiltinKind::Number,
+    BuiltinKind::IsWhitespace,
+];
+
+impl Hoist {
+    pub fn new(program: &Program) -> Self {
+        Self {
+            bound: Bindings::of(|b| b.visit_program(program)),
+            next: 0,
+        }
+    }
+
+    fn hoist(&mut self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        let mut out = Vec::new();
+        for stmt in stmts {
+            let stmt = match stmt.kind {
+                StmtKind::WhileLoop(..) | StmtKind::ForLoop(_) => {
+                    let (hoisted, stmt) = self.hoist_loop(stmt);
+                    out.extend(hoisted);
+                    stmt
+                }
+                _ => stmt,
+            };
+            // Loops nested in this one see its hoisted values as
+            // already guarded.
+            out.push(walk_stmt(self, stmt));
+        }
+        out
+    }
+
+    fn hoist_loop(&mut self, stmt: Stmt) -> (Vec<Stmt>, Stmt) {
+        let mut scan = LoopScan {
+            bound: &self.bound,
+            impure: false,
+        };
+        scan.visit_stmt(&stmt);
+        if scan.impure {
+            return (Vec::new(), stmt);
+        }
+
+        let mut guard = Guard {
+            bound: &self.bound,
+            assigned: Bindings::of(|b| b.visit_stmt(&stmt)).into_keys().collect(),
+            next: self.next,
+            hoisted: Vec::new(),
+        };
+        let Stmt { kind, span } = stmt;
+        let kind = match kind {
+            StmtKind::WhileLoop(cond, block) => {
+                StmtKind::WhileLoop(guard.fold_expr(cond), guard.fold_block(block))
+            }
+            StmtKind::ForLoop(floop) => StmtKind::ForLoop(ForLoop {
+                block: guard.fold_block(floop.block),
+                ..floop
+            }),
+            _ => unreachable!("only loops are hoisted from"),
+        };
+        self.next = guard.next;
+
+        let hoisted = guard
+            .hoisted
+            .into_iter()
+            .flat_map(|(n, expr)| hoisted_stmts(n, expr, span))
+            .collect();
+        (hoisted, Stmt::new(kind, span))
+    }
+}
+
+impl Fold for Hoist {
+    fn fold_program(&mut self, program: Program) -> Program {
+        Program {
+            stmts: self.hoist(program.stmts),
+        }
+    }
+
+    fn fold_block(&mut self, block: Block) -> Block {
+        Block::new(self.hoist(block.stmts))
+    }
+}
+
+/// The names of the value of a hoisted call and of whether computing it
+/// succeeded. They can't be written in source, so can't collide with a
+/// name in the program.
+fn hoisted_idents(n: usize) -> (Identifier, Identifier) {
+    (
+        Identifier::new(format!("%hoisted{n}")),
+        Identifier::new(format!("%hoisted{n}_ok")),
+    )
+}
+
+/// `val = null`, `ok = false` and `try val = <expr>; ok = true catch end`.
+fn hoisted_stmts(n: usize, expr: Expr, span: Span) -> Vec<Stmt> {
+    let (val, ok) = hoisted_idents(n);
+    let assign = |ident: &Identifier, kind: ExprKind| {
+        let lhs = Expr::new(ExprKind::Ident(ident.clone()), span);
+        Stmt::new(
+            StmtKind::Assign(lhs, Expr::new(kind, span), false, None),
+            span,
+        )
+    };
+    let compute = Block::new(vec![
+        assign(&val, expr.kind),
+        assign(&ok, ExprKind::Boolean(true)),
+    ]);
+    let catch = CatchLiteral::new(None, Block::default());
+    vec![
+        assign(&val, ExprKind::Null),
+        assign(&ok, ExprKind::Boolean(false)),
+        Stmt::new(StmtKind::TryCatch(compute, Some(catch), None), span),
+    ]
+}
+
+/// Replaces the hoistable calls in a loop with their guarded value.
+struct Guard<'a> {
+    bound: &'a HashMap<Identifier, usize>,
+    assigned: HashSet<Identifier>,
+    next: usize,
+    hoisted: Vec<(usize, Expr)>,
+}
+
+impl Guard<'_> {
+    /// Whether an expr always evaluates to the same value in the loop.
+    fn invariant(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            kind if Object::from_literal(kind).is_some() => true,
+            ExprKind::Ident(ident) => !self.assigned.contains(ident),
+            ExprKind::Infix(lhs, op, rhs) => {
+                op.kind != TokenKind::DbColon && self.invariant(lhs) && self.invariant(rhs)
+            }
+            ExprKind::Prefix(rhs, _) => self.invariant(rhs),
+            ExprKind::Field(obj, _) => self.invariant(obj),
+            ExprKind::Call(call) => {
+                is_builtin(&call.ident, self.bound)
+                    && BuiltinKind::is_from(&call.ident.name)
+                        .is_some_and(|b| PURE_BUILTINS.contains(&b))
+                    && call.kwargs.is_empty()
+                    && call.args.iter().all(|a| self.invariant(a))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Fold for Guard<'_> {
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        match &expr.kind {
+            ExprKind::Call(_) | ExprKind::Infix(..) | ExprKind::Prefix(..)
+                if has_call(&expr) && self.invariant(&expr) =>
+            {
+                let n = match self.hoisted.iter().find(|(_, e)| *e == expr) {
+                    Some((n, _)) => *n,
+                    None => {
+                        let n = self.next;
+                        self.next += 1;
+                        self.hoisted.push((n, expr.clone()));
+                        n
+                    }
+                };
+                let (val, ok) = hoisted_idents(n);
+                let ident = |ident| Box::new(Expr::new(ExprKind::Ident(ident), expr.span));
+                let span = expr.span;
+                Expr::new(
+                    ExprKind::Conditional(ident(ok), ident(val), Box::new(expr)),
+                    span,
+                )
+            }
+            // Already guarded by an outer loop.
+            ExprKind::Conditional(cond, ..) if is_hoisted(cond) => expr,
+            _ => walk_expr(self, expr),
+        }
+    }
+
+    fn fold_fn_def(&mut self, def: FuncDef) -> FuncDef {
+        def
+    }
+
+    fn fold_lambda(&mut self, lambda: LambdaLiteral) -> LambdaLiteral {
+        lambda
+    }
+}
+
+fn is_hoisted(expr: &Expr) -> bool {
+    matches!(&expr.kind, ExprKind::Ident(ident) if ident.name.starts_with('%'))
+}
+
+fn has_call(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Call(_) => true,
+        ExprKind::Infix(lhs, _, rhs) => has_call(lhs) || has_call(rhs),
+        ExprKind::Prefix(rhs, _) | ExprKind::Field(rhs, _) => has_call(rhs),
+        _ => false,
+    }
+}
+
+/// Finds whether a loop could change the value of a name it doesn't
+/// bind.
+struct LoopScan<'a> {
+    bound: &'a HashMap<Identifier, usize>,
+    impure: bool,
+}
+
+impl<'ast> Visitor<'ast> for LoopScan<'_> {
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        match &stmt.kind {
+            StmtKind::Assign(lhs, ..) if assign_targets(lhs).is_err() => self.impure = true,
+            StmtKind::Use(_) => self.impure = true,
+            _ => {}
+        }
+        visit::walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        match &expr.kind {
+            ExprKind::MethodCall(..) => self.impure = true,
+            ExprKind::Infix(_, op, _) if op.kind == TokenKind::DbColon => self.impure = true,
+            _ => {}
+        }
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_call(&mut self, call: &'ast CallLiteral) {
+        let builtin = match is_builtin(&call.ident, self.bound) {
+            true => BuiltinKind::is_from(&call.ident.name),
+            false => None,
+        };
+        if matches!(builtin, None | Some(BuiltinKind::Push)) {
+            self.impure = true;
+        }
+        visit::walk_call(self, call);
+    }
+
+    // Fns defined in the loop can only run if called, which makes it
+    // impure already.
+    fn visit_fn_def(&mut self, _def: &'ast FuncDef) {}
+
+    fn visit_lambda(&mut self, _lambda: &'ast LambdaLiteral) {}
+}
+
+/// Counts how many times each name is bound anywhere in a program, by
+/// assignment, `let`, `def`, `use`, a param or a binding in a loop,
+/// `catch` or pattern.
+#[derive(Default)]
+struct Bindings(HashMap<Identifier, usize>);
+
+impl Bindings {
+    fn of(visit: impl FnOnce(&mut Self)) -> HashMap<Identifier, usize> {
+        let mut bindings = Self::default();
+        visit(&mut bindings);
+        bindings.0
+    }
+
+    fn bind(&mut self, ident: &Identifier) {
+        *self.0.entry(ident.clone()).or_default() += 1;
+    }
+}
+
+impl<'ast> Visitor<'ast> for Bindings {
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        match &stmt.kind {
+            StmtKind::Assign(lhs, ..) | StmtKind::Let(lhs, ..) => {
+                for ident in assign_targets(lhs).unwrap_or_default() {
+                    self.bind(&ident);
+                }
+            }
+            StmtKind::Func(def, _) => self.bind(&def.ident),
+            StmtKind::TypeDecl(decl) => self.bind(&decl.ident),
+            StmtKind::Use(path) => {
+                let mut names = UseNames(Vec::new());
+                names.visit_expr(path);
+                names.0.iter().for_each(|ident| self.bind(ident));
+            }
+            _ => {}
+        }
+        visit::walk_stmt(self, stmt);
+    }
+
+    fn visit_fn_param(&mut self, param: &'ast FnParam) {
+        self.bind(&param.ident);
+        visit::walk_fn_param(self, param);
+    }
+
+    fn visit_for_loop(&mut self, floop: &'ast ForLoop) {
+        floop.idents.iter().for_each(|ident| self.bind(ident));
+        visit::walk_for_loop(self, floop);
+    }
+
+    fn visit_crawl_bindings(&mut self, bindings: &'ast CrawlBindings) {
+        self.bind(&bindings.link);
+        self.bind(&bindings.depth);
+    }
+
+    fn visit_catch(&mut self, lit: &'ast CatchLiteral) {
+        if let Some(binding) = &lit.binding {
+            self.bind(binding);
+        }
+        visit::walk_catch(self, lit);
+    }
+
+    fn visit_pattern(&mut self, pattern: &'ast Pattern) {
+        if let Pattern::Binding(ident) = pattern {
+            self.bind(ident);
+        }
+        visit::walk_pattern(self, pattern);
+    }
+}
+
+/// Every name in the path of a `use`.
+struct UseNames(Vec<Identifier>);
+
+impl<'ast> Visitor<'ast> for UseNames {
+    fn visit_ident(&mut self, ident: &'ast Identifier) {
+        self.0.push(ident.clone());
+    }
+}
+
+/// Whether a call is to a builtin, which it is when the name is not
+/// bound anywhere in the program.
+fn is_builtin(ident: &Identifier, bound: &HashMap<Identifier, usize>) -> bool {
+    BuiltinKind::is_from(&ident.name).is_some() && !bound.contains_key(ident)
+}
+
+fn literal(expr: &Expr) -> Option<Arc<Object>> {
+    Object::from_literal(&expr.kind).map(Arc::new)
+}
+
+/// The literal for a value, if it has one that reads back the same.
+fn to_literal(obj: &Object) -> Option<ExprKind> {
+    match obj {
+        Object::Str(s) => Some(ExprKind::Str(s.clone())),
+        Object::Number(n) if n.is_finite() => Some(ExprKind::Number(*n)),
+        Object::Boolean(b) => Some(ExprKind::Boolean(*b)),
+        Object::Null => Some(ExprKind::Null),
+        _ => None,
+    }
+}
+
+fn null_stmt(span: Span) -> Stmt {
+    Stmt::new(StmtKind::Expr(Expr::new(ExprKind::Null, span)), span)
+}
+
+#[cfg(test)]
+mod tests {
+    use scout_lexer::Lexer;
+    use scout_parser::Parser;
+    use test_case::test_case;
+
+    use super::*;
+    use crate::testing::{run, run_browser};
+    use crate::Engine;
+
+    fn parse(input: &str) -> Program {
+        Parser::new(Lexer::new(input)).parse_program().unwrap()
+    }
+
+    #[test_case("x = 1 + 2 * 3", "x = 7"; "arithmetic")]
+    #[test_case(r#"x = "a" + "b" + 1"#, r#"x = "ab" + 1"#; "concat")]
+    #[test_case(r#"x = "a ${1 + 1} b""#, r#"x = "a 2 b""#; "interpolation")]
+    #[test_case(r#"x = "a ${y} b" + "c""#, r#"x = "a ${y} bc""#; "concat after interpolation")]
+    #[test_case(r#"x = y + "a" + "b""#, r#"x = y + "ab""#; "concat after ident")]
+    #[test_case("x = -1 < 0 and y", "x = y"; "and")]
+    #[test_case("x = null ?? y", "x = y"; "null coalesce")]
+    #[test_case("x = if 0 then y else z", "x = z"; "conditional")]
+    #[test_case(r#"x = "a" - 1"#, r#"x = "a" - 1"#; "error left to runtime")]
+    #[test_case("x = 1 / 0", "x = 1 / 0"; "not finite")]
+    fn test_const_fold(input: &str, exp: &str) {
+        let folded = ConstFold.fold_program(parse(input));
+        assert_eq!(folded.stmts, parse(exp).stmts);
+    }
+
+    #[test_case("if 1 > 2 do\nf()\nend\ng()", "g()"; "falsy if")]
+    #[test_case("if true do\nf()\nelse\ng()\nend", "f()\nnull"; "truthy if")]
+    #[test_case("if true do\nx = 1\nend", "if true do\nx = 1\nend"; "truthy if with binding")]
+    #[test_case("if a do\nf()\nelif false do\ng()\nelif true do\nh()\nelse\ni()\nend", "if a do\nf()\nelse\nh()\nend"; "elifs")]
+    #[test_case("while false do\nf()\nend\ng()", "g()"; "falsy while")]
+    #[test_case("def f() do\nreturn 1\ng()\nend", "def f() do\nreturn 1\nend"; "after return")]
+    #[test_case("for x in xs do\nif x do\nbreak\nend\ncontinue\nf()\nend", "for x in xs do\nif x do\nbreak\nend\ncontinue\nend"; "after continue")]
+    fn test_prune(input: &str, exp: &str) {
+        let program = ConstFold.fold_program(parse(input));
+        let pruned = Prune.fold_program(program);
+        assert_eq!(pruned.stmts, parse(exp).stmts);
+    }
+
+    #[test_case("def press(code) do\nkeyAction(code)\nend\npress(k)", "def press(code) do\nkeyAction(code)\nend\nkeyAction(k)"; "trivial")]
+    #[test_case("def p(a, b) do\nreturn input(a, b, submit = true)\nend\np(x, y)", "def p(a, b) do\nreturn input(a, b, submit = true)\nend\ninput(x, y, submit = true)"; "literal kwarg")]
+    #[test_case("press(k)\ndef press(code) do\nkeyAction(code)\nend", "press(k)\ndef press(code) do\nkeyAction(code)\nend"; "before def")]
+    #[test_case("def p(a, b) do\ninput(b, a)\nend\np(x, y)", "def p(a, b) do\ninput(b, a)\nend\np(x, y)"; "reordered params")]
+    #[test_case("def p(a) do\nkeyAction(a)\nend\np = 1\np(x)", "def p(a) do\nkeyAction(a)\nend\np = 1\np(x)"; "rebound")]
+    #[test_case("def p(a) do\nf(a)\nend\np(x)", "def p(a) do\nf(a)\nend\np(x)"; "not a builtin")]
+    #[test_case("def p(a) do\nkeyAction(a)\nend\nx |> p()", "def p(a) do\nkeyAction(a)\nend\nx |> p()"; "piped")]
+    #[test_case("def p(a) do\nkeyAction(a)\nend\nm::p(x)", "def p(a) do\nkeyAction(a)\nend\nm::p(x)"; "module call")]
+    fn test_inline(input: &str, exp: &str) {
+        let program = parse(input);
+        let inlined = Inline::new(&program).fold_program(program);
+        assert_eq!(inlined.stmts, parse(exp).stmts);
+    }
+
+    #[test_case("use std::keys\nkeys::press(k)", "use std::keys\nkeyAction(k)"; "std wrapper")]
+    #[test_case("use std::keys\nkeys::press(ENTER)", "use std::keys\nkeyAction(keys::ENTER)"; "module const")]
+    #[test_case("use std::keys\nkeys::press(f())", "use std::keys\nkeys::press(f())"; "call arg")]
+    #[test_case("use std::keys\nkeys = 1\nkeys::press(k)", "use std::keys\nkeys = 1\nkeys::press(k)"; "rebound module")]
+    fn test_inline_module(input: &str, exp: &str) {
+        std::env::set_var("SCOUT_PATH", concat!(env!("CARGO_MANIFEST_DIR"), "/.."));
+        let program = parse(input);
+        let inlined = Inline::new(&program).fold_program(program);
+        assert_eq!(inlined.stmts, parse(exp).stmts);
+    }
+
+    #[test]
+    fn test_inline_keeps_body_span() {
+        let program = parse("def press(code) do\nkeyAction(code)\nend\npress(k)");
+        let inlined = Inline::new(&program).fold_program(program);
+        let StmtKind::Expr(call) = &inlined.stmts[1].kind else {
+            panic!("expected a call");
+        };
+        assert_eq!(call.span.line, 2);
+    }
+
+    #[test]
+    fn test_hoist() {
+        let program = parse("i = 0\nwhile i < len(s) - 1 do\ni = i + len(s)\nend");
+        let hoisted = Hoist::new(&program).fold_program(program);
+        let guarded = |n: usize, expr: &str| {
+            [
+                format!("%hoisted{n} = null"),
+                format!("%hoisted{n}_ok = false"),
+                format!("try\n%hoisted{n} = {expr}\n%hoisted{n}_ok = true\n\ncatch\n\nend\n"),
+            ]
+        };
+        let mut exp = vec!["i = 0".to_string()];
+        exp.extend(guarded(0, "len(s) - 1"));
+        exp.extend(guarded(1, "len(s)"));
+        exp.push(
+            "while i < if %hoisted0_ok then %hoisted0 else len(s) - 1 do\n\
+             i = i + if %hoisted1_ok then %hoisted1 else len(s)\nend\n"
+                .to_string(),
+        );
+        let got: Vec<String> = hoisted.stmts.iter().map(|s| s.to_string()).collect();
+        assert_eq!(got, exp);
+    }
+
+    #[test_case("while i < len(s) do\ns = s + \"a\"\nend"; "assigned in loop")]
+    #[test_case("while i < len(s) do\nf()\nend"; "calls a fn")]
+    #[test_case("while i < len(s) do\npush(s, 1)\nend"; "pushes")]
+    #[test_case("while i < len(s) do\ns[0] = 1\nend"; "assigns an index")]
+    #[test_case("len = 1\nwhile i < len(s) do\ni = i + 1\nend"; "shadowed builtin")]
+    #[test_case("for c in s do\nx = textContent(c)\nend"; "impure builtin")]
+    fn test_not_hoisted(input: &str) {
+        let program = parse(input);
+        let hoisted = Hoist::new(&program).fold_program(program);
+        assert_eq!(hoisted.stmts, parse(input).stmts);
+    }
+
+    #[test_case("s = \"ab\"\ni = 0\nwhile i < 5 do\ni = i + len(s)\nend\ni"; "hoisted")]
+    #[test_case("s = 1\ni = 0\nwhile i < 3 do\ni = i + 1\nif i > 5 do\ni = len(s)\nend\nend\ni"; "hoisted error never reached")]
+    #[test_case("s = 1\ni = 0\nwhile i < 3 do\ni = i + 1\nif i == 2 do\ni = i + len(s)\nend\nend\ni"; "hoisted error raised in loop")]
+    #[test_case("s = \"ab\"\nout = []\nfor c in [1, 2] do\nfor d in [3] do\nx = c + len(s) + d\nend\nend\nx"; "nested loops")]
+    #[test_case("out = []\nfor x in [1, 2, 3] do\nif 1 < 2 do\npush(out, x)\nif x == 2 do\nbreak\nend\nend\nend\nout"; "spliced if in loop")]
+    #[test_case("x = 1\nif true do\nfor x in [5] do\nend\nend\nx"; "spliced loop binding")]
+    #[test_case("x = 1\nif true do\nlet x = 2\nend\nx"; "taken block with let")]
+    #[test_case("if true do\n1\nend"; "value of spliced if")]
+    #[test_case("def f() do\nif false do\nreturn 1\nelif true do\nreturn 2\nend\nreturn 3\nend\nf()"; "pruned return")]
+    #[tokio::test]
+    async fn test_optimize_keeps_output(input: &str) {
+        for engine in [Engine::Tree, Engine::Vm] {
+            let optimized = run(input, engine, true).await;
+            assert_eq!(optimized, run(input, engine, false).await, "{engine:?}");
+        }
+    }
+
+    #[test_case("goto \"https://example.com\"\nfor i in [1, 2] do\nif true do\nscrape { n: len(\"abc\") + i }\nend\nend"; "scrape in spliced if")]
+    #[ignore = "needs geckodriver"]
+    #[tokio::test]
+    async fn test_optimize_keeps_results(input: &str) {
+        let optimized = run_browser(input, Engine::Tree, true).await;
+        assert_eq!(optimized, run_browser(input, Engine::Tree, false).await);
+    }
+}
@@ -0,0 +1,115 @@
+//! Helpers for running programs in tests without a browser.
+
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+use scout_lexer::Lexer;
+use scout_parser::{ast::NodeKind, Parser};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{
+    builder::InterpreterBuilder,
+    env::EnvPointer,
+    eval::{eval, ScrapeResults},
+    limits::{Budget, CancellationToken, Limits},
+    opt, vm, Engine,
+};
+
+/// Connects to a WebDriver stub that accepts a session and answers every
+/// other command with `null`, for programs that never touch the page.
+pub(crate) async fn stub_crawler() -> fantoccini::Client {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve(stream));
+        }
+    });
+    fantoccini::ClientBuilder::native()
+        .connect(&format!("http://{addr}"))
+        .await
+        .unwrap()
+}
+
+async fn serve(stream: TcpStream) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    loop {
+        let mut request = String::new();
+        if stream.read_line(&mut request).await? == 0 {
+            return Ok(());
+        }
+        let mut len = 0;
+        loop {
+            let mut header = String::new();
+            stream.read_line(&mut header).await?;
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, val)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    len = val.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        stream.read_exact(&mut vec![0; len]).await?;
+
+        let body = match request.starts_with("POST /session ") {
+            true => r#"{"value":{"sessionId":"stub","capabilities":{}}}"#,
+            false => r#"{"value":null}"#,
+        };
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        stream.get_mut().write_all(response.as_bytes()).await?;
+    }
+}
+
+/// Runs `input` on `engine` in a fresh module scope, returning the value
+/// of its last statement as JSON or the error it stopped with.
+pub(crate) async fn run(input: &str, engine: Engine, optimize: bool) -> Result<Value, String> {
+    let program = Parser::new(Lexer::new(input))
+        .parse_program()
+        .expect("parses");
+    let program = match optimize {
+        true => opt::optimize(program),
+        false => program,
+    };
+    let crawler = stub_crawler().await;
+    let env = EnvPointer::default();
+    let results = Arc::new(Mutex::new(ScrapeResults::default()));
+    let budget = Budget::new(Limits::default(), CancellationToken::new());
+    let res = match engine {
+        Engine::Tree => eval(NodeKind::Program(program), &crawler, env, results, &budget).await,
+        Engine::Vm => vm::eval_program(&program, &crawler, env, results, &budget).await,
+    };
+    match res {
+        Ok(obj) => Ok(obj.to_json().await),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Runs `input` on `engine` in a real browser, returning what [`run`]
+/// does along with the results scraped as JSON. Needs geckodriver.
+pub(crate) async fn run_browser(
+    input: &str,
+    engine: Engine,
+    optimize: bool,
+) -> (Result<Value, String>, String) {
+    let interpreter = InterpreterBuilder::default()
+        .with_engine(engine)
+        .with_optimize(optimize)
+        .build()
+        .await
+        .expect("geckodriver is installed");
+    let res = match interpreter.eval(input).await {
+        Ok(obj) => Ok(obj.to_json().await),
+        Err(e) => Err(e.to_string()),
+    };
+    let results = interpreter.results().lock().await.to_json();
+    interpreter.close().await;
+    (res, results)
+}
@@ -0,0 +1,1677 @@
+//! A bytecode VM, run instead of the tree-walker in [`crate::eval`] when
+//! the interpreter is built with [`Engine::Vm`](crate::Engine::Vm).
+//!
+//! Programs are compiled to [`Proto`]s with the names bound in fn and
+//! block scopes resolved to slots in a frame. Only module scopes live in
+//! an [`Env`], so the VM only locks an env to read a module level name
+//! and only awaits to call a builtin, import a module or drive the
+//! browser.
+//!
+//! A module entered with `::` or a method call resolves the names it does
+//! not define from the frame that entered it, like the module's caller in
+//! the tree-walker.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, MutexGuard};
+
+use fantoccini::{elements::Element, wd::WindowHandle, Locator};
+use futures::{future::BoxFuture, lock::Mutex, FutureExt};
+use indexmap::IndexMap;
+use scout_lexer::{Lexer, Span};
+use scout_parser::{
+    ast::{Identifier, ParamKind, Program},
+    Parser,
+};
+
+use self::code::{Capture, Loc, Op, ParamDefault, Proto, Raise};
+use crate::{
+    builtin::Kwargs,
+    env::{Env, EnvPointer},
+    eval::{
+        apply_debug_border, apply_debug_border_all, check_record, eval_infix_op, eval_prefix,
+        lookup_type, resolve_link, wait_for_goto_ready, EvalError, EvalResult, Frame as TraceFrame,
+        GrowStack, ImportError, SchemaPolicy, ScrapeResultsPtr, MAX_CALL_DEPTH, MAX_DEPTH,
+    },
+    import::resolve_module,
+    limits::Budget,
+    object::{obj_map_to_json, Object},
+    opt,
+};
+
+mod code;
+mod compile;
+
+/// Compiles and runs a program, with `env` as its module scope.
+pub async fn eval_program(
+    program: &Program,
+    crawler: &fantoccini::Client,
+    env: EnvPointer,
+    results: ScrapeResultsPtr,
+    budget: &Budget,
+) -> EvalResult {
+    let mut vm = Vm {
+        crawler,
+        results,
+        budget,
+        stack: Vec::new(),
+        frames: Vec::new(),
+        depth: 0,
+    };
+    vm.run_module(program, env).await
+}
+
+type Cell = Arc<std::sync::Mutex<Binding>>;
+
+/// A compiled fn along with the bindings it closes over.
+pub struct Closure {
+    proto: Arc<Proto>,
+    upvalues: Vec<Cell>,
+    /// The scope of the module the fn was defined in.
+    globals: EnvPointer,
+    /// The modules entered with `::` around the fn's definition.
+    overrides: Vec<Option<EnvPointer>>,
+    /// Evaluates each param default that is not a literal.
+    defaults: Vec<Option<Arc<Closure>>>,
+}
+
+impl std::fmt::Debug for Closure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Closure")
+            .field("name", &self.proto.name)
+            .finish()
+    }
+}
+
+impl Closure {
+    /// Takes the values the closure captured and the scopes it holds, for
+    /// [`crate::env::clear`].
+    pub(crate) fn clear(&self, envs: &mut Vec<EnvPointer>, objs: &mut Vec<Arc<Object>>) {
+        objs.extend(
+            self.upvalues
+                .iter()
+                .filter_map(|cell| lock(cell).val.take()),
+        );
+        envs.push(self.globals.clone());
+        envs.extend(self.overrides.iter().flatten().cloned());
+        for default in self.defaults.iter().flatten() {
+            default.clear(envs, objs);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Binding {
+    val: Option<Arc<Object>>,
+    constant: bool,
+}
+
+impl Binding {
+    fn bind(&mut self, val: Arc<Object>) {
+        self.val = Some(val);
+        self.constant = false;
+    }
+
+    fn declare(
+        &mut self,
+        ident: &Identifier,
+        val: Arc<Object>,
+        constant: bool,
+    ) -> Result<(), EvalError> {
+        if self.val.is_some() {
+            return Err(EvalError::DuplicateDeclare(ident.clone()));
+        }
+        self.val = Some(val);
+        self.constant = constant;
+        Ok(())
+    }
+
+    /// Assigns to the binding if it is bound, returning whether it was.
+    fn assign(&mut self, ident: &Identifier, val: &Arc<Object>) -> Result<bool, EvalError> {
+        match self.val {
+            None => Ok(false),
+            Some(_) if self.constant => Err(EvalError::ConstAssign(ident.clone())),
+            Some(_) => {
+                self.val = Some(val.clone());
+                Ok(true)
+            }
+        }
+    }
+}
+
+fn lock(cell: &Cell) -> MutexGuard<'_, Binding> {
+    cell.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A slot is only shared when a closure captures it.
+enum Slot {
+    Local(Binding),
+    Shared(Cell),
+}
+
+impl Slot {
+    fn new(shared: bool) -> Self {
+        match shared {
+            true => Self::Shared(Cell::default()),
+            false => Self::Local(Binding::default()),
+        }
+    }
+
+    fn get(&self) -> Option<Arc<Object>> {
+        match self {
+            Self::Local(b) => b.val.clone(),
+            Self::Shared(cell) => lock(cell).val.clone(),
+        }
+    }
+
+    fn with<T>(&mut self, f: impl FnOnce(&mut Binding) -> T) -> T {
+        match self {
+            Self::Local(b) => f(b),
+            Self::Shared(cell) => f(&mut lock(cell)),
+        }
+    }
+
+    fn cell(&self) -> Cell {
+        match self {
+            Self::Shared(cell) => cell.clone(),
+            Self::Local(_) => unreachable!("captured slots are shared"),
+        }
+    }
+}
+
+/// What a `break`, `continue`, `return` or error is unwinding the stack
+/// with.
+enum Unwind {
+    Break,
+    Continue,
+    Return(Arc<Object>),
+    Error(EvalError),
+}
+
+impl From<EvalError> for Unwind {
+    fn from(e: EvalError) -> Self {
+        Self::Error(e)
+    }
+}
+
+/// A construct that handles some kinds of unwinding, along with the
+/// stack height and entered modules to restore when it does.
+struct Region {
+    kind: RegionKind,
+    sp: usize,
+    ov: usize,
+}
+
+enum RegionKind {
+    Loop {
+        brk: u32,
+        cont: u32,
+    },
+    Iter {
+        brk: u32,
+        cont: u32,
+        items: std::vec::IntoIter<Arc<Object>>,
+    },
+    Catch(u32),
+    Finally(u32),
+    /// A running `finally` block, whose pending unwind is dropped if
+    /// another one replaces it.
+    InFinally,
+    Match(u32),
+    Crawl(Box<CrawlState>),
+}
+
+struct CrawlState {
+    site: usize,
+    span: Span,
+    visited: HashSet<String>,
+    /// The pages being crawled, from the one the crawl started on.
+    levels: Vec<Level>,
+    /// The link the body is running for.
+    link: String,
+}
+
+struct Level {
+    start: WindowHandle,
+    elems: Vec<Element>,
+    next: usize,
+    depth: usize,
+}
+
+struct Frame {
+    closure: Arc<Closure>,
+    ip: usize,
+    /// The height of the stack when the frame was entered.
+    base: usize,
+    slots: Vec<Slot>,
+    regions: Vec<Region>,
+    overrides: Vec<Option<EnvPointer>>,
+    /// How many of the overrides were entered around the fn's definition
+    /// rather than by the frame.
+    inherited: usize,
+    /// What was unwinding when each running `finally` block began.
+    pending: Vec<Option<Unwind>>,
+}
+
+impl Frame {
+    fn new(closure: Arc<Closure>, slots: Vec<Slot>, base: usize) -> Self {
+        Self {
+            overrides: closure.overrides.clone(),
+            inherited: closure.overrides.len(),
+            closure,
+            ip: 0,
+            base,
+            slots,
+            regions: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    fn read(&self, name: u32) -> Option<Arc<Object>> {
+        let name = &self.closure.proto.names[name as usize];
+        name.locs.iter().find_map(|loc| match *loc {
+            Loc::Local(slot) => self.slots[slot as usize].get(),
+            Loc::Upvalue(up) => lock(&self.closure.upvalues[up as usize]).val.clone(),
+            Loc::Override(k) => {
+                let env = self.overrides.get(k as usize)?.as_ref()?;
+                env.try_lock()?.store.get(&name.ident.name).cloned()
+            }
+            Loc::Global => self.closure.globals.try_lock()?.get(&name.ident),
+        })
+    }
+
+    /// Assigns to the first place the name is bound, returning whether
+    /// there was one.
+    fn assign(&mut self, name: u32, val: &Arc<Object>) -> Result<bool, EvalError> {
+        let name = &self.closure.proto.names[name as usize];
+        let ident = &name.ident;
+        for loc in &name.locs {
+            match *loc {
+                Loc::Local(slot) => {
+                    if self.slots[slot as usize].with(|b| b.assign(ident, val))? {
+                        return Ok(true);
+                    }
+                }
+                Loc::Upvalue(up) => {
+                    if lock(&self.closure.upvalues[up as usize]).assign(ident, val)? {
+                        return Ok(true);
+                    }
+                }
+                Loc::Override(k) => {
+                    if let Some(Some(env)) = self.overrides.get(k as usize) {
+                        let mut env = env.try_lock().ok_or(EvalError::LockError)?;
+                        if env.store.contains_key(&ident.name) {
+                            env.set(ident, val.clone())?;
+                            return Ok(true);
+                        }
+                    }
+                }
+                Loc::Global => {
+                    let mut env = self.globals()?;
+                    if env.get(ident).is_some() {
+                        env.set(ident, val.clone())?;
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Defines an unbound name where it defaults to.
+    fn define(&mut self, name: u32, val: Arc<Object>) -> Result<(), EvalError> {
+        let name = &self.closure.proto.names[name as usize];
+        match name.default {
+            Loc::Local(slot) => {
+                self.slots[slot as usize].with(|b| b.bind(val));
+                Ok(())
+            }
+            _ => self.globals()?.set(&name.ident, val),
+        }
+    }
+
+    fn globals(&self) -> Result<futures::lock::MutexGuard<'_, Env>, EvalError> {
+        self.closure.globals.try_lock().ok_or(EvalError::LockError)
+    }
+
+    /// Looks up a name from the scope of the current instruction, for the
+    /// types named by the fields of another type and for the fns a module
+    /// entered by this frame calls.
+    fn lookup(&self, ident: &Identifier) -> Option<Arc<Object>> {
+        let ip = self.ip as u32 - 1;
+        let proto = &self.closure.proto;
+        let local = proto
+            .slots
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, s)| s.ident == *ident && s.start <= ip && ip < s.end)
+            .find_map(|(slot, _)| self.slots[slot].get());
+        local
+            .or_else(|| {
+                proto
+                    .upvalues
+                    .iter()
+                    .zip(&self.closure.upvalues)
+                    .filter(|((_, id), _)| id == ident)
+                    .find_map(|(_, cell)| lock(cell).val.clone())
+            })
+            .or_else(|| {
+                self.overrides
+                    .iter()
+                    .rev()
+                    .flatten()
+                    .find_map(|env| env.try_lock()?.store.get(&ident.name).cloned())
+            })
+            .or_else(|| self.closure.globals.try_lock()?.get(ident))
+    }
+
+    /// Assigns to the binding of `ident` in the scope of the current
+    /// instruction like [`Frame::lookup`], returning whether there was one.
+    fn assign_named(&mut self, ident: &Identifier, val: &Arc<Object>) -> Result<bool, EvalError> {
+        let ip = self.ip as u32 - 1;
+        let proto = self.closure.proto.clone();
+        for (slot, _) in proto
+            .slots
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, s)| s.ident == *ident && s.start <= ip && ip < s.end)
+        {
+            if self.slots[slot].with(|b| b.assign(ident, val))? {
+                return Ok(true);
+            }
+        }
+        for ((_, id), cell) in proto.upvalues.iter().zip(&self.closure.upvalues) {
+            if id == ident && lock(cell).assign(ident, val)? {
+                return Ok(true);
+            }
+        }
+        for env in self.overrides.iter().rev().flatten() {
+            let mut env = env.try_lock().ok_or(EvalError::LockError)?;
+            if env.store.contains_key(&ident.name) {
+                env.set(ident, val.clone())?;
+                return Ok(true);
+            }
+        }
+        let mut env = self.globals()?;
+        if env.get(ident).is_some() {
+            env.set(ident, val.clone())?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Whether the frame itself entered `module` with `::` or a method
+    /// call that is still running.
+    fn entered(&self, module: &EnvPointer) -> bool {
+        self.overrides[self.inherited..]
+            .iter()
+            .flatten()
+            .any(|env| Arc::ptr_eq(env, module))
+    }
+
+    /// Creates a closure for `protos[i]` over the current scope.
+    fn closure(&self, i: u32) -> Arc<Closure> {
+        let proto = self.closure.proto.protos[i as usize].clone();
+        let upvalues = proto
+            .upvalues
+            .iter()
+            .map(|(capture, _)| match *capture {
+                Capture::Slot(slot) => self.slots[slot as usize].cell(),
+                Capture::Upvalue(up) => self.closure.upvalues[up as usize].clone(),
+            })
+            .collect();
+        let defaults = proto
+            .params
+            .iter()
+            .map(|p| match p.default {
+                ParamDefault::Thunk(thunk) => Some(self.closure(thunk)),
+                _ => None,
+            })
+            .collect();
+        Arc::new(Closure {
+            proto,
+            upvalues,
+            globals: self.closure.globals.clone(),
+            overrides: self.overrides.clone(),
+            defaults,
+        })
+    }
+
+    fn crawl(&mut self) -> &mut CrawlState {
+        match self.regions.last_mut().map(|r| &mut r.kind) {
+            Some(RegionKind::Crawl(state)) => state,
+            _ => unreachable!("crawl instructions run inside a crawl"),
+        }
+    }
+}
+
+struct Vm<'a> {
+    crawler: &'a fantoccini::Client,
+    results: ScrapeResultsPtr,
+    budget: &'a Budget,
+    stack: Vec<Arc<Object>>,
+    frames: Vec<Frame>,
+    /// How many fn calls and module loads are running, which unlike
+    /// `frames` leaves out module scopes and param defaults.
+    depth: usize,
+}
+
+macro_rules! frame {
+    ($vm:ident) => {
+        $vm.frames.last_mut().expect("a frame is running")
+    };
+}
+
+impl<'a> Vm<'a> {
+    fn pop(&mut self) -> Arc<Object> {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    fn peek(&self) -> &Arc<Object> {
+        self.stack.last().expect("stack underflow")
+    }
+
+    fn push(&mut self, obj: Object) {
+        self.stack.push(Arc::new(obj));
+    }
+
+    /// Reads a name of the current frame, falling back to the scope of
+    /// the frame that entered its module.
+    fn read(&self, name: u32) -> Option<Arc<Object>> {
+        let idx = self.frames.len() - 1;
+        let frame = &self.frames[idx];
+        frame
+            .read(name)
+            .or_else(|| self.caller_lookup(idx, &frame.closure.proto.names[name as usize].ident))
+    }
+
+    /// Looks up a name from the scope of the current instruction like
+    /// [`Frame::lookup`], with the same fallback as [`Vm::read`].
+    fn lookup(&self, ident: &Identifier) -> Option<Arc<Object>> {
+        let idx = self.frames.len() - 1;
+        self.frames[idx]
+            .lookup(ident)
+            .or_else(|| self.caller_lookup(idx, ident))
+    }
+
+    /// Assigns to a name of the current frame. A name not bound in its
+    /// scope is assigned in the scope of the frame that entered its module
+    /// if it is bound there, and defined where it defaults to otherwise.
+    fn write(&mut self, name: u32, val: Arc<Object>) -> Result<(), EvalError> {
+        let idx = self.frames.len() - 1;
+        if self.frames[idx].assign(name, &val)? {
+            return Ok(());
+        }
+        let ident = self.frames[idx].closure.proto.names[name as usize]
+            .ident
+            .clone();
+        let mut at = idx;
+        while let Some(caller) = self.caller(at) {
+            if self.frames[caller].assign_named(&ident, &val)? {
+                return Ok(());
+            }
+            at = caller;
+        }
+        self.frames[idx].define(name, val)
+    }
+
+    /// The frame that most recently entered the module the frame at `idx`
+    /// runs in, whose scope names the module does not define resolve from
+    /// like they do through a module's caller in the tree-walker.
+    fn caller(&self, idx: usize) -> Option<usize> {
+        let module = &self.frames[idx].closure.globals;
+        (0..idx).rev().find(|&i| self.frames[i].entered(module))
+    }
+
+    fn caller_lookup(&self, idx: usize, ident: &Identifier) -> Option<Arc<Object>> {
+        let caller = self.caller(idx)?;
+        self.frames[caller]
+            .lookup(ident)
+            .or_else(|| self.caller_lookup(caller, ident))
+    }
+
+    fn push_region(&mut self, kind: RegionKind) {
+        let frame = frame!(self);
+        frame.regions.push(Region {
+            kind,
+            sp: self.stack.len(),
+            ov: frame.overrides.len(),
+        });
+    }
+
+    async fn run_module(&mut self, program: &Program, env: EnvPointer) -> EvalResult {
+        let closure = Arc::new(Closure {
+            proto: Arc::new(compile::compile(program)),
+            upvalues: Vec::new(),
+            globals: env,
+            overrides: Vec::new(),
+            defaults: Vec::new(),
+        });
+        self.invoke(closure, Vec::new(), Kwargs::new()).await
+    }
+
+    /// Calls a closure and runs it to completion.
+    fn invoke(
+        &mut self,
+        closure: Arc<Closure>,
+        args: Vec<Arc<Object>>,
+        kwargs: Kwargs,
+    ) -> BoxFuture<'_, EvalResult> {
+        async move {
+            let stop = self.frames.len();
+            self.enter(closure, args, kwargs).await?;
+            self.run(stop).await
+        }
+        .boxed()
+    }
+
+    /// Binds the args to the params of a closure and pushes its frame.
+    async fn enter(
+        &mut self,
+        closure: Arc<Closure>,
+        args: Vec<Arc<Object>>,
+        mut kwargs: Kwargs,
+    ) -> Result<(), EvalError> {
+        let proto = closure.proto.clone();
+        let mut slots: Vec<Slot> = proto.slots.iter().map(|s| Slot::new(s.captured)).collect();
+        let mut provided = args.into_iter();
+        for (i, param) in proto.params.iter().enumerate() {
+            let val = match param.kind {
+                ParamKind::Rest => {
+                    let rest = provided.by_ref().collect();
+                    Arc::new(Object::List(Mutex::new(rest)))
+                }
+                ParamKind::Kwargs => {
+                    let opts = std::mem::take(&mut kwargs);
+                    Arc::new(Object::Map(Mutex::new(opts)))
+                }
+                ParamKind::Positional => match (provided.next(), kwargs.shift_remove(&param.ident))
+                {
+                    (Some(_), Some(_)) => {
+                        return Err(EvalError::DuplicateKwarg(param.ident.clone()))
+                    }
+                    (Some(val), None) | (None, Some(val)) => val,
+                    (None, None) => match &param.default {
+                        ParamDefault::Const(val) => val.clone(),
+                        ParamDefault::Thunk(_) => {
+                            let thunk = closure.defaults[i].clone().expect("thunks are created");
+                            self.invoke(thunk, Vec::new(), Kwargs::new()).await?
+                        }
+                        ParamDefault::None => return Err(EvalError::InvalidFnParams),
+                    },
+                },
+            };
+            slots[param.slot as usize].with(|b| b.bind(val));
+        }
+
+        if provided.next().is_some() {
+            return Err(EvalError::InvalidFnParams);
+        }
+        if let Some(unknown) = kwargs.keys().next() {
+            return Err(EvalError::UnknownKwarg(unknown.clone()));
+        }
+
+        self.frames
+            .push(Frame::new(closure, slots, self.stack.len()));
+        Ok(())
+    }
+
+    /// Runs until the frame at depth `stop` returns.
+    fn run(&mut self, stop: usize) -> BoxFuture<'_, EvalResult> {
+        async move {
+            loop {
+                let frame = frame!(self);
+                let op = frame.closure.proto.code[frame.ip];
+                frame.ip += 1;
+                let unwind = match self.step(op).await {
+                    Ok(()) => continue,
+                    Err(Unwind::Error(e)) => {
+                        let frame = frame!(self);
+                        Unwind::Error(e.with_span(frame.closure.proto.spans[frame.ip - 1]))
+                    }
+                    Err(Unwind::Return(val)) if op == Op::Ret => match self.leave(val, stop) {
+                        Some(val) => return Ok(val),
+                        None => continue,
+                    },
+                    Err(unwind) => unwind,
+                };
+                if let Some(res) = self.unwind(unwind, stop).await {
+                    return res;
+                }
+            }
+        }
+        .boxed()
+    }
+
+    /// Pops the current frame, returning its value if it was the last one
+    /// to run or handing it to the caller otherwise.
+    fn leave(&mut self, val: Arc<Object>, stop: usize) -> Option<Arc<Object>> {
+        let frame = self.frames.pop().expect("a frame is running");
+        self.stack.truncate(frame.base);
+        if self.frames.len() == stop {
+            return Some(val);
+        }
+        self.depth -= 1;
+        self.stack.push(val);
+        None
+    }
+
+    /// Describes the call the current frame is at, which entered `callee`.
+    fn trace_frame(&self, callee: &Frame) -> TraceFrame {
+        let caller = self.frames.last().expect("a frame is running");
+        let proto = &caller.closure.proto;
+        let (Op::Call(i) | Op::MethodCall(i)) = proto.code[caller.ip - 1] else {
+            unreachable!("frames within a run are entered by calls");
+        };
+        let name = proto.calls[i as usize].name;
+        TraceFrame {
+            name: Some(proto.names[name as usize].ident.clone()),
+            module: callee
+                .closure
+                .globals
+                .try_lock()
+                .and_then(|env| env.module_path()),
+            span: proto.spans[caller.ip - 1],
+        }
+    }
+
+    /// Unwinds to the innermost region that handles `unwind`, returning a
+    /// result if it leaves the frame at depth `stop`.
+    async fn unwind(&mut self, mut unwind: Unwind, stop: usize) -> Option<EvalResult> {
+        loop {
+            let frame = frame!(self);
+            while let Some(Region { kind, sp, ov }) = frame.regions.pop() {
+                self.stack.truncate(sp);
+                frame.overrides.truncate(ov);
+                unwind = match (kind, unwind) {
+                    (RegionKind::Loop { brk, .. }, Unwind::Break)
+                    | (RegionKind::Iter { brk, .. }, Unwind::Break) => {
+                        frame.ip = brk as usize;
+                        return None;
+                    }
+                    (
+                        kind @ (RegionKind::Loop { .. } | RegionKind::Iter { .. }),
+                        Unwind::Continue,
+                    ) => {
+                        let (RegionKind::Loop { cont, .. } | RegionKind::Iter { cont, .. }) = kind
+                        else {
+                            unreachable!()
+                        };
+                        frame.ip = cont as usize;
+                        frame.regions.push(Region { kind, sp, ov });
+                        return None;
+                    }
+                    (RegionKind::Catch(target), Unwind::Error(e)) if e.limit().is_none() => {
+                        self.stack.push(Arc::new(e.to_object()));
+                        frame.ip = target as usize;
+                        return None;
+                    }
+                    (RegionKind::Finally(target), unwind) => {
+                        frame.pending.push(Some(unwind));
+                        frame.regions.push(Region {
+                            kind: RegionKind::InFinally,
+                            sp,
+                            ov,
+                        });
+                        frame.ip = target as usize;
+                        return None;
+                    }
+                    (RegionKind::InFinally, unwind) => {
+                        frame.pending.pop();
+                        unwind
+                    }
+                    (RegionKind::Match(_), Unwind::Error(e)) => Unwind::Error(e),
+                    (RegionKind::Match(target), unwind) => {
+                        let val = match unwind {
+                            Unwind::Break => Object::Break,
+                            Unwind::Continue => Object::Continue,
+                            Unwind::Return(val) => Object::Return(val),
+                            Unwind::Error(_) => unreachable!(),
+                        };
+                        self.stack.push(Arc::new(val));
+                        frame.ip = target as usize;
+                        return None;
+                    }
+                    (RegionKind::Crawl(state), Unwind::Continue) => {
+                        frame.ip = frame.closure.proto.crawls[state.site].descend as usize;
+                        frame.regions.push(Region {
+                            kind: RegionKind::Crawl(state),
+                            sp,
+                            ov,
+                        });
+                        return None;
+                    }
+                    (RegionKind::Crawl(state), unwind @ (Unwind::Break | Unwind::Return(_))) => {
+                        // Leaving a crawl early returns to the page each
+                        // level started on.
+                        let mut unwind = unwind;
+                        for level in state.levels.iter().rev() {
+                            if let Err(e) = self.crawler.switch_to_window(level.start.clone()).await
+                            {
+                                unwind = Unwind::Error(EvalError::from(e).with_span(state.span));
+                                break;
+                            }
+                        }
+                        if let Unwind::Break = unwind {
+                            frame.ip = frame.closure.proto.crawls[state.site].exit as usize;
+                            return None;
+                        }
+                        unwind
+                    }
+                    (_, unwind) => unwind,
+                };
+            }
+
+            let frame = self.frames.pop().expect("a frame is running");
+            self.stack.truncate(frame.base);
+            if self.frames.len() > stop {
+                self.depth -= 1;
+            }
+            let val = match unwind {
+                Unwind::Error(e) if self.frames.len() == stop => return Some(Err(e)),
+                Unwind::Error(e) => {
+                    unwind = Unwind::Error(e.with_frame(self.trace_frame(&frame)));
+                    continue;
+                }
+                Unwind::Break => Arc::new(Object::Break),
+                Unwind::Continue => Arc::new(Object::Continue),
+                Unwind::Return(val) if frame.closure.proto.main => Arc::new(Object::Return(val)),
+                Unwind::Return(val) => val,
+            };
+            if self.frames.len() == stop {
+                return Some(Ok(val));
+            }
+            self.stack.push(val);
+            return None;
+        }
+    }
+
+    async fn step(&mut self, op: Op) -> Result<(), Unwind> {
+        match op {
+            Op::Const(i) => {
+                let val = frame!(self).closure.proto.consts[i as usize].clone();
+                self.stack.push(val);
+            }
+            Op::Pop => {
+                self.pop();
+            }
+            Op::Dup => self.stack.push(self.peek().clone()),
+            Op::PopN(n) => self.stack.truncate(self.stack.len() - n as usize),
+            Op::PopUnder => {
+                let top = self.pop();
+                self.pop();
+                self.stack.push(top);
+            }
+
+            Op::Load(i) => match self.read(i) {
+                Some(val) => self.stack.push(val),
+                None => {
+                    let ident = &frame!(self).closure.proto.names[i as usize].ident;
+                    return Err(EvalError::UnknownIdent(ident.clone()).into());
+                }
+            },
+            Op::Store(i) => {
+                let val = self.pop();
+                self.write(i, val)?;
+            }
+            Op::AddGlobal(i) => {
+                let val = self.pop();
+                let frame = frame!(self);
+                let ident = &frame.closure.proto.idents[i as usize];
+                frame.globals()?.add_global(ident, val)?;
+            }
+            Op::Declare(slot, constant) => {
+                let val = self.pop();
+                let frame = frame!(self);
+                let ident = &frame.closure.proto.slots[slot as usize].ident;
+                frame.slots[slot as usize].with(|b| b.declare(ident, val, constant))?;
+            }
+            Op::DeclareGlobal(i, constant) => {
+                let val = self.pop();
+                let frame = frame!(self);
+                let ident = &frame.closure.proto.idents[i as usize];
+                frame.globals()?.declare(ident, val, constant)?;
+            }
+            Op::Bind(slot) => {
+                let val = self.pop();
+                frame!(self).slots[slot as usize].with(|b| b.bind(val));
+            }
+            Op::InitSlots(start, end) => {
+                let frame = frame!(self);
+                for slot in start as usize..end as usize {
+                    frame.slots[slot] = Slot::new(frame.closure.proto.slots[slot].captured);
+                }
+            }
+            Op::Unpack(n) => {
+                let val = self.pop();
+                let Object::List(items) = &*val else {
+                    return Err(EvalError::TypeMismatch.into());
+                };
+                let items = items.try_lock().ok_or(EvalError::LockError)?.clone();
+                if items.len() != n as usize {
+                    return Err(EvalError::DestructureMismatch(n as usize, items.len()).into());
+                }
+                self.stack.extend(items.into_iter().rev());
+            }
+
+            Op::List(n) => {
+                let items = self.stack.split_off(self.stack.len() - n as usize);
+                self.push(Object::List(Mutex::new(items)));
+            }
+            Op::Interp(n) => {
+                let parts = self.stack.split_off(self.stack.len() - n as usize);
+                let out = parts.iter().map(|p| p.to_display()).collect();
+                self.push(Object::Str(out));
+            }
+            Op::Map(i) => {
+                let proto = frame!(self).closure.proto.clone();
+                let keys = &proto.maps[i as usize];
+                let count = keys.len() + keys.iter().filter(|k| k.is_none()).count();
+                let mut vals = self.stack.split_off(self.stack.len() - count).into_iter();
+                let mut map = IndexMap::new();
+                for key in keys {
+                    let key = match key {
+                        Some(key) => key.clone(),
+                        None => match &*vals.next().expect("a key was pushed") {
+                            Object::Str(s) => Identifier::new(s.clone()),
+                            _ => unreachable!("keys are checked when pushed"),
+                        },
+                    };
+                    map.insert(key, vals.next().expect("a value was pushed"));
+                }
+                self.push(Object::Map(Mutex::new(map)));
+            }
+            Op::CheckKey => {
+                if !matches!(**self.peek(), Object::Str(_)) {
+                    return Err(EvalError::InvalidIndex.into());
+                }
+            }
+            Op::Field(i) => {
+                let obj = self.pop();
+                let field = &frame!(self).closure.proto.idents[i as usize];
+                let val = match &*obj {
+                    Object::Map(m) => match m.try_lock().ok_or(EvalError::LockError)?.get(field) {
+                        Some(val) => val.clone(),
+                        None => return Err(EvalError::UnknownKey(field.clone()).into()),
+                    },
+                    Object::Module(env) => {
+                        match env.try_lock().ok_or(EvalError::LockError)?.get(field) {
+                            Some(val) => val.clone(),
+                            None => return Err(EvalError::UnknownIdent(field.clone()).into()),
+                        }
+                    }
+                    _ => return Err(EvalError::InvalidIndex.into()),
+                };
+                self.stack.push(val);
+            }
+            Op::SetIndex => {
+                let container = self.pop();
+                let idx = self.pop();
+                let val = self.pop();
+                match (&*container, &*idx) {
+                    (Object::List(v), Object::Number(idx)) => {
+                        let mut inner = v.try_lock().ok_or(EvalError::LockError)?;
+                        let idx = *idx as usize;
+                        if idx >= inner.len() {
+                            return Err(EvalError::IndexOutOfBounds.into());
+                        }
+                        inner[idx] = val;
+                    }
+                    (Object::Map(m), Object::Str(s)) => {
+                        let mut inner = m.try_lock().ok_or(EvalError::LockError)?;
+                        inner.insert(Identifier::new(s.clone()), val);
+                    }
+                    _ => return Err(EvalError::InvalidIndex.into()),
+                }
+            }
+            Op::SetField(i) => {
+                let obj = self.pop();
+                let val = self.pop();
+                let field = &frame!(self).closure.proto.idents[i as usize];
+                match &*obj {
+                    Object::Map(m) => {
+                        let mut inner = m.try_lock().ok_or(EvalError::LockError)?;
+                        inner.insert(field.clone(), val);
+                    }
+                    _ => return Err(EvalError::InvalidAssign.into()),
+                }
+            }
+            Op::Infix(kind) => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                self.stack.push(eval_infix_op(lhs, &kind, rhs)?);
+            }
+            Op::Prefix(kind) => {
+                let rhs = self.pop();
+                self.stack.push(eval_prefix(rhs, &kind)?);
+            }
+            Op::Closure(i) => {
+                let closure = frame!(self).closure(i);
+                self.push(Object::Closure(closure));
+            }
+            Op::Type(i) => {
+                let found = self.read(i);
+                let ident = &frame!(self).closure.proto.names[i as usize].ident;
+                let decl = lookup_type(ident, &|_| found.clone())?;
+                self.push(Object::Type(decl));
+            }
+            Op::Record => {
+                let map = self.pop();
+                let ty = self.pop();
+                let (Object::Type(decl), Object::Map(map)) = (&*ty, &*map) else {
+                    unreachable!("a type and map are pushed");
+                };
+                let map = map.try_lock().ok_or(EvalError::LockError)?.clone();
+                let checked = check_record(decl.clone(), map, &|id| self.lookup(id))?;
+                self.push(Object::Map(Mutex::new(checked)));
+            }
+            Op::Select(i) => self.select(i).await?,
+
+            Op::Jump(target) => frame!(self).ip = target as usize,
+            Op::JumpIfFalse(target) => {
+                if !self.pop().is_truthy() {
+                    frame!(self).ip = target as usize;
+                }
+            }
+            Op::And(target) => {
+                if self.peek().is_truthy() {
+                    self.pop();
+                } else {
+                    frame!(self).ip = target as usize;
+                }
+            }
+            Op::Or(target) => {
+                if self.peek().is_truthy() {
+                    frame!(self).ip = target as usize;
+                } else {
+                    self.pop();
+                }
+            }
+            Op::Coalesce(target) => {
+                if matches!(**self.peek(), Object::Null) {
+                    self.pop();
+                } else {
+                    frame!(self).ip = target as usize;
+                }
+            }
+            Op::SkipIfNull(target) => {
+                if matches!(**self.peek(), Object::Null) {
+                    frame!(self).ip = target as usize;
+                }
+            }
+
+            Op::Call(i) => self.call(i, false).await?,
+            Op::MethodCall(i) => self.call(i, true).await?,
+            Op::EnterModule => {
+                let val = self.pop();
+                let Object::Module(env) = &*val else {
+                    return Err(EvalError::UnknownInfixOp.into());
+                };
+                self.enter_module(env.clone());
+            }
+            Op::EnterMethod => match &**self.peek() {
+                Object::Module(env) => self.enter_module(env.clone()),
+                _ => frame!(self).overrides.push(None),
+            },
+            Op::ExitModule => {
+                frame!(self).overrides.pop();
+            }
+
+            Op::Ret | Op::Return => return Err(Unwind::Return(self.pop())),
+            Op::Break => return Err(Unwind::Break),
+            Op::Continue => return Err(Unwind::Continue),
+            Op::CheckControl => {
+                let unwind = match &**self.peek() {
+                    Object::Break => Unwind::Break,
+                    Object::Continue => Unwind::Continue,
+                    Object::Return(val) => Unwind::Return(val.clone()),
+                    _ => return Ok(()),
+                };
+                self.pop();
+                return Err(unwind);
+            }
+            Op::Step => self.budget.step()?,
+
+            Op::Loop(brk, cont) => self.push_region(RegionKind::Loop { brk, cont }),
+            Op::Iter(brk, cont) => {
+                let items = self.pop().into_iterable().ok_or(EvalError::NonIterable)?;
+                self.push_region(RegionKind::Iter {
+                    brk,
+                    cont,
+                    items: items.into_iter(),
+                });
+            }
+            Op::Next(exit) => {
+                let frame = frame!(self);
+                let Some(RegionKind::Iter { items, .. }) =
+                    frame.regions.last_mut().map(|r| &mut r.kind)
+                else {
+                    unreachable!("`Next` runs inside a for loop");
+                };
+                match items.next() {
+                    Some(item) => self.stack.push(item),
+                    None => {
+                        frame.regions.pop();
+                        frame.ip = exit as usize;
+                    }
+                }
+            }
+            Op::PopRegion => {
+                frame!(self).regions.pop();
+            }
+            Op::Catch(target) => self.push_region(RegionKind::Catch(target)),
+            Op::Finally(target) => self.push_region(RegionKind::Finally(target)),
+            Op::EnterFinally => {
+                frame!(self).pending.push(None);
+                self.push_region(RegionKind::InFinally);
+            }
+            Op::EndFinally => {
+                let frame = frame!(self);
+                frame.regions.pop();
+                if let Some(unwind) = frame.pending.pop().flatten() {
+                    return Err(unwind);
+                }
+            }
+            Op::Match(target) => self.push_region(RegionKind::Match(target)),
+            Op::PatList(n, fail) => {
+                let val = self.pop();
+                let items = match &*val {
+                    Object::List(items) => items.try_lock().ok_or(EvalError::LockError)?.clone(),
+                    _ => Vec::new(),
+                };
+                if matches!(&*val, Object::List(_)) && items.len() == n as usize {
+                    self.stack.extend(items.into_iter().rev());
+                } else {
+                    frame!(self).ip = fail as usize;
+                }
+            }
+            Op::PatMap(fail) => {
+                if !matches!(**self.peek(), Object::Map(_)) {
+                    frame!(self).ip = fail as usize;
+                }
+            }
+            Op::PatKey(key, fail) => {
+                let map = self.peek().clone();
+                let Object::Map(map) = &*map else {
+                    unreachable!("`PatMap` checked for a map");
+                };
+                let frame = frame!(self);
+                let key = &frame.closure.proto.idents[key as usize];
+                let val = map
+                    .try_lock()
+                    .ok_or(EvalError::LockError)?
+                    .get(key)
+                    .cloned();
+                match val {
+                    Some(val) => self.stack.push(val),
+                    None => frame.ip = fail as usize,
+                }
+            }
+
+            Op::Goto => {
+                let val = self.pop();
+                let Object::Str(url) = &*val else {
+                    return Err(EvalError::InvalidFnParams.into());
+                };
+                self.budget.visit()?;
+                if self.crawler.goto(url.as_str()).await.is_err() {
+                    return Err(EvalError::InvalidUrl.into());
+                }
+                wait_for_goto_ready().await;
+            }
+            Op::Throw => return Err(EvalError::Throw(self.pop()).into()),
+            Op::Scrape => self.scrape(None).await?,
+            Op::ScrapeAs(i) => self.scrape(Some(i)).await?,
+            Op::Screenshot(i) => {
+                let path = frame!(self).closure.proto.consts[i as usize].clone();
+                let Object::Str(path) = &*path else {
+                    unreachable!("screenshot paths are strings");
+                };
+                let png = self.crawler.screenshot().await.map_err(EvalError::from)?;
+                let img = image::io::Reader::new(std::io::Cursor::new(png))
+                    .with_guessed_format()
+                    .map_err(|_| EvalError::ScreenshotError)?
+                    .decode()
+                    .map_err(EvalError::from)?;
+                img.save(path).map_err(EvalError::from)?;
+            }
+            Op::Use(i) => self.use_module(i).await?,
+            Op::Crawl(i) => self.crawl_start(i).await?,
+            Op::CrawlNext => self.crawl_next().await?,
+            Op::CrawlVisit => self.crawl_visit().await?,
+            Op::CrawlDescend => self.crawl_descend().await?,
+            Op::Raise(i) => {
+                let e = match &frame!(self).closure.proto.raises[i as usize] {
+                    Raise::InvalidAssign => EvalError::InvalidAssign,
+                    Raise::DuplicateKwarg(ident) => EvalError::DuplicateKwarg(ident.clone()),
+                    Raise::UnknownModule => EvalError::InvalidImport(ImportError::UnknownModule),
+                };
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves names in `env` until the matching `ExitModule`, with the
+    /// current scope as its caller like `::` in the tree-walker.
+    fn enter_module(&mut self, env: EnvPointer) {
+        frame!(self).overrides.push(Some(env));
+    }
+
+    async fn call(&mut self, i: u32, method: bool) -> Result<(), EvalError> {
+        let proto = frame!(self).closure.proto.clone();
+        let site = &proto.calls[i as usize];
+        let callee = self.read(site.name);
+        let module = method && matches!(frame!(self).overrides.last(), Some(Some(_)));
+
+        let kwarg_vals = self.stack.split_off(self.stack.len() - site.kwargs.len());
+        let mut args = self.stack.split_off(self.stack.len() - site.args);
+        if site.piped || (method && !module) {
+            args.insert(0, self.pop());
+        } else if module {
+            self.pop();
+        }
+        let kwargs: Kwargs = site.kwargs.iter().cloned().zip(kwarg_vals).collect();
+
+        match callee.as_deref() {
+            Some(Object::Closure(closure)) => {
+                if self.depth >= MAX_CALL_DEPTH {
+                    return Err(EvalError::RecursionLimit);
+                }
+                self.enter(closure.clone(), args, kwargs).await?;
+                self.depth += 1;
+                Ok(())
+            }
+            Some(_) => Err(EvalError::InvalidExpr),
+            None => match &site.builtin {
+                Some(builtin) => {
+                    let val = builtin
+                        .apply(self.crawler, self.results.clone(), args, kwargs)
+                        .await?;
+                    self.stack.push(val);
+                    Ok(())
+                }
+                None => Err(EvalError::UnknownIdent(
+                    proto.names[site.name as usize].ident.clone(),
+                )),
+            },
+        }
+    }
+
+    async fn select(&mut self, i: u32) -> Result<(), EvalError> {
+        let proto = frame!(self).closure.proto.clone();
+        let site = &proto.selects[i as usize];
+        let locator = Locator::Css(&site.selector);
+        let scope = match site.scope {
+            Some(name) => match self.read(name) {
+                Some(obj) => Some(obj),
+                None => {
+                    let ident = &proto.names[name as usize].ident;
+                    return Err(EvalError::UnknownIdent(ident.clone()));
+                }
+            },
+            None => None,
+        };
+        let elem = match scope.as_deref() {
+            Some(Object::Node(elem)) => Some(elem),
+            Some(_) if site.all => {
+                return Err(EvalError::InvalidUsage("cannot select non-node".into()))
+            }
+            Some(_) => return Err(EvalError::InvalidUsage("Cannot select non-node".into())),
+            None => None,
+        };
+
+        let found = match (elem, site.all) {
+            (Some(elem), false) => elem.find(locator).await.map(|n| vec![n]),
+            (Some(elem), true) => elem.find_all(locator).await,
+            (None, false) => self.crawler.find(locator).await.map(|n| vec![n]),
+            (None, true) => self.crawler.find_all(locator).await,
+        };
+        let val = match found {
+            Ok(mut nodes) if !site.all => {
+                apply_debug_border(self.crawler, &site.selector).await;
+                Object::Node(nodes.remove(0))
+            }
+            Ok(nodes) => {
+                apply_debug_border_all(self.crawler, &site.selector).await;
+                let elems = nodes
+                    .into_iter()
+                    .map(|e| Arc::new(Object::Node(e)))
+                    .collect();
+                Object::List(Mutex::new(elems))
+            }
+            Err(_) => Object::Null,
+        };
+        self.push(val);
+        Ok(())
+    }
+
+    async fn scrape(&mut self, ty: Option<u32>) -> Result<(), EvalError> {
+        let map = self.pop();
+        let Object::Map(map) = &*map else {
+            unreachable!("scrape pushes a map");
+        };
+        let mut res = map.try_lock().ok_or(EvalError::LockError)?.clone();
+        if let Some(i) = ty {
+            let policy = self.results.lock().await.schema_policy();
+            let found = self.read(i);
+            let ident = &frame!(self).closure.proto.names[i as usize].ident;
+            let decl = lookup_type(ident, &|_| found.clone())?;
+            match check_record(decl, res.clone(), &|id| self.lookup(id)) {
+                Ok(checked) => res = checked,
+                Err(e @ EvalError::InvalidRecord(..)) if policy == SchemaPolicy::Warn => {
+                    eprintln!("warning: {e}");
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let json = obj_map_to_json(&res).await;
+        let url = self.crawler.current_url().await?;
+        self.budget.record()?;
+        self.results.lock().await.add_result(json, url.as_str());
+        Ok(())
+    }
+
+    /// Imports a module like `eval_use_chain`, binding it to the name
+    /// used in the `use`.
+    async fn use_module(&mut self, i: u32) -> Result<(), EvalError> {
+        let frame = frame!(self);
+        let proto = frame.closure.proto.clone();
+        let site = &proto.uses[i as usize];
+        let globals = frame.closure.globals.clone();
+        let inherit = match site.top {
+            true => globals_of(&globals)?,
+            false => site
+                .globals
+                .iter()
+                .filter_map(|&g| Some((proto.names[g as usize].ident.clone(), self.read(g)?)))
+                .collect(),
+        };
+        let resolved = resolve_module(&site.path)?;
+        let path = std::env::current_dir()
+            .map_err(|e| EvalError::OSError(e.to_string()))?
+            .join(&resolved.filepath);
+
+        if path.with_extension("sct").exists() {
+            let module = self
+                .load_module(path.with_extension("sct"), &inherit)
+                .await?;
+            return self.write(site.ident, Arc::new(Object::Module(module)));
+        }
+        if path.is_dir() {
+            let module = self.load_dir(&path, &inherit).await?;
+            return self.write(site.ident, Arc::new(Object::Module(module)));
+        }
+        let parent = path.parent().ok_or(import_error(ImportError::PathError))?;
+        if !parent.with_extension("sct").exists() {
+            return Err(import_error(ImportError::UnknownModule));
+        }
+
+        // The parent is bound to the name it was written as, unless it is
+        // a directory like `std` with a name of its own.
+        let parent_ident = file_ident(parent)?;
+        let parent_name = site
+            .parent
+            .filter(|&p| proto.names[p as usize].ident == parent_ident);
+        let read_parent = |vm: &mut Self| -> Result<_, EvalError> {
+            match parent_name {
+                Some(name) => Ok(vm.read(name)),
+                None => Ok(globals
+                    .try_lock()
+                    .ok_or(EvalError::LockError)?
+                    .get(&parent_ident)),
+            }
+        };
+        let mut found = read_parent(self)?;
+        if found.is_none() {
+            let module = self
+                .load_module(parent.with_extension("sct"), &inherit)
+                .await?;
+            let module = Arc::new(Object::Module(module));
+            match parent_name {
+                Some(name) => self.write(name, module)?,
+                None => globals
+                    .try_lock()
+                    .ok_or(EvalError::LockError)?
+                    .set(&parent_ident, module)?,
+            }
+            found = read_parent(self)?;
+        }
+        match found.as_deref() {
+            Some(Object::Module(env)) => {
+                let obj = env
+                    .try_lock()
+                    .ok_or(EvalError::LockError)?
+                    .get(&file_ident(&path)?)
+                    .ok_or(import_error(ImportError::UnknownModule))?;
+                self.write(site.ident, obj)
+            }
+            _ => Err(import_error(ImportError::UnknownModule)),
+        }
+    }
+
+    /// Imports a module into `env` by the name of its file, for the files
+    /// of an imported directory.
+    fn use_in_env(
+        &mut self,
+        path: PathBuf,
+        ident: Identifier,
+        env: EnvPointer,
+    ) -> BoxFuture<'_, Result<(), EvalError>> {
+        async move {
+            if path.with_extension("sct").exists() {
+                let inherit = globals_of(&env)?;
+                let module = self
+                    .load_module(path.with_extension("sct"), &inherit)
+                    .await?;
+                return set(&env, &ident, Object::Module(module));
+            }
+            if path.is_dir() {
+                let module = self.load_dir(&path, &globals_of(&env)?).await?;
+                return set(&env, &dir_ident(&path)?, Object::Module(module));
+            }
+            let parent = path.parent().ok_or(import_error(ImportError::PathError))?;
+            if !parent.with_extension("sct").exists() {
+                return Err(import_error(ImportError::UnknownModule));
+            }
+            let parent_ident = file_ident(parent)?;
+            let found = env
+                .try_lock()
+                .ok_or(EvalError::LockError)?
+                .get(&parent_ident);
+            match found.as_deref() {
+                Some(Object::Module(mod_env)) => {
+                    let final_ident = file_ident(&path)?;
+                    let obj = mod_env
+                        .try_lock()
+                        .ok_or(EvalError::LockError)?
+                        .get(&final_ident)
+                        .ok_or(import_error(ImportError::UnknownModule))?;
+                    env.try_lock()
+                        .ok_or(EvalError::LockError)?
+                        .set(&final_ident, obj)
+                }
+                Some(_) => Err(import_error(ImportError::UnknownModule)),
+                None => {
+                    let parent = parent.to_path_buf();
+                    self.use_in_env(parent, parent_ident, env.clone()).await?;
+                    self.use_in_env(path, ident, env).await
+                }
+            }
+        }
+        .boxed()
+    }
+
+    async fn load_module(
+        &mut self,
+        file: PathBuf,
+        inherit: &Globals,
+    ) -> Result<EnvPointer, EvalError> {
+        let content =
+            std::fs::read_to_string(&file).map_err(|e| EvalError::OSError(e.to_string()))?;
+        let program = Parser::new(Lexer::new(&content))
+            .parse_program()
+            .map_err(|e| import_error(ImportError::ParseError(e)))?;
+        let program = match self.results.lock().await.optimize() {
+            true => opt::optimize(program),
+            false => program,
+        };
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(EvalError::RecursionLimit);
+        }
+        let frame = frame!(self);
+        let span = frame.closure.proto.spans[frame.ip - 1];
+        let env = module_env(Env::module(file.clone(), self.depth + 1), inherit)?;
+        self.depth += 1;
+        let res = GrowStack(self.run_module(&program, env.clone()).boxed()).await;
+        self.depth -= 1;
+        res.map_err(|e| {
+            e.with_frame(TraceFrame {
+                name: None,
+                module: Some(file),
+                span,
+            })
+        })?;
+        Ok(env)
+    }
+
+    async fn load_dir(&mut self, path: &Path, inherit: &Globals) -> Result<EnvPointer, EvalError> {
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(EvalError::RecursionLimit);
+        }
+        let env = module_env(Env::module(path.to_path_buf(), self.depth + 1), inherit)?;
+        let entries = path
+            .read_dir()
+            .map_err(|e| EvalError::OSError(e.to_string()))?;
+        self.depth += 1;
+        let res = async {
+            for entry in entries.flatten() {
+                let stem = entry
+                    .path()
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .ok_or(import_error(ImportError::PathError))?
+                    .to_string();
+                // Files that are not valid modules are skipped, as in the
+                // tree-walker.
+                let _ = self
+                    .use_in_env(entry.path(), Identifier::new(stem), env.clone())
+                    .await;
+            }
+            Ok::<_, EvalError>(())
+        }
+        .await;
+        self.depth -= 1;
+        res?;
+        Ok(env)
+    }
+
+    async fn crawl_start(&mut self, i: u32) -> Result<(), EvalError> {
+        let start = self.crawler.window().await?;
+        let elems = self.crawler.find_all(Locator::Css("a[href]")).await?;
+        let frame = frame!(self);
+        let state = CrawlState {
+            site: i as usize,
+            span: frame.closure.proto.spans[frame.ip - 1],
+            visited: HashSet::new(),
+            levels: vec![Level {
+                start,
+                elems,
+                next: 0,
+                depth: 1,
+            }],
+            link: String::new(),
+        };
+        self.push_region(RegionKind::Crawl(Box::new(state)));
+        Ok(())
+    }
+
+    /// Pushes the next link and its depth, going back up a level when one
+    /// runs out of links and leaving the crawl when the first one does.
+    async fn crawl_next(&mut self) -> Result<(), EvalError> {
+        loop {
+            let frame = frame!(self);
+            let state = frame.crawl();
+            let level = state.levels.last_mut().expect("a level is being crawled");
+            if let Some(elem) = level.elems.get(level.next).cloned() {
+                level.next += 1;
+                let depth = level.depth;
+                if let Ok(Some(href)) = elem.attr("href").await {
+                    let curr_url = self.crawler.current_url().await?;
+                    let link = resolve_link(&curr_url, &href)?;
+                    frame!(self).crawl().link = link.clone();
+                    self.push(Object::Str(link));
+                    self.push(Object::Number(depth as f64));
+                    return Ok(());
+                }
+                continue;
+            }
+
+            state.levels.pop();
+            match state.levels.last() {
+                Some(parent) => {
+                    let start = parent.start.clone();
+                    self.crawler.switch_to_window(start).await?;
+                }
+                None => {
+                    let site = state.site;
+                    frame.regions.pop();
+                    frame.ip = frame.closure.proto.crawls[site].exit as usize;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Opens the current link in a new window if it passed the filter and
+    /// has not been visited yet, or skips to the next one.
+    async fn crawl_visit(&mut self) -> Result<(), EvalError> {
+        let passed = self.pop().is_truthy();
+        let frame = frame!(self);
+        let state = frame.crawl();
+        if state.visited.contains(&state.link) || !passed {
+            let site = state.site;
+            frame.ip = frame.closure.proto.crawls[site].next as usize;
+            return Ok(());
+        }
+
+        self.budget.visit()?;
+        let new_tab = self.crawler.new_window(true).await?;
+        self.crawler.switch_to_window(new_tab.handle).await?;
+        let _ = self.crawler.goto(&state.link).await;
+        // Add both the starting url and resolved url to the visited.
+        state.visited.insert(std::mem::take(&mut state.link));
+        state
+            .visited
+            .insert(self.crawler.current_url().await?.to_string());
+        Ok(())
+    }
+
+    /// Crawls the links of the page just visited, or goes back to the
+    /// page its link was on at the max depth.
+    async fn crawl_descend(&mut self) -> Result<(), EvalError> {
+        let frame = frame!(self);
+        let state = frame.crawl();
+        let level = state.levels.last().expect("a level is being crawled");
+        if level.depth < MAX_DEPTH {
+            let depth = level.depth + 1;
+            let start = self.crawler.window().await?;
+            let elems = self.crawler.find_all(Locator::Css("a[href]")).await?;
+            state.levels.push(Level {
+                start,
+                elems,
+                next: 0,
+                depth,
+            });
+        } else {
+            self.crawler.switch_to_window(level.start.clone()).await?;
+        }
+        let site = state.site;
+        frame.ip = frame.closure.proto.crawls[site].next as usize;
+        Ok(())
+    }
+}
+
+fn import_error(e: ImportError) -> EvalError {
+    EvalError::InvalidImport(e)
+}
+
+fn set(env: &EnvPointer, ident: &Identifier, obj: Object) -> Result<(), EvalError> {
+    env.try_lock()
+        .ok_or(EvalError::LockError)?
+        .set(ident, Arc::new(obj))
+}
+
+/// The globals a module passes on to the modules it imports.
+type Globals = Vec<(Identifier, Arc<Object>)>;
+
+fn globals_of(env: &EnvPointer) -> Result<Globals, EvalError> {
+    let env = env.try_lock().ok_or(EvalError::LockError)?;
+    Ok(env
+        .globals
+        .iter()
+        .filter_map(|g| Some((g.clone(), env.get(g)?)))
+        .collect())
+}
+
+/// A new module scope, with the globals of the importing scope.
+fn module_env(mut env: Env, inherit: &Globals) -> Result<EnvPointer, EvalError> {
+    for (ident, val) in inherit {
+        env.add_global(ident, val.clone())?;
+    }
+    Ok(Arc::new(Mutex::new(env)))
+}
+
+fn file_ident(path: &Path) -> Result<Identifier, EvalError> {
+    let name = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .ok_or(import_error(ImportError::PathError))?;
+    Ok(Identifier::new(name.to_string()))
+}
+
+/// The name an imported directory is bound to, which is `std` for the
+/// standard lib.
+fn dir_ident(path: &Path) -> Result<Identifier, EvalError> {
+    let ident = file_ident(path)?;
+    match ident.name.as_str() {
+        "scout-lib" => Ok(Identifier::new("std".into())),
+        _ => Ok(ident),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use crate::testing::{run, run_browser};
+    use crate::Engine;
+
+    #[test_case(
+        "out = []\ni = 0\nwhile i < 5 do\ni = i + 1\nif i == 2 do\ncontinue\nend\nfor j in [1, 2, 3] do\nif j == 3 do\nbreak\nend\npush(out, i * j)\nend\nend\nout";
+        "loops"
+    )]
+    #[test_case(
+        "out = []\nfor x in [1, 2, 3, 4, 5] do\nmatch x case 2 do continue case 4 do break case n do push(out, n) end\npush(out, 0)\nend\nout";
+        "break and continue in match"
+    )]
+    #[test_case(
+        "out = []\ndef f() do\ntry\nthrow \"e\"\ncatch e do\npush(out, e)\nreturn 1\nfinally\npush(out, 2)\nend\nend\npush(out, f())\nfor x in [1, 2] do\ntry\nbreak\nfinally\npush(out, x)\nend\nend\nout";
+        "try finally"
+    )]
+    #[test_case(
+        "def counter() do\nn = 0\nreturn fn() do\nn = n + 1\nreturn n\nend\nend\nc = counter()\nd = counter()\nc()\nout = [c(), c(), d()]\nout";
+        "closures"
+    )]
+    #[test_case(
+        "def f(a, b = 1, *rest, **opts) do\nreturn [a, b, rest, opts]\nend\n[f(1), f(1, 2, 3, 4, k = 5)]";
+        "kwargs and rest"
+    )]
+    #[test_case(
+        "use tests::fixtures::caller\ndef f() do\nx = 1\na = caller::get()\ncaller::put()\nreturn [a, x]\nend\nf()";
+        "module call sees caller locals"
+    )]
+    #[test_case(
+        "def f() do\nglobal g = 1\nuse tests::fixtures::inherit\nreturn inherit::y\nend\nf()";
+        "use in fn passes globals"
+    )]
+    #[test_case("x = 1\nout = [x, y]"; "unknown ident")]
+    #[tokio::test]
+    async fn test_matches_tree_walker(input: &str) {
+        let vm = run(input, Engine::Vm, false).await;
+        assert_eq!(vm, run(input, Engine::Tree, false).await);
+    }
+
+    #[test_case(
+        "goto \"https://example.com\"\nn = 0\ncrawl link, depth where depth < 1 do\nn = n + 1\nscrape { depth: depth }\nend\nn";
+        "crawl"
+    )]
+    #[ignore = "needs geckodriver"]
+    #[tokio::test]
+    async fn test_crawl_matches_tree_walker(input: &str) {
+        let vm = run_browser(input, Engine::Vm, false).await;
+        assert_eq!(vm, run_browser(input, Engine::Tree, false).await);
+    }
+}
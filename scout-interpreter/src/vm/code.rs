@@ -0,0 +1,254 @@
+//! The instruction set and compiled fns run by the VM.
+
+use std::sync::Arc;
+
+use scout_lexer::{Span, TokenKind};
+use scout_parser::ast::{Expr, Identifier, ParamKind};
+
+use crate::{builtin::BuiltinKind, object::Object};
+
+/// A single instruction. Operands index into the tables of the [`Proto`]
+/// the instruction belongs to, or are jump targets within its code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Op {
+    /// Pushes `consts[i]`.
+    Const(u32),
+    Pop,
+    Dup,
+    /// Pops `n` values.
+    PopN(u32),
+    /// Drops the value under the top of the stack.
+    PopUnder,
+
+    /// Pushes the value of `names[i]`.
+    Load(u32),
+    /// Pops a value and assigns it to `names[i]`.
+    Store(u32),
+    /// `global x = v` at the top level of a module.
+    AddGlobal(u32),
+    /// `let` or `const` in a fn or block scope.
+    Declare(u32, bool),
+    /// `let` or `const` at the top level of a module, naming `idents[i]`.
+    DeclareGlobal(u32, bool),
+    /// Pops a value into a slot, shadowing any previous declaration.
+    Bind(u32),
+    /// Resets the slots in `start..end` when a block scope is entered.
+    InitSlots(u32, u32),
+    /// Replaces the list on top with its `n` items, the first on top.
+    Unpack(u32),
+
+    List(u32),
+    /// Joins the top `n` values into a str, each as `print` shows it.
+    Interp(u32),
+    /// Builds a map with the keys in `maps[i]`.
+    Map(u32),
+    /// Checks that a computed map key is a string.
+    CheckKey,
+    Field(u32),
+    /// `[v, idx, container]`, from `container[idx] = v`.
+    SetIndex,
+    /// `[v, obj]`, from `obj.field = v`.
+    SetField(u32),
+    Infix(TokenKind),
+    Prefix(TokenKind),
+    Closure(u32),
+    /// Pushes the record type named by `names[i]`.
+    Type(u32),
+    /// Checks the map on top against the type under it.
+    Record,
+    Select(u32),
+
+    Jump(u32),
+    /// Pops the condition.
+    JumpIfFalse(u32),
+    /// Short-circuits `and`: jumps keeping a falsy lhs, else pops it.
+    And(u32),
+    Or(u32),
+    /// Short-circuits `??`: jumps keeping a non-null lhs, else pops it.
+    Coalesce(u32),
+    /// Ends a `?|>` chain early, keeping the null on top.
+    SkipIfNull(u32),
+
+    Call(u32),
+    /// Like `Call`, for `recv.f(..)` after `EnterMethod`.
+    MethodCall(u32),
+    /// Pops a module and resolves names in it until `ExitModule`.
+    EnterModule,
+    /// Peeks at a method receiver, resolving names in it until `ExitModule`
+    /// if it is a module.
+    EnterMethod,
+    ExitModule,
+
+    /// Returns the value on top from the current frame.
+    Ret,
+    /// `return`, which runs any `finally` blocks on the way out.
+    Return,
+    Break,
+    Continue,
+    /// Unwinds if an expression statement evaluated to a `break`,
+    /// `continue` or `return` value.
+    CheckControl,
+    /// Counts a statement, or a `while` loop iteration, against the
+    /// interpreter's limits.
+    Step,
+
+    /// Enters a `while` loop: `break` jumps to the first target,
+    /// `continue` to the second.
+    Loop(u32, u32),
+    /// Enters a `for` loop over the iterable on top.
+    Iter(u32, u32),
+    /// Pushes the next item of the innermost `for` loop, or leaves it and
+    /// jumps to the target.
+    Next(u32),
+    PopRegion,
+    Catch(u32),
+    Finally(u32),
+    /// Enters a `finally` block after the `try` completed normally.
+    EnterFinally,
+    /// Resumes whatever was unwinding when the `finally` block began.
+    EndFinally,
+    /// Catches `break`, `continue` and `return` in a match arm as its value.
+    Match(u32),
+    /// Matches a list of exactly `n` items, replacing it with the items.
+    PatList(u32, u32),
+    /// Checks the value on top is a map.
+    PatMap(u32),
+    /// Pushes the value of a key of the map on top.
+    PatKey(u32, u32),
+
+    Goto,
+    Throw,
+    Scrape,
+    /// `scrape Type { .. }`, with the type in `names[i]`.
+    ScrapeAs(u32),
+    Screenshot(u32),
+    Use(u32),
+    Crawl(u32),
+    /// Pushes the next link and depth to crawl, or leaves the crawl.
+    CrawlNext,
+    /// Pops the filter result and opens the link if it should be visited.
+    CrawlVisit,
+    /// Crawls the links of the page just visited, up to the max depth.
+    CrawlDescend,
+    /// Raises `raises[i]`.
+    Raise(u32),
+}
+
+/// Where a name may be bound, tried in order when it is read or assigned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Loc {
+    Local(u32),
+    Upvalue(u32),
+    /// The module entered by the `n`th enclosing `::`.
+    Override(u32),
+    /// The module the fn was defined in.
+    Global,
+}
+
+#[derive(Debug)]
+pub(crate) struct Name {
+    pub ident: Identifier,
+    pub locs: Vec<Loc>,
+    /// Where assigning to the name defines it when it is not bound yet.
+    pub default: Loc,
+}
+
+#[derive(Debug)]
+pub(crate) struct SlotInfo {
+    pub ident: Identifier,
+    /// Whether a closure captures the slot, so it needs to be shared.
+    pub captured: bool,
+    /// The code the slot is in scope for.
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Capture {
+    Slot(u32),
+    Upvalue(u32),
+}
+
+#[derive(Debug)]
+pub(crate) enum ParamDefault {
+    None,
+    Const(Arc<Object>),
+    /// A fn in the defining proto that evaluates the default.
+    Thunk(u32),
+}
+
+#[derive(Debug)]
+pub(crate) struct Param {
+    pub ident: Identifier,
+    pub kind: ParamKind,
+    pub slot: u32,
+    pub default: ParamDefault,
+}
+
+#[derive(Debug)]
+pub(crate) struct CallSite {
+    pub name: u32,
+    pub args: usize,
+    pub kwargs: Vec<Identifier>,
+    /// Whether a piped value is under the args.
+    pub piped: bool,
+    /// The builtin called when the name is not bound.
+    pub builtin: Option<BuiltinKind>,
+}
+
+#[derive(Debug)]
+pub(crate) struct SelectSite {
+    pub selector: String,
+    pub scope: Option<u32>,
+    pub all: bool,
+}
+
+#[derive(Debug)]
+pub(crate) struct UseSite {
+    pub path: Expr,
+    pub ident: u32,
+    /// The module a `use a::b` path imports from.
+    pub parent: Option<u32>,
+    /// Whether the module inherits the importing module's globals.
+    pub top: bool,
+    /// The globals assigned before a `use` below the top level, which the
+    /// module inherits instead.
+    pub globals: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub(crate) struct CrawlSite {
+    pub next: u32,
+    pub descend: u32,
+    pub exit: u32,
+}
+
+#[derive(Debug)]
+pub(crate) enum Raise {
+    InvalidAssign,
+    DuplicateKwarg(Identifier),
+    UnknownModule,
+}
+
+/// A compiled fn, or the top level of a module.
+#[derive(Debug, Default)]
+pub(crate) struct Proto {
+    pub name: Option<Identifier>,
+    pub main: bool,
+    pub params: Vec<Param>,
+    pub code: Vec<Op>,
+    /// The span of the node each instruction was compiled from.
+    pub spans: Vec<Span>,
+    pub consts: Vec<Arc<Object>>,
+    pub idents: Vec<Identifier>,
+    pub names: Vec<Name>,
+    pub slots: Vec<SlotInfo>,
+    pub upvalues: Vec<(Capture, Identifier)>,
+    pub protos: Vec<Arc<Proto>>,
+    pub calls: Vec<CallSite>,
+    pub maps: Vec<Vec<Option<Identifier>>>,
+    pub selects: Vec<SelectSite>,
+    pub uses: Vec<UseSite>,
+    pub crawls: Vec<CrawlSite>,
+    pub raises: Vec<Raise>,
+}
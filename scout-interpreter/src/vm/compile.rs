@@ -0,0 +1,1125 @@
+//! Compiles a [`Program`] into the [`Proto`]s run by the VM.
+//!
+//! Scopes follow the tree-walker's envs: a fn scope holds its params, the
+//! names its body assigns and its top level `let`s, while each block
+//! scope holds its own bindings and `let`s. Every such name gets a slot
+//! in the fn's frame, and each name used is resolved to the list of slots,
+//! captured slots, entered modules and module scope it may be bound in,
+//! innermost first. Which one is bound is only known at runtime, since
+//! assignment defines a name in the innermost fn scope unless it is
+//! already bound further out.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::Arc;
+
+use scout_lexer::{Span, TokenKind};
+use scout_parser::ast::{
+    visit::{walk_stmt, Visitor},
+    Block, CallLiteral, CrawlLiteral, Expr, ExprKind, FnParam, ForLoop, FuncDef, HashLiteral,
+    Identifier, IfElseLiteral, LambdaLiteral, MapKey, MatchLiteral, Pattern, Program, Stmt,
+    StmtKind,
+};
+
+use super::code::{
+    CallSite, Capture, CrawlSite, Loc, Name, Op, Param, ParamDefault, Proto, Raise, SelectSite,
+    SlotInfo, UseSite,
+};
+use crate::{builtin::BuiltinKind, eval::assign_targets, object::Object};
+
+/// Compiles the top level of a module.
+pub(crate) fn compile(program: &Program) -> Proto {
+    let mut compiler = Compiler::default();
+    compiler.begin_fn(None, true, Vec::new());
+    compiler.value_block(&program.stmts, Span::default());
+    compiler.emit(Op::Ret, Span::default());
+    compiler.end_fn()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScopeKind {
+    /// The scope of a module's top level, whose names live in its env.
+    Module,
+    Fn,
+    Block,
+    /// The rhs of the `n`th enclosing `::` or module method call.
+    Override(u32),
+}
+
+struct Scope {
+    kind: ScopeKind,
+    names: HashMap<String, u32>,
+    slots: Range<u32>,
+    /// The names assigned with `global` in the scope so far, which a `use`
+    /// in it passes on below the top level.
+    globals: Vec<Identifier>,
+}
+
+struct FnState {
+    proto: Proto,
+    scopes: Vec<Scope>,
+    /// The number of entered modules, including those entered around the
+    /// fn's definition.
+    overrides: u32,
+    idents: HashMap<String, u32>,
+    names: HashMap<(String, Vec<Loc>, Loc), u32>,
+}
+
+#[derive(Default)]
+struct Compiler {
+    fns: Vec<FnState>,
+}
+
+impl Compiler {
+    fn state(&self) -> &FnState {
+        self.fns.last().expect("a fn is being compiled")
+    }
+
+    fn state_mut(&mut self) -> &mut FnState {
+        self.fns.last_mut().expect("a fn is being compiled")
+    }
+
+    fn here(&self) -> u32 {
+        self.state().proto.code.len() as u32
+    }
+
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        let proto = &mut self.state_mut().proto;
+        proto.code.push(op);
+        proto.spans.push(span);
+        proto.code.len() - 1
+    }
+
+    /// Points the forward jump of the instruction at `at` to the next
+    /// instruction emitted.
+    fn patch(&mut self, at: usize) {
+        let here = self.here();
+        match &mut self.state_mut().proto.code[at] {
+            Op::Jump(t)
+            | Op::JumpIfFalse(t)
+            | Op::And(t)
+            | Op::Or(t)
+            | Op::Coalesce(t)
+            | Op::SkipIfNull(t)
+            | Op::Next(t)
+            | Op::Catch(t)
+            | Op::Finally(t)
+            | Op::Match(t)
+            | Op::PatList(_, t)
+            | Op::PatMap(t)
+            | Op::PatKey(_, t)
+            | Op::Loop(t, _)
+            | Op::Iter(t, _) => *t = here,
+            op => unreachable!("{op:?} has no jump target"),
+        }
+    }
+
+    fn constant(&mut self, obj: Object, span: Span) {
+        let consts = &mut self.state_mut().proto.consts;
+        consts.push(Arc::new(obj));
+        let idx = consts.len() as u32 - 1;
+        self.emit(Op::Const(idx), span);
+    }
+
+    fn ident(&mut self, ident: &Identifier) -> u32 {
+        let state = self.state_mut();
+        if let Some(&idx) = state.idents.get(&ident.name) {
+            return idx;
+        }
+        state.proto.idents.push(ident.clone());
+        let idx = state.proto.idents.len() as u32 - 1;
+        state.idents.insert(ident.name.clone(), idx);
+        idx
+    }
+
+    fn begin_fn(&mut self, name: Option<&Identifier>, main: bool, names: Vec<Identifier>) {
+        let overrides = self.fns.last().map_or(0, |f| f.overrides);
+        self.fns.push(FnState {
+            proto: Proto {
+                name: name.cloned(),
+                main,
+                ..Default::default()
+            },
+            scopes: Vec::new(),
+            overrides,
+            idents: HashMap::new(),
+            names: HashMap::new(),
+        });
+        let kind = if main {
+            ScopeKind::Module
+        } else {
+            ScopeKind::Fn
+        };
+        let scope = self.alloc_scope(kind, names);
+        self.state_mut().scopes.push(scope);
+    }
+
+    fn end_fn(&mut self) -> Proto {
+        while !self.state().scopes.is_empty() {
+            self.pop_scope();
+        }
+        self.fns.pop().expect("a fn is being compiled").proto
+    }
+
+    /// Adds a compiled fn to the fn being compiled.
+    fn add_proto(&mut self, proto: Proto) -> u32 {
+        let protos = &mut self.state_mut().proto.protos;
+        protos.push(Arc::new(proto));
+        protos.len() as u32 - 1
+    }
+
+    /// Gives each distinct name a slot, in scope from the next instruction.
+    fn alloc_scope(&mut self, kind: ScopeKind, idents: Vec<Identifier>) -> Scope {
+        let start = self.here();
+        let proto = &mut self.state_mut().proto;
+        let first = proto.slots.len() as u32;
+        let mut names = HashMap::new();
+        for ident in idents {
+            if names.contains_key(&ident.name) {
+                continue;
+            }
+            names.insert(ident.name.clone(), proto.slots.len() as u32);
+            proto.slots.push(SlotInfo {
+                ident,
+                captured: false,
+                start,
+                end: u32::MAX,
+            });
+        }
+        let slots = first..proto.slots.len() as u32;
+        Scope {
+            kind,
+            names,
+            slots,
+            globals: Vec::new(),
+        }
+    }
+
+    fn pop_scope(&mut self) {
+        let end = self.here();
+        let state = self.state_mut();
+        let scope = state.scopes.pop().expect("a scope is open");
+        if let ScopeKind::Override(_) = scope.kind {
+            state.overrides -= 1;
+        }
+        for slot in scope.slots {
+            state.proto.slots[slot as usize].end = end;
+        }
+    }
+
+    fn push_override(&mut self) {
+        let state = self.state_mut();
+        state.scopes.push(Scope {
+            kind: ScopeKind::Override(state.overrides),
+            names: HashMap::new(),
+            slots: 0..0,
+            globals: Vec::new(),
+        });
+        state.overrides += 1;
+    }
+
+    /// Enters a block scope holding `bound` and the `let`s of `stmts`.
+    fn enter_block(&mut self, bound: Vec<Identifier>, stmts: &[Stmt], span: Span) {
+        let mut names = bound;
+        names.extend(declared(stmts));
+        let scope = self.alloc_scope(ScopeKind::Block, names);
+        self.init_slots(&scope, span);
+        self.state_mut().scopes.push(scope);
+    }
+
+    fn init_slots(&mut self, scope: &Scope, span: Span) {
+        if !scope.slots.is_empty() {
+            self.emit(Op::InitSlots(scope.slots.start, scope.slots.end), span);
+        }
+    }
+
+    /// The slot of a name bound in the innermost scope.
+    fn local(&self, ident: &Identifier) -> u32 {
+        let scope = self
+            .state()
+            .scopes
+            .iter()
+            .rev()
+            .find(|s| !matches!(s.kind, ScopeKind::Override(_)))
+            .expect("a scope is open");
+        scope.names[&ident.name]
+    }
+
+    /// The globals assigned in the innermost scope so far.
+    fn scope_globals(&mut self) -> &mut Vec<Identifier> {
+        let scope = self
+            .state_mut()
+            .scopes
+            .iter_mut()
+            .rev()
+            .find(|s| !matches!(s.kind, ScopeKind::Override(_)))
+            .expect("a scope is open");
+        &mut scope.globals
+    }
+
+    /// Whether statements are at the top level of a module, where
+    /// `global` and `let` act on its env.
+    fn at_top(&self) -> bool {
+        let state = self.state();
+        state.proto.main && state.scopes.len() == 1
+    }
+
+    /// Where a name may be bound in the fn at `depth`, innermost first.
+    fn resolve(&mut self, depth: usize, ident: &Identifier) -> Vec<Loc> {
+        let state = &self.fns[depth];
+        let mut locs = Vec::new();
+        for scope in state.scopes.iter().rev() {
+            match scope.kind {
+                ScopeKind::Override(k) => locs.push(Loc::Override(k)),
+                _ => {
+                    if let Some(&slot) = scope.names.get(&ident.name) {
+                        locs.push(Loc::Local(slot));
+                    }
+                }
+            }
+        }
+        if state.proto.main {
+            locs.push(Loc::Global);
+            return locs;
+        }
+
+        for loc in self.resolve(depth - 1, ident) {
+            let loc = match loc {
+                Loc::Local(slot) => {
+                    self.fns[depth - 1].proto.slots[slot as usize].captured = true;
+                    Loc::Upvalue(self.capture(depth, Capture::Slot(slot), ident))
+                }
+                Loc::Upvalue(up) => Loc::Upvalue(self.capture(depth, Capture::Upvalue(up), ident)),
+                loc => loc,
+            };
+            locs.push(loc);
+        }
+        locs
+    }
+
+    fn capture(&mut self, depth: usize, capture: Capture, ident: &Identifier) -> u32 {
+        let upvalues = &mut self.fns[depth].proto.upvalues;
+        match upvalues.iter().position(|(c, _)| *c == capture) {
+            Some(idx) => idx as u32,
+            None => {
+                upvalues.push((capture, ident.clone()));
+                upvalues.len() as u32 - 1
+            }
+        }
+    }
+
+    fn name(&mut self, ident: &Identifier) -> u32 {
+        let depth = self.fns.len() - 1;
+        let locs = self.resolve(depth, ident);
+        let state = &mut self.fns[depth];
+        let default = match state.scopes[0].names.get(&ident.name) {
+            Some(&slot) if !state.proto.main => Loc::Local(slot),
+            _ => Loc::Global,
+        };
+        let key = (ident.name.clone(), locs, default);
+        if let Some(&idx) = state.names.get(&key) {
+            return idx;
+        }
+        state.proto.names.push(Name {
+            ident: ident.clone(),
+            locs: key.1.clone(),
+            default,
+        });
+        let idx = state.proto.names.len() as u32 - 1;
+        state.names.insert(key, idx);
+        idx
+    }
+
+    fn raise(&mut self, raise: Raise, span: Span) {
+        let raises = &mut self.state_mut().proto.raises;
+        raises.push(raise);
+        let idx = raises.len() as u32 - 1;
+        self.emit(Op::Raise(idx), span);
+    }
+
+    /// Compiles a block whose last expression statement is its value, like
+    /// a fn body or match arm.
+    fn value_block(&mut self, stmts: &[Stmt], span: Span) {
+        match stmts.split_last() {
+            Some((last, init)) => {
+                self.stmts(init);
+                match &last.kind {
+                    StmtKind::Expr(expr) => {
+                        self.emit(Op::Step, last.span);
+                        self.expr(expr);
+                        self.emit(Op::CheckControl, last.span);
+                    }
+                    _ => {
+                        self.stmt(last);
+                        self.constant(Object::Null, last.span);
+                    }
+                }
+            }
+            None => self.constant(Object::Null, span),
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    /// Compiles a block in a new scope.
+    fn block(&mut self, block: &Block, span: Span) {
+        self.enter_block(Vec::new(), &block.stmts, span);
+        self.stmts(&block.stmts);
+        self.pop_scope();
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        let span = stmt.span;
+        self.emit(Op::Step, span);
+        match &stmt.kind {
+            StmtKind::Goto(expr) => {
+                self.expr(expr);
+                self.emit(Op::Goto, span);
+            }
+            StmtKind::TryCatch(try_block, catch_lit, finally_block) => {
+                let finally = finally_block
+                    .as_ref()
+                    .map(|_| self.emit(Op::Finally(0), span));
+                let catch = catch_lit.as_ref().map(|_| self.emit(Op::Catch(0), span));
+                self.block(try_block, span);
+                if let (Some(catch), Some(lit)) = (catch, catch_lit) {
+                    self.emit(Op::PopRegion, span);
+                    let skip = self.emit(Op::Jump(0), span);
+                    self.patch(catch);
+                    let bound = lit.binding.iter().cloned().collect();
+                    self.enter_block(bound, &lit.block.stmts, span);
+                    match &lit.binding {
+                        Some(ident) => self.emit(Op::Bind(self.local(ident)), span),
+                        None => self.emit(Op::Pop, span),
+                    };
+                    self.stmts(&lit.block.stmts);
+                    self.pop_scope();
+                    self.patch(skip);
+                }
+                if let (Some(finally), Some(block)) = (finally, finally_block) {
+                    self.emit(Op::PopRegion, span);
+                    self.emit(Op::EnterFinally, span);
+                    self.patch(finally);
+                    self.block(block, span);
+                    self.emit(Op::EndFinally, span);
+                }
+            }
+            StmtKind::Throw(expr) => {
+                self.expr(expr);
+                self.emit(Op::Throw, span);
+            }
+            StmtKind::Scrape(ty, hash) => {
+                self.map(hash, span);
+                match ty {
+                    Some(ty) => {
+                        let name = self.name(ty);
+                        self.emit(Op::ScrapeAs(name), span)
+                    }
+                    None => self.emit(Op::Scrape, span),
+                };
+            }
+            StmtKind::Expr(expr) => {
+                self.expr(expr);
+                self.emit(Op::CheckControl, span);
+                self.emit(Op::Pop, span);
+            }
+            StmtKind::ForLoop(floop) => self.for_loop(floop, span),
+            StmtKind::WhileLoop(cond, block) => {
+                let region = self.emit(Op::Loop(0, self.here() + 1), span);
+                let head = self.here();
+                self.expr(cond);
+                let done = self.emit(Op::JumpIfFalse(0), span);
+                self.emit(Op::Step, span);
+                self.block(block, span);
+                self.emit(Op::Jump(head), span);
+                self.patch(done);
+                self.emit(Op::PopRegion, span);
+                self.patch(region);
+            }
+            StmtKind::Assign(lhs, rhs, global, _) => self.assign(lhs, rhs, *global, span),
+            StmtKind::Let(lhs, rhs, constant, _) => {
+                self.expr(rhs);
+                let Ok(idents) = assign_targets(lhs) else {
+                    self.emit(Op::Pop, span);
+                    return self.raise(Raise::InvalidAssign, span);
+                };
+                if idents.len() != 1 {
+                    self.emit(Op::Unpack(idents.len() as u32), span);
+                }
+                for ident in &idents {
+                    if self.at_top() {
+                        let ident = self.ident(ident);
+                        self.emit(Op::DeclareGlobal(ident, *constant), span);
+                    } else {
+                        self.emit(Op::Declare(self.local(ident), *constant), span);
+                    }
+                }
+            }
+            StmtKind::Screenshot(path) => {
+                let consts = &mut self.state_mut().proto.consts;
+                consts.push(Arc::new(Object::Str(path.clone())));
+                let idx = consts.len() as u32 - 1;
+                self.emit(Op::Screenshot(idx), span);
+            }
+            StmtKind::IfElse(lit) => self.if_else(lit, span),
+            StmtKind::Func(def, global) => {
+                let FuncDef {
+                    ident,
+                    params,
+                    body,
+                    ..
+                } = def;
+                let proto = self.function(Some(ident), params, body, span);
+                self.emit(Op::Closure(proto), span);
+                self.store(ident, *global, span);
+            }
+            StmtKind::TypeDecl(decl) => {
+                self.constant(Object::Type(decl.clone()), span);
+                self.store(&decl.ident, false, span);
+            }
+            StmtKind::Break => {
+                self.emit(Op::Break, span);
+            }
+            StmtKind::Continue => {
+                self.emit(Op::Continue, span);
+            }
+            StmtKind::Return(expr) => {
+                match expr {
+                    Some(expr) => self.expr(expr),
+                    None => self.constant(Object::Null, span),
+                }
+                self.emit(Op::Return, span);
+            }
+            StmtKind::Use(path) => match use_names(path) {
+                Some((ident, parent)) => {
+                    let top = self.at_top();
+                    let globals = match top {
+                        true => Vec::new(),
+                        false => self.scope_globals().clone(),
+                    };
+                    let site = UseSite {
+                        path: path.clone(),
+                        ident: self.name(&ident),
+                        parent: parent.map(|p| self.name(&p)),
+                        top,
+                        globals: globals.iter().map(|g| self.name(g)).collect(),
+                    };
+                    let uses = &mut self.state_mut().proto.uses;
+                    uses.push(site);
+                    let idx = uses.len() as u32 - 1;
+                    self.emit(Op::Use(idx), span);
+                }
+                None => self.raise(Raise::UnknownModule, span),
+            },
+            StmtKind::Crawl(lit) => self.crawl(lit, span),
+        }
+    }
+
+    /// Pops a value into `ident`, or registers it as a global of the
+    /// module with `global`. Below the top level a global is assigned as
+    /// usual and only passed on by the `use`s in the same scope.
+    fn store(&mut self, ident: &Identifier, global: bool, span: Span) {
+        if global && self.at_top() {
+            let ident = self.ident(ident);
+            self.emit(Op::AddGlobal(ident), span);
+        } else {
+            if global {
+                self.scope_globals().push(ident.clone());
+            }
+            let name = self.name(ident);
+            self.emit(Op::Store(name), span);
+        }
+    }
+
+    fn assign(&mut self, lhs: &Expr, rhs: &Expr, global: bool, span: Span) {
+        self.expr(rhs);
+        match &lhs.kind {
+            ExprKind::Infix(container, t, idx) if t.kind == TokenKind::LBracket => {
+                self.expr(idx);
+                self.expr(container);
+                self.emit(Op::SetIndex, span);
+            }
+            ExprKind::Field(obj, field) => {
+                self.expr(obj);
+                let field = self.ident(field);
+                self.emit(Op::SetField(field), span);
+            }
+            ExprKind::Ident(ident) => self.store(ident, global, span),
+            ExprKind::List(_) => match assign_targets(lhs) {
+                Ok(idents) => {
+                    if idents.len() != 1 {
+                        self.emit(Op::Unpack(idents.len() as u32), span);
+                    }
+                    for ident in &idents {
+                        self.store(ident, false, span);
+                    }
+                }
+                Err(_) => {
+                    self.emit(Op::Pop, span);
+                    self.raise(Raise::InvalidAssign, span);
+                }
+            },
+            _ => {
+                self.emit(Op::Pop, span);
+                self.raise(Raise::InvalidAssign, span);
+            }
+        }
+    }
+
+    fn for_loop(&mut self, floop: &ForLoop, span: Span) {
+        self.expr(&floop.iterable);
+        let region = self.emit(Op::Iter(0, self.here() + 1), span);
+        let head = self.here();
+        let next = self.emit(Op::Next(0), span);
+        self.enter_block(floop.idents.clone(), &floop.block.stmts, span);
+        if floop.idents.len() != 1 {
+            self.emit(Op::Unpack(floop.idents.len() as u32), span);
+        }
+        for ident in &floop.idents {
+            self.emit(Op::Bind(self.local(ident)), span);
+        }
+        self.stmts(&floop.block.stmts);
+        self.pop_scope();
+        self.emit(Op::Jump(head), span);
+        self.patch(next);
+        self.patch(region);
+    }
+
+    fn if_else(&mut self, lit: &IfElseLiteral, span: Span) {
+        let mut ends = Vec::new();
+        for branch in std::iter::once(&lit.if_lit).chain(&lit.elifs) {
+            self.expr(&branch.cond);
+            let skip = self.emit(Op::JumpIfFalse(0), span);
+            self.block(&branch.block, span);
+            ends.push(self.emit(Op::Jump(0), span));
+            self.patch(skip);
+        }
+        if let Some(else_lit) = &lit.else_lit {
+            self.block(&else_lit.block, span);
+        }
+        for end in ends {
+            self.patch(end);
+        }
+    }
+
+    fn crawl(&mut self, lit: &CrawlLiteral, span: Span) {
+        let crawls = &mut self.state_mut().proto.crawls;
+        crawls.push(CrawlSite {
+            next: 0,
+            descend: 0,
+            exit: 0,
+        });
+        let site = crawls.len() as u32 - 1;
+        self.emit(Op::Crawl(site), span);
+
+        let next = self.here();
+        self.emit(Op::CrawlNext, span);
+        let bound = match &lit.bindings {
+            Some(b) => vec![b.link.clone(), b.depth.clone()],
+            None => Vec::new(),
+        };
+        self.enter_block(bound, &lit.body.stmts, span);
+        match &lit.bindings {
+            Some(b) => {
+                self.emit(Op::Bind(self.local(&b.depth)), span);
+                self.emit(Op::Bind(self.local(&b.link)), span);
+            }
+            None => {
+                self.emit(Op::PopN(2), span);
+            }
+        }
+        match &lit.filter {
+            Some(filter) => self.expr(filter),
+            None => self.constant(Object::Boolean(true), span),
+        }
+        self.emit(Op::CrawlVisit, span);
+        self.stmts(&lit.body.stmts);
+        self.pop_scope();
+        let descend = self.here();
+        self.emit(Op::CrawlDescend, span);
+        let exit = self.here();
+        self.state_mut().proto.crawls[site as usize] = CrawlSite {
+            next,
+            descend,
+            exit,
+        };
+    }
+
+    /// Compiles a fn into a proto of the fn being compiled. Defaults that
+    /// are not literals become fns of their own, evaluated in the scope
+    /// the fn is defined in.
+    fn function(
+        &mut self,
+        name: Option<&Identifier>,
+        params: &[FnParam],
+        body: &Block,
+        span: Span,
+    ) -> u32 {
+        let defaults: Vec<ParamDefault> = params
+            .iter()
+            .map(|p| match &p.default {
+                None => ParamDefault::None,
+                Some(expr) => match Object::from_literal(&expr.kind) {
+                    Some(obj) => ParamDefault::Const(Arc::new(obj)),
+                    None => ParamDefault::Thunk(self.thunk(expr)),
+                },
+            })
+            .collect();
+
+        let mut names: Vec<Identifier> = params.iter().map(|p| p.ident.clone()).collect();
+        let mut assigned = Assigned::default();
+        for stmt in &body.stmts {
+            assigned.visit_stmt(stmt);
+        }
+        names.extend(assigned.0);
+        names.extend(declared(&body.stmts));
+
+        self.begin_fn(name, false, names);
+        let params = params
+            .iter()
+            .zip(defaults)
+            .map(|(p, default)| Param {
+                ident: p.ident.clone(),
+                kind: p.kind,
+                slot: self.local(&p.ident),
+                default,
+            })
+            .collect();
+        self.value_block(&body.stmts, span);
+        self.emit(Op::Ret, span);
+        let mut proto = self.end_fn();
+        proto.params = params;
+        self.add_proto(proto)
+    }
+
+    fn thunk(&mut self, expr: &Expr) -> u32 {
+        self.begin_fn(None, false, Vec::new());
+        self.expr(expr);
+        self.emit(Op::Ret, expr.span);
+        let proto = self.end_fn();
+        self.add_proto(proto)
+    }
+
+    /// Compiles the entries of a map literal, with computed keys pushed
+    /// before their values.
+    fn map(&mut self, hash: &HashLiteral, span: Span) {
+        let mut keys = Vec::new();
+        for (key, val) in &hash.pairs {
+            match key {
+                MapKey::Ident(ident) => keys.push(Some(ident.clone())),
+                MapKey::Computed(expr) => {
+                    self.expr(expr);
+                    self.emit(Op::CheckKey, expr.span);
+                    keys.push(None);
+                }
+            }
+            self.expr(val);
+        }
+        let maps = &mut self.state_mut().proto.maps;
+        maps.push(keys);
+        let idx = maps.len() as u32 - 1;
+        self.emit(Op::Map(idx), span);
+    }
+
+    fn call(&mut self, lit: &CallLiteral, piped: bool, method: bool, span: Span) {
+        for arg in &lit.args {
+            self.expr(arg);
+        }
+        let mut kwargs: Vec<Identifier> = Vec::new();
+        for kwarg in &lit.kwargs {
+            self.expr(&kwarg.expr);
+            if kwargs.contains(&kwarg.ident) {
+                return self.raise(Raise::DuplicateKwarg(kwarg.ident.clone()), span);
+            }
+            kwargs.push(kwarg.ident.clone());
+        }
+        let site = CallSite {
+            name: self.name(&lit.ident),
+            args: lit.args.len(),
+            kwargs,
+            piped,
+            builtin: BuiltinKind::is_from(&lit.ident.name),
+        };
+        let calls = &mut self.state_mut().proto.calls;
+        calls.push(site);
+        let idx = calls.len() as u32 - 1;
+        let op = if method {
+            Op::MethodCall(idx)
+        } else {
+            Op::Call(idx)
+        };
+        self.emit(op, span);
+    }
+
+    fn select(&mut self, selector: &str, scope: &Option<Identifier>, all: bool, span: Span) {
+        let site = SelectSite {
+            selector: selector.to_owned(),
+            scope: scope.as_ref().map(|s| self.name(s)),
+            all,
+        };
+        let selects = &mut self.state_mut().proto.selects;
+        selects.push(site);
+        let idx = selects.len() as u32 - 1;
+        self.emit(Op::Select(idx), span);
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        let span = expr.span;
+        match &expr.kind {
+            ExprKind::Str(s) => self.constant(Object::Str(s.clone()), span),
+            ExprKind::Number(n) => self.constant(Object::Number(*n), span),
+            ExprKind::Boolean(b) => self.constant(Object::Boolean(*b), span),
+            ExprKind::Null => self.constant(Object::Null, span),
+            ExprKind::Ident(ident) => {
+                let name = self.name(ident);
+                self.emit(Op::Load(name), span);
+            }
+            ExprKind::List(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+                self.emit(Op::List(items.len() as u32), span);
+            }
+            ExprKind::Interp(parts) => {
+                for part in parts {
+                    self.expr(part);
+                }
+                self.emit(Op::Interp(parts.len() as u32), span);
+            }
+            ExprKind::Map(hash) => self.map(hash, span),
+            ExprKind::Select(selector, scope) => self.select(selector, scope, false, span),
+            ExprKind::SelectAll(selector, scope) => self.select(selector, scope, true, span),
+            ExprKind::Call(lit) => self.call(lit, false, false, span),
+            ExprKind::Lambda(LambdaLiteral { params, body, .. }) => {
+                let proto = self.function(None, params, body, span);
+                self.emit(Op::Closure(proto), span);
+            }
+            ExprKind::Match(lit) => self.match_expr(lit, span),
+            ExprKind::Record(ty, hash) => {
+                let name = self.name(ty);
+                self.emit(Op::Type(name), span);
+                self.map(hash, span);
+                self.emit(Op::Record, span);
+            }
+            ExprKind::Conditional(cond, then, otherwise) => {
+                self.expr(cond);
+                let skip = self.emit(Op::JumpIfFalse(0), span);
+                self.expr(then);
+                let end = self.emit(Op::Jump(0), span);
+                self.patch(skip);
+                self.expr(otherwise);
+                self.patch(end);
+            }
+            ExprKind::Field(obj, field) => {
+                self.expr(obj);
+                let field = self.ident(field);
+                self.emit(Op::Field(field), span);
+            }
+            ExprKind::MethodCall(obj, lit) => {
+                self.expr(obj);
+                self.emit(Op::EnterMethod, span);
+                self.push_override();
+                self.call(lit, false, true, span);
+                self.pop_scope();
+                self.emit(Op::ExitModule, span);
+            }
+            ExprKind::Chain(exprs) | ExprKind::SafeChain(exprs) => {
+                let safe = matches!(expr.kind, ExprKind::SafeChain(_));
+                let mut skips = Vec::new();
+                for (i, link) in exprs.iter().enumerate() {
+                    if i > 0 && safe {
+                        skips.push(self.emit(Op::SkipIfNull(0), span));
+                    }
+                    match &link.kind {
+                        ExprKind::Call(lit) => self.call(lit, i > 0, false, link.span),
+                        _ => {
+                            if i > 0 {
+                                self.emit(Op::Pop, span);
+                            }
+                            self.expr(link);
+                        }
+                    }
+                }
+                if exprs.is_empty() {
+                    self.constant(Object::Null, span);
+                }
+                for skip in skips {
+                    self.patch(skip);
+                }
+            }
+            ExprKind::Infix(lhs, op, rhs) => {
+                self.expr(lhs);
+                let jump = match op.kind {
+                    TokenKind::DbQuestion => Op::Coalesce(0),
+                    TokenKind::And => Op::And(0),
+                    TokenKind::Or => Op::Or(0),
+                    TokenKind::DbColon => {
+                        self.emit(Op::EnterModule, span);
+                        self.push_override();
+                        self.expr(rhs);
+                        self.pop_scope();
+                        self.emit(Op::ExitModule, span);
+                        return;
+                    }
+                    kind => {
+                        self.expr(rhs);
+                        self.emit(Op::Infix(kind), span);
+                        return;
+                    }
+                };
+                let skip = self.emit(jump, span);
+                self.expr(rhs);
+                self.patch(skip);
+            }
+            ExprKind::Prefix(rhs, op) => {
+                self.expr(rhs);
+                self.emit(Op::Prefix(op.kind), span);
+            }
+        }
+    }
+
+    /// Each arm checks a copy of the subject. A failed check jumps to a
+    /// stub that pops whatever the pattern left on the stack and moves on
+    /// to the next arm.
+    fn match_expr(&mut self, lit: &MatchLiteral, span: Span) {
+        self.expr(&lit.subject);
+        let mut ends = Vec::new();
+        for arm in &lit.arms {
+            let mut names = Vec::new();
+            pattern_bindings(&arm.pattern, &mut names);
+            names.extend(declared(&arm.block.stmts));
+            let scope = self.alloc_scope(ScopeKind::Block, names);
+            self.init_slots(&scope, span);
+            self.emit(Op::Dup, span);
+            let mut fails = Vec::new();
+            self.pattern(&arm.pattern, &scope.names, 1, &mut fails, span);
+            self.state_mut().scopes.push(scope);
+
+            if let Some(guard) = &arm.guard {
+                self.expr(guard);
+                fails.push((self.emit(Op::JumpIfFalse(0), span), 0));
+            }
+            let region = self.emit(Op::Match(0), span);
+            self.value_block(&arm.block.stmts, span);
+            self.emit(Op::PopRegion, span);
+            self.patch(region);
+            self.pop_scope();
+            ends.push(self.emit(Op::Jump(0), span));
+
+            let mut by_depth: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+            for (at, depth) in fails {
+                by_depth.entry(depth).or_default().push(at);
+            }
+            let mut next_arm = by_depth.remove(&0).unwrap_or_default();
+            for (depth, jumps) in by_depth {
+                for at in jumps {
+                    self.patch(at);
+                }
+                self.emit(Op::PopN(depth), span);
+                next_arm.push(self.emit(Op::Jump(0), span));
+            }
+            for at in next_arm {
+                self.patch(at);
+            }
+        }
+        self.constant(Object::Null, span);
+        for end in ends {
+            self.patch(end);
+        }
+        self.emit(Op::PopUnder, span);
+    }
+
+    /// Matches the value on top of the stack, which has `depth - 1` values
+    /// pushed by the pattern under it, recording the jumps taken on failure
+    /// along with how many values are left to pop.
+    fn pattern(
+        &mut self,
+        pattern: &Pattern,
+        slots: &HashMap<String, u32>,
+        depth: u32,
+        fails: &mut Vec<(usize, u32)>,
+        span: Span,
+    ) {
+        match pattern {
+            Pattern::Wildcard => {
+                self.emit(Op::Pop, span);
+            }
+            Pattern::Binding(ident) => {
+                self.emit(Op::Bind(slots[&ident.name]), span);
+            }
+            Pattern::Literal(expr) => {
+                self.expr(expr);
+                self.emit(Op::Infix(TokenKind::EQ), span);
+                fails.push((self.emit(Op::JumpIfFalse(0), span), depth - 1));
+            }
+            Pattern::List(pats) => {
+                let n = pats.len() as u32;
+                fails.push((self.emit(Op::PatList(n, 0), span), depth - 1));
+                for (i, pat) in pats.iter().enumerate() {
+                    self.pattern(pat, slots, depth - 1 + n - i as u32, fails, span);
+                }
+            }
+            Pattern::Map(pairs) => {
+                fails.push((self.emit(Op::PatMap(0), span), depth));
+                for (key, pat) in pairs {
+                    let key = self.ident(key);
+                    fails.push((self.emit(Op::PatKey(key, 0), span), depth));
+                    self.pattern(pat, slots, depth + 1, fails, span);
+                }
+                self.emit(Op::Pop, span);
+            }
+        }
+    }
+}
+
+/// The names declared by the `let`s and `const`s directly in a block.
+fn declared(stmts: &[Stmt]) -> Vec<Identifier> {
+    stmts
+        .iter()
+        .filter_map(|stmt| match &stmt.kind {
+            StmtKind::Let(lhs, ..) => assign_targets(lhs).ok(),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+fn pattern_bindings(pattern: &Pattern, out: &mut Vec<Identifier>) {
+    match pattern {
+        Pattern::Binding(ident) => out.push(ident.clone()),
+        Pattern::List(pats) => pats.iter().for_each(|p| pattern_bindings(p, out)),
+        Pattern::Map(pairs) => pairs.iter().for_each(|(_, p)| pattern_bindings(p, out)),
+        Pattern::Wildcard | Pattern::Literal(_) => {}
+    }
+}
+
+/// The name a `use` binds, and the module it imports from for `a::b`.
+fn use_names(path: &Expr) -> Option<(Identifier, Option<Identifier>)> {
+    match &path.kind {
+        ExprKind::Ident(ident) => Some((ident.clone(), None)),
+        ExprKind::Infix(lhs, _, rhs) => {
+            let ExprKind::Ident(ident) = &rhs.kind else {
+                return None;
+            };
+            let parent = match &lhs.kind {
+                ExprKind::Ident(parent) => Some(parent.clone()),
+                ExprKind::Infix(_, _, parent) => match &parent.kind {
+                    ExprKind::Ident(parent) => Some(parent.clone()),
+                    _ => None,
+                },
+                _ => None,
+            };
+            Some((ident.clone(), parent))
+        }
+        _ => None,
+    }
+}
+
+/// Collects the names a fn body may assign to, which belong to the fn's
+/// scope unless they are bound further out. Nested fns have scopes of
+/// their own, so are not descended into.
+#[derive(Default)]
+struct Assigned(Vec<Identifier>);
+
+impl<'ast> Visitor<'ast> for Assigned {
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        match &stmt.kind {
+            StmtKind::Assign(lhs, ..) => self.0.extend(assign_targets(lhs).unwrap_or_default()),
+            StmtKind::Func(def, _) => self.0.push(def.ident.clone()),
+            StmtKind::TypeDecl(decl) => self.0.push(decl.ident.clone()),
+            StmtKind::Use(path) => {
+                if let Some((ident, parent)) = use_names(path) {
+                    self.0.push(ident);
+                    self.0.extend(parent);
+                }
+            }
+            _ => {}
+        }
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_fn_def(&mut self, _def: &'ast FuncDef) {}
+
+    fn visit_lambda(&mut self, _lambda: &'ast LambdaLiteral) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use scout_lexer::Lexer;
+    use scout_parser::Parser;
+    use test_case::test_case;
+
+    use super::*;
+
+    fn compile_src(input: &str) -> Proto {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("parses");
+        compile(&program)
+    }
+
+    fn name_locs<'a>(proto: &'a Proto, ident: &str) -> Vec<&'a [Loc]> {
+        proto
+            .names
+            .iter()
+            .filter(|n| n.ident.name == ident)
+            .map(|n| n.locs.as_slice())
+            .collect()
+    }
+
+    #[test_case("x = 1\nx", "x", &[&[Loc::Global]]; "module names")]
+    #[test_case("for x in [1] do\nx\nend", "x", &[&[Loc::Local(0), Loc::Global]]; "loop binding")]
+    #[test_case("m::f(x)", "x", &[&[Loc::Override(0), Loc::Global]]; "module rhs")]
+    fn test_main_names(input: &str, ident: &str, exp: &[&[Loc]]) {
+        let proto = compile_src(input);
+        assert_eq!(name_locs(&proto, ident), exp);
+    }
+
+    #[test_case("def f(a) do\na\nend", "a", &[&[Loc::Local(0), Loc::Global]]; "param")]
+    #[test_case("def f() do\nx = 1\nx\nend", "x", &[&[Loc::Local(0), Loc::Global]]; "assigned")]
+    #[test_case("def f() do\nlen\nend", "len", &[&[Loc::Global]]; "unbound")]
+    #[test_case(
+        "def f(a) do\nreturn fn() do\na\nend\nend",
+        "a",
+        &[&[Loc::Upvalue(0), Loc::Global]];
+        "captured"
+    )]
+    fn test_fn_names(input: &str, ident: &str, exp: &[&[Loc]]) {
+        let main = compile_src(input);
+        let mut proto = &main.protos[0];
+        while let Some(inner) = proto.protos.first() {
+            proto = inner;
+        }
+        assert_eq!(name_locs(proto, ident), exp);
+    }
+
+    #[test]
+    fn test_captured_slots_are_marked() {
+        let main = compile_src("def f(a, b) do\nreturn fn() do\na\nend\nend");
+        let f = &main.protos[0];
+        let captured: Vec<bool> = f.slots.iter().map(|s| s.captured).collect();
+        assert_eq!(captured, vec![true, false]);
+    }
+
+    #[test_case(r#"def f(a = 1, b = len("ab")) do end"#; "defaults")]
+    fn test_default_thunks(input: &str) {
+        let main = compile_src(input);
+        let f = &main.protos[1];
+        assert!(matches!(f.params[0].default, ParamDefault::Const(_)));
+        assert!(matches!(f.params[1].default, ParamDefault::Thunk(0)));
+    }
+
+    #[test]
+    fn test_compile_std() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../scout-lib");
+        for entry in dir.read_dir().unwrap().flatten() {
+            let content = std::fs::read_to_string(entry.path()).unwrap();
+            let proto = compile_src(&content);
+            assert_eq!(proto.code.len(), proto.spans.len());
+        }
+    }
+}
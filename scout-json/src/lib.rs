@@ -32,7 +32,7 @@ impl ScoutJSON {
     pub fn to_ast(&self) -> Program {
         let mut stmts = Vec::new();
         for step in &self.steps {
-            stmts.push(step.to_stmt());
+            stmts.push(step.to_stmt().into());
         }
 
         Program { stmts }
@@ -47,32 +47,32 @@ impl Step {
                 let lit = CallLiteral {
                     ident: Identifier::new("setViewport".to_string()),
                     args: vec![
-                        ExprKind::Number(*width as f64),
-                        ExprKind::Number(*height as f64),
+                        ExprKind::Number(*width as f64).into(),
+                        ExprKind::Number(*height as f64).into(),
                     ],
                     kwargs: Vec::new(),
                 };
-                StmtKind::Expr(ExprKind::Call(lit))
+                StmtKind::Expr(ExprKind::Call(lit).into())
             }
-            Navigate { url } => StmtKind::Goto(ExprKind::Str(url.clone())),
+            Navigate { url } => StmtKind::Goto(ExprKind::Str(url.clone()).into()),
             Click { selectors } => {
                 let elem = ExprKind::Select(selector_from_recorder_mtx(selectors.as_ref()), None);
                 let lit = CallLiteral {
                     ident: Identifier::new("click".to_string()),
-                    args: vec![elem],
+                    args: vec![elem.into()],
                     kwargs: Vec::new(),
                 };
-                StmtKind::Expr(ExprKind::Call(lit))
+                StmtKind::Expr(ExprKind::Call(lit).into())
             }
             Change { value, selectors } => {
                 let elem = ExprKind::Select(selector_from_recorder_mtx(selectors.as_ref()), None);
                 let val = ExprKind::Str(value.clone());
                 let lit = CallLiteral {
                     ident: Identifier::new("input".to_string()),
-                    args: vec![elem, val],
+                    args: vec![elem.into(), val.into()],
                     kwargs: Vec::new(),
                 };
-                StmtKind::Expr(ExprKind::Call(lit))
+                StmtKind::Expr(ExprKind::Call(lit).into())
             }
         }
     }
@@ -109,7 +109,7 @@ mod tests {
                 }
             ]
         }"#,
-        StmtKind::Goto(ExprKind::Str("https://stackoverflow.com/".to_string()));
+        StmtKind::Goto(ExprKind::Str("https://stackoverflow.com/".to_string()).into());
         "navigate step"
     )]
     #[test_case(
@@ -133,9 +133,9 @@ mod tests {
         }"##,
         StmtKind::Expr(ExprKind::Call(CallLiteral {
             ident: Identifier::new("click".to_string()),
-            args: vec![ExprKind::Select("#question-summary-78853169 h3 > a".to_string(), None)],
+            args: vec![ExprKind::Select("#question-summary-78853169 h3 > a".to_string(), None).into()],
             kwargs: Vec::new(),
-        }));
+        }).into());
         "click step"
     )]
     #[test_case(
@@ -151,11 +151,11 @@ mod tests {
         StmtKind::Expr(ExprKind::Call(CallLiteral {
             ident: Identifier::new("setViewport".to_string()),
             args: vec![
-                ExprKind::Number(1365.),
-                ExprKind::Number(945.),
+                ExprKind::Number(1365.).into(),
+                ExprKind::Number(945.).into(),
             ],
             kwargs: Vec::new(),
-        }));
+        }).into());
         "setViewport step"
     )]
     fn parse_step_json(input: &str, exp: StmtKind) {
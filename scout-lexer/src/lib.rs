@@ -1,30 +1,114 @@
+pub mod span;
 pub mod token;
 
+pub use span::Span;
 pub use token::{Token, TokenKind};
 
-#[derive(Default)]
 pub struct Lexer {
     input: Vec<char>,
     pos: usize,
     read_pos: usize,
+    // Byte offset, line and column of the char at `read_pos`.
+    byte: usize,
+    line: usize,
+    col: usize,
+    // Unclosed `{` count for each `${` interpolation currently being lexed.
+    interp: Vec<usize>,
+    comments: Vec<Comment>,
+    invalid_escapes: Vec<Token>,
+}
+
+/// A `//` comment, which the lexer skips but keeps for tools such as the
+/// formatter that need to reproduce it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    /// The text after the `//`, up to the end of the line.
+    pub text: String,
+    pub span: Span,
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Self {
         Self {
             input: input.chars().collect(),
-            ..Default::default()
+            pos: 0,
+            read_pos: 0,
+            byte: 0,
+            line: 1,
+            col: 1,
+            interp: Vec::new(),
+            comments: Vec::new(),
+            invalid_escapes: Vec::new(),
         }
     }
 
+    /// The comments skipped so far, in source order.
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    /// The malformed or unknown escapes in the strings lexed so far, as
+    /// `Illegal` tokens holding the escape as written. They are kept in
+    /// the string as written, so the parser can report them all at once.
+    pub fn invalid_escapes(&self) -> &[Token] {
+        &self.invalid_escapes
+    }
+
     pub fn next_token(&mut self) -> Token {
+        self.skip_trivia();
+        let (start, line, col) = (self.byte, self.line, self.col);
+        let mut token = self.read_token();
+        token.span = Span::new(start, self.byte, line, col);
+        token
+    }
+
+    /// Consumes whitespace and comments up to the start of the next token.
+    fn skip_trivia(&mut self) {
+        loop {
+            match self.peek().copied() {
+                Some(c) if c.is_whitespace() => {
+                    self.next();
+                }
+                Some('/') if self.input.get(self.read_pos + 1) == Some(&'/') => {
+                    let (start, line, col) = (self.byte, self.line, self.col);
+                    self.next();
+                    self.next();
+                    let mut text = String::new();
+                    while let Some(c) = self.peek().copied().filter(|c| *c != '\n') {
+                        text.push(c);
+                        self.next();
+                    }
+                    let span = Span::new(start, self.byte, line, col);
+                    self.comments.push(Comment { text, span });
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn read_token(&mut self) -> Token {
         use TokenKind::*;
-        match self.next() {
-            Some(c) => match *c {
+        match self.next().copied() {
+            Some(c) => match c {
                 '(' => Token::new(LParen, c.to_string()),
                 ')' => Token::new(RParen, c.to_string()),
-                '{' => Token::new(LBrace, c.to_string()),
-                '}' => Token::new(RBrace, c.to_string()),
+                '{' => {
+                    if let Some(depth) = self.interp.last_mut() {
+                        *depth += 1;
+                    }
+                    Token::new(LBrace, c.to_string())
+                }
+                '}' => match self.interp.last_mut() {
+                    Some(0) => {
+                        self.interp.pop();
+                        self.read_string(false)
+                    }
+                    Some(depth) => {
+                        *depth -= 1;
+                        Token::new(RBrace, c.to_string())
+                    }
+                    None => Token::new(RBrace, c.to_string()),
+                },
                 '[' => Token::new(LBracket, c.to_string()),
                 ']' => Token::new(RBracket, c.to_string()),
                 ':' => match self.peek() {
@@ -35,9 +119,30 @@ impl Lexer {
                     _ => Token::new(Colon, ':'.into()),
                 },
                 ',' => Token::new(Comma, c.to_string()),
+                '.' => Token::new(Dot, c.to_string()),
                 '+' => Token::new(Plus, c.to_string()),
-                '-' => Token::new(Minus, c.to_string()),
-                '*' => Token::new(Asterisk, c.to_string()),
+                '-' => match self.peek() {
+                    Some('>') => {
+                        self.next();
+                        Token::new(Arrow, "->".into())
+                    }
+                    _ => Token::new(Minus, c.to_string()),
+                },
+                '*' => match self.peek() {
+                    Some('*') => {
+                        self.next();
+                        Token::new(DbAsterisk, "**".into())
+                    }
+                    _ => Token::new(Asterisk, '*'.into()),
+                },
+                '%' => Token::new(Percent, c.to_string()),
+                '~' => match self.peek() {
+                    Some('/') => {
+                        self.next();
+                        Token::new(TildeSlash, "~/".into())
+                    }
+                    _ => Token::new(Illegal, '~'.into()),
+                },
                 '<' => match self.peek() {
                     Some('=') => {
                         self.next();
@@ -48,7 +153,7 @@ impl Lexer {
                 '>' => match self.peek() {
                     Some('=') => {
                         self.next();
-                        Token::new(GTE, "<=".to_string())
+                        Token::new(GTE, ">=".to_string())
                     }
                     _ => Token::new(GT, '>'.to_string()),
                 },
@@ -66,10 +171,14 @@ impl Lexer {
                     }
                     _ => Token::new(Bang, '!'.to_string()),
                 },
-                '"' => {
-                    let literal = self.read_string();
-                    Token::new(Str, literal)
+                '"' if self.peek() == Some(&'"')
+                    && self.input.get(self.read_pos + 1) == Some(&'"') =>
+                {
+                    self.next();
+                    self.next();
+                    Token::new(Str, self.read_raw_string())
                 }
+                '"' => self.read_string(true),
                 '|' => match self.peek() {
                     Some(pc) => match *pc {
                         '>' => {
@@ -81,6 +190,18 @@ impl Lexer {
                     },
                     None => Token::new(Illegal, '|'.to_string()),
                 },
+                '?' => match self.peek().copied() {
+                    Some('?') => {
+                        self.next();
+                        Token::new(DbQuestion, "??".into())
+                    }
+                    Some('|') if self.input.get(self.read_pos + 1) == Some(&'>') => {
+                        self.next();
+                        self.next();
+                        Token::new(SafePipe, "?|>".into())
+                    }
+                    _ => Token::new(Question, '?'.into()),
+                },
                 '$' => match self.peek() {
                     Some(c) => match *c {
                         '$' => {
@@ -91,24 +212,17 @@ impl Lexer {
                     },
                     None => Token::new(Illegal, '$'.to_string()),
                 },
-                '/' => match self.peek() {
-                    Some('/') => {
-                        while self.next() != Some(&'\n') {}
-
-                        self.next_token()
-                    }
-                    _ => Token::new(Slash, '/'.to_string()),
-                },
-                _ if c.is_whitespace() => self.next_token(),
+                '/' => Token::new(Slash, '/'.to_string()),
                 _ if c.is_numeric() => {
                     let lit = self.read_numeric();
-                    if lit.contains('.') {
+                    let is_hex = lit.starts_with("0x") || lit.starts_with("0X");
+                    if !is_hex && lit.contains(['.', 'e', 'E']) {
                         Token::new(Float, lit)
                     } else {
                         Token::new(Int, lit)
                     }
                 }
-                _ if is_valid_identifier(c) => {
+                _ if is_valid_identifier(&c) => {
                     let lit = self.read_identifier();
                     match TokenKind::is_to_keyword(&lit) {
                         Some(t) => Token::new(t, lit),
@@ -122,10 +236,18 @@ impl Lexer {
     }
 
     fn next(&mut self) -> Option<&char> {
-        let out = self.input.get(self.read_pos);
         self.pos = self.read_pos;
         self.read_pos += 1;
-        out
+        if let Some(c) = self.input.get(self.pos) {
+            self.byte += c.len_utf8();
+            if *c == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
+        }
+        self.input.get(self.pos)
     }
 
     fn peek(&mut self) -> Option<&char> {
@@ -141,32 +263,144 @@ impl Lexer {
         i.iter().collect()
     }
 
+    /// Reads decimal (`1_000`, `1.5`), scientific (`2.5e-3`) and
+    /// hex (`0xff`) literals. The literal is kept as written, with any
+    /// `_` separators, and is converted to a number by the parser.
     fn read_numeric(&mut self) -> String {
         let start = self.pos;
         let mut i = vec![self.input[start]];
+        if self.input[start] == '0' && matches!(self.peek(), Some('x' | 'X')) {
+            i.push(*self.next().unwrap());
+            while self
+                .peek()
+                .is_some_and(|c| c.is_ascii_hexdigit() || *c == '_')
+            {
+                i.push(*self.next().unwrap());
+            }
+            return i.iter().collect();
+        }
+
         while self.peek().is_some() && is_valid_numeric(self.peek().unwrap()) {
             i.push(*self.next().unwrap());
         }
+
+        // Only treat `e` as an exponent when digits follow it, so `2e`
+        // still lexes as a number followed by an identifier.
+        if matches!(self.peek(), Some('e' | 'E')) {
+            let digit_at = match self.input.get(self.read_pos + 1) {
+                Some('+' | '-') => self.read_pos + 2,
+                _ => self.read_pos + 1,
+            };
+            if self.input.get(digit_at).is_some_and(|c| c.is_ascii_digit()) {
+                while self.read_pos < digit_at {
+                    i.push(*self.next().unwrap());
+                }
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || *c == '_') {
+                    i.push(*self.next().unwrap());
+                }
+            }
+        }
         i.iter().collect()
     }
 
-    fn read_string(&mut self) -> String {
-        let mut i: Vec<char> = Vec::new();
+    /// Reads string contents up to the closing `"`, decoding escapes. If a
+    /// `${` is found the string is interpolated, so the text so far is
+    /// returned and lexing continues with the embedded expression until
+    /// its closing `}`, which resumes the string.
+    fn read_string(&mut self, start: bool) -> Token {
+        use TokenKind::*;
+        let mut i = String::new();
         loop {
-            match self.peek() {
-                Some(c) if *c == '"' => {
-                    let _ = self.next();
-                    break;
+            match self.next().copied() {
+                Some('"') | None => {
+                    let kind = if start { Str } else { InterpEnd };
+                    return Token::new(kind, i);
                 }
-                Some(_) => {
-                    i.push(*self.next().unwrap());
+                Some('$') if self.peek() == Some(&'{') => {
+                    self.next();
+                    self.interp.push(0);
+                    let kind = if start { InterpStart } else { InterpMid };
+                    return Token::new(kind, i);
+                }
+                Some('\\') => self.read_escape(&mut i),
+                Some(c) => i.push(c),
+            }
+        }
+    }
+
+    /// Decodes the escape after a `\`. Malformed and unknown escapes are
+    /// kept as written and recorded in [`Lexer::invalid_escapes`].
+    fn read_escape(&mut self, out: &mut String) {
+        // The `\` has already been read.
+        let start = (self.pos, self.byte - 1, self.line, self.col - 1);
+        let decoded = match self.next().copied() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some(c @ ('"' | '\\' | '$')) => c,
+            Some('u') if self.peek() == Some(&'{') => {
+                let rest = &self.input[self.read_pos..];
+                // Without a `}` before the end of the string or line, the
+                // escape only covers the `\u`.
+                let close = rest
+                    .iter()
+                    .position(|c| matches!(c, '}' | '"' | '\n'))
+                    .filter(|len| rest[*len] == '}');
+                if let Some(len) = close {
+                    let hex: String = rest[1..len].iter().collect();
+                    for _ in 0..=len {
+                        self.next();
+                    }
+                    match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                        Some(c) => c,
+                        None => return self.invalid_escape(out, start),
+                    }
+                } else {
+                    return self.invalid_escape(out, start);
                 }
-                None => {
+            }
+            Some(_) => return self.invalid_escape(out, start),
+            // The string is unterminated, which the parser reports.
+            None => {
+                out.push('\\');
+                return;
+            }
+        };
+        out.push(decoded);
+    }
+
+    /// Records the escape read since `start` as invalid and keeps it in
+    /// the string as written.
+    fn invalid_escape(&mut self, out: &mut String, start: (usize, usize, usize, usize)) {
+        let (pos, byte, line, col) = start;
+        let text: String = self.input[pos..=self.pos].iter().collect();
+        out.push_str(&text);
+        let mut token = Token::new(TokenKind::Illegal, text);
+        token.span = Span::new(byte, self.byte, line, col);
+        self.invalid_escapes.push(token);
+    }
+
+    /// Reads a `"""` string verbatim, without escapes or interpolation,
+    /// so inline JS and JSON can be pasted as is. A newline directly after
+    /// the opening quotes is dropped.
+    fn read_raw_string(&mut self) -> String {
+        if self.peek() == Some(&'\n') {
+            self.next();
+        }
+        let mut i = String::new();
+        loop {
+            match self.next().copied() {
+                Some('"') if self.input[self.read_pos..].starts_with(&['"', '"']) => {
+                    self.next();
+                    self.next();
                     break;
                 }
-            };
+                Some(c) => i.push(c),
+                None => break,
+            }
         }
-        i.iter().collect()
+        i
     }
 }
 
@@ -175,7 +409,7 @@ fn is_valid_identifier(c: &char) -> bool {
 }
 
 fn is_valid_numeric(c: &char) -> bool {
-    c.is_numeric() || *c == '.'
+    c.is_numeric() || *c == '.' || *c == '_'
 }
 
 #[cfg(test)]
@@ -190,8 +424,19 @@ mod tests {
     #[test_case("for x", vec!(Token::new(For, "for".into()), Token::new(Ident, "x".into())))]
     #[test_case("\"x\"", vec!(Token::new(Str, "x".into())))]
     #[test_case("|>", vec!(Token::new(Pipe, "|>".into())))]
+    #[test_case("a.b(1.5)", vec!(Token::new(Ident, "a".into()), Token::new(Dot, ".".into()), Token::new(Ident, "b".into()), Token::new(LParen, "(".into()), Token::new(Float, "1.5".into()), Token::new(RParen, ")".into())); "dot access")]
+    #[test_case("a ?|> b ?? c ?", vec!(Token::new(Ident, "a".into()), Token::new(SafePipe, "?|>".into()), Token::new(Ident, "b".into()), Token::new(DbQuestion, "??".into()), Token::new(Ident, "c".into()), Token::new(Question, "?".into())); "null safe operators")]
+    #[test_case("a->b - c", vec!(Token::new(Ident, "a".into()), Token::new(Arrow, "->".into()), Token::new(Ident, "b".into()), Token::new(Minus, "-".into()), Token::new(Ident, "c".into())); "arrow")]
     #[test_case(r#"$".div" $"a""#, vec!(Token::new(Select, "$".into()), Token::new(Str, ".div".into()), Token::new(Select, "$".into()), Token::new(Str, "a".into())))]
     #[test_case(r#"$$".div""#, vec!(Token::new(SelectAll, "$$".into()), ))]
+    #[test_case("1_000 2.5e-3 1E6 0xFF", vec!(Token::new(Int, "1_000".into()), Token::new(Float, "2.5e-3".into()), Token::new(Float, "1E6".into()), Token::new(Int, "0xFF".into())); "numeric literals")]
+    #[test_case("2e x", vec!(Token::new(Int, "2".into()), Token::new(Ident, "e".into()), Token::new(Ident, "x".into())); "e without exponent digits")]
+    #[test_case("a ** b ~/ c % d", vec!(Token::new(Ident, "a".into()), Token::new(DbAsterisk, "**".into()), Token::new(Ident, "b".into()), Token::new(TildeSlash, "~/".into()), Token::new(Ident, "c".into()), Token::new(Percent, "%".into()), Token::new(Ident, "d".into())); "arithmetic operators")]
+    #[test_case(r#""a\"b\\c\n\u{e006}\$""#, vec!(Token::new(Str, "a\"b\\c\n\u{e006}$".into())); "string escapes")]
+    #[test_case(r#""\d \u{zz}""#, vec!(Token::new(Str, "\\d \\u{zz}".into())); "invalid escapes kept")]
+    #[test_case("\"\"\"\nlet a = \"${x}\\n\";\n\"\"\" x", vec!(Token::new(Str, "let a = \"${x}\\n\";\n".into()), Token::new(Ident, "x".into())); "triple quoted raw string")]
+    #[test_case(r#""page ${n} of ${m["a"]}!""#, vec!(Token::new(InterpStart, "page ".into()), Token::new(Ident, "n".into()), Token::new(InterpMid, " of ".into()), Token::new(Ident, "m".into()), Token::new(LBracket, "[".into()), Token::new(Str, "a".into()), Token::new(RBracket, "]".into()), Token::new(InterpEnd, "!".into()), Token::new(EOF, "".into())); "interpolated string")]
+    #[test_case(r#""${ {a: 1} }" {}"#, vec!(Token::new(InterpStart, "".into()), Token::new(LBrace, "{".into()), Token::new(Ident, "a".into()), Token::new(Colon, ":".into()), Token::new(Int, "1".into()), Token::new(RBrace, "}".into()), Token::new(InterpEnd, "".into()), Token::new(LBrace, "{".into()), Token::new(RBrace, "}".into())); "braces inside interpolation")]
     #[test_case(r#"$(x)"a""#, vec!(Token::new(Select, "$".into()), Token::new(LParen, '('.into()), Token::new(Ident, "x".into()), Token::new(RParen, ')'.into())))]
     fn test_token(input: &str, exp: Vec<Token>) {
         let mut l = Lexer::new(input);
@@ -200,4 +445,45 @@ mod tests {
             assert_eq!(t, *tt);
         }
     }
+
+    #[test_case("x", vec![Span::new(0, 1, 1, 1)]; "single token span")]
+    #[test_case("for x\n  y", vec![Span::new(0, 3, 1, 1), Span::new(4, 5, 1, 5), Span::new(8, 9, 2, 3)]; "multi line spans")]
+    #[test_case("// c\n\"é\" x", vec![Span::new(5, 9, 2, 1), Span::new(10, 11, 2, 5)]; "spans after comment and multibyte")]
+    #[test_case("x // trailing", vec![Span::new(0, 1, 1, 1), Span::new(13, 13, 1, 14)]; "trailing comment at eof")]
+    fn test_token_span(input: &str, exp: Vec<Span>) {
+        let mut l = Lexer::new(input);
+        for span in exp.iter() {
+            let t = l.next_token();
+            assert_eq!(t.span, *span);
+        }
+    }
+
+    #[test_case(r#""\q""#, vec![("\\q", Span::new(1, 3, 1, 2))]; "unknown escape")]
+    #[test_case(r#""a\u{zz}" "\u{110000}""#, vec![("\\u{zz}", Span::new(2, 8, 1, 3)), ("\\u{110000}", Span::new(11, 21, 1, 12))]; "invalid code points")]
+    #[test_case(r#""\u{d800}\u{}""#, vec![("\\u{d800}", Span::new(1, 9, 1, 2)), ("\\u{}", Span::new(9, 13, 1, 10))]; "surrogate and empty code points")]
+    #[test_case(r#""\u{41" \u"#, vec![("\\u", Span::new(1, 3, 1, 2))]; "unclosed code point")]
+    #[test_case(r#""\n\u{e006}\$""#, vec![]; "valid escapes")]
+    fn test_invalid_escapes(input: &str, exp: Vec<(&str, Span)>) {
+        let mut l = Lexer::new(input);
+        while l.next_token().kind != EOF {}
+        let escapes: Vec<(&str, Span)> = l
+            .invalid_escapes()
+            .iter()
+            .map(|t| (t.literal.as_str(), t.span))
+            .collect();
+        assert_eq!(escapes, exp);
+    }
+
+    #[test_case("x // a\n// b\ny", vec![(" a", Span::new(2, 6, 1, 3)), (" b", Span::new(7, 11, 2, 1))]; "line and trailing comments")]
+    #[test_case("\"// not a comment\" //", vec![("", Span::new(19, 21, 1, 20))]; "comment markers in strings")]
+    fn test_comments(input: &str, exp: Vec<(&str, Span)>) {
+        let mut l = Lexer::new(input);
+        while l.next_token().kind != EOF {}
+        let comments: Vec<(&str, Span)> = l
+            .comments()
+            .iter()
+            .map(|c| (c.text.as_str(), c.span))
+            .collect();
+        assert_eq!(comments, exp);
+    }
 }
@@ -1,4 +1,8 @@
-#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+use serde::{Deserialize, Serialize};
+
+use crate::Span;
+
+#[derive(Debug, Clone, PartialEq, Eq, Copy, Serialize, Deserialize)]
 pub enum TokenKind {
     Illegal,
     EOF,
@@ -6,10 +10,20 @@ pub enum TokenKind {
     Int,
     Float,
     Str,
+    /// The pieces of an interpolated string `"a ${x} b ${y} c"`: `InterpStart`
+    /// holds `a `, `InterpMid` holds ` b ` and `InterpEnd` holds ` c`.
+    InterpStart,
+    InterpMid,
+    InterpEnd,
 
     Comma,
     Colon,
+    Dot,
     Pipe,
+    SafePipe,
+    Arrow,
+    Question,
+    DbQuestion,
     LParen,
     RParen,
     LBrace,
@@ -24,7 +38,10 @@ pub enum TokenKind {
     Plus,
     Minus,
     Asterisk,
+    DbAsterisk,
     Slash,
+    TildeSlash,
+    Percent,
     GT,
     LT,
     GTE,
@@ -39,6 +56,7 @@ pub enum TokenKind {
     For,
     In,
     Do,
+    Then,
     End,
     Goto,
     Scrape,
@@ -46,18 +64,26 @@ pub enum TokenKind {
     True,
     False,
     Def,
+    Fn,
     Null,
     Return,
     Use,
     Try,
     Catch,
+    Finally,
+    Match,
+    Case,
     Throw,
+    Break,
+    Continue,
     Crawl,
     Where,
     And,
     Or,
     While,
     Global,
+    Let,
+    Const,
 }
 
 impl TokenKind {
@@ -72,6 +98,7 @@ impl TokenKind {
             "elif" => Some(Elif),
             "else" => Some(Else),
             "do" => Some(Do),
+            "then" => Some(Then),
             "end" => Some(End),
             "goto" => Some(Goto),
             "scrape" => Some(Scrape),
@@ -79,29 +106,144 @@ impl TokenKind {
             "true" => Some(True),
             "false" => Some(False),
             "def" => Some(Def),
+            "fn" => Some(Fn),
             "null" => Some(Null),
             "return" => Some(Return),
             "use" => Some(Use),
             "try" => Some(Try),
             "catch" => Some(Catch),
+            "finally" => Some(Finally),
+            "match" => Some(Match),
+            "case" => Some(Case),
             "crawl" => Some(Crawl),
             "throw" => Some(Throw),
+            "break" => Some(Break),
+            "continue" => Some(Continue),
             "and" => Some(And),
             "or" => Some(Or),
             "global" => Some(Global),
+            "let" => Some(Let),
+            "const" => Some(Const),
             _ => None,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+impl std::fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use TokenKind::*;
+        let s = match self {
+            Illegal => "illegal token",
+            EOF => "end of file",
+            Ident => "identifier",
+            Int | Float => "number",
+            Str => "string",
+            InterpStart | InterpMid => "interpolated string",
+            InterpEnd => "`}`",
+            Comma => "`,`",
+            Colon => "`:`",
+            Dot => "`.`",
+            Pipe => "`|>`",
+            SafePipe => "`?|>`",
+            Arrow => "`->`",
+            Question => "`?`",
+            DbQuestion => "`??`",
+            LParen => "`(`",
+            RParen => "`)`",
+            LBrace => "`{`",
+            RBrace => "`}`",
+            LBracket => "`[`",
+            RBracket => "`]`",
+            Select => "`$`",
+            SelectAll => "`$$`",
+            Assign => "`=`",
+            EQ => "`==`",
+            NEQ => "`!=`",
+            Plus => "`+`",
+            Minus => "`-`",
+            Asterisk => "`*`",
+            DbAsterisk => "`**`",
+            Slash => "`/`",
+            TildeSlash => "`~/`",
+            Percent => "`%`",
+            GT => "`>`",
+            LT => "`<`",
+            GTE => "`>=`",
+            LTE => "`<=`",
+            Bang => "`!`",
+            DbColon => "`::`",
+            If => "`if`",
+            Elif => "`elif`",
+            Else => "`else`",
+            For => "`for`",
+            In => "`in`",
+            Do => "`do`",
+            Then => "`then`",
+            End => "`end`",
+            Goto => "`goto`",
+            Scrape => "`scrape`",
+            Screenshot => "`screenshot`",
+            True => "`true`",
+            False => "`false`",
+            Def => "`def`",
+            Fn => "`fn`",
+            Null => "`null`",
+            Return => "`return`",
+            Use => "`use`",
+            Try => "`try`",
+            Catch => "`catch`",
+            Finally => "`finally`",
+            Match => "`match`",
+            Case => "`case`",
+            Throw => "`throw`",
+            Break => "`break`",
+            Continue => "`continue`",
+            Crawl => "`crawl`",
+            Where => "`where`",
+            And => "`and`",
+            Or => "`or`",
+            While => "`while`",
+            Global => "`global`",
+            Let => "`let`",
+            Const => "`const`",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[derive(Debug, Clone, Eq, Serialize, Deserialize)]
 pub struct Token {
     pub kind: TokenKind,
     pub literal: String,
+    pub span: Span,
 }
 
 impl Token {
     pub fn new(kind: TokenKind, literal: String) -> Self {
-        Self { kind, literal }
+        Self {
+            kind,
+            literal,
+            span: Span::default(),
+        }
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use TokenKind::*;
+        match self.kind {
+            Ident | Int | Float => write!(f, "{} `{}`", self.kind, self.literal),
+            Str => write!(f, "string \"{}\"", self.literal),
+            Illegal => write!(f, "illegal token `{}`", self.literal),
+            _ => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+/// Spans are positional metadata, so two tokens are equal
+/// whenever their kind and literal match.
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.literal == other.literal
     }
 }
@@ -0,0 +1,769 @@
+//! Lints for ScoutLang scripts that can be found from the AST alone,
+//! without starting a browser.
+//!
+//! Names are resolved per fn scope rather than in program order, so a name
+//! is defined for its whole scope once it is assigned anywhere in it. Each
+//! scope is walked completely before the fns nested in it, which lets
+//! assignments inside a fn to a name from an outer scope be treated the
+//! way the interpreter does, as updates to the outer binding. Names
+//! declared with `let` or `const` and loop, catch and match bindings only
+//! resolve inside the block they are declared in.
+//!
+//! Top level fns, constants and variables are never reported as unused,
+//! since a file may be a module that exists to export them.
+
+use std::path::Path;
+
+use indexmap::IndexMap;
+use scout_interpreter::{builtin::BuiltinKind, import::resolve_module};
+use scout_lexer::{Span, TokenKind};
+use scout_parser::{
+    ast::{
+        Block, CallLiteral, Expr, ExprKind, FnParam, HashLiteral, Identifier, MapKey, MatchLiteral,
+        ParamKind, Pattern, Program, Stmt, StmtKind,
+    },
+    diagnostic::{Diagnostic, Severity},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintKind {
+    UndefinedIdent,
+    UnusedVariable,
+    UnusedFn,
+    Unreachable,
+    ShadowedBuiltin,
+    WrongArity,
+    UnresolvedImport,
+}
+
+impl LintKind {
+    /// A short, stable name for the lint.
+    pub fn name(&self) -> &'static str {
+        use LintKind::*;
+        match self {
+            UndefinedIdent => "undefined_ident",
+            UnusedVariable => "unused_variable",
+            UnusedFn => "unused_fn",
+            Unreachable => "unreachable",
+            ShadowedBuiltin => "shadowed_builtin",
+            WrongArity => "wrong_arity",
+            UnresolvedImport => "unresolved_import",
+        }
+    }
+
+    /// Undefined names and imports fail at runtime, the rest are
+    /// likely mistakes.
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::UndefinedIdent | Self::UnresolvedImport => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+
+    fn hint(&self) -> Option<&'static str> {
+        use LintKind::*;
+        match self {
+            UnusedVariable | UnusedFn => Some("prefix the name with `_` if this is intended"),
+            ShadowedBuiltin => Some("rename it to keep the builtin callable"),
+            UnresolvedImport => Some("modules are resolved from the current directory"),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub kind: LintKind,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Lint {
+    fn new(kind: LintKind, message: String, span: Span) -> Self {
+        Self {
+            kind,
+            message,
+            span,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diag =
+            Diagnostic::new(self.message.clone(), self.span).with_severity(self.kind.severity());
+        match self.kind.hint() {
+            Some(hint) => diag.with_hint(hint),
+            None => diag,
+        }
+    }
+}
+
+/// Lints a program, returning the lints in source order.
+pub fn lint(program: &Program) -> Vec<Lint> {
+    let mut linter = Linter::default();
+    linter.lint_scope(&[], &program.stmts, Span::default());
+    let mut lints = linter.lints;
+    lints.sort_by_key(|l| l.span.start);
+    lints
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DefKind {
+    Var,
+    Const,
+    Fn,
+    /// Names that are fine to leave unused: loop variables and other
+    /// bindings.
+    Binding,
+    /// Also fine to leave unused, and free to shadow a builtin since the
+    /// name is part of the fn's interface for kwargs.
+    Param,
+    /// Assigned with `global`, so it may be read by imported modules.
+    Global,
+    Type,
+    Module,
+}
+
+struct Def<'a> {
+    kind: DefKind,
+    span: Span,
+    used: bool,
+    /// False for plain assignments, which update an existing binding in
+    /// an outer scope instead of defining a new one.
+    local: bool,
+    /// The params of a fn defined exactly once under this name.
+    params: Option<&'a [FnParam]>,
+}
+
+enum UseKind {
+    Read,
+    Call {
+        positional: usize,
+        kwargs: Vec<Identifier>,
+    },
+}
+
+struct Use {
+    ident: Identifier,
+    span: Span,
+    kind: UseKind,
+}
+
+#[derive(Default)]
+struct Scope<'a> {
+    /// Set for the scope of a block, which only holds `let` and `const`
+    /// declarations and bindings.
+    block: bool,
+    defs: IndexMap<String, Def<'a>>,
+    uses: Vec<Use>,
+    /// Bodies of the fns defined in this scope and where they were
+    /// defined, linted once it is done.
+    fns: Vec<(&'a [FnParam], &'a Block, Span)>,
+}
+
+impl Scope<'_> {
+    /// The names defined in this scope that shadow a builtin fn.
+    fn shadowed_builtins(&self) -> Vec<(String, Span)> {
+        self.defs
+            .iter()
+            .filter(|(name, def)| {
+                !matches!(def.kind, DefKind::Param | DefKind::Type | DefKind::Module)
+                    && BuiltinKind::is_from(name).is_some()
+            })
+            .map(|(name, def)| (name.clone(), def.span))
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Linter<'a> {
+    scopes: Vec<Scope<'a>>,
+    lints: Vec<Lint>,
+}
+
+impl<'a> Linter<'a> {
+    fn push_lint(&mut self, kind: LintKind, message: String, span: Span) {
+        self.lints.push(Lint::new(kind, message, span));
+    }
+
+    fn scope(&mut self) -> &mut Scope<'a> {
+        self.scopes.last_mut().expect("linting outside of a scope")
+    }
+
+    fn lint_scope(&mut self, params: &'a [FnParam], stmts: &'a [Stmt], span: Span) {
+        self.scopes.push(Scope::default());
+        for param in params {
+            self.define(&param.ident, DefKind::Param, span, true, None);
+        }
+        self.walk_stmts(stmts);
+
+        // Assignments to names from an outer scope don't define new ones.
+        let (scope, outer) = self.scopes.split_last_mut().unwrap();
+        scope
+            .defs
+            .retain(|name, def| def.local || !outer.iter().any(|s| s.defs.contains_key(name)));
+
+        let defs = scope.shadowed_builtins();
+        self.report_shadowed(defs);
+
+        let uses = std::mem::take(&mut self.scope().uses);
+        for u in uses {
+            self.resolve(u);
+        }
+
+        let fns = std::mem::take(&mut self.scope().fns);
+        for (params, body, span) in fns {
+            self.lint_scope(params, &body.stmts, span);
+        }
+
+        let scope = self.scopes.pop().unwrap();
+        let module = self.scopes.is_empty();
+        self.report_unused(scope.defs, module);
+    }
+
+    /// Walks `block` in a new block scope, with `bind` declaring the
+    /// bindings it introduces. Uses of names the block does not declare
+    /// are resolved along with those of the enclosing scope.
+    fn lint_block(&mut self, block: &'a Block, bind: impl FnOnce(&mut Self)) {
+        self.scopes.push(Scope {
+            block: true,
+            ..Default::default()
+        });
+        bind(self);
+        self.walk_block(block);
+        self.exit_block();
+    }
+
+    fn exit_block(&mut self) {
+        let defs = self.scope().shadowed_builtins();
+        self.report_shadowed(defs);
+
+        let uses = std::mem::take(&mut self.scope().uses);
+        for u in uses {
+            if self.scope().defs.contains_key(&u.ident.name) {
+                self.resolve(u);
+            } else {
+                let (_, outer) = self.scopes.split_last_mut().unwrap();
+                outer.last_mut().unwrap().uses.push(u);
+            }
+        }
+
+        let fns = std::mem::take(&mut self.scope().fns);
+        for (params, body, span) in fns {
+            self.lint_scope(params, &body.stmts, span);
+        }
+
+        let scope = self.scopes.pop().unwrap();
+        self.report_unused(scope.defs, false);
+    }
+
+    fn report_shadowed(&mut self, defs: Vec<(String, Span)>) {
+        for (name, span) in defs {
+            let msg = format!("`{name}` shadows the builtin fn of the same name");
+            self.push_lint(LintKind::ShadowedBuiltin, msg, span);
+        }
+    }
+
+    /// Reports the unused names of a scope. Fns, constants and variables
+    /// at the top of a `module` are left alone, since they may be imported.
+    fn report_unused(&mut self, defs: IndexMap<String, Def<'a>>, module: bool) {
+        for (name, def) in defs {
+            if def.used || name.starts_with('_') {
+                continue;
+            }
+            match def.kind {
+                DefKind::Fn | DefKind::Const | DefKind::Var if module => {}
+                DefKind::Var | DefKind::Const => {
+                    let msg = format!("unused variable `{name}`");
+                    self.push_lint(LintKind::UnusedVariable, msg, def.span);
+                }
+                DefKind::Fn => {
+                    let msg = format!("unused fn `{name}`");
+                    self.push_lint(LintKind::UnusedFn, msg, def.span);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Defines a name in the nearest fn or module scope. A plain
+    /// assignment to a name declared in an enclosing block updates that
+    /// instead.
+    fn define(
+        &mut self,
+        ident: &Identifier,
+        kind: DefKind,
+        span: Span,
+        local: bool,
+        params: Option<&'a [FnParam]>,
+    ) {
+        let idx = self.scopes.iter().rposition(|s| !s.block).unwrap();
+        if !local
+            && self.scopes[idx + 1..]
+                .iter()
+                .any(|s| s.defs.contains_key(&ident.name))
+        {
+            return;
+        }
+        let defs = &mut self.scopes[idx].defs;
+        match defs.get_mut(&ident.name) {
+            Some(def) => {
+                // A name defined more than once has no single arity.
+                def.params = None;
+                def.local |= local;
+                if kind == DefKind::Global {
+                    def.kind = kind;
+                }
+            }
+            None => {
+                let def = Def {
+                    kind,
+                    span,
+                    used: false,
+                    local,
+                    params,
+                };
+                defs.insert(ident.name.clone(), def);
+            }
+        }
+    }
+
+    /// Declares a name in the innermost scope, as `let`, `const` and
+    /// bindings do.
+    fn declare(&mut self, ident: &Identifier, kind: DefKind, span: Span) {
+        let def = Def {
+            kind,
+            span,
+            used: false,
+            local: true,
+            params: None,
+        };
+        self.scope().defs.insert(ident.name.clone(), def);
+    }
+
+    fn read(&mut self, ident: &Identifier, span: Span) {
+        self.scope().uses.push(Use {
+            ident: ident.clone(),
+            span,
+            kind: UseKind::Read,
+        });
+    }
+
+    /// Finds the nearest definition of a use and marks it as used.
+    fn resolve(&mut self, u: Use) {
+        let def = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|s| s.defs.get_mut(&u.ident.name));
+        let def = match def {
+            Some(def) => def,
+            None => {
+                if BuiltinKind::is_from(&u.ident.name).is_none() {
+                    let msg = format!("unknown identifier `{}`", u.ident);
+                    self.push_lint(LintKind::UndefinedIdent, msg, u.span);
+                }
+                return;
+            }
+        };
+        def.used = true;
+
+        if let (Some(params), UseKind::Call { positional, kwargs }) = (def.params, &u.kind) {
+            if let Some(msg) = check_arity(&u.ident, params, *positional, kwargs) {
+                self.push_lint(LintKind::WrongArity, msg, u.span);
+            }
+        }
+    }
+
+    fn is_module(&self, ident: &Identifier) -> bool {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|s| s.defs.get(&ident.name))
+            .is_some_and(|def| def.kind == DefKind::Module)
+    }
+
+    fn walk_stmts(&mut self, stmts: &'a [Stmt]) {
+        let mut exit = None;
+        for stmt in stmts {
+            if let Some(keyword) = exit.take() {
+                let msg = format!("unreachable code after `{keyword}`");
+                self.push_lint(LintKind::Unreachable, msg, stmt.span);
+            }
+            self.walk_stmt(stmt);
+            exit = match stmt.kind {
+                StmtKind::Return(_) => Some("return"),
+                StmtKind::Break => Some("break"),
+                StmtKind::Continue => Some("continue"),
+                StmtKind::Throw(_) => Some("throw"),
+                _ => None,
+            };
+        }
+    }
+
+    fn walk_block(&mut self, block: &'a Block) {
+        self.walk_stmts(&block.stmts);
+    }
+
+    fn walk_stmt(&mut self, stmt: &'a Stmt) {
+        match &stmt.kind {
+            StmtKind::Assign(lhs, rhs, global, _) => {
+                self.walk_expr(rhs);
+                let kind = if *global {
+                    DefKind::Global
+                } else {
+                    DefKind::Var
+                };
+                self.walk_assign(lhs, kind, false);
+            }
+            StmtKind::Let(lhs, rhs, constant, _) => {
+                self.walk_expr(rhs);
+                let kind = if *constant {
+                    DefKind::Const
+                } else {
+                    DefKind::Var
+                };
+                self.walk_assign(lhs, kind, true);
+            }
+            StmtKind::Func(def, global) => {
+                let kind = if *global {
+                    DefKind::Global
+                } else {
+                    DefKind::Fn
+                };
+                self.define(&def.ident, kind, stmt.span, false, Some(&def.params));
+                self.walk_params(&def.params);
+                self.scope().fns.push((&def.params, &def.body, stmt.span));
+            }
+            StmtKind::Expr(expr) | StmtKind::Goto(expr) | StmtKind::Throw(expr) => {
+                self.walk_expr(expr);
+            }
+            StmtKind::Return(expr) => {
+                if let Some(expr) = expr {
+                    self.walk_expr(expr);
+                }
+            }
+            StmtKind::IfElse(lit) => {
+                self.walk_expr(&lit.if_lit.cond);
+                self.lint_block(&lit.if_lit.block, |_| {});
+                for elif in &lit.elifs {
+                    self.walk_expr(&elif.cond);
+                    self.lint_block(&elif.block, |_| {});
+                }
+                if let Some(else_lit) = &lit.else_lit {
+                    self.lint_block(&else_lit.block, |_| {});
+                }
+            }
+            StmtKind::ForLoop(floop) => {
+                self.walk_expr(&floop.iterable);
+                self.lint_block(&floop.block, |l| {
+                    for ident in &floop.idents {
+                        l.declare(ident, DefKind::Binding, stmt.span);
+                    }
+                });
+            }
+            StmtKind::WhileLoop(cond, block) => {
+                self.walk_expr(cond);
+                self.lint_block(block, |_| {});
+            }
+            StmtKind::Crawl(lit) => {
+                self.lint_block(&lit.body, |l| {
+                    if let Some(bindings) = &lit.bindings {
+                        l.declare(&bindings.link, DefKind::Binding, stmt.span);
+                        l.declare(&bindings.depth, DefKind::Binding, stmt.span);
+                    }
+                    if let Some(filter) = &lit.filter {
+                        l.walk_expr(filter);
+                    }
+                });
+            }
+            StmtKind::TryCatch(block, catch, finally) => {
+                self.lint_block(block, |_| {});
+                if let Some(catch) = catch {
+                    self.lint_block(&catch.block, |l| {
+                        if let Some(binding) = &catch.binding {
+                            l.declare(binding, DefKind::Binding, stmt.span);
+                        }
+                    });
+                }
+                if let Some(finally) = finally {
+                    self.lint_block(finally, |_| {});
+                }
+            }
+            StmtKind::Scrape(ty, hash) => {
+                if let Some(ty) = ty {
+                    self.read(ty, stmt.span);
+                }
+                self.walk_hash(hash);
+            }
+            StmtKind::TypeDecl(decl) => {
+                self.define(&decl.ident, DefKind::Type, stmt.span, true, None);
+            }
+            StmtKind::Use(import) => self.walk_use(import),
+            StmtKind::Screenshot(_) | StmtKind::Break | StmtKind::Continue => {}
+        }
+    }
+
+    /// Defines the names assigned to, or declares them when `declare` is
+    /// set for `let` and `const`.
+    fn walk_assign(&mut self, lhs: &'a Expr, kind: DefKind, declare: bool) {
+        match &lhs.kind {
+            ExprKind::Ident(ident) if declare => self.declare(ident, kind, lhs.span),
+            ExprKind::Ident(ident) => self.define(ident, kind, lhs.span, false, None),
+            ExprKind::List(items) => {
+                for item in items {
+                    self.walk_assign(item, kind, declare);
+                }
+            }
+            // Index and field assignments read the value they update.
+            _ => self.walk_expr(lhs),
+        }
+    }
+
+    fn walk_params(&mut self, params: &'a [FnParam]) {
+        for param in params {
+            if let Some(default) = &param.default {
+                self.walk_expr(default);
+            }
+        }
+    }
+
+    fn walk_use(&mut self, import: &'a Expr) {
+        let resolved = match resolve_module(import) {
+            Ok(resolved) => resolved,
+            Err(_) => {
+                let msg = format!("invalid module path `{import}`");
+                self.push_lint(LintKind::UnresolvedImport, msg, import.span);
+                return;
+            }
+        };
+        let path = Path::new(&resolved.filepath);
+        if !path.with_extension("sct").exists() && !path.is_dir() {
+            let msg = format!("cannot find module `{}`", resolved.filepath);
+            self.push_lint(LintKind::UnresolvedImport, msg, import.span);
+        }
+        self.define(&resolved.ident, DefKind::Module, import.span, true, None);
+    }
+
+    fn walk_hash(&mut self, hash: &'a HashLiteral) {
+        for (key, val) in &hash.pairs {
+            if let MapKey::Computed(expr) = key {
+                self.walk_expr(expr);
+            }
+            self.walk_expr(val);
+        }
+    }
+
+    /// Records a call, with `piped` set when a value is passed in as the
+    /// first argument by a pipe or method call.
+    fn walk_call(&mut self, call: &'a CallLiteral, span: Span, piped: bool) {
+        self.walk_args(call);
+        self.scope().uses.push(Use {
+            ident: call.ident.clone(),
+            span,
+            kind: UseKind::Call {
+                positional: call.args.len() + usize::from(piped),
+                kwargs: call.kwargs.iter().map(|k| k.ident.clone()).collect(),
+            },
+        });
+    }
+
+    fn walk_args(&mut self, call: &'a CallLiteral) {
+        for arg in &call.args {
+            self.walk_expr(arg);
+        }
+        for kwarg in &call.kwargs {
+            self.walk_expr(&kwarg.expr);
+        }
+    }
+
+    fn walk_expr(&mut self, expr: &'a Expr) {
+        match &expr.kind {
+            ExprKind::Str(_) | ExprKind::Number(_) | ExprKind::Boolean(_) | ExprKind::Null => {}
+            ExprKind::Ident(ident) => self.read(ident, expr.span),
+            ExprKind::List(items) | ExprKind::Interp(items) => {
+                for item in items {
+                    self.walk_expr(item);
+                }
+            }
+            ExprKind::Map(hash) => self.walk_hash(hash),
+            ExprKind::Select(_, scope) | ExprKind::SelectAll(_, scope) => {
+                if let Some(ident) = scope {
+                    self.read(ident, expr.span);
+                }
+            }
+            ExprKind::Call(call) => self.walk_call(call, expr.span, false),
+            ExprKind::Lambda(lit) => {
+                self.walk_params(&lit.params);
+                self.scope().fns.push((&lit.params, &lit.body, expr.span));
+            }
+            ExprKind::Match(MatchLiteral { subject, arms }) => {
+                self.walk_expr(subject);
+                for arm in arms {
+                    self.lint_block(&arm.block, |l| {
+                        l.walk_pattern(&arm.pattern, expr.span);
+                        if let Some(guard) = &arm.guard {
+                            l.walk_expr(guard);
+                        }
+                    });
+                }
+            }
+            ExprKind::Record(ty, hash) => {
+                self.read(ty, expr.span);
+                self.walk_hash(hash);
+            }
+            ExprKind::Conditional(cond, then, other) => {
+                self.walk_expr(cond);
+                self.walk_expr(then);
+                self.walk_expr(other);
+            }
+            ExprKind::Field(obj, _) => self.walk_expr(obj),
+            ExprKind::MethodCall(recv, call) => {
+                self.walk_expr(recv);
+                match &recv.kind {
+                    // Calls into a module, which can't be followed.
+                    ExprKind::Ident(ident) if self.is_module(ident) => self.walk_args(call),
+                    _ => self.walk_call(call, expr.span, true),
+                }
+            }
+            ExprKind::Chain(exprs) | ExprKind::SafeChain(exprs) => {
+                for (idx, link) in exprs.iter().enumerate() {
+                    match &link.kind {
+                        ExprKind::Call(call) if idx > 0 => self.walk_call(call, link.span, true),
+                        _ => self.walk_expr(link),
+                    }
+                }
+            }
+            ExprKind::Infix(lhs, op, rhs) if op.kind == TokenKind::DbColon => {
+                self.walk_expr(lhs);
+                // The rhs is looked up in the module, but args are
+                // evaluated in the caller's scope.
+                match &rhs.kind {
+                    ExprKind::Ident(_) => {}
+                    ExprKind::Call(call) => self.walk_args(call),
+                    _ => self.walk_expr(rhs),
+                }
+            }
+            ExprKind::Infix(lhs, _, rhs) => {
+                self.walk_expr(lhs);
+                self.walk_expr(rhs);
+            }
+            ExprKind::Prefix(operand, _) => self.walk_expr(operand),
+        }
+    }
+
+    fn walk_pattern(&mut self, pattern: &'a Pattern, span: Span) {
+        match pattern {
+            Pattern::Wildcard => {}
+            Pattern::Binding(ident) => self.declare(ident, DefKind::Binding, span),
+            Pattern::Literal(expr) => self.walk_expr(expr),
+            Pattern::List(items) => {
+                for item in items {
+                    self.walk_pattern(item, span);
+                }
+            }
+            Pattern::Map(fields) => {
+                for (_, item) in fields {
+                    self.walk_pattern(item, span);
+                }
+            }
+        }
+    }
+}
+
+/// Checks the args of a call against the params of the fn it calls,
+/// returning a message if they can't be bound.
+fn check_arity(
+    ident: &Identifier,
+    params: &[FnParam],
+    positional: usize,
+    kwargs: &[Identifier],
+) -> Option<String> {
+    let named = params
+        .iter()
+        .filter(|p| p.kind == ParamKind::Positional)
+        .collect::<Vec<_>>();
+    let has_rest = params.iter().any(|p| p.kind == ParamKind::Rest);
+    let has_kwargs = params.iter().any(|p| p.kind == ParamKind::Kwargs);
+
+    if positional > named.len() && !has_rest {
+        return Some(format!(
+            "`{ident}` takes at most {} arguments, found {positional}",
+            named.len()
+        ));
+    }
+    if let Some(kwarg) = kwargs
+        .iter()
+        .find(|k| !has_kwargs && !named.iter().any(|p| p.ident == **k))
+    {
+        return Some(format!("`{ident}` has no parameter `{kwarg}`"));
+    }
+    let missing = named
+        .iter()
+        .enumerate()
+        .find(|(idx, p)| *idx >= positional && p.default.is_none() && !kwargs.contains(&p.ident));
+    missing.map(|(_, p)| format!("`{ident}` is missing an argument for `{}`", p.ident))
+}
+
+#[cfg(test)]
+mod tests {
+    use scout_lexer::Lexer;
+    use scout_parser::Parser;
+    use test_case::test_case;
+
+    use super::*;
+
+    fn lint_str(input: &str) -> Vec<(LintKind, String)> {
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        lint(&program)
+            .into_iter()
+            .map(|l| (l.kind, l.message))
+            .collect()
+    }
+
+    #[test_case("print(x)", LintKind::UndefinedIdent, "unknown identifier `x`"; "undefined ident")]
+    #[test_case("f(1)", LintKind::UndefinedIdent, "unknown identifier `f`"; "undefined fn")]
+    #[test_case("def f() do\n  x = 1\nend\nf()", LintKind::UnusedVariable, "unused variable `x`"; "unused variable")]
+    #[test_case("def f() do\n  let x = 1\nend\nf()", LintKind::UnusedVariable, "unused variable `x`"; "unused local")]
+    #[test_case("def f() do\n  def g() do end\nend\nf()", LintKind::UnusedFn, "unused fn `g`"; "unused fn")]
+    #[test_case("for x in [1] do\n  let inner = x\n  print(inner)\nend\nprint(inner)", LintKind::UndefinedIdent, "unknown identifier `inner`"; "let after block")]
+    #[test_case("if true do\n  const c = 1\nend", LintKind::UnusedVariable, "unused variable `c`"; "unused block const")]
+    #[test_case("def f() do\n  return 1\n  print(2)\nend\nf()", LintKind::Unreachable, "unreachable code after `return`"; "after return")]
+    #[test_case("for x in [1] do\n  break\n  print(x)\nend", LintKind::Unreachable, "unreachable code after `break`"; "after break")]
+    #[test_case("url = 1\nprint(url)", LintKind::ShadowedBuiltin, "`url` shadows the builtin fn of the same name"; "shadowed builtin")]
+    #[test_case("def f() do\n  for len in [1] do print(len) end\nend\nf()", LintKind::ShadowedBuiltin, "`len` shadows the builtin fn of the same name"; "shadowed by binding")]
+    #[test_case("def f(a, b = 1) do print(a, b) end\nf(1, 2, 3)", LintKind::WrongArity, "`f` takes at most 2 arguments, found 3"; "too many args")]
+    #[test_case("def f(a, b) do print(a, b) end\nf(1)", LintKind::WrongArity, "`f` is missing an argument for `b`"; "missing arg")]
+    #[test_case("def f(a) do print(a) end\n1 |> f(2)", LintKind::WrongArity, "`f` takes at most 1 arguments, found 2"; "piped arg")]
+    #[test_case("def f(a) do print(a) end\nf(b = 1)", LintKind::WrongArity, "`f` has no parameter `b`"; "unknown kwarg")]
+    #[test_case("use nowhere::mod", LintKind::UnresolvedImport, "cannot find module `nowhere/mod`"; "missing module")]
+    fn test_lint(input: &str, kind: LintKind, exp: &str) {
+        assert_eq!(lint_str(input), vec![(kind, exp.to_string())]);
+    }
+
+    #[test_case("def f() do\n  return g()\nend\ndef g() do\n  return 1\nend\nprint(f())"; "fn defined later")]
+    #[test_case("x = 0\ndef inc() do\n  x = x + 1\nend\ninc()\nprint(x)"; "assign to outer")]
+    #[test_case("def f(a, *rest, **opts) do\n  print(a, rest, opts)\nend\nf(1, 2, 3, k = 4)"; "variadic params")]
+    #[test_case("for a, b in [[1, 2]] do\n  print(a)\nend"; "unused loop var")]
+    #[test_case("x = match 1 case [a, _] if a do a case n do n end\nprint(x)"; "match bindings")]
+    #[test_case("try\n  throw 1\ncatch e do\n  print(e)\nend"; "catch binding")]
+    #[test_case("_tmp = 1\nglobal g = 2"; "ignored names")]
+    #[test_case("type P { a: str }\nscrape P { a: \"x\" }"; "record type")]
+    #[test_case("f = fn(a) do return a end\nprint(f(1, 2))"; "lambda arity unknown")]
+    #[test_case("def f() do end\nconst c = 1"; "module exports")]
+    #[test_case("x = 1\nx = 2\nlet y = 3"; "module exports variable")]
+    #[test_case("def get(url) do\n  print(url)\nend"; "param named like builtin")]
+    #[test_case("x = 0\nif true do\n  let y = 1\n  x = y\nend\nprint(x)"; "assign outer in block")]
+    fn test_no_lints(input: &str) {
+        assert_eq!(lint_str(input), vec![]);
+    }
+
+    #[test]
+    fn test_scout_lib_is_clean() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../scout-lib");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let input = std::fs::read_to_string(&path).unwrap();
+            assert_eq!(lint_str(&input), vec![], "{}", path.display());
+        }
+    }
+}
@@ -0,0 +1,594 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use lsp_types::{
+    CompletionItem, CompletionItemKind, DiagnosticSeverity, DocumentSymbol, Documentation, Hover,
+    HoverContents, Location, MarkupContent, MarkupKind, Position, SymbolKind, Url,
+};
+use scout_interpreter::{
+    builtin::BuiltinKind,
+    check::check,
+    import::{resolve_module, std_dir},
+};
+use scout_lexer::{Span, TokenKind};
+use scout_lint::lint;
+use scout_parser::{
+    ast::{Block, Expr, ExprKind, FuncDef, Identifier, Stmt, StmtKind},
+    diagnostic::Severity,
+};
+
+use crate::document::Document;
+
+/// Parse errors, or when the file parses, type errors and lints.
+pub fn diagnostics(doc: &Document) -> Vec<lsp_types::Diagnostic> {
+    let diags = if doc.parse_errors.is_empty() {
+        let mut diags = check(&doc.program);
+        diags.extend(lint(&doc.program).iter().map(|l| l.diagnostic()));
+        diags.sort_by_key(|d| d.span.start);
+        diags
+    } else {
+        doc.parse_errors.clone()
+    };
+
+    diags
+        .into_iter()
+        .map(|d| {
+            let severity = match d.severity {
+                Severity::Error => DiagnosticSeverity::ERROR,
+                Severity::Warning => DiagnosticSeverity::WARNING,
+            };
+            let message = match &d.hint {
+                Some(hint) => format!("{}\nhint: {hint}", d.message),
+                None => d.message,
+            };
+            lsp_types::Diagnostic {
+                range: doc.range(d.span),
+                severity: Some(severity),
+                source: Some("scout".into()),
+                message,
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Finds the `def` or module the identifier at `pos` refers to. `mod::f`
+/// jumps to `f` in the module's file, and a `use` path to the file itself.
+pub fn definition(doc: &Document, uri: &Url, pos: Position) -> Option<Location> {
+    let (idx, token) = doc.ident_at(doc.offset(pos))?;
+    let dir = module_dir(uri);
+
+    for stmt in &doc.program.stmts {
+        if let StmtKind::Use(import) = &stmt.kind {
+            if import.span.start <= token.span.start && token.span.end <= import.span.end {
+                let (_, file) = module_file(import, &dir)?;
+                return file_start(&file);
+            }
+        }
+    }
+
+    let modules = used_modules(doc, &dir);
+    if idx >= 2 && doc.tokens[idx - 1].kind == TokenKind::DbColon {
+        let module = &doc.tokens[idx - 2].literal;
+        let (_, file) = modules.iter().find(|(ident, _)| ident.name == *module)?;
+        let text = fs::read_to_string(file).ok()?;
+        let module_doc = Document::new(text);
+        let stmt = find_def(&module_doc.program.stmts, &token.literal)?;
+        let range = module_doc.range(def_name(&module_doc, stmt));
+        return Some(Location::new(Url::from_file_path(file).ok()?, range));
+    }
+
+    if let Some((_, file)) = modules
+        .iter()
+        .find(|(ident, _)| ident.name == token.literal)
+    {
+        return file_start(file);
+    }
+
+    let stmt = find_def(&doc.program.stmts, &token.literal)?;
+    Some(Location::new(uri.clone(), doc.range(def_name(doc, stmt))))
+}
+
+/// Shows the signature of the `def` or builtin at `pos`, along with the
+/// docs for builtins.
+pub fn hover(doc: &Document, pos: Position) -> Option<Hover> {
+    let (_, token) = doc.ident_at(doc.offset(pos))?;
+    let value = match find_def(&doc.program.stmts, &token.literal) {
+        Some(Stmt {
+            kind: StmtKind::Func(def, _),
+            ..
+        }) => format!("```scout\n{}\n```", def_signature(def)),
+        _ => {
+            let builtin = BuiltinKind::is_from(&token.literal)?;
+            format!(
+                "```scout\n{}\n```\n\n{}",
+                builtin_signature(&builtin),
+                builtin.doc()
+            )
+        }
+    };
+
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value,
+        }),
+        range: Some(doc.range(token.span)),
+    })
+}
+
+/// Completes std modules after `use std::`, the fns of a used module after
+/// `mod::`, and otherwise the names defined in the file and builtins.
+pub fn completion(doc: &Document, uri: &Url, pos: Position) -> Vec<CompletionItem> {
+    let offset = doc.offset(pos);
+    let mut idx = doc.token_index_after(offset);
+    // Skip back over the identifier being typed.
+    if idx > 0 {
+        let prev = &doc.tokens[idx - 1];
+        if prev.kind == TokenKind::Ident && prev.span.end == offset {
+            idx -= 1;
+        }
+    }
+
+    let prev_kind = |back: usize| idx.checked_sub(back).map(|i| doc.tokens[i].kind);
+    if prev_kind(1) == Some(TokenKind::DbColon) && prev_kind(2) == Some(TokenKind::Ident) {
+        let base = &doc.tokens[idx - 2].literal;
+        if base == "std" {
+            return std_modules();
+        }
+        let dir = module_dir(uri);
+        return used_modules(doc, &dir)
+            .into_iter()
+            .find(|(ident, _)| ident.name == *base)
+            .and_then(|(_, file)| fs::read_to_string(file).ok())
+            .map(|text| {
+                let module_doc = Document::new(text);
+                let mut defs = Vec::new();
+                fn_defs(&module_doc.program.stmts, &mut defs);
+                defs.into_iter().map(def_completion).collect()
+            })
+            .unwrap_or_default();
+    }
+    if prev_kind(1) == Some(TokenKind::Use) {
+        return vec![CompletionItem {
+            label: "std".into(),
+            kind: Some(CompletionItemKind::MODULE),
+            ..Default::default()
+        }];
+    }
+
+    let mut items: Vec<CompletionItem> = Vec::new();
+    let mut defs = Vec::new();
+    fn_defs(&doc.program.stmts, &mut defs);
+    items.extend(defs.into_iter().map(def_completion));
+
+    let mut names = Vec::new();
+    defined_names(&doc.program.stmts, &mut names);
+    for (name, kind) in names {
+        if !items.iter().any(|i| i.label == name) {
+            items.push(CompletionItem {
+                label: name,
+                kind: Some(kind),
+                ..Default::default()
+            });
+        }
+    }
+
+    for builtin in BuiltinKind::ALL {
+        if !items.iter().any(|i| i.label == builtin.name()) {
+            items.push(CompletionItem {
+                label: builtin.name().into(),
+                kind: Some(CompletionItemKind::FUNCTION),
+                detail: Some(builtin_signature(&builtin)),
+                documentation: Some(Documentation::String(builtin.doc().into())),
+                ..Default::default()
+            });
+        }
+    }
+    items
+}
+
+/// The `def`s and `type`s in the file, along with its top level
+/// variables. Nested `def`s are children of the `def` they are in.
+pub fn symbols(doc: &Document) -> Vec<DocumentSymbol> {
+    let mut out: Vec<DocumentSymbol> = Vec::new();
+    for stmt in &doc.program.stmts {
+        let (lhs, kind) = match &stmt.kind {
+            StmtKind::Let(lhs, _, true, _) => (lhs, SymbolKind::CONSTANT),
+            StmtKind::Let(lhs, ..) | StmtKind::Assign(lhs, ..) => (lhs, SymbolKind::VARIABLE),
+            _ => continue,
+        };
+        // Only the first assignment of a name defines it.
+        match &lhs.kind {
+            ExprKind::Ident(ident) if !out.iter().any(|s| s.name == ident.name) => {
+                let sym = symbol(doc, ident.name.clone(), kind, stmt.span, lhs.span, None);
+                out.push(sym);
+            }
+            _ => {}
+        }
+    }
+    out.extend(nested_symbols(doc, &doc.program.stmts));
+    out.sort_by_key(|s| (s.range.start.line, s.range.start.character));
+    out
+}
+
+fn nested_symbols(doc: &Document, stmts: &[Stmt]) -> Vec<DocumentSymbol> {
+    let mut out = Vec::new();
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::Func(def, _) => {
+                let children = nested_symbols(doc, &def.body.stmts);
+                let sym = symbol(
+                    doc,
+                    def.ident.name.clone(),
+                    SymbolKind::FUNCTION,
+                    stmt.span,
+                    def_name(doc, stmt),
+                    Some(children),
+                );
+                out.push(sym);
+            }
+            StmtKind::TypeDecl(decl) => {
+                // The name follows the contextual `type` keyword.
+                let idx = doc.token_index_after(stmt.span.start) + 1;
+                let name = doc.tokens.get(idx).map_or(stmt.span, |t| t.span);
+                let sym = symbol(
+                    doc,
+                    decl.ident.name.clone(),
+                    SymbolKind::STRUCT,
+                    stmt.span,
+                    name,
+                    None,
+                );
+                out.push(sym);
+            }
+            kind => {
+                for block in child_blocks(kind) {
+                    out.extend(nested_symbols(doc, &block.stmts));
+                }
+            }
+        }
+    }
+    out
+}
+
+#[allow(deprecated)]
+fn symbol(
+    doc: &Document,
+    name: String,
+    kind: SymbolKind,
+    span: Span,
+    selection: Span,
+    children: Option<Vec<DocumentSymbol>>,
+) -> DocumentSymbol {
+    DocumentSymbol {
+        name,
+        detail: None,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: doc.range(span),
+        selection_range: doc.range(selection),
+        children,
+    }
+}
+
+/// The blocks directly inside a statement, other than a `def` body.
+fn child_blocks(kind: &StmtKind) -> Vec<&Block> {
+    match kind {
+        StmtKind::IfElse(lit) => std::iter::once(&lit.if_lit.block)
+            .chain(lit.elifs.iter().map(|e| &e.block))
+            .chain(lit.else_lit.iter().map(|e| &e.block))
+            .collect(),
+        StmtKind::ForLoop(floop) => vec![&floop.block],
+        StmtKind::WhileLoop(_, block) => vec![block],
+        StmtKind::Crawl(lit) => vec![&lit.body],
+        StmtKind::TryCatch(t, c, f) => std::iter::once(t)
+            .chain(c.iter().map(|c| &c.block))
+            .chain(f.iter())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Every `def` in `stmts`, including those nested in other blocks.
+fn fn_defs<'a>(stmts: &'a [Stmt], out: &mut Vec<&'a FuncDef>) {
+    for stmt in stmts {
+        if let StmtKind::Func(def, _) = &stmt.kind {
+            out.push(def);
+            fn_defs(&def.body.stmts, out);
+        }
+        for block in child_blocks(&stmt.kind) {
+            fn_defs(&block.stmts, out);
+        }
+    }
+}
+
+/// The first `def` of `name`, looking at the top level before nested
+/// blocks.
+fn find_def<'a>(stmts: &'a [Stmt], name: &str) -> Option<&'a Stmt> {
+    let top = stmts
+        .iter()
+        .find(|s| matches!(&s.kind, StmtKind::Func(def, _) if def.ident.name == name));
+    top.or_else(|| {
+        stmts.iter().find_map(|s| {
+            let mut blocks = child_blocks(&s.kind);
+            if let StmtKind::Func(def, _) = &s.kind {
+                blocks.push(&def.body);
+            }
+            blocks.into_iter().find_map(|b| find_def(&b.stmts, name))
+        })
+    })
+}
+
+/// The names assigned, declared or bound as params and loop variables.
+fn defined_names(stmts: &[Stmt], out: &mut Vec<(String, CompletionItemKind)>) {
+    fn push(
+        out: &mut Vec<(String, CompletionItemKind)>,
+        ident: &Identifier,
+        kind: CompletionItemKind,
+    ) {
+        out.push((ident.name.clone(), kind));
+    }
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::Assign(lhs, ..) | StmtKind::Let(lhs, ..) => match &lhs.kind {
+                ExprKind::Ident(ident) => push(out, ident, CompletionItemKind::VARIABLE),
+                ExprKind::List(items) => {
+                    for item in items {
+                        if let ExprKind::Ident(ident) = &item.kind {
+                            push(out, ident, CompletionItemKind::VARIABLE);
+                        }
+                    }
+                }
+                _ => {}
+            },
+            StmtKind::ForLoop(floop) => {
+                for ident in &floop.idents {
+                    push(out, ident, CompletionItemKind::VARIABLE);
+                }
+            }
+            StmtKind::Crawl(lit) => {
+                if let Some(bindings) = &lit.bindings {
+                    push(out, &bindings.link, CompletionItemKind::VARIABLE);
+                    push(out, &bindings.depth, CompletionItemKind::VARIABLE);
+                }
+            }
+            StmtKind::TryCatch(_, Some(catch), _) => {
+                if let Some(ident) = &catch.binding {
+                    push(out, ident, CompletionItemKind::VARIABLE);
+                }
+            }
+            StmtKind::Func(def, _) => {
+                for param in &def.params {
+                    push(out, &param.ident, CompletionItemKind::VARIABLE);
+                }
+            }
+            StmtKind::TypeDecl(decl) => push(out, &decl.ident, CompletionItemKind::STRUCT),
+            StmtKind::Use(import) => {
+                if let Ok(resolved) = resolve_module(import) {
+                    push(out, &resolved.ident, CompletionItemKind::MODULE);
+                }
+            }
+            _ => {}
+        }
+        if let StmtKind::Func(def, _) = &stmt.kind {
+            defined_names(&def.body.stmts, out);
+        }
+        for block in child_blocks(&stmt.kind) {
+            defined_names(&block.stmts, out);
+        }
+    }
+}
+
+fn def_completion(def: &FuncDef) -> CompletionItem {
+    CompletionItem {
+        label: def.ident.name.clone(),
+        kind: Some(CompletionItemKind::FUNCTION),
+        detail: Some(def_signature(def)),
+        ..Default::default()
+    }
+}
+
+fn std_modules() -> Vec<CompletionItem> {
+    let Some(entries) = std_dir().ok().and_then(|dir| fs::read_dir(dir).ok()) else {
+        return Vec::new();
+    };
+    let mut items: Vec<CompletionItem> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let path = e.path();
+            let is_module = path.is_dir() || path.extension().is_some_and(|ext| ext == "sct");
+            let name = path.file_stem()?.to_str()?.to_string();
+            is_module.then(|| CompletionItem {
+                label: name,
+                kind: Some(CompletionItemKind::MODULE),
+                ..Default::default()
+            })
+        })
+        .collect();
+    items.sort_by(|a, b| a.label.cmp(&b.label));
+    items
+}
+
+/// The span of the name in a `def` statement.
+fn def_name(doc: &Document, stmt: &Stmt) -> Span {
+    let idx = doc.token_index_after(stmt.span.start);
+    doc.tokens[idx..]
+        .iter()
+        .position(|t| t.kind == TokenKind::Def)
+        .and_then(|def| doc.tokens.get(idx + def + 1))
+        .map_or(stmt.span, |t| t.span)
+}
+
+fn def_signature(def: &FuncDef) -> String {
+    let params = def
+        .params
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    let ret = def
+        .ret
+        .as_ref()
+        .map(|t| format!(" -> {t}"))
+        .unwrap_or_default();
+    format!("def {}({params}){ret}", def.ident)
+}
+
+fn builtin_signature(builtin: &BuiltinKind) -> String {
+    let sig = builtin.signature();
+    let mut params = sig.params.join(", ");
+    if sig.variadic {
+        params.push_str("...");
+    }
+    format!("{}({params}) -> {}", builtin.name(), sig.ret)
+}
+
+/// Relative module paths are resolved from the directory of the file.
+fn module_dir(uri: &Url) -> PathBuf {
+    uri.to_file_path()
+        .ok()
+        .and_then(|p| p.parent().map(Path::to_path_buf))
+        .unwrap_or_default()
+}
+
+fn module_file(import: &Expr, dir: &Path) -> Option<(Identifier, PathBuf)> {
+    let resolved = resolve_module(import).ok()?;
+    let file = dir.join(resolved.filepath).with_extension("sct");
+    Some((resolved.ident, file))
+}
+
+/// The modules brought in with `use` at the top level of the file.
+fn used_modules(doc: &Document, dir: &Path) -> Vec<(Identifier, PathBuf)> {
+    doc.program
+        .stmts
+        .iter()
+        .filter_map(|s| match &s.kind {
+            StmtKind::Use(import) => module_file(import, dir),
+            _ => None,
+        })
+        .collect()
+}
+
+fn file_start(file: &Path) -> Option<Location> {
+    if !file.is_file() {
+        return None;
+    }
+    let uri = Url::from_file_path(file).ok()?;
+    Some(Location::new(uri, Default::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const SRC: &str = "use helpers\ndef add(a, b = 1) -> number do\n  def inner() do\n  end\n  a + b\nend\ntotal = add(1)\nprint(helpers::greet(total))\n";
+
+    fn uri() -> Url {
+        Url::from_file_path(std::env::temp_dir().join("scout-lsp-test/main.sct")).unwrap()
+    }
+
+    fn labels(items: &[CompletionItem]) -> Vec<&str> {
+        items.iter().map(|i| i.label.as_str()).collect()
+    }
+
+    #[test_case("x = (", vec![DiagnosticSeverity::ERROR]; "parse error")]
+    #[test_case("len = z", vec![DiagnosticSeverity::WARNING, DiagnosticSeverity::ERROR]; "sorted by position")]
+    #[test_case("print(1)", vec![]; "clean file")]
+    fn test_diagnostics(src: &str, exp: Vec<DiagnosticSeverity>) {
+        let diags = diagnostics(&Document::new(src.into()));
+        let severities: Vec<DiagnosticSeverity> = diags.iter().filter_map(|d| d.severity).collect();
+        assert_eq!(severities, exp);
+    }
+
+    #[test_case(Position::new(6, 9), Some(Position::new(1, 4)); "call to def")]
+    #[test_case(Position::new(2, 7), Some(Position::new(2, 6)); "nested def name")]
+    #[test_case(Position::new(7, 1), None; "builtin has no def")]
+    fn test_definition(pos: Position, exp: Option<Position>) {
+        let doc = Document::new(SRC.into());
+        let loc = definition(&doc, &uri(), pos);
+        assert_eq!(loc.map(|l| l.range.start), exp);
+    }
+
+    #[test]
+    fn test_definition_in_module() {
+        let dir = std::env::temp_dir().join("scout-lsp-test");
+        fs::create_dir_all(&dir).unwrap();
+        let module = dir.join("helpers.sct");
+        fs::write(&module, "x = 1\ndef greet(name) do\n  name\nend\n").unwrap();
+        let doc = Document::new(SRC.into());
+
+        let loc = definition(&doc, &uri(), Position::new(7, 16)).unwrap();
+        assert_eq!(loc.uri, Url::from_file_path(&module).unwrap());
+        assert_eq!(loc.range.start, Position::new(1, 4));
+
+        let loc = definition(&doc, &uri(), Position::new(0, 6)).unwrap();
+        assert_eq!(loc.range.start, Position::new(0, 0));
+
+        let items = completion(&doc, &uri(), Position::new(7, 15));
+        assert_eq!(labels(&items), vec!["greet"]);
+    }
+
+    #[test_case(Position::new(7, 2), "print(any...) -> null"; "builtin")]
+    #[test_case(Position::new(6, 10), "def add(a, b = 1) -> number"; "user def")]
+    fn test_hover(pos: Position, exp: &str) {
+        let doc = Document::new(SRC.into());
+        let hover = hover(&doc, pos).unwrap();
+        match hover.contents {
+            HoverContents::Markup(content) => assert!(content.value.contains(exp)),
+            _ => panic!("expected markup"),
+        }
+    }
+
+    #[test]
+    fn test_completion() {
+        let doc = Document::new(SRC.into());
+        let items = completion(&doc, &uri(), Position::new(6, 0));
+        let labels = labels(&items);
+        for label in [
+            "add",
+            "inner",
+            "a",
+            "total",
+            "helpers",
+            "print",
+            "httpRequest",
+        ] {
+            assert!(labels.contains(&label), "missing {label}");
+        }
+        assert_eq!(labels.iter().filter(|l| **l == "add").count(), 1);
+    }
+
+    #[test]
+    fn test_completion_after_use() {
+        let doc = Document::new("use ".into());
+        let items = completion(&doc, &uri(), Position::new(0, 4));
+        assert_eq!(labels(&items), vec!["std"]);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let doc = Document::new(format!(
+            "type P {{ a: str }}\nconst K = 1\n{SRC}total = 2\n"
+        ));
+        let syms = symbols(&doc);
+        let names: Vec<(&str, SymbolKind)> =
+            syms.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+        assert_eq!(
+            names,
+            vec![
+                ("P", SymbolKind::STRUCT),
+                ("K", SymbolKind::CONSTANT),
+                ("add", SymbolKind::FUNCTION),
+                ("total", SymbolKind::VARIABLE),
+            ]
+        );
+        let children = syms[2].children.as_ref().unwrap();
+        assert_eq!(children[0].name, "inner");
+        assert_eq!(syms[2].selection_range.start, Position::new(3, 4));
+    }
+}
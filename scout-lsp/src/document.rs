@@ -0,0 +1,99 @@
+use lsp_types::{Position, Range};
+use scout_lexer::{Lexer, Span, Token, TokenKind};
+use scout_parser::{ast::Program, diagnostic::Diagnostic, Parser};
+
+/// An open `.sct` file along with everything parsed from it.
+pub struct Document {
+    pub text: String,
+    /// The statements that parsed, which is all of them when
+    /// `parse_errors` is empty.
+    pub program: Program,
+    pub parse_errors: Vec<Diagnostic>,
+    pub tokens: Vec<Token>,
+    /// Byte offset of the start of each line.
+    line_starts: Vec<usize>,
+}
+
+impl Document {
+    pub fn new(text: String) -> Self {
+        let (program, errors) = Parser::new(Lexer::new(&text)).parse_program_partial();
+        let mut lex = Lexer::new(&text);
+        let mut tokens = Vec::new();
+        loop {
+            let token = lex.next_token();
+            if token.kind == TokenKind::EOF {
+                break;
+            }
+            tokens.push(token);
+        }
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self {
+            program,
+            parse_errors: errors.iter().map(|e| e.diagnostic()).collect(),
+            tokens,
+            line_starts,
+            text,
+        }
+    }
+
+    /// Converts a byte offset into an LSP position, which counts columns
+    /// in UTF-16 code units.
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.text.len());
+        let line = self.line_starts.partition_point(|s| *s <= offset) - 1;
+        let start = self.line_starts[line];
+        let character = self.text[start..offset].encode_utf16().count();
+        Position::new(line as u32, character as u32)
+    }
+
+    /// Converts an LSP position into a byte offset, clamped to the end of
+    /// its line.
+    pub fn offset(&self, pos: Position) -> usize {
+        let Some(start) = self.line_starts.get(pos.line as usize).copied() else {
+            return self.text.len();
+        };
+        let mut units = 0;
+        for (idx, c) in self.text[start..].char_indices() {
+            if c == '\n' || units >= pos.character as usize {
+                return start + idx;
+            }
+            units += c.len_utf16();
+        }
+        self.text.len()
+    }
+
+    pub fn range(&self, span: Span) -> Range {
+        Range::new(self.position(span.start), self.position(span.end))
+    }
+
+    /// The identifier under `offset`, including when `offset` is just past
+    /// its last char.
+    pub fn ident_at(&self, offset: usize) -> Option<(usize, &Token)> {
+        self.tokens.iter().enumerate().find(|(_, t)| {
+            t.kind == TokenKind::Ident && t.span.start <= offset && offset <= t.span.end
+        })
+    }
+
+    /// The index of the first token starting at or after `offset`.
+    pub fn token_index_after(&self, offset: usize) -> usize {
+        self.tokens.partition_point(|t| t.span.start < offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("ab\ncd", 4, Position::new(1, 1); "second line")]
+    #[test_case("é😀x", 6, Position::new(0, 3); "utf16 columns")]
+    #[test_case("ab", 9, Position::new(0, 2); "clamped to end")]
+    fn test_position(text: &str, offset: usize, exp: Position) {
+        let doc = Document::new(text.into());
+        assert_eq!(doc.position(offset), exp);
+        assert_eq!(doc.offset(exp), offset.min(text.len()));
+    }
+}
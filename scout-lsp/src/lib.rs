@@ -0,0 +1,6 @@
+//! A language server for `.sct` files, providing diagnostics,
+//! go-to-definition, hover, completion and document symbols.
+
+pub mod analysis;
+pub mod document;
+pub mod server;
@@ -0,0 +1,6 @@
+fn main() {
+    if let Err(e) = scout_lsp::server::run() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+}
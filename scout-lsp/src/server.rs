@@ -0,0 +1,167 @@
+use std::{collections::HashMap, error::Error};
+
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+        Notification as NotificationTrait, PublishDiagnostics,
+    },
+    request::{Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as _},
+    CompletionOptions, CompletionParams, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DocumentSymbolParams, DocumentSymbolResponse, GotoDefinitionParams,
+    GotoDefinitionResponse, HoverParams, HoverProviderCapability, OneOf, PublishDiagnosticsParams,
+    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+
+use crate::{analysis, document::Document};
+
+pub type ServerResult<T> = Result<T, Box<dyn Error + Sync + Send>>;
+
+/// Runs the server over stdin and stdout until the client shuts it down.
+pub fn run() -> ServerResult<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let caps = serde_json::to_value(capabilities())?;
+    connection.initialize(caps)?;
+    Server::new(&connection).run()?;
+    // The writer thread only stops once every sender is gone.
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![":".into()]),
+            ..Default::default()
+        }),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    }
+}
+
+struct Server<'a> {
+    connection: &'a Connection,
+    docs: HashMap<Url, Document>,
+}
+
+impl<'a> Server<'a> {
+    fn new(connection: &'a Connection) -> Self {
+        Self {
+            connection,
+            docs: HashMap::new(),
+        }
+    }
+
+    fn run(&mut self) -> ServerResult<()> {
+        for msg in &self.connection.receiver {
+            match msg {
+                Message::Request(req) => {
+                    if self.connection.handle_shutdown(&req)? {
+                        return Ok(());
+                    }
+                    let resp = self.handle_request(req)?;
+                    self.connection.sender.send(Message::Response(resp))?;
+                }
+                Message::Notification(not) => self.handle_notification(not)?,
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, req: Request) -> ServerResult<Response> {
+        let id = req.id.clone();
+        let result = match req.method.as_str() {
+            GotoDefinition::METHOD => {
+                let params: GotoDefinitionParams = serde_json::from_value(req.params)?;
+                let pos = params.text_document_position_params;
+                let loc = self.docs.get(&pos.text_document.uri).and_then(|doc| {
+                    analysis::definition(doc, &pos.text_document.uri, pos.position)
+                });
+                serde_json::to_value(loc.map(GotoDefinitionResponse::Scalar))?
+            }
+            HoverRequest::METHOD => {
+                let params: HoverParams = serde_json::from_value(req.params)?;
+                let pos = params.text_document_position_params;
+                let hover = self
+                    .docs
+                    .get(&pos.text_document.uri)
+                    .and_then(|doc| analysis::hover(doc, pos.position));
+                serde_json::to_value(hover)?
+            }
+            Completion::METHOD => {
+                let params: CompletionParams = serde_json::from_value(req.params)?;
+                let pos = params.text_document_position;
+                let items = self
+                    .docs
+                    .get(&pos.text_document.uri)
+                    .map(|doc| analysis::completion(doc, &pos.text_document.uri, pos.position))
+                    .unwrap_or_default();
+                serde_json::to_value(items)?
+            }
+            DocumentSymbolRequest::METHOD => {
+                let params: DocumentSymbolParams = serde_json::from_value(req.params)?;
+                let symbols = self
+                    .docs
+                    .get(&params.text_document.uri)
+                    .map(analysis::symbols)
+                    .unwrap_or_default();
+                serde_json::to_value(DocumentSymbolResponse::Nested(symbols))?
+            }
+            _ => {
+                let code = lsp_server::ErrorCode::MethodNotFound as i32;
+                let msg = format!("unsupported method `{}`", req.method);
+                return Ok(Response::new_err(id, code, msg));
+            }
+        };
+        Ok(Response::new_ok(id, result))
+    }
+
+    fn handle_notification(&mut self, not: Notification) -> ServerResult<()> {
+        match not.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = serde_json::from_value(not.params)?;
+                let doc = params.text_document;
+                self.update(doc.uri, doc.text)?;
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams = serde_json::from_value(not.params)?;
+                // Documents are synced in full, so the last change is the
+                // whole text.
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.update(params.text_document.uri, change.text)?;
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(not.params)?;
+                let uri = params.text_document.uri;
+                self.docs.remove(&uri);
+                self.publish(uri, Vec::new())?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, uri: Url, text: String) -> ServerResult<()> {
+        let doc = Document::new(text);
+        let diagnostics = analysis::diagnostics(&doc);
+        self.docs.insert(uri.clone(), doc);
+        self.publish(uri, diagnostics)
+    }
+
+    fn publish(&self, uri: Url, diagnostics: Vec<lsp_types::Diagnostic>) -> ServerResult<()> {
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        };
+        let not = Notification::new(PublishDiagnostics::METHOD.into(), params);
+        self.connection.sender.send(Message::Notification(not))?;
+        Ok(())
+    }
+}
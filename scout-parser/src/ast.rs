@@ -1,44 +1,117 @@
-use std::{collections::HashMap, fmt::Display};
+use std::fmt::Display;
 
-use scout_lexer::Token;
+use scout_lexer::{Span, Token, TokenKind};
+use serde::{Deserialize, Serialize};
+
+pub mod fold;
+pub mod visit;
+pub mod visit_mut;
+
+pub use fold::Fold;
+pub use visit::Visitor;
+pub use visit_mut::VisitorMut;
 
 #[derive(Debug)]
 pub enum NodeKind {
     Program(Program),
-    Stmt(StmtKind),
-    Expr(ExprKind),
+    Stmt(Stmt),
+    Expr(Expr),
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Program {
-    pub stmts: Vec<StmtKind>,
+    pub stmts: Vec<Stmt>,
+}
+
+/// A statement along with the region of source it was parsed from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl From<StmtKind> for Stmt {
+    fn from(kind: StmtKind) -> Self {
+        Self::new(kind, Span::default())
+    }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// Spans are ignored so that structurally identical trees compare
+/// equal regardless of where they were parsed.
+impl PartialEq for Stmt {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+/// An expression along with the region of source it was parsed from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl From<ExprKind> for Expr {
+    fn from(kind: ExprKind) -> Self {
+        Self::new(kind, Span::default())
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum StmtKind {
-    Assign(ExprKind, ExprKind, bool),
+    /// `x = 1`, `global x = 1` when the bool is set, or `x: number = 1`
+    /// with a type annotation.
+    Assign(Expr, Expr, bool, Option<TypeExpr>),
+    Break,
+    Continue,
     Crawl(CrawlLiteral),
-    Expr(ExprKind),
+    Expr(Expr),
     ForLoop(ForLoop),
-    WhileLoop(ExprKind, Block),
+    WhileLoop(Expr, Block),
     Func(FuncDef, bool),
-    Goto(ExprKind),
+    Goto(Expr),
     IfElse(IfElseLiteral),
-    Return(Option<ExprKind>),
-    Scrape(HashLiteral),
+    /// `let x = 1`, or `const X = 1` when the bool is set, with an
+    /// optional type annotation.
+    Let(Expr, Expr, bool, Option<TypeExpr>),
+    Return(Option<Expr>),
+    /// `scrape { ... }`, or `scrape Product { ... }` to check the
+    /// fields against a declared type.
+    Scrape(Option<Identifier>, HashLiteral),
     Screenshot(String),
-    TryCatch(Block, Option<Block>),
-    Use(ExprKind),
+    Throw(Expr),
+    TypeDecl(TypeDecl),
+    /// `try`, its optional `catch` and optional `finally` blocks.
+    TryCatch(Block, Option<CatchLiteral>, Option<Block>),
+    Use(Expr),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum ExprKind {
     // Literals
     Str(String),
     Number(f64),
     Boolean(bool),
     Ident(Identifier),
-    List(Vec<ExprKind>),
+    List(Vec<Expr>),
     Map(HashLiteral),
     Null,
 
@@ -48,39 +121,56 @@ pub enum ExprKind {
 
     // Rest
     Call(CallLiteral),
-    Chain(Vec<ExprKind>),
-    Infix(Box<ExprKind>, Token, Box<ExprKind>),
-    Prefix(Box<ExprKind>, Token),
-}
-
-#[derive(Debug, PartialEq, Clone)]
+    Lambda(LambdaLiteral),
+    Match(MatchLiteral),
+    /// `Product { name: "a" }`, a map checked against a declared type.
+    Record(Identifier, HashLiteral),
+    /// `if cond then a else b`
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// Field access `m.key`.
+    Field(Box<Expr>, Identifier),
+    /// Method-call sugar `recv.f(a)`, which calls `f` with `recv` piped
+    /// in as the first argument.
+    MethodCall(Box<Expr>, CallLiteral),
+    Chain(Vec<Expr>),
+    /// A chain joined by `?|>`, which evaluates to null instead of
+    /// calling the next fn when the piped value is null.
+    SafeChain(Vec<Expr>),
+    Infix(Box<Expr>, Token, Box<Expr>),
+    Prefix(Box<Expr>, Token),
+    /// `"page ${n} of ${total}"`, the text and the interpolated exprs in
+    /// order. Each value is appended as `print` would show it.
+    Interp(Vec<Expr>),
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct CallLiteral {
     pub ident: Identifier,
-    pub args: Vec<ExprKind>,
+    pub args: Vec<Expr>,
     pub kwargs: Vec<Kwarg>,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Kwarg {
     pub ident: Identifier,
-    pub expr: ExprKind,
+    pub expr: Expr,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct CrawlLiteral {
     pub bindings: Option<CrawlBindings>,
-    pub filter: Option<ExprKind>,
+    pub filter: Option<Expr>,
     pub body: Block,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct CrawlBindings {
     pub link: Identifier,
     pub depth: Identifier,
 }
 
 impl CrawlLiteral {
-    pub fn new(bindings: Option<CrawlBindings>, filter: Option<ExprKind>, body: Block) -> Self {
+    pub fn new(bindings: Option<CrawlBindings>, filter: Option<Expr>, body: Block) -> Self {
         Self {
             bindings,
             filter,
@@ -89,7 +179,8 @@ impl CrawlLiteral {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Identifier {
     pub name: String,
 }
@@ -100,28 +191,30 @@ impl Identifier {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct IfElseLiteral {
     pub if_lit: IfLiteral,
     pub elifs: Vec<IfLiteral>,
     pub else_lit: Option<ElseLiteral>,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct IfLiteral {
-    pub cond: ExprKind,
+    pub cond: Expr,
     pub block: Block,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct ElseLiteral {
     pub block: Block,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct FuncDef {
     pub ident: Identifier,
     pub params: Vec<FnParam>,
+    /// The `-> type` annotation, if any.
+    pub ret: Option<TypeExpr>,
     pub body: Block,
 }
 
@@ -130,20 +223,65 @@ impl FuncDef {
         Self {
             ident,
             params,
+            ret: None,
             body,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// An anonymous function, `fn(a, b = 1) do ... end`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct LambdaLiteral {
+    pub params: Vec<FnParam>,
+    pub ret: Option<TypeExpr>,
+    pub body: Block,
+}
+
+impl LambdaLiteral {
+    pub fn new(params: Vec<FnParam>, body: Block) -> Self {
+        Self {
+            params,
+            ret: None,
+            body,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct FnParam {
     pub ident: Identifier,
-    pub default: Option<ExprKind>,
+    /// The `: type` annotation, if any.
+    pub ty: Option<TypeExpr>,
+    pub default: Option<Expr>,
+    pub kind: ParamKind,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum ParamKind {
+    Positional,
+    /// `*rest`, which collects any extra positional args into a list.
+    Rest,
+    /// `**opts`, which collects any unmatched kwargs into a map.
+    Kwargs,
 }
 
 impl FnParam {
-    pub fn new(ident: Identifier, default: Option<ExprKind>) -> Self {
-        Self { ident, default }
+    pub fn new(ident: Identifier, default: Option<Expr>) -> Self {
+        Self {
+            ident,
+            ty: None,
+            default,
+            kind: ParamKind::Positional,
+        }
+    }
+
+    pub fn variadic(ident: Identifier, kind: ParamKind) -> Self {
+        Self {
+            ident,
+            ty: None,
+            default: None,
+            kind,
+        }
     }
 }
 
@@ -158,14 +296,27 @@ impl SelectLiteral {
     }
 }
 
-#[derive(Default, Debug, PartialEq, Clone)]
+/// The key of a map or scrape literal entry.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum MapKey {
+    /// A bare `key` or quoted `"content-type"` key.
+    Ident(Identifier),
+    /// A `[expr]` key, evaluated to a string at runtime.
+    Computed(Expr),
+}
+
+/// Map entries in the order they were written.
+#[derive(Default, Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct HashLiteral {
-    pub pairs: HashMap<Identifier, ExprKind>,
+    pub pairs: Vec<(MapKey, Expr)>,
 }
 
-impl From<Vec<(Identifier, ExprKind)>> for HashLiteral {
-    fn from(value: Vec<(Identifier, ExprKind)>) -> Self {
-        let pairs = HashMap::from_iter(value.iter().map(|(i, s)| (i.clone(), s.clone())));
+impl From<Vec<(Identifier, Expr)>> for HashLiteral {
+    fn from(value: Vec<(Identifier, Expr)>) -> Self {
+        let pairs = value
+            .into_iter()
+            .map(|(i, s)| (MapKey::Ident(i), s))
+            .collect();
         Self { pairs }
     }
 }
@@ -176,37 +327,105 @@ impl Display for Identifier {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// `for a, b in <iterable> do <block> end`. With more than one
+/// identifier, each item is destructured like `[a, b] = item`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct ForLoop {
-    pub ident: Identifier,
-    pub iterable: ExprKind,
+    pub idents: Vec<Identifier>,
+    pub iterable: Expr,
     pub block: Block,
 }
 
 impl ForLoop {
-    pub fn new(ident: Identifier, iterable: ExprKind, block: Block) -> Self {
+    pub fn new(idents: Vec<Identifier>, iterable: Expr, block: Block) -> Self {
         Self {
-            ident,
+            idents,
             iterable,
             block,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Default)]
+/// `type Product { name: str, price: number? }`
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TypeDecl {
+    pub ident: Identifier,
+    pub fields: Vec<(Identifier, TypeExpr)>,
+}
+
+/// A type name such as `str`, or `str?` when null is also allowed.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TypeExpr {
+    pub name: Identifier,
+    pub optional: bool,
+}
+
+impl TypeExpr {
+    pub fn new(name: Identifier, optional: bool) -> Self {
+        Self { name, optional }
+    }
+}
+
+/// `catch e do <block>`, where the error binding is optional.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct CatchLiteral {
+    pub binding: Option<Identifier>,
+    pub block: Block,
+}
+
+impl CatchLiteral {
+    pub fn new(binding: Option<Identifier>, block: Block) -> Self {
+        Self { binding, block }
+    }
+}
+
+/// `match <subject> case <pattern> [if <guard>] do <block> ... end`
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct MatchLiteral {
+    pub subject: Box<Expr>,
+    pub arms: Vec<MatchArm>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub block: Block,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum Pattern {
+    /// `_`, which matches anything.
+    Wildcard,
+    /// A name, which matches anything and binds it.
+    Binding(Identifier),
+    /// A string, number, boolean or null literal, matched by equality.
+    Literal(Expr),
+    /// `[a, b]`, which matches lists of exactly that length.
+    List(Vec<Pattern>),
+    /// `{ key: pattern }`, which matches maps containing every listed key.
+    Map(Vec<(Identifier, Pattern)>),
+}
+
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
 pub struct Block {
-    pub stmts: Vec<StmtKind>,
+    pub stmts: Vec<Stmt>,
 }
 
 impl Block {
-    pub fn new(stmts: Vec<StmtKind>) -> Self {
+    pub fn new(stmts: Vec<Stmt>) -> Self {
         Self { stmts }
     }
 }
 
 impl std::fmt::Display for FnParam {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.ident)?;
+        match self.kind {
+            ParamKind::Positional => {}
+            ParamKind::Rest => write!(f, "*")?,
+            ParamKind::Kwargs => write!(f, "**")?,
+        }
+        write!(f, "{}{}", self.ident, annotation(&self.ty))?;
         if let Some(default) = &self.default {
             write!(f, " = {default}")?;
         }
@@ -215,41 +434,57 @@ impl std::fmt::Display for FnParam {
     }
 }
 
+fn annotation(ty: &Option<TypeExpr>) -> String {
+    match ty {
+        Some(ty) => format!(": {ty}"),
+        None => String::new(),
+    }
+}
+
+fn ret_to_string(ret: &Option<TypeExpr>) -> String {
+    match ret {
+        Some(ty) => format!(" -> {ty}"),
+        None => String::new(),
+    }
+}
+
+fn params_to_string(params: &[FnParam]) -> String {
+    params
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
 impl std::fmt::Display for FuncDef {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut param_str = String::new();
-        for (idx, param) in self.params.iter().enumerate() {
-            param_str.push_str(param.to_string().as_str());
-            if idx != self.params.len() - 1 {
-                param_str.push_str(", ");
-            }
-        }
-        writeln!(f, "def {}({param_str}) do\n{}\nend", self.ident, self.body)
+        let param_str = params_to_string(&self.params);
+        let ret = ret_to_string(&self.ret);
+        writeln!(
+            f,
+            "def {}({param_str}){ret} do\n{}\nend",
+            self.ident, self.body
+        )
     }
 }
 
-impl std::fmt::Display for CallLiteral {
+impl std::fmt::Display for LambdaLiteral {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}(", self.ident)?;
-        for (idx, arg) in self.args.iter().enumerate() {
-            write!(f, "{arg}")?;
-            if idx != self.args.len() - 1 {
-                write!(f, ", ")?;
-            }
-        }
-
-        if !self.kwargs.is_empty() {
-            write!(f, ", ")?;
-
-            for (idx, kwarg) in self.kwargs.iter().enumerate() {
-                write!(f, "{kwarg}")?;
-                if idx != self.kwargs.len() - 1 {
-                    write!(f, ", ")?;
-                }
-            }
-        }
+        let param_str = params_to_string(&self.params);
+        let ret = ret_to_string(&self.ret);
+        write!(f, "fn({param_str}){ret} do\n{}end", self.body)
+    }
+}
 
-        write!(f, ")")
+impl std::fmt::Display for CallLiteral {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let args = self
+            .args
+            .iter()
+            .map(|a| a.to_string())
+            .chain(self.kwargs.iter().map(|k| k.to_string()))
+            .collect::<Vec<String>>();
+        write!(f, "{}({})", self.ident, args.join(", "))
     }
 }
 
@@ -259,8 +494,29 @@ impl std::fmt::Display for Kwarg {
     }
 }
 
+impl std::fmt::Display for MapKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapKey::Ident(ident) => {
+                let mut chars = ident.name.chars();
+                let bare = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+                    && chars.all(|c| c.is_alphanumeric() || c == '_');
+                if bare {
+                    write!(f, "{ident}")
+                } else {
+                    write!(f, r#""{}""#, escape_str(&ident.name))
+                }
+            }
+            MapKey::Computed(expr) => write!(f, "[{expr}]"),
+        }
+    }
+}
+
 impl std::fmt::Display for HashLiteral {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.pairs.is_empty() {
+            return write!(f, "{{}}");
+        }
         write!(f, "{{ ")?;
         for (idx, (i, o)) in self.pairs.iter().enumerate() {
             write!(f, "{}: {}", i, o)?;
@@ -272,20 +528,118 @@ impl std::fmt::Display for HashLiteral {
     }
 }
 
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Pattern::*;
+        match self {
+            Wildcard => write!(f, "_"),
+            Binding(ident) => write!(f, "{ident}"),
+            Literal(expr) => write!(f, "{expr}"),
+            List(pats) => {
+                let pats = pats.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+                write!(f, "[{}]", pats.join(", "))
+            }
+            Map(pairs) => {
+                let pairs = pairs
+                    .iter()
+                    .map(|(k, p)| format!("{}: {p}", MapKey::Ident(k.clone())))
+                    .collect::<Vec<_>>();
+                write!(f, "{{ {} }}", pairs.join(", "))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for TypeExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if self.optional {
+            write!(f, "?")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for TypeDecl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields = self
+            .fields
+            .iter()
+            .map(|(k, ty)| format!("{}: {ty}", MapKey::Ident(k.clone())))
+            .collect::<Vec<_>>();
+        write!(f, "type {} {{ {} }}", self.ident, fields.join(", "))
+    }
+}
+
+impl std::fmt::Display for MatchLiteral {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "match {}", self.subject)?;
+        for arm in &self.arms {
+            write!(f, "case {}", arm.pattern)?;
+            if let Some(guard) = &arm.guard {
+                write!(f, " if {guard}")?;
+            }
+            writeln!(f, " do\n{}", arm.block)?;
+        }
+        write!(f, "end")
+    }
+}
+
 impl std::fmt::Display for Block {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for stmt in &self.stmts {
-            write!(f, "{stmt}\n")?;
+            writeln!(f, "{stmt}")?;
         }
         Ok(())
     }
 }
 
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl std::fmt::Display for Stmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+fn write_chain(f: &mut std::fmt::Formatter<'_>, exprs: &[Expr], sep: &str) -> std::fmt::Result {
+    for (i, expr) in exprs.iter().enumerate() {
+        write!(f, "{expr}")?;
+        if i != exprs.len() - 1 {
+            write!(f, "{sep}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Escapes a string so that it lexes back to the same value.
+pub(crate) fn escape_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            '$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
 impl std::fmt::Display for ExprKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use ExprKind::*;
         match self {
-            Str(s) => write!(f, r#""{s}""#),
+            Str(s) => write!(f, r#""{}""#, escape_str(s)),
             Number(n) => write!(f, "{n}"),
             Boolean(b) => write!(f, "{b}"),
             Ident(ident) => write!(f, "{ident}"),
@@ -303,25 +657,40 @@ impl std::fmt::Display for ExprKind {
             Map(hash) => write!(f, "{hash}"),
             Null => write!(f, "null"),
             Select(s, mb_ident) => match mb_ident {
-                Some(ident) => write!(f, r#"$({ident})"{s}""#),
-                None => write!(f, r#"$"{s}""#),
+                Some(ident) => write!(f, r#"$({ident})"{}""#, escape_str(s)),
+                None => write!(f, r#"$"{}""#, escape_str(s)),
             },
             SelectAll(s, mb_ident) => match mb_ident {
-                Some(ident) => write!(f, r#"$$({ident})"{s}""#),
-                None => write!(f, r#"$$"{s}""#),
+                Some(ident) => write!(f, r#"$$({ident})"{}""#, escape_str(s)),
+                None => write!(f, r#"$$"{}""#, escape_str(s)),
             },
             Call(lit) => write!(f, "{lit}"),
-            Chain(exprs) => {
-                for (i, expr) in exprs.iter().enumerate() {
-                    write!(f, "{expr}")?;
-                    if i != exprs.len() - 1 {
-                        write!(f, " |> ")?;
+            Lambda(lit) => write!(f, "{lit}"),
+            Match(lit) => write!(f, "{lit}"),
+            Record(ty, hash) => write!(f, "{ty} {hash}"),
+            Conditional(cond, then, otherwise) => {
+                write!(f, "if {cond} then {then} else {otherwise}")
+            }
+            Field(obj, field) => write!(f, "{obj}.{field}"),
+            MethodCall(obj, lit) => write!(f, "{obj}.{lit}"),
+            Chain(exprs) => write_chain(f, exprs, " |> "),
+            SafeChain(exprs) => write_chain(f, exprs, " ?|> "),
+            Infix(lhs, op, rhs) => match op.kind {
+                TokenKind::LBracket => write!(f, "{lhs}[{rhs}]"),
+                TokenKind::DbColon => write!(f, "{lhs}::{rhs}"),
+                _ => write!(f, "{lhs} {} {rhs}", op.literal),
+            },
+            Prefix(rhs, op) => write!(f, "{}{rhs}", op.literal),
+            Interp(parts) => {
+                write!(f, "\"")?;
+                for part in parts {
+                    match &part.kind {
+                        Str(s) => write!(f, "{}", escape_str(s))?,
+                        _ => write!(f, "${{{part}}}")?,
                     }
                 }
-                Ok(())
+                write!(f, "\"")
             }
-            Infix(lhs, op, rhs) => write!(f, "{lhs} {} {rhs}", op.literal),
-            Prefix(lhs, op) => write!(f, "{lhs} {}", op.literal),
         }
     }
 }
@@ -330,12 +699,15 @@ impl std::fmt::Display for StmtKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use StmtKind::*;
         match self {
-            Assign(lhs, rhs, g) => {
+            Assign(lhs, rhs, g, ty) => {
                 if *g {
-                    write!(f, "global {lhs} = {rhs}")
-                } else {
-                    write!(f, "{lhs} = {rhs}")
+                    write!(f, "global ")?;
                 }
+                write!(f, "{lhs}{} = {rhs}", annotation(ty))
+            }
+            Let(lhs, rhs, constant, ty) => {
+                let kw = if *constant { "const" } else { "let" };
+                write!(f, "{kw} {lhs}{} = {rhs}", annotation(ty))
             }
             Crawl(lit) => {
                 write!(f, "crawl ")?;
@@ -345,17 +717,23 @@ impl std::fmt::Display for StmtKind {
                 }
 
                 if let Some(filter) = &lit.filter {
-                    write!(f, "where {filter}")?;
+                    write!(f, "where {filter} ")?;
                 }
 
                 write!(f, "do\n{}end\n", lit.body)
             }
             Expr(expr) => write!(f, "{expr}"),
             ForLoop(floop) => {
+                let idents = floop
+                    .idents
+                    .iter()
+                    .map(|i| i.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
                 write!(
                     f,
-                    "for {} in {} do\n{}end\n",
-                    floop.ident, floop.iterable, floop.block
+                    "for {idents} in {} do\n{}end\n",
+                    floop.iterable, floop.block
                 )
             }
             WhileLoop(cond, block) => write!(f, "while {cond} do\n{block}end\n"),
@@ -377,19 +755,30 @@ impl std::fmt::Display for StmtKind {
                 }
                 writeln!(f, "end")
             }
+            Break => write!(f, "break"),
+            Continue => write!(f, "continue"),
             Return(mb_expr) => {
                 write!(f, "return")?;
                 if let Some(expr) = mb_expr {
-                    write!(f, "{expr}")?;
+                    write!(f, " {expr}")?;
                 }
                 Ok(())
             }
-            Scrape(hash) => write!(f, "scrape {hash}"),
-            Screenshot(s) => write!(f, "screenshot {s}"),
-            TryCatch(t, c) => {
+            Scrape(None, hash) => write!(f, "scrape {hash}"),
+            Scrape(Some(ty), hash) => write!(f, "scrape {ty} {hash}"),
+            TypeDecl(decl) => write!(f, "{decl}"),
+            Screenshot(s) => write!(f, r#"screenshot "{}""#, escape_str(s)),
+            Throw(expr) => write!(f, "throw {expr}"),
+            TryCatch(t, c, fin) => {
                 write!(f, "try\n{t}\n")?;
                 if let Some(catch) = c {
-                    write!(f, "catch\n{catch}\n")?;
+                    match &catch.binding {
+                        Some(ident) => write!(f, "catch {ident} do\n{}\n", catch.block)?,
+                        None => write!(f, "catch\n{}\n", catch.block)?,
+                    }
+                }
+                if let Some(fin) = fin {
+                    write!(f, "finally\n{fin}\n")?;
                 }
                 writeln!(f, "end")
             }
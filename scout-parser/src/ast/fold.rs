@@ -0,0 +1,390 @@
+//! Owning transformation of the AST.
+//!
+//! Every `fold_*` method defaults to the matching `walk_*` fn, which folds
+//! the children of the node and rebuilds it around them. Override a method
+//! to replace a node, and call its `walk_*` fn to fold its children first.
+
+use super::{
+    Block, CallLiteral, CatchLiteral, CrawlBindings, CrawlLiteral, ElseLiteral, Expr, ExprKind,
+    FnParam, ForLoop, FuncDef, HashLiteral, Identifier, IfElseLiteral, IfLiteral, Kwarg,
+    LambdaLiteral, MapKey, MatchArm, MatchLiteral, Pattern, Program, Stmt, StmtKind, TypeDecl,
+    TypeExpr,
+};
+
+pub trait Fold: Sized {
+    fn fold_program(&mut self, program: Program) -> Program {
+        walk_program(self, program)
+    }
+
+    fn fold_stmt(&mut self, stmt: Stmt) -> Stmt {
+        walk_stmt(self, stmt)
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        walk_expr(self, expr)
+    }
+
+    fn fold_block(&mut self, block: Block) -> Block {
+        walk_block(self, block)
+    }
+
+    fn fold_ident(&mut self, ident: Identifier) -> Identifier {
+        ident
+    }
+
+    fn fold_call(&mut self, call: CallLiteral) -> CallLiteral {
+        walk_call(self, call)
+    }
+
+    fn fold_kwarg(&mut self, kwarg: Kwarg) -> Kwarg {
+        walk_kwarg(self, kwarg)
+    }
+
+    fn fold_fn_def(&mut self, def: FuncDef) -> FuncDef {
+        walk_fn_def(self, def)
+    }
+
+    fn fold_lambda(&mut self, lambda: LambdaLiteral) -> LambdaLiteral {
+        walk_lambda(self, lambda)
+    }
+
+    fn fold_fn_param(&mut self, param: FnParam) -> FnParam {
+        walk_fn_param(self, param)
+    }
+
+    fn fold_hash(&mut self, hash: HashLiteral) -> HashLiteral {
+        walk_hash(self, hash)
+    }
+
+    fn fold_map_key(&mut self, key: MapKey) -> MapKey {
+        walk_map_key(self, key)
+    }
+
+    fn fold_if_else(&mut self, lit: IfElseLiteral) -> IfElseLiteral {
+        walk_if_else(self, lit)
+    }
+
+    fn fold_if(&mut self, lit: IfLiteral) -> IfLiteral {
+        walk_if(self, lit)
+    }
+
+    fn fold_else(&mut self, lit: ElseLiteral) -> ElseLiteral {
+        walk_else(self, lit)
+    }
+
+    fn fold_for_loop(&mut self, floop: ForLoop) -> ForLoop {
+        walk_for_loop(self, floop)
+    }
+
+    fn fold_crawl(&mut self, lit: CrawlLiteral) -> CrawlLiteral {
+        walk_crawl(self, lit)
+    }
+
+    fn fold_crawl_bindings(&mut self, bindings: CrawlBindings) -> CrawlBindings {
+        walk_crawl_bindings(self, bindings)
+    }
+
+    fn fold_catch(&mut self, lit: CatchLiteral) -> CatchLiteral {
+        walk_catch(self, lit)
+    }
+
+    fn fold_match(&mut self, lit: MatchLiteral) -> MatchLiteral {
+        walk_match(self, lit)
+    }
+
+    fn fold_match_arm(&mut self, arm: MatchArm) -> MatchArm {
+        walk_match_arm(self, arm)
+    }
+
+    fn fold_pattern(&mut self, pattern: Pattern) -> Pattern {
+        walk_pattern(self, pattern)
+    }
+
+    fn fold_type_decl(&mut self, decl: TypeDecl) -> TypeDecl {
+        walk_type_decl(self, decl)
+    }
+
+    fn fold_type_expr(&mut self, ty: TypeExpr) -> TypeExpr {
+        walk_type_expr(self, ty)
+    }
+}
+
+pub fn walk_program<F: Fold>(f: &mut F, program: Program) -> Program {
+    Program {
+        stmts: program.stmts.into_iter().map(|s| f.fold_stmt(s)).collect(),
+    }
+}
+
+pub fn walk_stmt<F: Fold>(f: &mut F, stmt: Stmt) -> Stmt {
+    use StmtKind::*;
+    let kind = match stmt.kind {
+        Assign(lhs, rhs, global, ty) => Assign(
+            f.fold_expr(lhs),
+            f.fold_expr(rhs),
+            global,
+            ty.map(|ty| f.fold_type_expr(ty)),
+        ),
+        Let(lhs, rhs, global, ty) => Let(
+            f.fold_expr(lhs),
+            f.fold_expr(rhs),
+            global,
+            ty.map(|ty| f.fold_type_expr(ty)),
+        ),
+        kind @ (Break | Continue | Screenshot(_)) => kind,
+        Crawl(lit) => Crawl(f.fold_crawl(lit)),
+        Expr(expr) => Expr(f.fold_expr(expr)),
+        Goto(expr) => Goto(f.fold_expr(expr)),
+        Throw(expr) => Throw(f.fold_expr(expr)),
+        Use(expr) => Use(f.fold_expr(expr)),
+        ForLoop(floop) => ForLoop(f.fold_for_loop(floop)),
+        WhileLoop(cond, block) => WhileLoop(f.fold_expr(cond), f.fold_block(block)),
+        Func(def, exported) => Func(f.fold_fn_def(def), exported),
+        IfElse(lit) => IfElse(f.fold_if_else(lit)),
+        Return(expr) => Return(expr.map(|e| f.fold_expr(e))),
+        Scrape(ty, hash) => Scrape(ty.map(|ty| f.fold_ident(ty)), f.fold_hash(hash)),
+        TypeDecl(decl) => TypeDecl(f.fold_type_decl(decl)),
+        TryCatch(try_b, catch, finally) => TryCatch(
+            f.fold_block(try_b),
+            catch.map(|c| f.fold_catch(c)),
+            finally.map(|b| f.fold_block(b)),
+        ),
+    };
+    Stmt::new(kind, stmt.span)
+}
+
+pub fn walk_expr<F: Fold>(f: &mut F, expr: Expr) -> Expr {
+    use ExprKind::*;
+    let kind = match expr.kind {
+        kind @ (Str(_) | Number(_) | Boolean(_) | Null) => kind,
+        Ident(ident) => Ident(f.fold_ident(ident)),
+        List(exprs) => List(fold_exprs(f, exprs)),
+        Interp(exprs) => Interp(fold_exprs(f, exprs)),
+        Chain(exprs) => Chain(fold_exprs(f, exprs)),
+        SafeChain(exprs) => SafeChain(fold_exprs(f, exprs)),
+        Map(hash) => Map(f.fold_hash(hash)),
+        Select(selector, scope) => Select(selector, scope.map(|s| f.fold_ident(s))),
+        SelectAll(selector, scope) => SelectAll(selector, scope.map(|s| f.fold_ident(s))),
+        Call(call) => Call(f.fold_call(call)),
+        Lambda(lambda) => Lambda(f.fold_lambda(lambda)),
+        Match(lit) => Match(f.fold_match(lit)),
+        Record(ty, hash) => Record(f.fold_ident(ty), f.fold_hash(hash)),
+        Conditional(cond, then, otherwise) => Conditional(
+            fold_boxed(f, cond),
+            fold_boxed(f, then),
+            fold_boxed(f, otherwise),
+        ),
+        Field(obj, field) => Field(fold_boxed(f, obj), f.fold_ident(field)),
+        MethodCall(obj, call) => MethodCall(fold_boxed(f, obj), f.fold_call(call)),
+        Infix(lhs, op, rhs) => Infix(fold_boxed(f, lhs), op, fold_boxed(f, rhs)),
+        Prefix(rhs, op) => Prefix(fold_boxed(f, rhs), op),
+    };
+    Expr::new(kind, expr.span)
+}
+
+fn fold_exprs<F: Fold>(f: &mut F, exprs: Vec<Expr>) -> Vec<Expr> {
+    exprs.into_iter().map(|e| f.fold_expr(e)).collect()
+}
+
+/// Folds a boxed expr, reusing its allocation.
+fn fold_boxed<F: Fold>(f: &mut F, mut expr: Box<Expr>) -> Box<Expr> {
+    *expr = f.fold_expr(*expr);
+    expr
+}
+
+pub fn walk_block<F: Fold>(f: &mut F, block: Block) -> Block {
+    Block::new(block.stmts.into_iter().map(|s| f.fold_stmt(s)).collect())
+}
+
+pub fn walk_call<F: Fold>(f: &mut F, call: CallLiteral) -> CallLiteral {
+    CallLiteral {
+        ident: f.fold_ident(call.ident),
+        args: fold_exprs(f, call.args),
+        kwargs: call.kwargs.into_iter().map(|k| f.fold_kwarg(k)).collect(),
+    }
+}
+
+pub fn walk_kwarg<F: Fold>(f: &mut F, kwarg: Kwarg) -> Kwarg {
+    Kwarg {
+        ident: f.fold_ident(kwarg.ident),
+        expr: f.fold_expr(kwarg.expr),
+    }
+}
+
+pub fn walk_fn_def<F: Fold>(f: &mut F, def: FuncDef) -> FuncDef {
+    FuncDef {
+        ident: f.fold_ident(def.ident),
+        params: def.params.into_iter().map(|p| f.fold_fn_param(p)).collect(),
+        ret: def.ret.map(|ty| f.fold_type_expr(ty)),
+        body: f.fold_block(def.body),
+    }
+}
+
+pub fn walk_lambda<F: Fold>(f: &mut F, lambda: LambdaLiteral) -> LambdaLiteral {
+    LambdaLiteral {
+        params: lambda
+            .params
+            .into_iter()
+            .map(|p| f.fold_fn_param(p))
+            .collect(),
+        ret: lambda.ret.map(|ty| f.fold_type_expr(ty)),
+        body: f.fold_block(lambda.body),
+    }
+}
+
+pub fn walk_fn_param<F: Fold>(f: &mut F, param: FnParam) -> FnParam {
+    FnParam {
+        ident: f.fold_ident(param.ident),
+        ty: param.ty.map(|ty| f.fold_type_expr(ty)),
+        default: param.default.map(|e| f.fold_expr(e)),
+        kind: param.kind,
+    }
+}
+
+pub fn walk_hash<F: Fold>(f: &mut F, hash: HashLiteral) -> HashLiteral {
+    HashLiteral {
+        pairs: hash
+            .pairs
+            .into_iter()
+            .map(|(key, val)| (f.fold_map_key(key), f.fold_expr(val)))
+            .collect(),
+    }
+}
+
+pub fn walk_map_key<F: Fold>(f: &mut F, key: MapKey) -> MapKey {
+    match key {
+        MapKey::Ident(ident) => MapKey::Ident(f.fold_ident(ident)),
+        MapKey::Computed(expr) => MapKey::Computed(f.fold_expr(expr)),
+    }
+}
+
+pub fn walk_if_else<F: Fold>(f: &mut F, lit: IfElseLiteral) -> IfElseLiteral {
+    IfElseLiteral {
+        if_lit: f.fold_if(lit.if_lit),
+        elifs: lit.elifs.into_iter().map(|e| f.fold_if(e)).collect(),
+        else_lit: lit.else_lit.map(|e| f.fold_else(e)),
+    }
+}
+
+pub fn walk_if<F: Fold>(f: &mut F, lit: IfLiteral) -> IfLiteral {
+    IfLiteral {
+        cond: f.fold_expr(lit.cond),
+        block: f.fold_block(lit.block),
+    }
+}
+
+pub fn walk_else<F: Fold>(f: &mut F, lit: ElseLiteral) -> ElseLiteral {
+    ElseLiteral {
+        block: f.fold_block(lit.block),
+    }
+}
+
+pub fn walk_for_loop<F: Fold>(f: &mut F, floop: ForLoop) -> ForLoop {
+    ForLoop {
+        idents: floop.idents.into_iter().map(|i| f.fold_ident(i)).collect(),
+        iterable: f.fold_expr(floop.iterable),
+        block: f.fold_block(floop.block),
+    }
+}
+
+pub fn walk_crawl<F: Fold>(f: &mut F, lit: CrawlLiteral) -> CrawlLiteral {
+    CrawlLiteral {
+        bindings: lit.bindings.map(|b| f.fold_crawl_bindings(b)),
+        filter: lit.filter.map(|e| f.fold_expr(e)),
+        body: f.fold_block(lit.body),
+    }
+}
+
+pub fn walk_crawl_bindings<F: Fold>(f: &mut F, bindings: CrawlBindings) -> CrawlBindings {
+    CrawlBindings {
+        link: f.fold_ident(bindings.link),
+        depth: f.fold_ident(bindings.depth),
+    }
+}
+
+pub fn walk_catch<F: Fold>(f: &mut F, lit: CatchLiteral) -> CatchLiteral {
+    CatchLiteral {
+        binding: lit.binding.map(|i| f.fold_ident(i)),
+        block: f.fold_block(lit.block),
+    }
+}
+
+pub fn walk_match<F: Fold>(f: &mut F, lit: MatchLiteral) -> MatchLiteral {
+    MatchLiteral {
+        subject: fold_boxed(f, lit.subject),
+        arms: lit.arms.into_iter().map(|a| f.fold_match_arm(a)).collect(),
+    }
+}
+
+pub fn walk_match_arm<F: Fold>(f: &mut F, arm: MatchArm) -> MatchArm {
+    MatchArm {
+        pattern: f.fold_pattern(arm.pattern),
+        guard: arm.guard.map(|e| f.fold_expr(e)),
+        block: f.fold_block(arm.block),
+    }
+}
+
+pub fn walk_pattern<F: Fold>(f: &mut F, pattern: Pattern) -> Pattern {
+    match pattern {
+        Pattern::Wildcard => Pattern::Wildcard,
+        Pattern::Binding(ident) => Pattern::Binding(f.fold_ident(ident)),
+        Pattern::Literal(expr) => Pattern::Literal(f.fold_expr(expr)),
+        Pattern::List(pats) => Pattern::List(pats.into_iter().map(|p| f.fold_pattern(p)).collect()),
+        Pattern::Map(pairs) => Pattern::Map(
+            pairs
+                .into_iter()
+                .map(|(key, pat)| (f.fold_ident(key), f.fold_pattern(pat)))
+                .collect(),
+        ),
+    }
+}
+
+pub fn walk_type_decl<F: Fold>(f: &mut F, decl: TypeDecl) -> TypeDecl {
+    TypeDecl {
+        ident: f.fold_ident(decl.ident),
+        fields: decl
+            .fields
+            .into_iter()
+            .map(|(field, ty)| (f.fold_ident(field), f.fold_type_expr(ty)))
+            .collect(),
+    }
+}
+
+pub fn walk_type_expr<F: Fold>(f: &mut F, ty: TypeExpr) -> TypeExpr {
+    TypeExpr {
+        name: f.fold_ident(ty.name),
+        optional: ty.optional,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+    use scout_lexer::Lexer;
+    use test_case::test_case;
+
+    fn parse(input: &str) -> Program {
+        Parser::new(Lexer::new(input)).parse_program().unwrap()
+    }
+
+    /// Replaces every `null` with `0`.
+    struct NullToZero;
+
+    impl Fold for NullToZero {
+        fn fold_expr(&mut self, expr: Expr) -> Expr {
+            match expr.kind {
+                ExprKind::Null => Expr::new(ExprKind::Number(0.), expr.span),
+                _ => walk_expr(self, expr),
+            }
+        }
+    }
+
+    #[test_case("x = [null, f(null, k = null)]", "x = [0, f(0, k = 0)]"; "nested exprs")]
+    #[test_case("def f(a = null) do\nreturn null\nend", "def f(a = 0) do\nreturn 0\nend"; "fn def")]
+    #[test_case("if null do\nelif null do\nelse\nnull\nend", "if 0 do\nelif 0 do\nelse\n0\nend"; "if else")]
+    #[test_case("x = match y case null do null end", "x = match y case 0 do 0 end"; "match arms")]
+    fn test_fold(input: &str, exp: &str) {
+        let folded = NullToZero.fold_program(parse(input));
+        assert_eq!(folded.stmts, parse(exp).stmts);
+    }
+}
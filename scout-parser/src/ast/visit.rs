@@ -0,0 +1,396 @@
+//! Read-only traversal of the AST.
+//!
+//! Every `visit_*` method defaults to the matching `walk_*` fn, which
+//! visits the children of the node. Override a method to act on a node,
+//! and call its `walk_*` fn to keep descending into it.
+
+use super::{
+    Block, CallLiteral, CatchLiteral, CrawlBindings, CrawlLiteral, ElseLiteral, Expr, ExprKind,
+    FnParam, ForLoop, FuncDef, HashLiteral, Identifier, IfElseLiteral, IfLiteral, Kwarg,
+    LambdaLiteral, MapKey, MatchArm, MatchLiteral, Pattern, Program, Stmt, StmtKind, TypeDecl,
+    TypeExpr,
+};
+
+pub trait Visitor<'ast>: Sized {
+    fn visit_program(&mut self, program: &'ast Program) {
+        walk_program(self, program)
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        walk_block(self, block)
+    }
+
+    fn visit_ident(&mut self, _ident: &'ast Identifier) {}
+
+    fn visit_call(&mut self, call: &'ast CallLiteral) {
+        walk_call(self, call)
+    }
+
+    fn visit_kwarg(&mut self, kwarg: &'ast Kwarg) {
+        walk_kwarg(self, kwarg)
+    }
+
+    fn visit_fn_def(&mut self, def: &'ast FuncDef) {
+        walk_fn_def(self, def)
+    }
+
+    fn visit_lambda(&mut self, lambda: &'ast LambdaLiteral) {
+        walk_lambda(self, lambda)
+    }
+
+    fn visit_fn_param(&mut self, param: &'ast FnParam) {
+        walk_fn_param(self, param)
+    }
+
+    fn visit_hash(&mut self, hash: &'ast HashLiteral) {
+        walk_hash(self, hash)
+    }
+
+    fn visit_map_key(&mut self, key: &'ast MapKey) {
+        walk_map_key(self, key)
+    }
+
+    fn visit_if_else(&mut self, lit: &'ast IfElseLiteral) {
+        walk_if_else(self, lit)
+    }
+
+    fn visit_if(&mut self, lit: &'ast IfLiteral) {
+        walk_if(self, lit)
+    }
+
+    fn visit_else(&mut self, lit: &'ast ElseLiteral) {
+        walk_else(self, lit)
+    }
+
+    fn visit_for_loop(&mut self, floop: &'ast ForLoop) {
+        walk_for_loop(self, floop)
+    }
+
+    fn visit_crawl(&mut self, lit: &'ast CrawlLiteral) {
+        walk_crawl(self, lit)
+    }
+
+    fn visit_crawl_bindings(&mut self, bindings: &'ast CrawlBindings) {
+        walk_crawl_bindings(self, bindings)
+    }
+
+    fn visit_catch(&mut self, lit: &'ast CatchLiteral) {
+        walk_catch(self, lit)
+    }
+
+    fn visit_match(&mut self, lit: &'ast MatchLiteral) {
+        walk_match(self, lit)
+    }
+
+    fn visit_match_arm(&mut self, arm: &'ast MatchArm) {
+        walk_match_arm(self, arm)
+    }
+
+    fn visit_pattern(&mut self, pattern: &'ast Pattern) {
+        walk_pattern(self, pattern)
+    }
+
+    fn visit_type_decl(&mut self, decl: &'ast TypeDecl) {
+        walk_type_decl(self, decl)
+    }
+
+    fn visit_type_expr(&mut self, ty: &'ast TypeExpr) {
+        walk_type_expr(self, ty)
+    }
+}
+
+pub fn walk_program<'ast, V: Visitor<'ast>>(v: &mut V, program: &'ast Program) {
+    for stmt in &program.stmts {
+        v.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_stmt<'ast, V: Visitor<'ast>>(v: &mut V, stmt: &'ast Stmt) {
+    use StmtKind::*;
+    match &stmt.kind {
+        Assign(lhs, rhs, _, ty) | Let(lhs, rhs, _, ty) => {
+            v.visit_expr(lhs);
+            if let Some(ty) = ty {
+                v.visit_type_expr(ty);
+            }
+            v.visit_expr(rhs);
+        }
+        Break | Continue | Screenshot(_) => {}
+        Crawl(lit) => v.visit_crawl(lit),
+        Expr(expr) | Goto(expr) | Throw(expr) | Use(expr) => v.visit_expr(expr),
+        ForLoop(floop) => v.visit_for_loop(floop),
+        WhileLoop(cond, block) => {
+            v.visit_expr(cond);
+            v.visit_block(block);
+        }
+        Func(def, _) => v.visit_fn_def(def),
+        IfElse(lit) => v.visit_if_else(lit),
+        Return(expr) => {
+            if let Some(expr) = expr {
+                v.visit_expr(expr);
+            }
+        }
+        Scrape(ty, hash) => {
+            if let Some(ty) = ty {
+                v.visit_ident(ty);
+            }
+            v.visit_hash(hash);
+        }
+        TypeDecl(decl) => v.visit_type_decl(decl),
+        TryCatch(try_b, catch, finally) => {
+            v.visit_block(try_b);
+            if let Some(catch) = catch {
+                v.visit_catch(catch);
+            }
+            if let Some(finally) = finally {
+                v.visit_block(finally);
+            }
+        }
+    }
+}
+
+pub fn walk_expr<'ast, V: Visitor<'ast>>(v: &mut V, expr: &'ast Expr) {
+    use ExprKind::*;
+    match &expr.kind {
+        Str(_) | Number(_) | Boolean(_) | Null => {}
+        Ident(ident) => v.visit_ident(ident),
+        List(exprs) | Chain(exprs) | SafeChain(exprs) | Interp(exprs) => {
+            for expr in exprs {
+                v.visit_expr(expr);
+            }
+        }
+        Map(hash) => v.visit_hash(hash),
+        Select(_, scope) | SelectAll(_, scope) => {
+            if let Some(scope) = scope {
+                v.visit_ident(scope);
+            }
+        }
+        Call(call) => v.visit_call(call),
+        Lambda(lambda) => v.visit_lambda(lambda),
+        Match(lit) => v.visit_match(lit),
+        Record(ty, hash) => {
+            v.visit_ident(ty);
+            v.visit_hash(hash);
+        }
+        Conditional(cond, then, otherwise) => {
+            v.visit_expr(cond);
+            v.visit_expr(then);
+            v.visit_expr(otherwise);
+        }
+        Field(obj, field) => {
+            v.visit_expr(obj);
+            v.visit_ident(field);
+        }
+        MethodCall(obj, call) => {
+            v.visit_expr(obj);
+            v.visit_call(call);
+        }
+        Infix(lhs, _, rhs) => {
+            v.visit_expr(lhs);
+            v.visit_expr(rhs);
+        }
+        Prefix(rhs, _) => v.visit_expr(rhs),
+    }
+}
+
+pub fn walk_block<'ast, V: Visitor<'ast>>(v: &mut V, block: &'ast Block) {
+    for stmt in &block.stmts {
+        v.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_call<'ast, V: Visitor<'ast>>(v: &mut V, call: &'ast CallLiteral) {
+    v.visit_ident(&call.ident);
+    for arg in &call.args {
+        v.visit_expr(arg);
+    }
+    for kwarg in &call.kwargs {
+        v.visit_kwarg(kwarg);
+    }
+}
+
+pub fn walk_kwarg<'ast, V: Visitor<'ast>>(v: &mut V, kwarg: &'ast Kwarg) {
+    v.visit_ident(&kwarg.ident);
+    v.visit_expr(&kwarg.expr);
+}
+
+pub fn walk_fn_def<'ast, V: Visitor<'ast>>(v: &mut V, def: &'ast FuncDef) {
+    v.visit_ident(&def.ident);
+    for param in &def.params {
+        v.visit_fn_param(param);
+    }
+    if let Some(ret) = &def.ret {
+        v.visit_type_expr(ret);
+    }
+    v.visit_block(&def.body);
+}
+
+pub fn walk_lambda<'ast, V: Visitor<'ast>>(v: &mut V, lambda: &'ast LambdaLiteral) {
+    for param in &lambda.params {
+        v.visit_fn_param(param);
+    }
+    if let Some(ret) = &lambda.ret {
+        v.visit_type_expr(ret);
+    }
+    v.visit_block(&lambda.body);
+}
+
+pub fn walk_fn_param<'ast, V: Visitor<'ast>>(v: &mut V, param: &'ast FnParam) {
+    v.visit_ident(&param.ident);
+    if let Some(ty) = &param.ty {
+        v.visit_type_expr(ty);
+    }
+    if let Some(default) = &param.default {
+        v.visit_expr(default);
+    }
+}
+
+pub fn walk_hash<'ast, V: Visitor<'ast>>(v: &mut V, hash: &'ast HashLiteral) {
+    for (key, val) in &hash.pairs {
+        v.visit_map_key(key);
+        v.visit_expr(val);
+    }
+}
+
+pub fn walk_map_key<'ast, V: Visitor<'ast>>(v: &mut V, key: &'ast MapKey) {
+    match key {
+        MapKey::Ident(ident) => v.visit_ident(ident),
+        MapKey::Computed(expr) => v.visit_expr(expr),
+    }
+}
+
+pub fn walk_if_else<'ast, V: Visitor<'ast>>(v: &mut V, lit: &'ast IfElseLiteral) {
+    v.visit_if(&lit.if_lit);
+    for elif in &lit.elifs {
+        v.visit_if(elif);
+    }
+    if let Some(else_lit) = &lit.else_lit {
+        v.visit_else(else_lit);
+    }
+}
+
+pub fn walk_if<'ast, V: Visitor<'ast>>(v: &mut V, lit: &'ast IfLiteral) {
+    v.visit_expr(&lit.cond);
+    v.visit_block(&lit.block);
+}
+
+pub fn walk_else<'ast, V: Visitor<'ast>>(v: &mut V, lit: &'ast ElseLiteral) {
+    v.visit_block(&lit.block);
+}
+
+pub fn walk_for_loop<'ast, V: Visitor<'ast>>(v: &mut V, floop: &'ast ForLoop) {
+    for ident in &floop.idents {
+        v.visit_ident(ident);
+    }
+    v.visit_expr(&floop.iterable);
+    v.visit_block(&floop.block);
+}
+
+pub fn walk_crawl<'ast, V: Visitor<'ast>>(v: &mut V, lit: &'ast CrawlLiteral) {
+    if let Some(bindings) = &lit.bindings {
+        v.visit_crawl_bindings(bindings);
+    }
+    if let Some(filter) = &lit.filter {
+        v.visit_expr(filter);
+    }
+    v.visit_block(&lit.body);
+}
+
+pub fn walk_crawl_bindings<'ast, V: Visitor<'ast>>(v: &mut V, bindings: &'ast CrawlBindings) {
+    v.visit_ident(&bindings.link);
+    v.visit_ident(&bindings.depth);
+}
+
+pub fn walk_catch<'ast, V: Visitor<'ast>>(v: &mut V, lit: &'ast CatchLiteral) {
+    if let Some(binding) = &lit.binding {
+        v.visit_ident(binding);
+    }
+    v.visit_block(&lit.block);
+}
+
+pub fn walk_match<'ast, V: Visitor<'ast>>(v: &mut V, lit: &'ast MatchLiteral) {
+    v.visit_expr(&lit.subject);
+    for arm in &lit.arms {
+        v.visit_match_arm(arm);
+    }
+}
+
+pub fn walk_match_arm<'ast, V: Visitor<'ast>>(v: &mut V, arm: &'ast MatchArm) {
+    v.visit_pattern(&arm.pattern);
+    if let Some(guard) = &arm.guard {
+        v.visit_expr(guard);
+    }
+    v.visit_block(&arm.block);
+}
+
+pub fn walk_pattern<'ast, V: Visitor<'ast>>(v: &mut V, pattern: &'ast Pattern) {
+    match pattern {
+        Pattern::Wildcard => {}
+        Pattern::Binding(ident) => v.visit_ident(ident),
+        Pattern::Literal(expr) => v.visit_expr(expr),
+        Pattern::List(pats) => {
+            for pat in pats {
+                v.visit_pattern(pat);
+            }
+        }
+        Pattern::Map(pairs) => {
+            for (key, pat) in pairs {
+                v.visit_ident(key);
+                v.visit_pattern(pat);
+            }
+        }
+    }
+}
+
+pub fn walk_type_decl<'ast, V: Visitor<'ast>>(v: &mut V, decl: &'ast TypeDecl) {
+    v.visit_ident(&decl.ident);
+    for (field, ty) in &decl.fields {
+        v.visit_ident(field);
+        v.visit_type_expr(ty);
+    }
+}
+
+pub fn walk_type_expr<'ast, V: Visitor<'ast>>(v: &mut V, ty: &'ast TypeExpr) {
+    v.visit_ident(&ty.name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+    use scout_lexer::Lexer;
+    use test_case::test_case;
+
+    #[derive(Default)]
+    struct Idents(Vec<String>);
+
+    impl<'ast> Visitor<'ast> for Idents {
+        fn visit_ident(&mut self, ident: &'ast Identifier) {
+            self.0.push(ident.name.clone());
+        }
+    }
+
+    #[test_case("x = f(a, k = b)", &["x", "f", "a", "k", "b"]; "call")]
+    #[test_case("def f(a: T = d) -> R do\nreturn a\nend", &["f", "a", "T", "d", "R", "a"]; "fn def")]
+    #[test_case("y = fn(p) do\nq\nend", &["y", "p", "q"]; "lambda")]
+    #[test_case("for a, b in l do\nend", &["a", "b", "l"]; "for loop")]
+    #[test_case("crawl l, d where d < n do\nend", &["l", "d", "d", "n"]; "crawl")]
+    #[test_case("try\nt\ncatch e do\nc\nfinally\nf\nend", &["t", "e", "c", "f"]; "try catch")]
+    #[test_case("x = match s case [a, _] if g do b case { k: c } do d end", &["x", "s", "a", "g", "b", "k", "c", "d"]; "match arms")]
+    #[test_case("scrape R { a: $(s)\"b\", [k]: o.m(v) }", &["R", "a", "s", "k", "o", "m", "v"]; "scrape")]
+    #[test_case("type T { a: str? }", &["T", "a", "str"]; "type decl")]
+    fn test_visit_idents(input: &str, exp: &[&str]) {
+        let prgm = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let mut idents = Idents::default();
+        idents.visit_program(&prgm);
+        assert_eq!(idents.0, exp);
+    }
+}
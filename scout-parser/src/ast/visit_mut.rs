@@ -0,0 +1,390 @@
+//! In-place traversal of the AST, for passes that rewrite nodes without
+//! changing their shape.
+//!
+//! Mirrors [`super::visit`]: every `visit_*` method defaults to the
+//! matching `walk_*` fn, which visits the children of the node. Override a
+//! method to act on a node, and call its `walk_*` fn to keep descending.
+
+use super::{
+    Block, CallLiteral, CatchLiteral, CrawlBindings, CrawlLiteral, ElseLiteral, Expr, ExprKind,
+    FnParam, ForLoop, FuncDef, HashLiteral, Identifier, IfElseLiteral, IfLiteral, Kwarg,
+    LambdaLiteral, MapKey, MatchArm, MatchLiteral, Pattern, Program, Stmt, StmtKind, TypeDecl,
+    TypeExpr,
+};
+
+pub trait VisitorMut: Sized {
+    fn visit_program(&mut self, program: &mut Program) {
+        walk_program(self, program)
+    }
+
+    fn visit_stmt(&mut self, stmt: &mut Stmt) {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_block(&mut self, block: &mut Block) {
+        walk_block(self, block)
+    }
+
+    fn visit_ident(&mut self, _ident: &mut Identifier) {}
+
+    fn visit_call(&mut self, call: &mut CallLiteral) {
+        walk_call(self, call)
+    }
+
+    fn visit_kwarg(&mut self, kwarg: &mut Kwarg) {
+        walk_kwarg(self, kwarg)
+    }
+
+    fn visit_fn_def(&mut self, def: &mut FuncDef) {
+        walk_fn_def(self, def)
+    }
+
+    fn visit_lambda(&mut self, lambda: &mut LambdaLiteral) {
+        walk_lambda(self, lambda)
+    }
+
+    fn visit_fn_param(&mut self, param: &mut FnParam) {
+        walk_fn_param(self, param)
+    }
+
+    fn visit_hash(&mut self, hash: &mut HashLiteral) {
+        walk_hash(self, hash)
+    }
+
+    fn visit_map_key(&mut self, key: &mut MapKey) {
+        walk_map_key(self, key)
+    }
+
+    fn visit_if_else(&mut self, lit: &mut IfElseLiteral) {
+        walk_if_else(self, lit)
+    }
+
+    fn visit_if(&mut self, lit: &mut IfLiteral) {
+        walk_if(self, lit)
+    }
+
+    fn visit_else(&mut self, lit: &mut ElseLiteral) {
+        walk_else(self, lit)
+    }
+
+    fn visit_for_loop(&mut self, floop: &mut ForLoop) {
+        walk_for_loop(self, floop)
+    }
+
+    fn visit_crawl(&mut self, lit: &mut CrawlLiteral) {
+        walk_crawl(self, lit)
+    }
+
+    fn visit_crawl_bindings(&mut self, bindings: &mut CrawlBindings) {
+        walk_crawl_bindings(self, bindings)
+    }
+
+    fn visit_catch(&mut self, lit: &mut CatchLiteral) {
+        walk_catch(self, lit)
+    }
+
+    fn visit_match(&mut self, lit: &mut MatchLiteral) {
+        walk_match(self, lit)
+    }
+
+    fn visit_match_arm(&mut self, arm: &mut MatchArm) {
+        walk_match_arm(self, arm)
+    }
+
+    fn visit_pattern(&mut self, pattern: &mut Pattern) {
+        walk_pattern(self, pattern)
+    }
+
+    fn visit_type_decl(&mut self, decl: &mut TypeDecl) {
+        walk_type_decl(self, decl)
+    }
+
+    fn visit_type_expr(&mut self, ty: &mut TypeExpr) {
+        walk_type_expr(self, ty)
+    }
+}
+
+pub fn walk_program<V: VisitorMut>(v: &mut V, program: &mut Program) {
+    for stmt in &mut program.stmts {
+        v.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_stmt<V: VisitorMut>(v: &mut V, stmt: &mut Stmt) {
+    use StmtKind::*;
+    match &mut stmt.kind {
+        Assign(lhs, rhs, _, ty) | Let(lhs, rhs, _, ty) => {
+            v.visit_expr(lhs);
+            if let Some(ty) = ty {
+                v.visit_type_expr(ty);
+            }
+            v.visit_expr(rhs);
+        }
+        Break | Continue | Screenshot(_) => {}
+        Crawl(lit) => v.visit_crawl(lit),
+        Expr(expr) | Goto(expr) | Throw(expr) | Use(expr) => v.visit_expr(expr),
+        ForLoop(floop) => v.visit_for_loop(floop),
+        WhileLoop(cond, block) => {
+            v.visit_expr(cond);
+            v.visit_block(block);
+        }
+        Func(def, _) => v.visit_fn_def(def),
+        IfElse(lit) => v.visit_if_else(lit),
+        Return(expr) => {
+            if let Some(expr) = expr {
+                v.visit_expr(expr);
+            }
+        }
+        Scrape(ty, hash) => {
+            if let Some(ty) = ty {
+                v.visit_ident(ty);
+            }
+            v.visit_hash(hash);
+        }
+        TypeDecl(decl) => v.visit_type_decl(decl),
+        TryCatch(try_b, catch, finally) => {
+            v.visit_block(try_b);
+            if let Some(catch) = catch {
+                v.visit_catch(catch);
+            }
+            if let Some(finally) = finally {
+                v.visit_block(finally);
+            }
+        }
+    }
+}
+
+pub fn walk_expr<V: VisitorMut>(v: &mut V, expr: &mut Expr) {
+    use ExprKind::*;
+    match &mut expr.kind {
+        Str(_) | Number(_) | Boolean(_) | Null => {}
+        Ident(ident) => v.visit_ident(ident),
+        List(exprs) | Chain(exprs) | SafeChain(exprs) | Interp(exprs) => {
+            for expr in exprs {
+                v.visit_expr(expr);
+            }
+        }
+        Map(hash) => v.visit_hash(hash),
+        Select(_, scope) | SelectAll(_, scope) => {
+            if let Some(scope) = scope {
+                v.visit_ident(scope);
+            }
+        }
+        Call(call) => v.visit_call(call),
+        Lambda(lambda) => v.visit_lambda(lambda),
+        Match(lit) => v.visit_match(lit),
+        Record(ty, hash) => {
+            v.visit_ident(ty);
+            v.visit_hash(hash);
+        }
+        Conditional(cond, then, otherwise) => {
+            v.visit_expr(cond);
+            v.visit_expr(then);
+            v.visit_expr(otherwise);
+        }
+        Field(obj, field) => {
+            v.visit_expr(obj);
+            v.visit_ident(field);
+        }
+        MethodCall(obj, call) => {
+            v.visit_expr(obj);
+            v.visit_call(call);
+        }
+        Infix(lhs, _, rhs) => {
+            v.visit_expr(lhs);
+            v.visit_expr(rhs);
+        }
+        Prefix(rhs, _) => v.visit_expr(rhs),
+    }
+}
+
+pub fn walk_block<V: VisitorMut>(v: &mut V, block: &mut Block) {
+    for stmt in &mut block.stmts {
+        v.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_call<V: VisitorMut>(v: &mut V, call: &mut CallLiteral) {
+    v.visit_ident(&mut call.ident);
+    for arg in &mut call.args {
+        v.visit_expr(arg);
+    }
+    for kwarg in &mut call.kwargs {
+        v.visit_kwarg(kwarg);
+    }
+}
+
+pub fn walk_kwarg<V: VisitorMut>(v: &mut V, kwarg: &mut Kwarg) {
+    v.visit_ident(&mut kwarg.ident);
+    v.visit_expr(&mut kwarg.expr);
+}
+
+pub fn walk_fn_def<V: VisitorMut>(v: &mut V, def: &mut FuncDef) {
+    v.visit_ident(&mut def.ident);
+    for param in &mut def.params {
+        v.visit_fn_param(param);
+    }
+    if let Some(ret) = &mut def.ret {
+        v.visit_type_expr(ret);
+    }
+    v.visit_block(&mut def.body);
+}
+
+pub fn walk_lambda<V: VisitorMut>(v: &mut V, lambda: &mut LambdaLiteral) {
+    for param in &mut lambda.params {
+        v.visit_fn_param(param);
+    }
+    if let Some(ret) = &mut lambda.ret {
+        v.visit_type_expr(ret);
+    }
+    v.visit_block(&mut lambda.body);
+}
+
+pub fn walk_fn_param<V: VisitorMut>(v: &mut V, param: &mut FnParam) {
+    v.visit_ident(&mut param.ident);
+    if let Some(ty) = &mut param.ty {
+        v.visit_type_expr(ty);
+    }
+    if let Some(default) = &mut param.default {
+        v.visit_expr(default);
+    }
+}
+
+pub fn walk_hash<V: VisitorMut>(v: &mut V, hash: &mut HashLiteral) {
+    for (key, val) in &mut hash.pairs {
+        v.visit_map_key(key);
+        v.visit_expr(val);
+    }
+}
+
+pub fn walk_map_key<V: VisitorMut>(v: &mut V, key: &mut MapKey) {
+    match key {
+        MapKey::Ident(ident) => v.visit_ident(ident),
+        MapKey::Computed(expr) => v.visit_expr(expr),
+    }
+}
+
+pub fn walk_if_else<V: VisitorMut>(v: &mut V, lit: &mut IfElseLiteral) {
+    v.visit_if(&mut lit.if_lit);
+    for elif in &mut lit.elifs {
+        v.visit_if(elif);
+    }
+    if let Some(else_lit) = &mut lit.else_lit {
+        v.visit_else(else_lit);
+    }
+}
+
+pub fn walk_if<V: VisitorMut>(v: &mut V, lit: &mut IfLiteral) {
+    v.visit_expr(&mut lit.cond);
+    v.visit_block(&mut lit.block);
+}
+
+pub fn walk_else<V: VisitorMut>(v: &mut V, lit: &mut ElseLiteral) {
+    v.visit_block(&mut lit.block);
+}
+
+pub fn walk_for_loop<V: VisitorMut>(v: &mut V, floop: &mut ForLoop) {
+    for ident in &mut floop.idents {
+        v.visit_ident(ident);
+    }
+    v.visit_expr(&mut floop.iterable);
+    v.visit_block(&mut floop.block);
+}
+
+pub fn walk_crawl<V: VisitorMut>(v: &mut V, lit: &mut CrawlLiteral) {
+    if let Some(bindings) = &mut lit.bindings {
+        v.visit_crawl_bindings(bindings);
+    }
+    if let Some(filter) = &mut lit.filter {
+        v.visit_expr(filter);
+    }
+    v.visit_block(&mut lit.body);
+}
+
+pub fn walk_crawl_bindings<V: VisitorMut>(v: &mut V, bindings: &mut CrawlBindings) {
+    v.visit_ident(&mut bindings.link);
+    v.visit_ident(&mut bindings.depth);
+}
+
+pub fn walk_catch<V: VisitorMut>(v: &mut V, lit: &mut CatchLiteral) {
+    if let Some(binding) = &mut lit.binding {
+        v.visit_ident(binding);
+    }
+    v.visit_block(&mut lit.block);
+}
+
+pub fn walk_match<V: VisitorMut>(v: &mut V, lit: &mut MatchLiteral) {
+    v.visit_expr(&mut lit.subject);
+    for arm in &mut lit.arms {
+        v.visit_match_arm(arm);
+    }
+}
+
+pub fn walk_match_arm<V: VisitorMut>(v: &mut V, arm: &mut MatchArm) {
+    v.visit_pattern(&mut arm.pattern);
+    if let Some(guard) = &mut arm.guard {
+        v.visit_expr(guard);
+    }
+    v.visit_block(&mut arm.block);
+}
+
+pub fn walk_pattern<V: VisitorMut>(v: &mut V, pattern: &mut Pattern) {
+    match pattern {
+        Pattern::Wildcard => {}
+        Pattern::Binding(ident) => v.visit_ident(ident),
+        Pattern::Literal(expr) => v.visit_expr(expr),
+        Pattern::List(pats) => {
+            for pat in pats {
+                v.visit_pattern(pat);
+            }
+        }
+        Pattern::Map(pairs) => {
+            for (key, pat) in pairs {
+                v.visit_ident(key);
+                v.visit_pattern(pat);
+            }
+        }
+    }
+}
+
+pub fn walk_type_decl<V: VisitorMut>(v: &mut V, decl: &mut TypeDecl) {
+    v.visit_ident(&mut decl.ident);
+    for (field, ty) in &mut decl.fields {
+        v.visit_ident(field);
+        v.visit_type_expr(ty);
+    }
+}
+
+pub fn walk_type_expr<V: VisitorMut>(v: &mut V, ty: &mut TypeExpr) {
+    v.visit_ident(&mut ty.name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+    use scout_lexer::Lexer;
+
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_ident(&mut self, ident: &mut Identifier) {
+            ident.name = ident.name.to_uppercase();
+        }
+    }
+
+    #[test]
+    fn test_visit_mut_rename() {
+        let parse = |s| Parser::new(Lexer::new(s)).parse_program().unwrap();
+        let mut prgm = parse("for a in b do\nc = f(a, k = $(e)\"d\")\nend");
+        Rename.visit_program(&mut prgm);
+        assert_eq!(
+            prgm.stmts,
+            parse("for A in B do\nC = F(A, K = $(E)\"d\")\nend").stmts
+        );
+    }
+}
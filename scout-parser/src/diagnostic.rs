@@ -0,0 +1,134 @@
+use scout_lexer::Span;
+
+/// A message tied to a region of source, rendered in the style of
+/// rustc errors:
+///
+/// ```text
+/// error: expected `do`, found identifier `x`
+///  --> script.sct:1:10
+///   |
+/// 1 | if a > 1 x
+///   |          ^
+///   = hint: blocks are opened with `do`, e.g. `if cond do ... end`
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub hint: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    #[default]
+    Error,
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            hint: None,
+        }
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let mut out = format!("{}: {}\n", self.severity, self.message);
+        let line_no = self.span.line.to_string();
+        let gutter = " ".repeat(line_no.len());
+        out.push_str(&format!("{gutter}--> {path}:{}\n", self.span));
+
+        if let Some(line) = source.lines().nth(self.span.line.saturating_sub(1)) {
+            // Keep tabs in the padding so the caret lines up with the excerpt.
+            let pad: String = line
+                .chars()
+                .take(self.span.col.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let rest = line.chars().count().saturating_sub(pad.chars().count());
+            let width = source
+                .get(self.span.start..self.span.end)
+                .map(|s| s.chars().take_while(|c| *c != '\n').count())
+                .unwrap_or(1)
+                .clamp(1, rest.max(1));
+
+            out.push_str(&format!("{gutter} |\n"));
+            out.push_str(&format!("{line_no} | {line}\n"));
+            out.push_str(&format!("{gutter} | {pad}{}\n", "^".repeat(width)));
+        }
+
+        if let Some(hint) = &self.hint {
+            out.push_str(&format!("{gutter} = hint: {hint}\n"));
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let src = "x = 1\nif a > 1 x\n";
+        let diag = Diagnostic::new(
+            "expected `do`, found identifier `x`",
+            Span::new(15, 16, 2, 10),
+        )
+        .with_hint("add a `do`");
+        let exp = "error: expected `do`, found identifier `x`
+ --> main.sct:2:10
+  |
+2 | if a > 1 x
+  |          ^
+  = hint: add a `do`
+";
+        assert_eq!(diag.render("main.sct", src), exp);
+    }
+
+    #[test]
+    fn test_render_out_of_bounds() {
+        let diag = Diagnostic::new("oops", Span::new(100, 104, 9, 3));
+        assert_eq!(
+            diag.render("main.sct", "x"),
+            "error: oops\n --> main.sct:9:3\n"
+        );
+    }
+
+    #[test]
+    fn test_render_warning() {
+        let diag = Diagnostic::new("unused variable `x`", Span::new(0, 1, 1, 1))
+            .with_severity(Severity::Warning);
+        let exp = "warning: unused variable `x`
+ --> main.sct:1:1
+  |
+1 | x = 1
+  | ^
+";
+        assert_eq!(diag.render("main.sct", "x = 1"), exp);
+    }
+}
@@ -0,0 +1,705 @@
+//! The canonical source formatter behind `scout fmt`.
+//!
+//! Formatting works from the parsed AST, so layout is normalized, while
+//! the source is consulted for the things the AST drops: comments, and
+//! literals such as `0xff`, `"""raw"""` and interpolated strings, which
+//! are kept exactly as written.
+
+use std::collections::HashSet;
+
+use scout_lexer::{Comment, Lexer, Span, Token, TokenKind};
+
+use crate::ast::{
+    escape_str, Block, CallLiteral, Expr, ExprKind, FnParam, HashLiteral, MapKey, MatchLiteral,
+    ParamKind, Pattern, Stmt, StmtKind, TypeDecl, TypeExpr,
+};
+use crate::{ParseError, Parser};
+
+const INDENT: &str = "    ";
+
+/// Lists, maps and type declarations wider than this are broken onto
+/// one line per entry.
+const MAX_WIDTH: usize = 80;
+
+/// Formats `source` in the canonical style. Fails with the parse errors
+/// if the source does not parse.
+pub fn format(source: &str) -> Result<String, Vec<ParseError>> {
+    let program = Parser::new(Lexer::new(source)).parse_program()?;
+
+    let mut lex = Lexer::new(source);
+    let mut tokens = Vec::new();
+    let mut literals = HashSet::new();
+    let mut interps = Vec::new();
+    loop {
+        let token = lex.next_token();
+        match token.kind {
+            TokenKind::EOF => break,
+            TokenKind::Str | TokenKind::Int | TokenKind::Float => {
+                literals.insert((token.span.start, token.span.end));
+            }
+            TokenKind::InterpStart => interps.push(token.span.start),
+            TokenKind::InterpEnd => {
+                if let Some(start) = interps.pop() {
+                    literals.insert((start, token.span.end));
+                }
+            }
+            _ => {}
+        }
+        tokens.push(token);
+    }
+
+    let mut printer = Printer {
+        source,
+        tokens,
+        literals,
+        comments: lex.comments().to_vec(),
+        next_comment: 0,
+        indent: 0,
+    };
+    Ok(printer.block(&program.stmts, usize::MAX))
+}
+
+struct Printer<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    /// Spans of the literals that are printed as written.
+    literals: HashSet<(usize, usize)>,
+    comments: Vec<Comment>,
+    /// The first comment that has not been printed yet.
+    next_comment: usize,
+    indent: usize,
+}
+
+impl Printer<'_> {
+    fn pad(&self) -> String {
+        INDENT.repeat(self.indent)
+    }
+
+    fn line_of(&self, pos: usize) -> usize {
+        let end = pos.min(self.source.len());
+        self.source[..end].matches('\n').count() + 1
+    }
+
+    fn token_after(&self, pos: usize) -> Option<&Token> {
+        let idx = self.tokens.partition_point(|t| t.span.start < pos);
+        self.tokens.get(idx)
+    }
+
+    /// Where the comments inside `block` end, which is the start of the
+    /// keyword closing it. `fallback` is used when the block is empty.
+    fn block_end(&self, block: &Block, fallback: usize) -> usize {
+        match block.stmts.last() {
+            Some(stmt) => self
+                .token_after(stmt.span.end)
+                .map_or(usize::MAX, |t| t.span.start),
+            None => fallback,
+        }
+    }
+
+    /// The start of the `end` that closes the construct spanning `span`.
+    fn closing(span: Span) -> usize {
+        span.end.saturating_sub("end".len())
+    }
+
+    fn fits(&self, flat: &str) -> bool {
+        !flat.contains('\n') && self.indent * INDENT.len() + flat.len() <= MAX_WIDTH
+    }
+
+    /// Prints every comment before `end` on its own line. A single blank
+    /// line is kept wherever the source had one or more.
+    fn comments_until(&mut self, end: usize, out: &mut String, last_line: &mut Option<usize>) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.span.start >= end {
+                break;
+            }
+            if last_line.is_some_and(|l| comment.span.line > l + 1) {
+                out.push('\n');
+            }
+            out.push_str(&format!("{}//{}\n", self.pad(), comment.text.trim_end()));
+            *last_line = Some(comment.span.line);
+            self.next_comment += 1;
+        }
+    }
+
+    /// Prints the comment after `end` if it is on the same `line`.
+    fn trailing_comment(&mut self, end: usize, line: usize, out: &mut String) {
+        if let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.span.start >= end && comment.span.line == line {
+                out.push_str(&format!(" //{}", comment.text.trim_end()));
+                self.next_comment += 1;
+            }
+        }
+    }
+
+    /// Returns true if a comment starts inside `span`.
+    fn has_comment_in(&self, span: Span) -> bool {
+        self.comments[self.next_comment..]
+            .iter()
+            .find(|c| c.span.start >= span.start)
+            .is_some_and(|c| c.span.start < span.end)
+    }
+
+    /// Prints statements at the current indent, one per line, along with
+    /// the comments before `end`.
+    fn block(&mut self, stmts: &[Stmt], end: usize) -> String {
+        let mut out = String::new();
+        let mut last_line = None;
+        for stmt in stmts {
+            self.comments_until(stmt.span.start, &mut out, &mut last_line);
+            if last_line.is_some_and(|l| stmt.span.line > l + 1) {
+                out.push('\n');
+            }
+            out.push_str(&self.pad());
+            out.push_str(&self.stmt(stmt));
+
+            let end_line = self.line_of(stmt.span.end);
+            self.trailing_comment(stmt.span.end, end_line, &mut out);
+            out.push('\n');
+            last_line = Some(end_line);
+        }
+        self.comments_until(end, &mut out, &mut last_line);
+        out
+    }
+
+    /// The start of the first `kind` token at or after `pos`.
+    fn find_token(&self, kind: TokenKind, pos: usize) -> usize {
+        let idx = self.tokens.partition_point(|t| t.span.start < pos);
+        self.tokens[idx..]
+            .iter()
+            .find(|t| t.kind == kind)
+            .map_or(usize::MAX, |t| t.span.start)
+    }
+
+    /// The start of the `do` of a fn, which follows any param defaults.
+    fn fn_do(&self, params: &[FnParam], start: usize) -> usize {
+        let from = params
+            .iter()
+            .filter_map(|p| p.default.as_ref().map(|d| d.span.end))
+            .max()
+            .unwrap_or(start);
+        self.find_token(TokenKind::Do, from)
+    }
+
+    /// The end of the last statement in `block`, or `fallback` if it is
+    /// empty. The clause after a block starts after this.
+    fn stmts_end(block: &Block, fallback: usize) -> usize {
+        block.stmts.last().map_or(fallback, |stmt| stmt.span.end)
+    }
+
+    /// Prints a `do ... end` body one level deeper than the current indent,
+    /// starting with the rest of the line of `open`, the keyword opening it.
+    fn body(&mut self, block: &Block, open: usize, end: usize) -> String {
+        let mut out = String::new();
+        if let Some(comment) = self.comments.get(self.next_comment) {
+            // A comment directly after the keyword stays on its line.
+            let idx = self
+                .tokens
+                .partition_point(|t| t.span.start < comment.span.start);
+            let after_open = idx
+                .checked_sub(1)
+                .map(|i| &self.tokens[i])
+                .is_some_and(|t| t.span.start == open && t.span.line == comment.span.line);
+            if after_open {
+                out.push_str(&format!(" //{}", comment.text.trim_end()));
+                self.next_comment += 1;
+            }
+        }
+        out.push('\n');
+        self.indent += 1;
+        out.push_str(&self.block(&block.stmts, end));
+        self.indent -= 1;
+        out
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> String {
+        use StmtKind::*;
+        let closing = Self::closing(stmt.span);
+        match &stmt.kind {
+            Assign(lhs, rhs, global, ty) => {
+                let kw = if *global { "global " } else { "" };
+                let lhs = self.expr(lhs);
+                format!("{kw}{lhs}{} = {}", annotation(ty), self.expr(rhs))
+            }
+            Let(lhs, rhs, constant, ty) => {
+                let kw = if *constant { "const" } else { "let" };
+                let lhs = self.expr(lhs);
+                format!("{kw} {lhs}{} = {}", annotation(ty), self.expr(rhs))
+            }
+            Break => "break".into(),
+            Continue => "continue".into(),
+            Crawl(lit) => {
+                let mut out = "crawl ".to_string();
+                if let Some(bindings) = &lit.bindings {
+                    out.push_str(&format!("{}, {} ", bindings.link, bindings.depth));
+                }
+                if let Some(filter) = &lit.filter {
+                    out.push_str(&format!("where {} ", self.expr(filter)));
+                }
+                let from = lit.filter.as_ref().map_or(stmt.span.start, |f| f.span.end);
+                let open = self.find_token(TokenKind::Do, from);
+                let end = self.block_end(&lit.body, closing);
+                let body = self.body(&lit.body, open, end);
+                format!("{out}do{body}{}end", self.pad())
+            }
+            Expr(expr) => self.expr(expr),
+            ForLoop(floop) => {
+                let idents = floop
+                    .idents
+                    .iter()
+                    .map(|i| i.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                let iterable = self.expr(&floop.iterable);
+                let open = self.find_token(TokenKind::Do, floop.iterable.span.end);
+                let end = self.block_end(&floop.block, closing);
+                let body = self.body(&floop.block, open, end);
+                format!("for {idents} in {iterable} do{body}{}end", self.pad())
+            }
+            WhileLoop(cond, block) => {
+                let open = self.find_token(TokenKind::Do, cond.span.end);
+                let cond = self.expr(cond);
+                let end = self.block_end(block, closing);
+                let body = self.body(block, open, end);
+                format!("while {cond} do{body}{}end", self.pad())
+            }
+            Func(def, global) => {
+                let kw = if *global { "global " } else { "" };
+                let params = self.params(&def.params);
+                let open = self.fn_do(&def.params, stmt.span.start);
+                let end = self.block_end(&def.body, closing);
+                let body = self.body(&def.body, open, end);
+                format!(
+                    "{kw}def {}({params}){} do{body}{}end",
+                    def.ident,
+                    ret(&def.ret),
+                    self.pad()
+                )
+            }
+            Goto(expr) => format!("goto {}", self.expr(expr)),
+            IfElse(lit) => {
+                let mut out = String::new();
+                let mut prev = stmt.span.start;
+                let branches = std::iter::once(&lit.if_lit).chain(&lit.elifs);
+                for (idx, branch) in branches.enumerate() {
+                    let kw = if idx == 0 { "if" } else { "elif" };
+                    let open = self.find_token(TokenKind::Do, branch.cond.span.end);
+                    prev = Self::stmts_end(&branch.block, open);
+                    let cond = self.expr(&branch.cond);
+                    let last = idx == lit.elifs.len() && lit.else_lit.is_none();
+                    let end = self.block_end(&branch.block, if last { closing } else { 0 });
+                    let body = self.body(&branch.block, open, end);
+                    if idx > 0 {
+                        out.push_str(&self.pad());
+                    }
+                    out.push_str(&format!("{kw} {cond} do{body}"));
+                }
+                if let Some(el) = &lit.else_lit {
+                    let open = self.find_token(TokenKind::Else, prev);
+                    let end = self.block_end(&el.block, closing);
+                    let body = self.body(&el.block, open, end);
+                    out.push_str(&format!("{}else{body}", self.pad()));
+                }
+                format!("{out}{}end", self.pad())
+            }
+            Return(None) => "return".into(),
+            Return(Some(expr)) => format!("return {}", self.expr(expr)),
+            Scrape(ty, hash) => {
+                let ty = ty.as_ref().map(|t| format!("{t} ")).unwrap_or_default();
+                format!("scrape {ty}{}", self.hash(hash, stmt.span, true))
+            }
+            Screenshot(path) => {
+                let lit = self.token_after(stmt.span.start + "screenshot".len());
+                match lit {
+                    Some(t) if t.kind == TokenKind::Str => {
+                        format!("screenshot {}", &self.source[t.span.start..t.span.end])
+                    }
+                    _ => format!(r#"screenshot "{}""#, escape_str(path)),
+                }
+            }
+            Throw(expr) => format!("throw {}", self.expr(expr)),
+            TypeDecl(decl) => self.type_decl(decl),
+            TryCatch(try_b, catch, finally) => {
+                let last = catch.is_none() && finally.is_none();
+                let end = self.block_end(try_b, if last { closing } else { 0 });
+                let mut out = format!("try{}", self.body(try_b, stmt.span.start, end));
+                let mut prev = Self::stmts_end(try_b, stmt.span.start);
+                if let Some(catch) = catch {
+                    let mut open = self.find_token(TokenKind::Catch, prev);
+                    if catch.binding.is_some() {
+                        open = self.find_token(TokenKind::Do, open);
+                    }
+                    prev = Self::stmts_end(&catch.block, open);
+                    let end =
+                        self.block_end(&catch.block, if finally.is_none() { closing } else { 0 });
+                    let body = self.body(&catch.block, open, end);
+                    match &catch.binding {
+                        Some(ident) => {
+                            out.push_str(&format!("{}catch {ident} do{body}", self.pad()))
+                        }
+                        None => out.push_str(&format!("{}catch{body}", self.pad())),
+                    }
+                }
+                if let Some(finally) = finally {
+                    let open = self.find_token(TokenKind::Finally, prev);
+                    let end = self.block_end(finally, closing);
+                    let body = self.body(finally, open, end);
+                    out.push_str(&format!("{}finally{body}", self.pad()));
+                }
+                format!("{out}{}end", self.pad())
+            }
+            Use(expr) => format!("use {}", self.expr(expr)),
+        }
+    }
+
+    fn params(&mut self, params: &[FnParam]) -> String {
+        params
+            .iter()
+            .map(|p| {
+                let prefix = match p.kind {
+                    ParamKind::Positional => "",
+                    ParamKind::Rest => "*",
+                    ParamKind::Kwargs => "**",
+                };
+                let mut out = format!("{prefix}{}{}", p.ident, annotation(&p.ty));
+                if let Some(default) = &p.default {
+                    out.push_str(&format!(" = {}", self.expr(default)));
+                }
+                out
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    fn type_decl(&self, decl: &TypeDecl) -> String {
+        let fields = decl
+            .fields
+            .iter()
+            .map(|(k, ty)| format!("{}: {ty}", MapKey::Ident(k.clone())))
+            .collect::<Vec<String>>();
+        let flat = format!("type {} {{ {} }}", decl.ident, fields.join(", "));
+        if fields.is_empty() {
+            format!("type {} {{}}", decl.ident)
+        } else if self.fits(&flat) {
+            flat
+        } else {
+            self.broken(&format!("type {} {{", decl.ident), fields, "}")
+        }
+    }
+
+    /// Prints `items` one per line between `open` and `close`, each
+    /// followed by a comma.
+    fn broken(&self, open: &str, items: Vec<String>, close: &str) -> String {
+        let pad = self.pad();
+        let mut out = format!("{open}\n");
+        for item in items {
+            out.push_str(&format!("{pad}{INDENT}{item},\n"));
+        }
+        format!("{out}{pad}{close}")
+    }
+
+    /// Prints the entries of a list or map spanning `span` on one line if
+    /// they fit, and one per line otherwise. `items` are the spans of the
+    /// entries' values. Entries are always one per line when `broken` is
+    /// set or there are comments inside, which are kept where they were.
+    fn entries(
+        &mut self,
+        (open, close): (&str, &str),
+        span: Span,
+        items: &[Span],
+        broken: bool,
+        mut entry: impl FnMut(&mut Self, usize) -> String,
+    ) -> String {
+        if !broken && !self.has_comment_in(span) {
+            let flat = (0..items.len()).map(|i| entry(self, i)).collect::<Vec<_>>();
+            let spaced = if open == "{" { " " } else { "" };
+            let flat = format!("{open}{spaced}{}{spaced}{close}", flat.join(", "));
+            if self.fits(&flat) {
+                return flat;
+            }
+        }
+
+        let pad = self.pad();
+        let mut out = format!("{open}\n");
+        let mut last_line = None;
+        self.indent += 1;
+        for (i, item) in items.iter().enumerate() {
+            self.comments_until(item.start, &mut out, &mut last_line);
+            let entry = entry(self, i);
+            out.push_str(&format!("{}{entry},", self.pad()));
+            let end_line = self.line_of(item.end);
+            self.trailing_comment(item.end, end_line, &mut out);
+            out.push('\n');
+            last_line = Some(end_line);
+        }
+        self.comments_until(span.end, &mut out, &mut last_line);
+        self.indent -= 1;
+        format!("{out}{pad}{close}")
+    }
+
+    /// Prints a map literal spanning `span`, always one entry per line
+    /// when `broken` is set and it has any.
+    fn hash(&mut self, hash: &HashLiteral, span: Span, broken: bool) -> String {
+        if hash.pairs.is_empty() {
+            return "{}".into();
+        }
+        let items = hash
+            .pairs
+            .iter()
+            .map(|(_, val)| val.span)
+            .collect::<Vec<_>>();
+        self.entries(("{", "}"), span, &items, broken, |p, i| {
+            let (key, val) = &hash.pairs[i];
+            let key = match key {
+                MapKey::Computed(expr) => format!("[{}]", p.expr(expr)),
+                key => key.to_string(),
+            };
+            format!("{key}: {}", p.expr(val))
+        })
+    }
+
+    fn call(&mut self, lit: &CallLiteral) -> String {
+        let mut args = lit.args.iter().map(|a| self.expr(a)).collect::<Vec<_>>();
+        for kwarg in &lit.kwargs {
+            args.push(format!("{} = {}", kwarg.ident, self.expr(&kwarg.expr)));
+        }
+        format!("{}({})", lit.ident, args.join(", "))
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> String {
+        match pattern {
+            Pattern::Literal(expr) => self.expr(expr),
+            Pattern::List(pats) => {
+                let pats = pats.iter().map(|p| self.pattern(p)).collect::<Vec<_>>();
+                format!("[{}]", pats.join(", "))
+            }
+            Pattern::Map(pairs) => {
+                let pairs = pairs
+                    .iter()
+                    .map(|(k, p)| {
+                        let key = MapKey::Ident(k.clone()).to_string();
+                        match p {
+                            Pattern::Binding(ident) if *ident == *k && key == k.name => key,
+                            p => format!("{key}: {}", self.pattern(p)),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                format!("{{ {} }}", pairs.join(", "))
+            }
+            pattern => pattern.to_string(),
+        }
+    }
+
+    fn match_expr(&mut self, lit: &MatchLiteral, span: Span) -> String {
+        let mut prev = lit.subject.span.end;
+        let mut out = format!("match {}\n", self.expr(&lit.subject));
+        for (idx, arm) in lit.arms.iter().enumerate() {
+            let case = self.find_token(TokenKind::Case, prev);
+            let from = arm.guard.as_ref().map_or(case, |g| g.span.end);
+            let open = self.find_token(TokenKind::Do, from);
+            prev = Self::stmts_end(&arm.block, open);
+            out.push_str(&format!(
+                "{}case {}",
+                self.pad(),
+                self.pattern(&arm.pattern)
+            ));
+            if let Some(guard) = &arm.guard {
+                out.push_str(&format!(" if {}", self.expr(guard)));
+            }
+            let last = idx == lit.arms.len() - 1;
+            let end = self.block_end(&arm.block, if last { Self::closing(span) } else { 0 });
+            out.push_str(&format!(" do{}", self.body(&arm.block, open, end)));
+        }
+        format!("{out}{}end", self.pad())
+    }
+
+    /// Prints a pipe chain on one line if it has a single short step, and
+    /// with each `|>` step on its own line otherwise.
+    fn chain(&mut self, expr: &Expr) -> String {
+        let mut steps = Vec::new();
+        let first = flatten_chain(expr, &mut steps);
+        let first = self.expr(first);
+
+        let start = self.next_comment;
+        if steps.len() == 1 {
+            let (sep, step) = steps[0];
+            let flat = format!("{first} {sep} {}", self.expr(step));
+            if flat.contains('\n') || self.fits(&flat) {
+                return flat;
+            }
+            self.next_comment = start;
+        }
+
+        self.indent += 1;
+        let pad = self.pad();
+        let mut out = first;
+        for (sep, step) in steps {
+            out.push_str(&format!("\n{pad}{sep} {}", self.expr(step)));
+        }
+        self.indent -= 1;
+        out
+    }
+
+    fn expr(&mut self, expr: &Expr) -> String {
+        use ExprKind::*;
+        let span = expr.span;
+        if self.literals.contains(&(span.start, span.end)) {
+            return self.source[span.start..span.end].to_string();
+        }
+        match &expr.kind {
+            List(items) => {
+                let spans = items.iter().map(|item| item.span).collect::<Vec<_>>();
+                self.entries(("[", "]"), span, &spans, false, |p, i| p.expr(&items[i]))
+            }
+            Map(hash) => self.hash(hash, span, false),
+            Call(lit) => self.call(lit),
+            Lambda(lit) => {
+                let params = self.params(&lit.params);
+                let open = self.fn_do(&lit.params, span.start);
+                let end = self.block_end(&lit.body, Self::closing(span));
+                let body = self.body(&lit.body, open, end);
+                format!("fn({params}){} do{body}{}end", ret(&lit.ret), self.pad())
+            }
+            Match(lit) => self.match_expr(lit, span),
+            Record(ty, hash) => format!("{ty} {}", self.hash(hash, span, false)),
+            Conditional(cond, then, otherwise) => format!(
+                "if {} then {} else {}",
+                self.expr(cond),
+                self.expr(then),
+                self.expr(otherwise)
+            ),
+            Field(obj, field) => format!("{}.{field}", self.expr(obj)),
+            MethodCall(obj, lit) => format!("{}.{}", self.expr(obj), self.call(lit)),
+            Chain(_) | SafeChain(_) => self.chain(expr),
+            Infix(lhs, op, rhs) => {
+                let (lhs, rhs) = (self.expr(lhs), self.expr(rhs));
+                match op.kind {
+                    TokenKind::LBracket => format!("{lhs}[{rhs}]"),
+                    TokenKind::DbColon => format!("{lhs}::{rhs}"),
+                    _ => format!("{lhs} {} {rhs}", op.literal),
+                }
+            }
+            Prefix(rhs, op) => format!("{}{}", op.literal, self.expr(rhs)),
+            kind => kind.to_string(),
+        }
+    }
+}
+
+/// Collects the steps of a chain, including chains nested as the first
+/// element of another, and returns the expression the chain starts from.
+fn flatten_chain<'a>(expr: &'a Expr, steps: &mut Vec<(&'static str, &'a Expr)>) -> &'a Expr {
+    let (sep, exprs) = match &expr.kind {
+        ExprKind::Chain(exprs) => ("|>", exprs),
+        ExprKind::SafeChain(exprs) => ("?|>", exprs),
+        _ => return expr,
+    };
+    let first = flatten_chain(&exprs[0], steps);
+    steps.extend(exprs[1..].iter().map(|e| (sep, e)));
+    first
+}
+
+fn annotation(ty: &Option<TypeExpr>) -> String {
+    ty.as_ref().map(|t| format!(": {t}")).unwrap_or_default()
+}
+
+fn ret(ty: &Option<TypeExpr>) -> String {
+    ty.as_ref().map(|t| format!(" -> {t}")).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const SAMPLE: &str = r#"// header
+use std::list
+type Big { aaaaaaaaaaa: str, bbbbbbbbbbbbbb: number?, cccccccccccccccc: str, ddddddddd: list }
+x: number = 0xff   // hex
+global g = "page ${x + 1} of ${ {a: 1}["a"] }"
+def f(a: str, b = 1, *rest, **opts) -> str do
+  if a >= 2 and !b do return a
+  elif b <= 3 do
+     return -a ** 2
+  else
+    // nothing here
+  end
+end
+m2 = { a: 1, "b-c": [1, 2], [x]: fn() do 1 end }
+m3 = {
+  // first
+  a: [1, // one
+    2],
+}
+crawl link, depth where depth < 2 do // each page
+  scrape Product { name: $"h1" |> textContent(), price: null }
+end
+try x catch e do print(e) finally print(1) end
+v = match x
+case { title, n: 2 } if n do title
+case _ do null
+end
+q = aaaaaaaaaaaaaaaaaaaaaaaaaa |> bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb() ?|> cccccccc(1, k = 2)
+m[1] = std::list::map(l, f)
+screenshot "a.png"
+"#;
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        Parser::new(Lexer::new(source))
+            .parse_program()
+            .unwrap()
+            .stmts
+    }
+
+    #[test_case("x=1\ny  =  2", "x = 1\ny = 2\n"; "spacing")]
+    #[test_case("def f(a) do\nif a do return a end\nend", "def f(a) do\n    if a do\n        return a\n    end\nend\n"; "nested indentation")]
+    #[test_case("a = 1\n\n\n\nb = 2", "a = 1\n\nb = 2\n"; "blank lines collapsed")]
+    #[test_case("// a\nx = 1 // b\n\n// c\n", "// a\nx = 1 // b\n\n// c\n"; "comments kept")]
+    #[test_case("for x in l do\n  // only a comment\nend", "for x in l do\n    // only a comment\nend\n"; "comment in empty block")]
+    #[test_case("if a do\n  x\n  // end of if\nelse\n  y\nend", "if a do\n    x\n    // end of if\nelse\n    y\nend\n"; "comment before else")]
+    #[test_case("x = a |> f()", "x = a |> f()\n"; "short chain")]
+    #[test_case("a |> f() |> g()", "a\n    |> f()\n    |> g()\n"; "long chain")]
+    #[test_case("x = [0x1F, 1_000, \"\\u{e006}\"]", "x = [0x1F, 1_000, \"\\u{e006}\"]\n"; "literals as written")]
+    #[test_case("scrape { a: $\"b\" }", "scrape {\n    a: $\"b\",\n}\n"; "scrape broken")]
+    #[test_case("f(k = -1)", "f(k = -1)\n"; "kwargs only")]
+    #[test_case("m = {\n  // in map\n  a: 1, // after entry\n}\nx = 1", "m = {\n    // in map\n    a: 1, // after entry\n}\nx = 1\n"; "comments in map")]
+    #[test_case("l = [1, // one\n  [2, // two\n  3],\n  // last\n]", "l = [\n    1, // one\n    [\n        2, // two\n        3,\n    ],\n    // last\n]\n"; "comments in nested lists")]
+    #[test_case("scrape { a: 1 // a\n}", "scrape {\n    a: 1, // a\n}\n"; "comment in scrape")]
+    #[test_case("for x in l do // loop\n  y\nend", "for x in l do // loop\n    y\nend\n"; "comment after do")]
+    #[test_case("try // t\nfinally // f\nend", "try // t\nfinally // f\nend\n"; "comments after keywords")]
+    #[test_case("if a do // a\nelif b do\n  x // x\nelse // c\nend", "if a do // a\nelif b do\n    x // x\nelse // c\nend\n"; "comments after branches")]
+    #[test_case("v = match x\ncase 1 if f(fn() do end) do // one\n  1\nend", "v = match x\ncase 1 if f(fn() do\nend) do // one\n    1\nend\n"; "comment after match arm")]
+    fn test_format(input: &str, exp: &str) {
+        assert_eq!(format(input).unwrap(), exp);
+    }
+
+    #[test]
+    fn test_format_idempotent() {
+        let once = format(SAMPLE).unwrap();
+        assert_eq!(format(&once).unwrap(), once);
+    }
+
+    #[test]
+    fn test_format_preserves_ast() {
+        let formatted = format(SAMPLE).unwrap();
+        assert_eq!(parse(&formatted), parse(SAMPLE));
+    }
+
+    #[test]
+    fn test_format_keeps_comments() {
+        let formatted = format(SAMPLE).unwrap();
+        for comment in [
+            "// header",
+            "// hex",
+            "// nothing here",
+            "// first",
+            "// one",
+            "do // each page",
+        ] {
+            assert!(formatted.contains(comment), "{comment} missing");
+        }
+    }
+
+    #[test]
+    fn test_format_parse_error() {
+        assert!(format("x = ").is_err());
+    }
+}
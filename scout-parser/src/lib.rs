@@ -1,26 +1,33 @@
-use std::collections::HashMap;
-
 use ast::{
-    CallLiteral, CrawlBindings, CrawlLiteral, ExprKind, FnParam, ForLoop, FuncDef, HashLiteral,
-    Identifier, IfElseLiteral, IfLiteral, Kwarg, Program, StmtKind,
+    CallLiteral, CatchLiteral, CrawlBindings, CrawlLiteral, Expr, ExprKind, FnParam, ForLoop,
+    FuncDef, HashLiteral, Identifier, IfElseLiteral, IfLiteral, Kwarg, LambdaLiteral, MapKey,
+    MatchArm, MatchLiteral, ParamKind, Pattern, Program, Stmt, StmtKind, TypeDecl, TypeExpr,
 };
-use scout_lexer::{Lexer, Token, TokenKind};
+use diagnostic::Diagnostic;
+use scout_lexer::{Lexer, Span, Token, TokenKind};
 
 use crate::ast::{Block, ElseLiteral};
 
 pub mod ast;
+pub mod diagnostic;
+pub mod fmt;
 
 type ParseResult<T> = Result<T, ParseError>;
 type PrefixParseFn = fn(parser: &mut Parser) -> ParseResult<ExprKind>;
-type InfixParseFn = fn(parser: &mut Parser, ExprKind) -> ParseResult<ExprKind>;
+type InfixParseFn = fn(parser: &mut Parser, Expr) -> ParseResult<ExprKind>;
 
 #[derive(Debug, PartialOrd, Ord, PartialEq, Eq)]
 enum Precedence {
     Lowest,
+    Coalesce,
+    Or,
+    And,
     Equals,
     LessGreater,
     Sum,
     Product,
+    Prefix,
+    Exponent,
     Call,
     Index,
 }
@@ -29,8 +36,8 @@ impl From<TokenKind> for Precedence {
     fn from(value: TokenKind) -> Self {
         use TokenKind::*;
         match value {
-            And => Self::Equals,
-            Or => Self::Equals,
+            And => Self::And,
+            Or => Self::Or,
             EQ => Self::Equals,
             NEQ => Self::Equals,
             LT => Self::LessGreater,
@@ -41,10 +48,16 @@ impl From<TokenKind> for Precedence {
             Minus => Self::Sum,
             Slash => Self::Product,
             Asterisk => Self::Product,
+            TildeSlash => Self::Product,
+            Percent => Self::Product,
+            DbAsterisk => Self::Exponent,
             DbColon => Self::Product,
             LParen => Self::Call,
             LBracket => Self::Index,
+            Dot => Self::Index,
             Pipe => Self::Index,
+            SafePipe => Self::Index,
+            DbQuestion => Self::Coalesce,
             _ => Self::Lowest,
         }
     }
@@ -59,12 +72,17 @@ fn map_prefix_fn(kind: &TokenKind) -> Option<PrefixParseFn> {
         True => Some(Parser::parse_boolean),
         False => Some(Parser::parse_boolean),
         Str => Some(Parser::parse_str_literal),
+        InterpStart => Some(Parser::parse_interpolated_str),
         Null => Some(Parser::parse_null),
         LBracket => Some(Parser::parse_list_literal),
         LBrace => Some(Parser::parse_map),
         SelectAll => Some(Parser::parse_select_all),
         Select => Some(Parser::parse_select),
         Bang => Some(Parser::parse_prefix),
+        Minus => Some(Parser::parse_prefix),
+        Fn => Some(Parser::parse_lambda),
+        Match => Some(Parser::parse_match),
+        If => Some(Parser::parse_conditional),
         _ => None,
     }
 }
@@ -76,6 +94,9 @@ fn map_infix_fn(kind: &TokenKind) -> Option<InfixParseFn> {
         Minus => Some(Parser::parse_infix),
         Slash => Some(Parser::parse_infix),
         Asterisk => Some(Parser::parse_infix),
+        DbAsterisk => Some(Parser::parse_infix),
+        TildeSlash => Some(Parser::parse_infix),
+        Percent => Some(Parser::parse_infix),
         EQ => Some(Parser::parse_infix),
         NEQ => Some(Parser::parse_infix),
         LT => Some(Parser::parse_infix),
@@ -87,56 +108,274 @@ fn map_infix_fn(kind: &TokenKind) -> Option<InfixParseFn> {
         DbColon => Some(Parser::parse_infix),
         LBracket => Some(Parser::parse_index),
         LParen => Some(Parser::parse_call_expr),
+        Dot => Some(Parser::parse_dot),
         Pipe => Some(Parser::parse_chain_expr),
+        SafePipe => Some(Parser::parse_chain_expr),
+        DbQuestion => Some(Parser::parse_infix),
         _ => None,
     }
 }
 
 #[derive(Debug)]
 pub enum ParseError {
-    UnexpectedToken(TokenKind, TokenKind),
-    InvalidToken(TokenKind),
-    InvalidNumber,
-    InvalidFnCall,
-    InvalidGlobal,
-    DefaultFnParamBefore,
-    UnknownPrefix(TokenKind),
+    /// The expected token kind and the token that was found instead.
+    UnexpectedToken(TokenKind, Token),
+    InvalidToken(Token),
+    InvalidNumber(Token),
+    InvalidFnCall(Span),
+    InvalidGlobal(Token),
+    DefaultFnParamBefore(Token),
+    /// A param after `**opts`, or a positional param after `*rest`.
+    ParamAfterVariadic(Token),
+    UnknownPrefix(Token),
+    /// A `break` or `continue` that is not inside a loop.
+    OutsideLoop(Token),
+    InvalidPattern(Token),
+    /// A malformed or unknown escape in a string.
+    InvalidEscape(Token),
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        use ParseError::*;
+        match self {
+            UnexpectedToken(_, t)
+            | InvalidToken(t)
+            | InvalidNumber(t)
+            | InvalidGlobal(t)
+            | DefaultFnParamBefore(t)
+            | ParamAfterVariadic(t)
+            | UnknownPrefix(t)
+            | OutsideLoop(t)
+            | InvalidPattern(t)
+            | InvalidEscape(t) => t.span,
+            InvalidFnCall(span) => *span,
+        }
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        use ParseError::*;
+        match self {
+            UnexpectedToken(TokenKind::Do, _) => {
+                Some("blocks are opened with `do`, e.g. `if cond do ... end`")
+            }
+            UnexpectedToken(TokenKind::RParen, _) => Some("check for a missing `)`"),
+            UnexpectedToken(TokenKind::RBracket, _) => Some("check for a missing `]`"),
+            InvalidGlobal(_) => Some("`global` must be followed by an assignment or a `def`"),
+            DefaultFnParamBefore(_) => {
+                Some("parameters with defaults must come after all parameters without defaults")
+            }
+            ParamAfterVariadic(_) => Some("`*rest` and then `**opts` must be the last parameters"),
+            InvalidFnCall(_) => Some("only named functions can be called"),
+            OutsideLoop(_) => Some("only `for`, `while` and `crawl` bodies can be exited early"),
+            InvalidPattern(_) => {
+                Some("patterns are literals, names, `_`, or lists and maps of patterns")
+            }
+            InvalidEscape(_) => Some(
+                r#"strings support `\n`, `\t`, `\r`, `\0`, `\"`, `\\`, `\$` and `\u{hex}` escapes"#,
+            ),
+            _ => None,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diag = Diagnostic::new(self.to_string(), self.span());
+        match self.hint() {
+            Some(hint) => diag.with_hint(hint),
+            None => diag,
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ParseError::*;
+        match self {
+            UnexpectedToken(expected, found) => write!(f, "expected {expected}, found {found}"),
+            InvalidToken(t) => write!(f, "unexpected {t}"),
+            InvalidNumber(t) => write!(f, "invalid number literal `{}`", t.literal),
+            InvalidFnCall(_) => write!(f, "invalid function call"),
+            InvalidGlobal(t) => write!(f, "expected assignment or `def` after `global`, found {t}"),
+            DefaultFnParamBefore(t) => {
+                write!(f, "parameter `{}` must have a default value", t.literal)
+            }
+            ParamAfterVariadic(t) => {
+                write!(
+                    f,
+                    "parameter `{}` cannot follow a variadic parameter",
+                    t.literal
+                )
+            }
+            UnknownPrefix(t) => write!(f, "expected an expression, found {t}"),
+            OutsideLoop(t) => write!(f, "{} outside of a loop", t.kind),
+            InvalidPattern(t) => write!(f, "expected a pattern, found {t}"),
+            InvalidEscape(t) => write!(f, "invalid escape `{}`", t.literal),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Returns true if the token kind can only begin a statement, which
+/// makes it a safe place to resume parsing after an error.
+fn starts_stmt(kind: TokenKind) -> bool {
+    use TokenKind::*;
+    matches!(
+        kind,
+        Def | Goto
+            | Scrape
+            | For
+            | While
+            | Screenshot
+            | If
+            | Global
+            | Return
+            | Break
+            | Continue
+            | Throw
+            | Use
+            | Try
+            | Crawl
+            | Let
+            | Const
+    )
 }
 
 pub struct Parser {
     lex: Lexer,
     curr: Token,
     peek: Token,
+    // Line of the token before `curr`, used to find tokens that start a line.
+    prev_line: usize,
+    // Number of loops enclosing `curr` within the current fn body.
+    loop_depth: usize,
+    errors: Vec<ParseError>,
 }
 
 impl Parser {
     pub fn new(mut lex: Lexer) -> Self {
         let curr = lex.next_token();
         let peek = lex.next_token();
-        Self { lex, curr, peek }
+        Self {
+            lex,
+            curr,
+            peek,
+            prev_line: 0,
+            loop_depth: 0,
+            errors: Vec::new(),
+        }
     }
 
-    pub fn parse_program(&mut self) -> ParseResult<Program> {
+    /// Parses the entire input, failing with every syntax error found.
+    pub fn parse_program(&mut self) -> Result<Program, Vec<ParseError>> {
+        let (prgm, errors) = self.parse_program_partial();
+        if errors.is_empty() {
+            Ok(prgm)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses the entire input, recovering at statement boundaries when a
+    /// statement fails to parse. Returns every statement that was parsed
+    /// along with all errors encountered, in source order.
+    pub fn parse_program_partial(&mut self) -> (Program, Vec<ParseError>) {
         let mut prgm = Program::default();
         while self.curr.kind != TokenKind::EOF {
-            let stmt = self.parse_stmt()?;
-            prgm.stmts.push(stmt);
-            self.next_token();
+            if let Some(stmt) = self.parse_stmt_recovering(&[]) {
+                prgm.stmts.push(stmt);
+            }
         }
-        Ok(prgm)
+        let mut errors = std::mem::take(&mut self.errors);
+        let escapes = self.lex.invalid_escapes().iter().cloned();
+        errors.extend(escapes.map(ParseError::InvalidEscape));
+        errors.sort_by_key(|e| e.span().start);
+        // An unterminated block is reported by every enclosing block as well.
+        errors.dedup_by_key(|e| e.span().start);
+        (prgm, errors)
     }
 
     fn next_token(&mut self) {
         let prev = std::mem::replace(&mut self.peek, self.lex.next_token());
+        self.prev_line = self.curr.span.line;
         self.curr = prev;
     }
 
+    /// Parses a statement and advances past it. On failure the error is
+    /// recorded and the parser skips ahead to the next statement boundary.
+    fn parse_stmt_recovering(&mut self, finalizers: &[TokenKind]) -> Option<Stmt> {
+        let start = self.curr.span.start;
+        match self.parse_stmt() {
+            Ok(stmt) => {
+                self.next_token();
+                Some(stmt)
+            }
+            Err(e) => {
+                self.errors.push(e);
+                self.synchronize(start, finalizers);
+                None
+            }
+        }
+    }
+
+    /// Skips tokens until a point where parsing can resume: a block
+    /// finalizer, the token after an `end` that closes a block opened while
+    /// skipping, or a statement keyword or assignment that starts a new line.
+    fn synchronize(&mut self, stmt_start: usize, finalizers: &[TokenKind]) {
+        if self.curr.span.start == stmt_start {
+            self.next_token();
+        }
+
+        let mut depth = 0;
+        let mut closed_block = false;
+        // The two tokens before `curr`, to spot the `do` of `catch e do`.
+        let mut before = [TokenKind::EOF; 2];
+        let mut case_head = false;
+        loop {
+            let kind = self.curr.kind;
+            if kind == TokenKind::EOF {
+                return;
+            }
+            if depth == 0 {
+                let at_line_start = self.curr.span.line > self.prev_line;
+                let starts_assign = kind == TokenKind::Ident && self.peek.kind == TokenKind::Assign;
+                if finalizers.contains(&kind)
+                    || closed_block
+                    || (at_line_start && (starts_stmt(kind) || starts_assign))
+                {
+                    return;
+                }
+            }
+
+            closed_block = false;
+            match kind {
+                // `catch e do` shares the `end` of its `try`.
+                TokenKind::Do if before == [TokenKind::Catch, TokenKind::Ident] => {}
+                // A `match` is closed by a single `end`, so the `do` of each
+                // of its `case`s opens nothing.
+                TokenKind::Match => depth += 1,
+                TokenKind::Case => case_head = true,
+                TokenKind::Do if case_head => case_head = false,
+                TokenKind::Do | TokenKind::Try => depth += 1,
+                // `elif` shares the `end` of its `if`, but is followed by its own `do`.
+                TokenKind::Elif if depth > 0 => depth -= 1,
+                TokenKind::End if depth > 0 => {
+                    depth -= 1;
+                    closed_block = depth == 0;
+                }
+                _ => {}
+            }
+            before = [before[1], kind];
+            self.next_token();
+        }
+    }
+
     fn expect_peek(&mut self, expected: TokenKind) -> ParseResult<()> {
         if self.peek.kind == expected {
             self.next_token();
             Ok(())
         } else {
-            Err(ParseError::UnexpectedToken(expected, self.peek.kind))
+            Err(ParseError::UnexpectedToken(expected, self.peek.clone()))
         }
     }
 
@@ -151,16 +390,72 @@ impl Parser {
     fn parse_fn_def(&mut self) -> ParseResult<FuncDef> {
         self.expect_peek(TokenKind::Ident)?;
         let ident = Identifier::new(self.curr.literal.clone());
+        let args = self.parse_fn_params()?;
+        let ret = self.parse_ret_type()?;
+        let block = self.parse_fn_body()?;
+        let mut def = FuncDef::new(ident, args, block);
+        def.ret = ret;
+        Ok(def)
+    }
+
+    /// `fn(a, b = 1) do <block> end`
+    fn parse_lambda(&mut self) -> ParseResult<ExprKind> {
+        let params = self.parse_fn_params()?;
+        let ret = self.parse_ret_type()?;
+        let body = self.parse_fn_body()?;
+        let mut lit = LambdaLiteral::new(params, body);
+        lit.ret = ret;
+        Ok(ExprKind::Lambda(lit))
+    }
+
+    /// An optional `-> type` following fn params.
+    fn parse_ret_type(&mut self) -> ParseResult<Option<TypeExpr>> {
+        if self.peek.kind != TokenKind::Arrow {
+            return Ok(None);
+        }
+        self.next_token();
+        self.parse_type_expr().map(Some)
+    }
+
+    /// An optional `: type` following a name.
+    fn parse_annotation(&mut self) -> ParseResult<Option<TypeExpr>> {
+        if self.peek.kind != TokenKind::Colon {
+            return Ok(None);
+        }
+        self.next_token();
+        self.parse_type_expr().map(Some)
+    }
+
+    /// `(a, b = 1)`
+    ///
+    /// Expects the peek token to be the opening LParen.
+    fn parse_fn_params(&mut self) -> ParseResult<Vec<FnParam>> {
         self.expect_peek(TokenKind::LParen)?;
 
-        let mut args = Vec::new();
+        let mut args: Vec<FnParam> = Vec::new();
         let mut has_defaults = false;
         while self.peek.kind == TokenKind::Comma || self.peek.kind != TokenKind::RParen {
             self.next_token();
-            match self.curr.kind {
-                TokenKind::Comma => {}
-                TokenKind::Ident => {
+            // Nothing may follow `**opts`, and only `**opts` may follow `*rest`.
+            let last_kind = args.last().map(|p| p.kind);
+            match (self.curr.kind, last_kind) {
+                (TokenKind::Comma, _) => {}
+                (_, Some(ParamKind::Kwargs))
+                | (TokenKind::Ident | TokenKind::Asterisk, Some(ParamKind::Rest)) => {
+                    return Err(ParseError::ParamAfterVariadic(self.curr.clone()));
+                }
+                (TokenKind::Asterisk | TokenKind::DbAsterisk, _) => {
+                    let kind = match self.curr.kind {
+                        TokenKind::Asterisk => ParamKind::Rest,
+                        _ => ParamKind::Kwargs,
+                    };
+                    self.expect_peek(TokenKind::Ident)?;
+                    let ident = Identifier::new(self.curr.literal.clone());
+                    args.push(FnParam::variadic(ident, kind));
+                }
+                (TokenKind::Ident, _) => {
                     let ident = Identifier::new(self.curr.literal.clone());
+                    let ty = self.parse_annotation()?;
                     let mut default = None;
                     if self.peek.kind == TokenKind::Assign {
                         self.next_token();
@@ -171,25 +466,49 @@ impl Parser {
                         // Dont allow non-default params after default params.
                         // If we dont disallow this then the interpreter will have a
                         // hard time
-                        return Err(ParseError::DefaultFnParamBefore);
+                        return Err(ParseError::DefaultFnParamBefore(self.curr.clone()));
                     }
-                    args.push(FnParam::new(ident, default));
+                    let mut param = FnParam::new(ident, default);
+                    param.ty = ty;
+                    args.push(param);
                 }
                 _ => {
-                    return Err(ParseError::InvalidToken(self.curr.kind));
+                    return Err(ParseError::InvalidToken(self.curr.clone()));
                 }
             }
         }
 
         self.expect_peek(TokenKind::RParen)?;
+        Ok(args)
+    }
+
+    fn parse_fn_body(&mut self) -> ParseResult<Block> {
+        self.expect_peek(TokenKind::Do)?;
+        self.next_token();
+        // Loops outside of the fn can't be exited from within it.
+        let outer_depth = std::mem::take(&mut self.loop_depth);
+        let block = self.parse_block(vec![TokenKind::End]);
+        self.loop_depth = outer_depth;
+        block
+    }
+
+    /// `do <block> end` for a body that `break` and `continue` apply to.
+    fn parse_loop_body(&mut self) -> ParseResult<Block> {
         self.expect_peek(TokenKind::Do)?;
         self.next_token();
+        self.loop_depth += 1;
+        let block = self.parse_block(vec![TokenKind::End]);
+        self.loop_depth -= 1;
+        block
+    }
 
-        let block = self.parse_block(vec![TokenKind::End])?;
-        Ok(FuncDef::new(ident, args, block))
+    fn parse_stmt(&mut self) -> ParseResult<Stmt> {
+        let start = self.curr.span;
+        let kind = self.parse_stmt_kind()?;
+        Ok(Stmt::new(kind, start.to(self.curr.span)))
     }
 
-    fn parse_stmt(&mut self) -> ParseResult<StmtKind> {
+    fn parse_stmt_kind(&mut self) -> ParseResult<StmtKind> {
         let lhs = match self.curr.kind {
             TokenKind::Def => Ok(StmtKind::Func(self.parse_fn_def()?, false)),
             TokenKind::Goto => self.parse_goto_stmt(),
@@ -198,6 +517,12 @@ impl Parser {
             TokenKind::While => self.parse_while_loop(),
             TokenKind::Screenshot => self.parse_screenshot_stmt(),
             TokenKind::If => self.parse_if_else(),
+            // `type` is only a keyword here, so `type(x)` still calls the builtin.
+            TokenKind::Ident
+                if self.curr.literal == "type" && self.peek.kind == TokenKind::Ident =>
+            {
+                self.parse_type_decl()
+            }
             TokenKind::Ident => match self.peek.kind {
                 TokenKind::Assign => {
                     // let ident = Identifier::new(self.curr.literal.clone());
@@ -205,7 +530,17 @@ impl Parser {
                     self.next_token();
                     self.next_token();
                     let val = self.parse_expr(Precedence::Lowest)?;
-                    Ok(StmtKind::Assign(lhs, val, false))
+                    Ok(StmtKind::Assign(lhs, val, false, None))
+                }
+                // `x: number = 1`
+                TokenKind::Colon => {
+                    let lhs = ExprKind::Ident(Identifier::new(self.curr.literal.clone()));
+                    let lhs = Expr::new(lhs, self.curr.span);
+                    let ty = self.parse_annotation()?;
+                    self.expect_peek(TokenKind::Assign)?;
+                    self.next_token();
+                    let val = self.parse_expr(Precedence::Lowest)?;
+                    Ok(StmtKind::Assign(lhs, val, false, ty))
                 }
                 _ => self.parse_expr_stmt(),
             },
@@ -219,9 +554,9 @@ impl Parser {
                             self.next_token();
                             self.next_token();
                             let val = self.parse_expr(Precedence::Lowest)?;
-                            Ok(StmtKind::Assign(lhs, val, true))
+                            Ok(StmtKind::Assign(lhs, val, true, None))
                         }
-                        _ => Err(ParseError::InvalidGlobal),
+                        _ => Err(ParseError::InvalidGlobal(self.peek.clone())),
                     }
                 }
                 TokenKind::Def => {
@@ -229,17 +564,32 @@ impl Parser {
                     let def = self.parse_fn_def()?;
                     Ok(StmtKind::Func(def, true))
                 }
-                _ => Err(ParseError::InvalidGlobal),
+                _ => Err(ParseError::InvalidGlobal(self.peek.clone())),
             },
+            // A bare `return` is followed by the end of its block or line.
+            TokenKind::Return
+                if matches!(self.peek.kind, TokenKind::End | TokenKind::EOF)
+                    || self.peek.span.line > self.curr.span.line =>
+            {
+                Ok(StmtKind::Return(None))
+            }
             TokenKind::Return => {
                 self.next_token();
-                match self.parse_expr(Precedence::Lowest) {
-                    Ok(expr) => Ok(StmtKind::Return(Some(expr))),
-                    Err(ParseError::InvalidToken(_)) => Ok(StmtKind::Return(None)),
-                    Err(e) => Err(e),
-                }
+                let expr = self.parse_expr(Precedence::Lowest)?;
+                Ok(StmtKind::Return(Some(expr)))
+            }
+            TokenKind::Break | TokenKind::Continue if self.loop_depth == 0 => {
+                Err(ParseError::OutsideLoop(self.curr.clone()))
+            }
+            TokenKind::Break => Ok(StmtKind::Break),
+            TokenKind::Continue => Ok(StmtKind::Continue),
+            TokenKind::Throw => {
+                self.next_token();
+                let expr = self.parse_expr(Precedence::Lowest)?;
+                Ok(StmtKind::Throw(expr))
             }
             TokenKind::Use => self.parse_use_stmt(),
+            TokenKind::Let | TokenKind::Const => self.parse_let_stmt(),
             TokenKind::Try => self.parse_try_catch(),
             TokenKind::Crawl => self.parse_crawl(),
             _ => self.parse_expr_stmt(),
@@ -250,7 +600,7 @@ impl Parser {
                 self.next_token();
                 self.next_token();
                 let rhs = self.parse_expr(Precedence::Lowest)?;
-                Ok(StmtKind::Assign(expr.clone(), rhs, false))
+                Ok(StmtKind::Assign(expr.clone(), rhs, false, None))
             }
             _ => Ok(lhs),
         }
@@ -277,9 +627,7 @@ impl Parser {
             let expr = self.parse_expr(Precedence::Lowest)?;
             filter = Some(expr);
         }
-        self.expect_peek(TokenKind::Do)?;
-        self.next_token();
-        let block = self.parse_block(vec![TokenKind::End])?;
+        let block = self.parse_loop_body()?;
         Ok(StmtKind::Crawl(CrawlLiteral::new(binding, filter, block)))
     }
 
@@ -312,60 +660,206 @@ impl Parser {
         Ok(IfLiteral { cond, block })
     }
 
+    /// `if <cond> then <expr> else <expr>`
+    fn parse_conditional(&mut self) -> ParseResult<ExprKind> {
+        self.next_token();
+        let cond = self.parse_expr(Precedence::Lowest)?;
+        self.expect_peek(TokenKind::Then)?;
+        self.next_token();
+        let then = self.parse_expr(Precedence::Lowest)?;
+        self.expect_peek(TokenKind::Else)?;
+        self.next_token();
+        let otherwise = self.parse_expr(Precedence::Lowest)?;
+        Ok(ExprKind::Conditional(
+            Box::new(cond),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    /// `match <expr> case <pattern> [if <guard>] do <block> ... end`
+    fn parse_match(&mut self) -> ParseResult<ExprKind> {
+        self.next_token();
+        let subject = self.parse_expr(Precedence::Lowest)?;
+        self.expect_peek(TokenKind::Case)?;
+        let mut arms = Vec::new();
+        while self.curr.kind == TokenKind::Case {
+            self.next_token();
+            let pattern = self.parse_pattern()?;
+            let mut guard = None;
+            if self.peek.kind == TokenKind::If {
+                self.next_token();
+                self.next_token();
+                guard = Some(self.parse_expr(Precedence::Lowest)?);
+            }
+            self.expect_peek(TokenKind::Do)?;
+            self.next_token();
+            let block = self.parse_block(vec![TokenKind::End, TokenKind::Case])?;
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                block,
+            });
+        }
+
+        Ok(ExprKind::Match(MatchLiteral {
+            subject: Box::new(subject),
+            arms,
+        }))
+    }
+
+    fn parse_pattern(&mut self) -> ParseResult<Pattern> {
+        match self.curr.kind {
+            TokenKind::Ident if self.curr.literal == "_" => Ok(Pattern::Wildcard),
+            TokenKind::Ident => Ok(Pattern::Binding(Identifier::new(self.curr.literal.clone()))),
+            TokenKind::Str
+            | TokenKind::InterpStart
+            | TokenKind::Int
+            | TokenKind::Float
+            | TokenKind::Minus
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Null => Ok(Pattern::Literal(self.parse_expr(Precedence::Prefix)?)),
+            TokenKind::LBracket => {
+                let mut pats = Vec::new();
+                while self.peek.kind != TokenKind::RBracket {
+                    self.next_token();
+                    pats.push(self.parse_pattern()?);
+                    if self.peek.kind != TokenKind::Comma {
+                        break;
+                    }
+                    self.next_token();
+                }
+                self.expect_peek(TokenKind::RBracket)?;
+                Ok(Pattern::List(pats))
+            }
+            TokenKind::LBrace => {
+                let mut pairs = Vec::new();
+                while self.peek.kind != TokenKind::RBrace {
+                    self.next_token();
+                    let key = match self.curr.kind {
+                        TokenKind::Ident | TokenKind::Str => {
+                            Identifier::new(self.curr.literal.clone())
+                        }
+                        _ => return Err(ParseError::InvalidPattern(self.curr.clone())),
+                    };
+                    // `{ title }` is short for `{ title: title }`.
+                    let pat = if self.peek.kind == TokenKind::Colon {
+                        self.next_token();
+                        self.next_token();
+                        self.parse_pattern()?
+                    } else if self.curr.kind == TokenKind::Ident {
+                        Pattern::Binding(key.clone())
+                    } else {
+                        return Err(ParseError::UnexpectedToken(
+                            TokenKind::Colon,
+                            self.peek.clone(),
+                        ));
+                    };
+                    pairs.push((key, pat));
+                    if self.peek.kind != TokenKind::Comma {
+                        break;
+                    }
+                    self.next_token();
+                }
+                self.expect_peek(TokenKind::RBrace)?;
+                Ok(Pattern::Map(pairs))
+            }
+            _ => Err(ParseError::InvalidPattern(self.curr.clone())),
+        }
+    }
+
     fn parse_prefix(&mut self) -> ParseResult<ExprKind> {
         let op = self.curr.clone();
+        let prec = match op.kind {
+            // `!` has always negated the entire expression that follows it.
+            TokenKind::Bang => Precedence::Lowest,
+            _ => Precedence::Prefix,
+        };
         self.next_token();
-        let expr = self.parse_expr(Precedence::Lowest)?;
+        let expr = self.parse_expr(prec)?;
         Ok(ExprKind::Prefix(Box::new(expr), op))
     }
 
     fn parse_block(&mut self, finalizers: Vec<TokenKind>) -> ParseResult<Block> {
         let mut stmts = Vec::new();
         while !finalizers.contains(&self.curr.kind) {
-            let stmt = self.parse_stmt()?;
-            stmts.push(stmt);
-            self.next_token();
+            if self.curr.kind == TokenKind::EOF {
+                return Err(ParseError::UnexpectedToken(
+                    finalizers[0],
+                    self.curr.clone(),
+                ));
+            }
+            if let Some(stmt) = self.parse_stmt_recovering(&finalizers) {
+                stmts.push(stmt);
+            }
         }
         Ok(Block::new(stmts))
     }
 
+    /// `try <block> [catch [<ident> do] <block>] [finally <block>] end`
     fn parse_try_catch(&mut self) -> ParseResult<StmtKind> {
         self.next_token();
-        let try_b = self.parse_block(vec![TokenKind::Catch, TokenKind::End])?;
+        let try_b = self.parse_block(vec![TokenKind::Catch, TokenKind::Finally, TokenKind::End])?;
         let catch_b = if self.curr.kind == TokenKind::Catch {
             self.next_token();
-            let block = self.parse_block(vec![TokenKind::End])?;
-            Some(block)
+            let mut binding = None;
+            if self.curr.kind == TokenKind::Ident && self.peek.kind == TokenKind::Do {
+                binding = Some(Identifier::new(self.curr.literal.clone()));
+                self.next_token();
+                self.next_token();
+            }
+            let block = self.parse_block(vec![TokenKind::Finally, TokenKind::End])?;
+            Some(CatchLiteral::new(binding, block))
+        } else {
+            None
+        };
+        let finally_b = if self.curr.kind == TokenKind::Finally {
+            self.next_token();
+            Some(self.parse_block(vec![TokenKind::End])?)
         } else {
             None
         };
 
-        Ok(StmtKind::TryCatch(try_b, catch_b))
+        Ok(StmtKind::TryCatch(try_b, catch_b, finally_b))
     }
 
     fn parse_while_loop(&mut self) -> ParseResult<StmtKind> {
         self.next_token();
         let condition = self.parse_expr(Precedence::Lowest)?;
-        self.expect_peek(TokenKind::Do)?;
-        self.next_token();
-        let block = self.parse_block(vec![TokenKind::End])?;
+        let block = self.parse_loop_body()?;
         Ok(StmtKind::WhileLoop(condition, block))
     }
 
-    /// `for <ident> in <expr> do <block> end`
+    /// `for <ident>[, <ident>...] in <expr> do <block> end`
     fn parse_for_loop(&mut self) -> ParseResult<StmtKind> {
         self.expect_peek(TokenKind::Ident)?;
-        let ident = Identifier::new(self.curr.literal.clone());
+        let mut idents = vec![Identifier::new(self.curr.literal.clone())];
+        while self.peek.kind == TokenKind::Comma {
+            self.next_token();
+            self.expect_peek(TokenKind::Ident)?;
+            idents.push(Identifier::new(self.curr.literal.clone()));
+        }
         self.expect_peek(TokenKind::In)?;
         self.next_token();
         let iterable = self.parse_expr(Precedence::Lowest)?;
-        self.expect_peek(TokenKind::Do)?;
-        self.next_token();
-        let block = self.parse_block(vec![TokenKind::End])?;
-        let floop = ForLoop::new(ident, iterable, block);
+        let block = self.parse_loop_body()?;
+        let floop = ForLoop::new(idents, iterable, block);
         Ok(StmtKind::ForLoop(floop))
     }
 
+    /// `let x = 1`, `let [a, b] = pair` or `const X = 1`
+    fn parse_let_stmt(&mut self) -> ParseResult<StmtKind> {
+        let constant = self.curr.kind == TokenKind::Const;
+        self.next_token();
+        let lhs = self.parse_expr(Precedence::Lowest)?;
+        let ty = self.parse_annotation()?;
+        self.expect_peek(TokenKind::Assign)?;
+        self.next_token();
+        let val = self.parse_expr(Precedence::Lowest)?;
+        Ok(StmtKind::Let(lhs, val, constant, ty))
+    }
+
     fn parse_use_stmt(&mut self) -> ParseResult<StmtKind> {
         self.next_token();
         let import = self.parse_expr(Precedence::Lowest)?;
@@ -393,25 +887,73 @@ impl Parser {
     }
 
     /// `scrape { body: ".body" }`
+    /// `scrape { ... }` or `scrape Product { ... }`
     fn parse_scrape_stmt(&mut self) -> ParseResult<StmtKind> {
+        let mut ty = None;
+        if self.peek.kind == TokenKind::Ident {
+            self.next_token();
+            ty = Some(Identifier::new(self.curr.literal.clone()));
+        }
         self.expect_peek(TokenKind::LBrace)?;
         let body = self.parse_hash_literal()?;
-        Ok(StmtKind::Scrape(body))
+        Ok(StmtKind::Scrape(ty, body))
     }
 
-    /// `{ a: "b", c: "d" }`
-    /// @TODO: allow expressions as values instead of strings
+    /// `type Product { name: str, price: number? }`
+    fn parse_type_decl(&mut self) -> ParseResult<StmtKind> {
+        self.expect_peek(TokenKind::Ident)?;
+        let ident = Identifier::new(self.curr.literal.clone());
+        self.expect_peek(TokenKind::LBrace)?;
+        let mut fields = Vec::new();
+        while self.peek.kind != TokenKind::RBrace {
+            self.next_token();
+            let field = match self.curr.kind {
+                TokenKind::Ident | TokenKind::Str => Identifier::new(self.curr.literal.clone()),
+                _ => {
+                    return Err(ParseError::UnexpectedToken(
+                        TokenKind::Ident,
+                        self.curr.clone(),
+                    ))
+                }
+            };
+            self.expect_peek(TokenKind::Colon)?;
+            fields.push((field, self.parse_type_expr()?));
+            if self.peek.kind != TokenKind::Comma {
+                break;
+            }
+            self.next_token();
+        }
+        self.expect_peek(TokenKind::RBrace)?;
+        Ok(StmtKind::TypeDecl(TypeDecl { ident, fields }))
+    }
+
+    /// A type name, optionally followed by `?`. Current token should be
+    /// the one before the name.
+    fn parse_type_expr(&mut self) -> ParseResult<TypeExpr> {
+        // `fn` and `null` are keywords, but also name types.
+        match self.peek.kind {
+            TokenKind::Fn | TokenKind::Null => self.next_token(),
+            _ => self.expect_peek(TokenKind::Ident)?,
+        }
+        let name = Identifier::new(self.curr.literal.clone());
+        let optional = self.peek.kind == TokenKind::Question;
+        if optional {
+            self.next_token();
+        }
+        Ok(TypeExpr::new(name, optional))
+    }
+
+    /// `{ a: "b", "content-type": "d", [key]: e }`
     ///
     /// Current token entering should be a LBrace
     fn parse_hash_literal(&mut self) -> ParseResult<HashLiteral> {
-        let mut pairs = HashMap::new();
+        let mut pairs = Vec::new();
         while self.peek.kind != TokenKind::RBrace {
-            self.expect_peek(TokenKind::Ident)?;
-            let ident = Identifier::new(self.curr.literal.clone());
+            let key = self.parse_map_key()?;
             self.expect_peek(TokenKind::Colon)?;
             self.next_token();
             let val = self.parse_expr(Precedence::Lowest)?;
-            pairs.insert(ident, val);
+            pairs.push((key, val));
             if self.peek.kind == TokenKind::Comma {
                 self.next_token();
             }
@@ -420,18 +962,42 @@ impl Parser {
         Ok(HashLiteral { pairs })
     }
 
+    /// Expects the peek token to be the start of the key.
+    fn parse_map_key(&mut self) -> ParseResult<MapKey> {
+        self.next_token();
+        match self.curr.kind {
+            TokenKind::Ident | TokenKind::Str => {
+                Ok(MapKey::Ident(Identifier::new(self.curr.literal.clone())))
+            }
+            TokenKind::InterpStart => Ok(MapKey::Computed(self.parse_expr(Precedence::Lowest)?)),
+            TokenKind::LBracket => {
+                self.next_token();
+                let expr = self.parse_expr(Precedence::Lowest)?;
+                self.expect_peek(TokenKind::RBracket)?;
+                Ok(MapKey::Computed(expr))
+            }
+            _ => Err(ParseError::UnexpectedToken(
+                TokenKind::Ident,
+                self.curr.clone(),
+            )),
+        }
+    }
+
     fn parse_map(&mut self) -> ParseResult<ExprKind> {
         let lit = self.parse_hash_literal()?;
         Ok(ExprKind::Map(lit))
     }
 
     fn parse_number_literal(&mut self) -> ParseResult<ExprKind> {
-        Ok(ExprKind::Number(
-            self.curr
-                .literal
-                .parse::<f64>()
-                .map_err(|_| ParseError::InvalidNumber)?,
-        ))
+        let lit = self.curr.literal.replace('_', "");
+        let num = match lit.strip_prefix("0x").or_else(|| lit.strip_prefix("0X")) {
+            Some(hex) => i64::from_str_radix(hex, 16).map(|n| n as f64).ok(),
+            None => lit.parse::<f64>().ok(),
+        };
+        // Literals too large for a float would have no JSON form in AST dumps.
+        num.filter(|n| n.is_finite())
+            .map(ExprKind::Number)
+            .ok_or_else(|| ParseError::InvalidNumber(self.curr.clone()))
     }
 
     fn parse_boolean(&mut self) -> ParseResult<ExprKind> {
@@ -443,6 +1009,37 @@ impl Parser {
         Ok(ExprKind::Str(self.curr.literal.clone()))
     }
 
+    /// `"page ${n} of ${total}"`, with the empty pieces of text left out.
+    fn parse_interpolated_str(&mut self) -> ParseResult<ExprKind> {
+        let mut parts = Vec::new();
+        if !self.curr.literal.is_empty() {
+            parts.push(Expr::new(
+                ExprKind::Str(self.curr.literal.clone()),
+                self.curr.span,
+            ));
+        }
+        loop {
+            self.next_token();
+            parts.push(self.parse_expr(Precedence::Lowest)?);
+
+            self.next_token();
+            let kind = self.curr.kind;
+            if !matches!(kind, TokenKind::InterpMid | TokenKind::InterpEnd) {
+                return Err(ParseError::UnexpectedToken(
+                    TokenKind::InterpEnd,
+                    self.curr.clone(),
+                ));
+            }
+            if !self.curr.literal.is_empty() {
+                let lit = Expr::new(ExprKind::Str(self.curr.literal.clone()), self.curr.span);
+                parts.push(lit);
+            }
+            if kind == TokenKind::InterpEnd {
+                return Ok(ExprKind::Interp(parts));
+            }
+        }
+    }
+
     fn parse_null(&mut self) -> ParseResult<ExprKind> {
         Ok(ExprKind::Null)
     }
@@ -477,7 +1074,7 @@ impl Parser {
                 let expr = ExprKind::Select(self.curr.literal.clone(), Some(ident));
                 Ok(expr)
             }
-            _ => Err(ParseError::InvalidToken(self.peek.kind)),
+            _ => Err(ParseError::InvalidToken(self.peek.clone())),
         }
     }
 
@@ -497,36 +1094,60 @@ impl Parser {
                 let expr = ExprKind::SelectAll(self.curr.literal.clone(), Some(ident));
                 Ok(expr)
             }
-            _ => Err(ParseError::InvalidToken(self.peek.kind)),
+            _ => Err(ParseError::InvalidToken(self.peek.clone())),
         }
     }
 
-    fn parse_infix(&mut self, lhs: ExprKind) -> ParseResult<ExprKind> {
+    fn parse_infix(&mut self, lhs: Expr) -> ParseResult<ExprKind> {
         // self.next_token();
         let op = self.curr.clone();
-        let prec = self.curr_precedence();
+        let prec = match op.kind {
+            // Right associative, so `2 ** 3 ** 2` is `2 ** (3 ** 2)`.
+            TokenKind::DbAsterisk => Precedence::Prefix,
+            _ => self.curr_precedence(),
+        };
         self.next_token();
         let rhs = self.parse_expr(prec)?;
         Ok(ExprKind::Infix(Box::new(lhs), op, Box::new(rhs)))
     }
 
-    fn parse_index(&mut self, ident: ExprKind) -> ParseResult<ExprKind> {
+    fn parse_index(&mut self, ident: Expr) -> ParseResult<ExprKind> {
         let infix = self.parse_infix(ident)?;
         self.expect_peek(TokenKind::RBracket)?;
         Ok(infix)
     }
 
-    fn parse_expr(&mut self, precedence: Precedence) -> ParseResult<ExprKind> {
+    /// Parses `obj.field`, or `obj.f(args)` when the field is called.
+    fn parse_dot(&mut self, obj: Expr) -> ParseResult<ExprKind> {
+        self.expect_peek(TokenKind::Ident)?;
+        let ident = Identifier::new(self.curr.literal.clone());
+        if self.peek.kind == TokenKind::LParen {
+            self.next_token();
+            let (args, kwargs) = self.parse_call_args(TokenKind::RParen)?;
+            let call = CallLiteral {
+                ident,
+                args,
+                kwargs,
+            };
+            Ok(ExprKind::MethodCall(Box::new(obj), call))
+        } else {
+            Ok(ExprKind::Field(Box::new(obj), ident))
+        }
+    }
+
+    fn parse_expr(&mut self, precedence: Precedence) -> ParseResult<Expr> {
         match map_prefix_fn(&self.curr.kind) {
-            None => Err(ParseError::UnknownPrefix(self.curr.kind)),
+            None => Err(ParseError::UnknownPrefix(self.curr.clone())),
             Some(f) => {
-                let mut lhs = f(self)?;
+                let start = self.curr.span;
+                let mut lhs = Expr::new(f(self)?, start.to(self.curr.span));
                 while precedence < self.peek_precedence() {
                     match map_infix_fn(&self.peek.kind) {
                         None => return Ok(lhs),
                         Some(in_fn) => {
                             self.next_token();
-                            lhs = in_fn(self, lhs)?;
+                            let start = lhs.span;
+                            lhs = Expr::new(in_fn(self, lhs)?, start.to(self.curr.span));
                         }
                     }
                 }
@@ -537,25 +1158,38 @@ impl Parser {
     }
 
     fn parse_ident(&mut self) -> ParseResult<ExprKind> {
-        Ok(ExprKind::Ident(Identifier::new(self.curr.literal.clone())))
+        let ident = Identifier::new(self.curr.literal.clone());
+        // `Product { ... }` constructs a record of a declared type. A `{`
+        // on a later line starts a new statement instead.
+        if self.peek.kind == TokenKind::LBrace && self.peek.span.line == self.curr.span.line {
+            self.next_token();
+            let body = self.parse_hash_literal()?;
+            return Ok(ExprKind::Record(ident, body));
+        }
+        Ok(ExprKind::Ident(ident))
     }
 
-    fn parse_chain_expr(&mut self, first: ExprKind) -> ParseResult<ExprKind> {
+    fn parse_chain_expr(&mut self, first: Expr) -> ParseResult<ExprKind> {
+        let pipe = self.curr.kind;
         let mut exprs = vec![first];
 
-        while self.curr.kind == TokenKind::Pipe {
+        while self.curr.kind == pipe {
             self.next_token();
-            let id = self.parse_ident()?;
+            let start = self.curr.span;
+            let id = Expr::new(self.parse_ident()?, start);
             self.expect_peek(TokenKind::LParen)?;
             let call = self.parse_call_expr(id)?;
-            exprs.push(call);
+            exprs.push(Expr::new(call, start.to(self.curr.span)));
         }
 
-        Ok(ExprKind::Chain(exprs))
+        match pipe {
+            TokenKind::SafePipe => Ok(ExprKind::SafeChain(exprs)),
+            _ => Ok(ExprKind::Chain(exprs)),
+        }
     }
 
-    fn parse_call_args(&mut self, end: TokenKind) -> ParseResult<(Vec<ExprKind>, Vec<Kwarg>)> {
-        let mut args: Vec<ExprKind> = Vec::new();
+    fn parse_call_args(&mut self, end: TokenKind) -> ParseResult<(Vec<Expr>, Vec<Kwarg>)> {
+        let mut args: Vec<Expr> = Vec::new();
         let mut kwargs: Vec<Kwarg> = Vec::new();
         if self.peek.kind == end {
             self.next_token();
@@ -613,8 +1247,8 @@ impl Parser {
         Ok((args, kwargs))
     }
 
-    fn parse_call_expr(&mut self, func: ExprKind) -> ParseResult<ExprKind> {
-        match func {
+    fn parse_call_expr(&mut self, func: Expr) -> ParseResult<ExprKind> {
+        match func.kind {
             ExprKind::Ident(ident) => {
                 let (args, kwargs) = self.parse_call_args(TokenKind::RParen)?;
                 Ok(ExprKind::Call(CallLiteral {
@@ -623,7 +1257,7 @@ impl Parser {
                     kwargs,
                 }))
             }
-            _ => Err(ParseError::InvalidFnCall),
+            _ => Err(ParseError::InvalidFnCall(func.span)),
         }
     }
 }
@@ -642,7 +1276,7 @@ mod tests {
     fn parse_stmts(i: &str) -> Vec<StmtKind> {
         let mut p = setup_parser(i);
         let prg = p.parse_program().unwrap();
-        prg.stmts
+        prg.stmts.into_iter().map(|s| s.kind).collect()
     }
 
     fn extract_first_stmt(i: &str) -> StmtKind {
@@ -650,14 +1284,15 @@ mod tests {
         stmts[0].clone()
     }
 
-    #[test_case(r#"goto "foo""#, StmtKind::Goto(ExprKind::Str("foo".into())); "simple goto")]
-    #[test_case("scrape {}", StmtKind::Scrape(HashLiteral::default()); "empty scrape")]
+    #[test_case(r#"goto "foo""#, StmtKind::Goto(ExprKind::Str("foo".into()).into()); "simple goto")]
+    #[test_case("scrape {}", StmtKind::Scrape(None, HashLiteral::default()); "empty scrape")]
     #[test_case(
         r#"scrape { a: $"b" }"#,
         StmtKind::Scrape(
+            None,
             HashLiteral::from(
                 vec![
-                    (Identifier::new("a".into()), ExprKind::Select("b".into(), None))
+                    (Identifier::new("a".into()), ExprKind::Select("b".into(), None).into())
                 ]
             )
         ); "scrape with single key"
@@ -665,10 +1300,11 @@ mod tests {
     #[test_case(
         r#"scrape { a: $"b", c: $"d" }"#,
         StmtKind::Scrape(
+            None,
             HashLiteral::from(
                 vec![
-                    (Identifier::new("a".into()), ExprKind::Select("b".into(), None)),
-                    (Identifier::new("c".into()), ExprKind::Select("d".into(), None))
+                    (Identifier::new("a".into()), ExprKind::Select("b".into(), None).into()),
+                    (Identifier::new("c".into()), ExprKind::Select("d".into(), None).into())
                 ]
             )
         ); "scrape with multi keys"
@@ -676,49 +1312,125 @@ mod tests {
     #[test_case(
         r#"scrape { a: $$"b" }"#,
         StmtKind::Scrape(
+            None,
             HashLiteral::from(
                 vec![
-                    (Identifier::new("a".into()), ExprKind::SelectAll("b".into(), None)),
+                    (Identifier::new("a".into()), ExprKind::SelectAll("b".into(), None).into()),
                 ]
             )
         ); "scrape with select all key"
     )]
     #[test_case(
-        r#"scrape { a: fn("a") }"#,
+        r#"scrape { a: f("a") }"#,
         StmtKind::Scrape(
+            None,
             HashLiteral::from(
                 vec![
                     (
                         Identifier::new("a".into()),
                         ExprKind::Call(
                             CallLiteral {
-                                ident: Identifier::new("fn".into()),
-                                args: vec![ExprKind::Str("a".into())],
+                                ident: Identifier::new("f".into()),
+                                args: vec![ExprKind::Str("a".into()).into()],
                                 kwargs: vec![]
                             }
-                        )
+                        ).into()
                     )
                 ]
             )
         ); "scrape with fn key"
     )]
+    #[test_case(
+        r#"$"span" ?|> textContent() ?? "n/a""#,
+        StmtKind::Expr(
+            ExprKind::Infix(
+                Box::new(ExprKind::SafeChain(vec![
+                    ExprKind::Select("span".into(), None).into(),
+                    ExprKind::Call(
+                        CallLiteral {
+                            ident: Identifier::new("textContent".into()),
+                            args: vec![],
+                            kwargs: vec![]
+                        }
+                    ).into()
+                ]).into()),
+                Token::new(TokenKind::DbQuestion, "??".to_string()),
+                Box::new(ExprKind::Str("n/a".into()).into())
+            ).into()
+        ); "null safe chain with default"
+    )]
+    #[test_case(
+        r#"a ?? b == c"#,
+        StmtKind::Expr(
+            ExprKind::Infix(
+                Box::new(ExprKind::Ident(Identifier::new("a".into())).into()),
+                Token::new(TokenKind::DbQuestion, "??".to_string()),
+                Box::new(ExprKind::Infix(
+                    Box::new(ExprKind::Ident(Identifier::new("b".into())).into()),
+                    Token::new(TokenKind::EQ, "==".to_string()),
+                    Box::new(ExprKind::Ident(Identifier::new("c".into())).into())
+                ).into())
+            ).into()
+        ); "coalesce binds loosest"
+    )]
+    #[test_case(
+        r#"a or b and c != """#,
+        StmtKind::Expr(
+            ExprKind::Infix(
+                Box::new(ExprKind::Ident(Identifier::new("a".into())).into()),
+                Token::new(TokenKind::Or, "or".to_string()),
+                Box::new(ExprKind::Infix(
+                    Box::new(ExprKind::Ident(Identifier::new("b".into())).into()),
+                    Token::new(TokenKind::And, "and".to_string()),
+                    Box::new(ExprKind::Infix(
+                        Box::new(ExprKind::Ident(Identifier::new("c".into())).into()),
+                        Token::new(TokenKind::NEQ, "!=".to_string()),
+                        Box::new(ExprKind::Str("".into()).into())
+                    ).into())
+                ).into())
+            ).into()
+        ); "and binds tighter than or"
+    )]
+    #[test_case(
+        r#"scrape { a: if b then 1 else if c then 2 else 3 }"#,
+        StmtKind::Scrape(
+            None,
+            HashLiteral::from(
+                vec![
+                    (
+                        Identifier::new("a".into()),
+                        ExprKind::Conditional(
+                            Box::new(ExprKind::Ident(Identifier::new("b".into())).into()),
+                            Box::new(ExprKind::Number(1.).into()),
+                            Box::new(ExprKind::Conditional(
+                                Box::new(ExprKind::Ident(Identifier::new("c".into())).into()),
+                                Box::new(ExprKind::Number(2.).into()),
+                                Box::new(ExprKind::Number(3.).into())
+                            ).into())
+                        ).into()
+                    )
+                ]
+            )
+        ); "nested conditional in scrape"
+    )]
     #[test_case(
         r#"scrape { a: $"b" |> fn("a") }"#,
         StmtKind::Scrape(
+            None,
             HashLiteral::from(
                 vec![
                     (
                         Identifier::new("a".into()),
                         ExprKind::Chain(vec![
-                            ExprKind::Select("b".into(), None),
+                            ExprKind::Select("b".into(), None).into(),
                             ExprKind::Call(
                                 CallLiteral {
                                     ident: Identifier::new("fn".into()),
-                                    args: vec![ExprKind::Str("a".into())],
+                                    args: vec![ExprKind::Str("a".into()).into()],
                                     kwargs: vec![]
                                 }
-                            )
-                        ])
+                            ).into()
+                        ]).into()
                     )
                 ]
             )
@@ -727,48 +1439,51 @@ mod tests {
     #[test_case(
         r#"for node in $$"a" do end"#,
         StmtKind::ForLoop(
-            ForLoop::new(Identifier::new("node".into()), ExprKind::SelectAll("a".into(), None), Block::new(vec![]))
+            ForLoop::new(vec![Identifier::new("node".into())], ExprKind::SelectAll("a".into(), None).into(), Block::new(vec![]))
         ); "for loop empty body"
     )]
     #[test_case(
         r#"for node in $$"a" do $"a" end"#,
         StmtKind::ForLoop(
-            ForLoop::new(Identifier::new("node".into()), ExprKind::SelectAll("a".into(), None), Block::new(vec![
-                StmtKind::Expr(ExprKind::Select("a".into(), None))
+            ForLoop::new(vec![Identifier::new("node".into())], ExprKind::SelectAll("a".into(), None).into(), Block::new(vec![
+                StmtKind::Expr(ExprKind::Select("a".into(), None).into()).into()
             ]))
         ); "for loop single select bodyd"
     )]
     #[test_case(
         r#"x = "a""#,
         StmtKind::Assign(
-            ExprKind::Ident(Identifier::new("x".into())),
-            ExprKind::Str("a".into()),
-            false
+            ExprKind::Ident(Identifier::new("x".into())).into(),
+            ExprKind::Str("a".into()).into(),
+            false,
+            None,
         ); "single assign"
     )]
     #[test_case(
         r#"global x = "a""#,
         StmtKind::Assign(
-            ExprKind::Ident(Identifier::new("x".into())),
-            ExprKind::Str("a".into()),
-            true
+            ExprKind::Ident(Identifier::new("x".into())).into(),
+            ExprKind::Str("a".into()).into(),
+            true,
+            None,
         ); "single global assign"
     )]
-    #[test_case(r#"null"#, StmtKind::Expr(ExprKind::Null); "null expr stmt")]
+    #[test_case(r#"null"#, StmtKind::Expr(ExprKind::Null.into()); "null expr stmt")]
     #[test_case(
         r#"for node in $$"a" do scrape {} end"#,
         StmtKind::ForLoop(
-            ForLoop::new(Identifier::new("node".into()), ExprKind::SelectAll("a".into(), None), Block::new(vec![
-                StmtKind::Scrape(HashLiteral::default())
+            ForLoop::new(vec![Identifier::new("node".into())], ExprKind::SelectAll("a".into(), None).into(), Block::new(vec![
+                StmtKind::Scrape(None, HashLiteral::default()).into()
             ]))
         ); "for loop with scrape body"
     )]
     #[test_case(
         r#"x = 1 == 2"#,
         StmtKind::Assign(
-            ExprKind::Ident(Identifier::new("x".to_string())),
-            ExprKind::Infix(Box::new(ExprKind::Number(1.)), Token::new(TokenKind::EQ, "==".to_string()), Box::new(ExprKind::Number(2.))),
-            false
+            ExprKind::Ident(Identifier::new("x".to_string())).into(),
+            ExprKind::Infix(Box::new(ExprKind::Number(1.).into()), Token::new(TokenKind::EQ, "==".to_string()), Box::new(ExprKind::Number(2.).into())).into(),
+            false,
+            None,
         ); "assign eq infix"
     )]
     #[test_case(
@@ -776,15 +1491,16 @@ mod tests {
         StmtKind::Assign(
             ExprKind::Infix(
                 Box::new(
-                    ExprKind::Ident(Identifier::new("a".into()))
+                    ExprKind::Ident(Identifier::new("a".into())).into()
                 ),
                 Token::new(TokenKind::LBracket, "[".to_string()),
                 Box::new(
-                    ExprKind::Number(0.)
+                    ExprKind::Number(0.).into()
                 )
-            ),
-            ExprKind::Number(1.),
+            ).into(),
+            ExprKind::Number(1.).into(),
             false,
+            None,
         ); "index assign"
     )]
     #[test_case(
@@ -794,12 +1510,12 @@ mod tests {
                 CallLiteral {
                     ident: Identifier::new("f".into()),
                     args: vec![
-                        ExprKind::Ident(Identifier::new("a".into())),
-                        ExprKind::Ident(Identifier::new("b".into()))
+                        ExprKind::Ident(Identifier::new("a".into())).into(),
+                        ExprKind::Ident(Identifier::new("b".into())).into()
                     ],
                     kwargs: vec![]
                 }
-            )
+            ).into()
         ); "fn call with multi params"
     )]
     #[test_case(
@@ -809,16 +1525,16 @@ mod tests {
                 CallLiteral {
                     ident: Identifier::new("f".into()),
                     args: vec![
-                        ExprKind::Ident(Identifier::new("a".into()))
+                        ExprKind::Ident(Identifier::new("a".into())).into()
                     ],
                     kwargs: vec![
                         Kwarg {
                             ident: Identifier::new("b".into()),
-                            expr: ExprKind::Number(1.),
+                            expr: ExprKind::Number(1.).into(),
                         }
                     ]
                 }
-            )
+            ).into()
         ); "fn call with kwarg & arg"
     )]
     #[test_case(
@@ -832,11 +1548,11 @@ mod tests {
                     kwargs: vec![
                         Kwarg {
                             ident: Identifier::new("b".into()),
-                            expr: ExprKind::Number(1.),
+                            expr: ExprKind::Number(1.).into(),
                         }
                     ]
                 }
-            )
+            ).into()
         ); "fn call with kwarg"
     )]
     #[test_case(
@@ -851,6 +1567,42 @@ mod tests {
             false
         ); "fn definition"
     )]
+    #[test_case(
+        "def f() do\n  return\nend",
+        StmtKind::Func(
+            FuncDef::new(
+                Identifier::new("f".into()),
+                vec![],
+                Block::new(vec![StmtKind::Return(None).into()])
+            ),
+            false
+        ); "fn def bare return"
+    )]
+    #[test_case(
+        "def f() do\n  return\n  x\nend",
+        StmtKind::Func(
+            FuncDef::new(
+                Identifier::new("f".into()),
+                vec![],
+                Block::new(vec![
+                    StmtKind::Return(None).into(),
+                    StmtKind::Expr(ExprKind::Ident(Identifier::new("x".into())).into()).into(),
+                ])
+            ),
+            false
+        ); "fn def bare return before newline"
+    )]
+    #[test_case(
+        "def f() do return 1 end",
+        StmtKind::Func(
+            FuncDef::new(
+                Identifier::new("f".into()),
+                vec![],
+                Block::new(vec![StmtKind::Return(Some(ExprKind::Number(1.).into())).into()])
+            ),
+            false
+        ); "fn def return value"
+    )]
     #[test_case(
         r#"global def f() do end"#,
         StmtKind::Func(
@@ -883,46 +1635,306 @@ mod tests {
             FuncDef::new(
                 Identifier::new("f".into()),
                 vec![
-                    FnParam::new(Identifier::new("a".into()), Some(ExprKind::Null))
+                    FnParam::new(Identifier::new("a".into()), Some(ExprKind::Null.into()))
                 ],
                 Block::default()
             ),
             false
         ); "fn def default param"
     )]
+    #[test_case(
+        r#"f = fn(a, b = 1) do a end"#,
+        StmtKind::Assign(
+            ExprKind::Ident(Identifier::new("f".into())).into(),
+            ExprKind::Lambda(
+                LambdaLiteral::new(
+                    vec![
+                        FnParam::new(Identifier::new("a".into()), None),
+                        FnParam::new(Identifier::new("b".into()), Some(ExprKind::Number(1.).into()))
+                    ],
+                    Block::new(vec![
+                        StmtKind::Expr(ExprKind::Ident(Identifier::new("a".into())).into()).into()
+                    ])
+                )
+            ).into(),
+            false,
+            None,
+        ); "assign lambda"
+    )]
+    #[test_case(
+        r#"f(fn() do end)"#,
+        StmtKind::Expr(
+            ExprKind::Call(
+                CallLiteral {
+                    ident: Identifier::new("f".into()),
+                    args: vec![
+                        ExprKind::Lambda(LambdaLiteral::new(vec![], Block::default())).into()
+                    ],
+                    kwargs: vec![]
+                }
+            ).into()
+        ); "lambda as fn arg"
+    )]
+    #[test_case(
+        r#"while a do break end"#,
+        StmtKind::WhileLoop(
+            ExprKind::Ident(Identifier::new("a".into())).into(),
+            Block::new(vec![StmtKind::Break.into()])
+        ); "while with break"
+    )]
+    #[test_case(
+        r#"for a in b do if a do continue end end"#,
+        StmtKind::ForLoop(
+            ForLoop::new(
+                vec![Identifier::new("a".into())],
+                ExprKind::Ident(Identifier::new("b".into())).into(),
+                Block::new(vec![StmtKind::IfElse(IfElseLiteral {
+                    if_lit: IfLiteral {
+                        cond: ExprKind::Ident(Identifier::new("a".into())).into(),
+                        block: Block::new(vec![StmtKind::Continue.into()]),
+                    },
+                    elifs: vec![],
+                    else_lit: None,
+                }).into()])
+            )
+        ); "for with nested continue"
+    )]
+    #[test_case(
+        r#"for k, v in m do end"#,
+        StmtKind::ForLoop(
+            ForLoop::new(
+                vec![Identifier::new("k".into()), Identifier::new("v".into())],
+                ExprKind::Ident(Identifier::new("m".into())).into(),
+                Block::default()
+            )
+        ); "loop with destructured binding"
+    )]
+    #[test_case(
+        r#"[a, b] = pair"#,
+        StmtKind::Assign(
+            ExprKind::List(vec![
+                ExprKind::Ident(Identifier::new("a".into())).into(),
+                ExprKind::Ident(Identifier::new("b".into())).into(),
+            ]).into(),
+            ExprKind::Ident(Identifier::new("pair".into())).into(),
+            false,
+            None,
+        ); "destructuring assign"
+    )]
+    #[test_case(
+        r#"match x case "a" do 1 case [a, _] if a > 1 do a case { title, kind: -1 } do title end"#,
+        StmtKind::Expr(
+            ExprKind::Match(MatchLiteral {
+                subject: Box::new(ExprKind::Ident(Identifier::new("x".into())).into()),
+                arms: vec![
+                    MatchArm {
+                        pattern: Pattern::Literal(ExprKind::Str("a".into()).into()),
+                        guard: None,
+                        block: Block::new(vec![StmtKind::Expr(ExprKind::Number(1.).into()).into()])
+                    },
+                    MatchArm {
+                        pattern: Pattern::List(vec![
+                            Pattern::Binding(Identifier::new("a".into())),
+                            Pattern::Wildcard
+                        ]),
+                        guard: Some(ExprKind::Infix(
+                            Box::new(ExprKind::Ident(Identifier::new("a".into())).into()),
+                            Token::new(TokenKind::GT, ">".into()),
+                            Box::new(ExprKind::Number(1.).into())
+                        ).into()),
+                        block: Block::new(vec![
+                            StmtKind::Expr(ExprKind::Ident(Identifier::new("a".into())).into()).into()
+                        ])
+                    },
+                    MatchArm {
+                        pattern: Pattern::Map(vec![
+                            (Identifier::new("title".into()), Pattern::Binding(Identifier::new("title".into()))),
+                            (
+                                Identifier::new("kind".into()),
+                                Pattern::Literal(ExprKind::Prefix(
+                                    Box::new(ExprKind::Number(1.).into()),
+                                    Token::new(TokenKind::Minus, "-".into())
+                                ).into())
+                            ),
+                        ]),
+                        guard: None,
+                        block: Block::new(vec![
+                            StmtKind::Expr(ExprKind::Ident(Identifier::new("title".into())).into()).into()
+                        ])
+                    },
+                ]
+            }).into()
+        ); "match with patterns and guard"
+    )]
+    #[test_case(
+        "def f(a, *rest, **opts) do end",
+        StmtKind::Func(
+            FuncDef::new(
+                Identifier::new("f".into()),
+                vec![
+                    FnParam::new(Identifier::new("a".into()), None),
+                    FnParam::variadic(Identifier::new("rest".into()), ParamKind::Rest),
+                    FnParam::variadic(Identifier::new("opts".into()), ParamKind::Kwargs),
+                ],
+                Block::default()
+            ),
+            false
+        ); "def with variadic params"
+    )]
+    #[test_case(
+        "let [a, b] = c",
+        StmtKind::Let(
+            ExprKind::List(vec![
+                ExprKind::Ident(Identifier::new("a".into())).into(),
+                ExprKind::Ident(Identifier::new("b".into())).into()
+            ]).into(),
+            ExprKind::Ident(Identifier::new("c".into())).into(),
+            false,
+            None,
+        ); "let destructure"
+    )]
+    #[test_case(
+        r#"const URL = "a""#,
+        StmtKind::Let(
+            ExprKind::Ident(Identifier::new("URL".into())).into(),
+            ExprKind::Str("a".into()).into(),
+            true,
+            None,
+        ); "const declaration"
+    )]
+    #[test_case(
+        "m.a.b",
+        StmtKind::Expr(
+            ExprKind::Field(
+                Box::new(
+                    ExprKind::Field(
+                        Box::new(ExprKind::Ident(Identifier::new("m".into())).into()),
+                        Identifier::new("a".into())
+                    ).into()
+                ),
+                Identifier::new("b".into())
+            ).into()
+        ); "nested field access"
+    )]
+    #[test_case(
+        "m.a = 1",
+        StmtKind::Assign(
+            ExprKind::Field(
+                Box::new(ExprKind::Ident(Identifier::new("m".into())).into()),
+                Identifier::new("a".into())
+            ).into(),
+            ExprKind::Number(1.).into(),
+            false,
+            None,
+        ); "field assign"
+    )]
+    #[test_case(
+        "xs[0].push(1)",
+        StmtKind::Expr(
+            ExprKind::MethodCall(
+                Box::new(
+                    ExprKind::Infix(
+                        Box::new(ExprKind::Ident(Identifier::new("xs".into())).into()),
+                        Token::new(TokenKind::LBracket, "[".into()),
+                        Box::new(ExprKind::Number(0.).into())
+                    ).into()
+                ),
+                CallLiteral {
+                    ident: Identifier::new("push".into()),
+                    args: vec![ExprKind::Number(1.).into()],
+                    kwargs: vec![]
+                }
+            ).into()
+        ); "method call on index"
+    )]
+    #[test_case(
+        r#"{ b: 1, "content-type": 2, [k]: 3, a: 4 }"#,
+        StmtKind::Expr(
+            ExprKind::Map(HashLiteral {
+                pairs: vec![
+                    (MapKey::Ident(Identifier::new("b".into())), ExprKind::Number(1.).into()),
+                    (MapKey::Ident(Identifier::new("content-type".into())), ExprKind::Number(2.).into()),
+                    (
+                        MapKey::Computed(ExprKind::Ident(Identifier::new("k".into())).into()),
+                        ExprKind::Number(3.).into()
+                    ),
+                    (MapKey::Ident(Identifier::new("a".into())), ExprKind::Number(4.).into()),
+                ]
+            }).into()
+        ); "map with quoted and computed keys in order"
+    )]
     #[test_case(
         r#"[1, "a"]"#,
         StmtKind::Expr(
             ExprKind::List(
                 vec![
-                    ExprKind::Number(1.0),
-                    ExprKind::Str("a".into()),
+                    ExprKind::Number(1.0).into(),
+                    ExprKind::Str("a".into()).into(),
                 ]
-            )
+            ).into()
         ); "list literal"
     )]
     #[test_case(
         r#"for a in [1, 2] do end"#,
         StmtKind::ForLoop(
-            ForLoop::new(Identifier::new("a".into()), ExprKind::List(vec![
-                ExprKind::Number(1.0),
-                ExprKind::Number(2.0),
-            ]), Block::new(vec![]))
+            ForLoop::new(vec![Identifier::new("a".into())], ExprKind::List(vec![
+                ExprKind::Number(1.0).into(),
+                ExprKind::Number(2.0).into(),
+            ]).into(), Block::new(vec![]))
         ); "loop over list literal"
     )]
     #[test_case(
         "try catch end",
-        StmtKind::TryCatch(Block::default(), Some(Block::default())); "empty try catch"
+        StmtKind::TryCatch(
+            Block::default(),
+            Some(CatchLiteral::new(None, Block::default())),
+            None
+        ); "empty try catch"
+    )]
+    #[test_case("try end", StmtKind::TryCatch(Block::default(), None, None); "try catch with no catch")]
+    #[test_case(
+        "try throw 1 catch e do e end",
+        StmtKind::TryCatch(
+            Block::new(vec![StmtKind::Throw(ExprKind::Number(1.).into()).into()]),
+            Some(CatchLiteral::new(
+                Some(Identifier::new("e".into())),
+                Block::new(vec![
+                    StmtKind::Expr(ExprKind::Ident(Identifier::new("e".into())).into()).into()
+                ])
+            )),
+            None
+        ); "try catch with binding"
+    )]
+    #[test_case(
+        "try catch finally a end",
+        StmtKind::TryCatch(
+            Block::default(),
+            Some(CatchLiteral::new(None, Block::default())),
+            Some(Block::new(vec![
+                StmtKind::Expr(ExprKind::Ident(Identifier::new("a".into())).into()).into()
+            ]))
+        ); "try catch finally"
+    )]
+    #[test_case(
+        "try finally end",
+        StmtKind::TryCatch(Block::default(), None, Some(Block::default())); "try finally"
+    )]
+    #[test_case(
+        "throw { message: \"a\" }",
+        StmtKind::Throw(
+            ExprKind::Map(HashLiteral::from(vec![
+                (Identifier::new("message".into()), ExprKind::Str("a".into()).into())
+            ])).into()
+        ); "throw map"
     )]
-    #[test_case("try end", StmtKind::TryCatch(Block::default(), None); "try catch with no catch")]
     #[test_case(
         "a[0]",
         StmtKind::Expr(
             ExprKind::Infix(
-                Box::new(ExprKind::Ident(Identifier::new("a".into()))),
+                Box::new(ExprKind::Ident(Identifier::new("a".into())).into()),
                 Token::new(TokenKind::LBracket, "[".to_string()),
-                Box::new(ExprKind::Number(0.))
-            )
+                Box::new(ExprKind::Number(0.).into())
+            ).into()
         ); "index"
     )]
     #[test_case(
@@ -938,30 +1950,84 @@ mod tests {
                     depth: Identifier::new("depth".into())
                 }),
                 Some(ExprKind::Infix(
-                    Box::new(ExprKind::Ident(Identifier::new("depth".into()))),
+                    Box::new(ExprKind::Ident(Identifier::new("depth".into())).into()),
                     Token::new(TokenKind::LT, "<".to_string()),
-                    Box::new(ExprKind::Number(1.))
-                )),
+                    Box::new(ExprKind::Number(1.).into())
+                ).into()),
                 Block::default()
             )
         ); "crawl stmt with bindings"
     )]
+    #[test_case(
+        "-a * 2",
+        StmtKind::Expr(ExprKind::Infix(
+            Box::new(ExprKind::Prefix(
+                Box::new(ExprKind::Ident(Identifier::new("a".into())).into()),
+                Token::new(TokenKind::Minus, "-".to_string())
+            ).into()),
+            Token::new(TokenKind::Asterisk, "*".to_string()),
+            Box::new(ExprKind::Number(2.).into())
+        ).into()); "minus prefix binds tighter than infix"
+    )]
+    #[test_case(
+        "-2 ** 3 ** 2",
+        StmtKind::Expr(ExprKind::Prefix(
+            Box::new(ExprKind::Infix(
+                Box::new(ExprKind::Number(2.).into()),
+                Token::new(TokenKind::DbAsterisk, "**".to_string()),
+                Box::new(ExprKind::Infix(
+                    Box::new(ExprKind::Number(3.).into()),
+                    Token::new(TokenKind::DbAsterisk, "**".to_string()),
+                    Box::new(ExprKind::Number(2.).into())
+                ).into())
+            ).into()),
+            Token::new(TokenKind::Minus, "-".to_string())
+        ).into()); "exponent is right assoc and binds tighter than minus"
+    )]
+    #[test_case(
+        "a + b % 3",
+        StmtKind::Expr(ExprKind::Infix(
+            Box::new(ExprKind::Ident(Identifier::new("a".into())).into()),
+            Token::new(TokenKind::Plus, "+".to_string()),
+            Box::new(ExprKind::Infix(
+                Box::new(ExprKind::Ident(Identifier::new("b".into())).into()),
+                Token::new(TokenKind::Percent, "%".to_string()),
+                Box::new(ExprKind::Number(3.).into())
+            ).into())
+        ).into()); "modulo precedence"
+    )]
+    #[test_case(
+        r#""page ${n + 1} of ${total}""#,
+        StmtKind::Expr(ExprKind::Interp(vec![
+            ExprKind::Str("page ".into()).into(),
+            ExprKind::Infix(
+                Box::new(ExprKind::Ident(Identifier::new("n".into())).into()),
+                Token::new(TokenKind::Plus, "+".to_string()),
+                Box::new(ExprKind::Number(1.).into())
+            ).into(),
+            ExprKind::Str(" of ".into()).into(),
+            ExprKind::Ident(Identifier::new("total".into())).into(),
+        ]).into()); "interpolated string"
+    )]
+    #[test_case("0xff", StmtKind::Expr(ExprKind::Number(255.).into()); "hex literal")]
+    #[test_case("1_000.5", StmtKind::Expr(ExprKind::Number(1000.5).into()); "underscore literal")]
+    #[test_case("2.5e-3", StmtKind::Expr(ExprKind::Number(0.0025).into()); "scientific literal")]
     #[test_case(
         "!true",
-        StmtKind::Expr(ExprKind::Prefix(Box::new(ExprKind::Boolean(true)), Token::new(TokenKind::Bang, "!".to_string()))); "bang prefix"
+        StmtKind::Expr(ExprKind::Prefix(Box::new(ExprKind::Boolean(true).into()), Token::new(TokenKind::Bang, "!".to_string())).into()); "bang prefix"
     )]
     #[test_case(
         "a::b",
         StmtKind::Expr(
             ExprKind::Infix(
                 Box::new(
-                    ExprKind::Ident(Identifier::new("a".into()))
+                    ExprKind::Ident(Identifier::new("a".into())).into()
                 ),
                 Token::new(TokenKind::DbColon, "::".to_string()),
                 Box::new(
-                    ExprKind::Ident(Identifier::new("b".into()))
+                    ExprKind::Ident(Identifier::new("b".into())).into()
                 )
-            )
+            ).into()
         ); "db colon"
     )]
     #[test_case(
@@ -969,16 +2035,68 @@ mod tests {
         StmtKind::WhileLoop(
             ExprKind::Infix(
                 Box::new(
-                    ExprKind::Ident(Identifier::new("a".into()))
+                    ExprKind::Ident(Identifier::new("a".into())).into()
                 ),
                 Token::new(TokenKind::LT, "<".to_string()),
                 Box::new(
-                    ExprKind::Number(1.)
+                    ExprKind::Number(1.).into()
                 )
-            ),
+            ).into(),
             Block::default(),
         ); "while loop"
     )]
+    #[test_case(
+        "type Product { name: str, price: number? }",
+        StmtKind::TypeDecl(TypeDecl {
+            ident: Identifier::new("Product".into()),
+            fields: vec![
+                (Identifier::new("name".into()), TypeExpr::new(Identifier::new("str".into()), false)),
+                (Identifier::new("price".into()), TypeExpr::new(Identifier::new("number".into()), true)),
+            ],
+        }); "type decl"
+    )]
+    #[test_case(
+        "x: null = null",
+        StmtKind::Assign(
+            ExprKind::Ident(Identifier::new("x".into())).into(),
+            ExprKind::Null.into(),
+            false,
+            Some(TypeExpr::new(Identifier::new("null".into()), false)),
+        ); "null annotation"
+    )]
+    #[test_case(
+        "x = Product { name: a }",
+        StmtKind::Assign(
+            ExprKind::Ident(Identifier::new("x".into())).into(),
+            ExprKind::Record(
+                Identifier::new("Product".into()),
+                HashLiteral::from(vec![(
+                    Identifier::new("name".into()),
+                    ExprKind::Ident(Identifier::new("a".into())).into(),
+                )]),
+            ).into(),
+            false,
+            None,
+        ); "record constructor"
+    )]
+    #[test_case(
+        "scrape Product { name: a }",
+        StmtKind::Scrape(
+            Some(Identifier::new("Product".into())),
+            HashLiteral::from(vec![(
+                Identifier::new("name".into()),
+                ExprKind::Ident(Identifier::new("a".into())).into(),
+            )]),
+        ); "typed scrape"
+    )]
+    #[test_case(
+        "type(x)",
+        StmtKind::Expr(ExprKind::Call(CallLiteral {
+            ident: Identifier::new("type".into()),
+            args: vec![ExprKind::Ident(Identifier::new("x".into())).into()],
+            kwargs: vec![],
+        }).into()); "type builtin call"
+    )]
     fn test_single_stmt(input: &str, exp: StmtKind) {
         let stmt = extract_first_stmt(input);
         assert_eq!(stmt, exp);
@@ -990,11 +2108,11 @@ mod tests {
         let stmt = extract_first_stmt(input);
         let exp = StmtKind::IfElse(IfElseLiteral {
             if_lit: IfLiteral {
-                cond: ExprKind::Number(1.),
+                cond: ExprKind::Number(1.).into(),
                 block: Block::default(),
             },
             elifs: vec![IfLiteral {
-                cond: ExprKind::Number(2.),
+                cond: ExprKind::Number(2.).into(),
                 block: Block::default(),
             }],
             else_lit: Some(ElseLiteral {
@@ -1003,4 +2121,143 @@ mod tests {
         });
         assert_eq!(stmt, exp);
     }
+
+    #[test]
+    fn test_brace_on_next_line_is_not_record() {
+        let stmts = parse_stmts("y = x\n{b: 2}");
+        let exp = vec![
+            StmtKind::Assign(
+                ExprKind::Ident(Identifier::new("y".into())).into(),
+                ExprKind::Ident(Identifier::new("x".into())).into(),
+                false,
+                None,
+            ),
+            StmtKind::Expr(
+                ExprKind::Map(HashLiteral::from(vec![(
+                    Identifier::new("b".into()),
+                    ExprKind::Number(2.).into(),
+                )]))
+                .into(),
+            ),
+        ];
+        assert_eq!(stmts, exp);
+    }
+
+    #[test_case(r#"x = "a\"b${c}\n""#; "escaped string")]
+    #[test_case(r#"$"a[title=\"x\"]""#; "escaped selector")]
+    #[test_case(r#"x = { a: 1, "2024": 2, [k]: 3 }"#; "map keys")]
+    #[test_case("m.a.b = $\"a\".text()"; "dot access")]
+    #[test_case("x = if a and b then c else d or e"; "conditional")]
+    #[test_case("let x = 1"; "let declaration")]
+    #[test_case("def f(a, b = 1, *rest, **opts) do\nend"; "variadic params")]
+    #[test_case("const X = [1]"; "const decl")]
+    #[test_case("x = match y case [1, a] if a do a case { \"k-v\": _ } do 2 case _ do null end"; "match arms")]
+    #[test_case("type Product { name: str, \"sale-price\": number?, tags: list }"; "type declaration")]
+    #[test_case("scrape Product { name: Product { a: 1 } }"; "records")]
+    #[test_case("def f(a: number, b: str? = null, *rest) -> str do\nend"; "annotated fn")]
+    #[test_case("f = fn(a: node) -> list do\nend"; "annotated lambda")]
+    #[test_case("def apply(f: fn, x: number?) -> null do\nend"; "fn and null types")]
+    #[test_case("x: number = 1"; "annotated assign")]
+    #[test_case("let y: Product? = z"; "annotated let")]
+    #[test_case("def f() do\nreturn -a ** 2\nend"; "return with prefix")]
+    #[test_case("x = f(k = !a)"; "kwargs only call")]
+    #[test_case("m[0] = std::list::map(l, f)"; "index and module access")]
+    #[test_case("crawl where x do\nend"; "crawl filter")]
+    #[test_case(r#"screenshot "a \"b\".png""#; "screenshot path")]
+    #[test_case("x = {}"; "empty map")]
+    fn test_display_roundtrip(input: &str) {
+        let stmts = parse_stmts(input);
+        let printed = stmts[0].to_string();
+        assert_eq!(parse_stmts(&printed), stmts);
+    }
+
+    #[test_case("def f(a: number, *rest, **kw) -> str? do\nreturn a\nend"; "fn def")]
+    #[test_case("x = $$\"a\" |> f(k = -1) |> g()"; "chain and kwargs")]
+    #[test_case("scrape Item { a: $(c)\"b\", [k]: m ?|> f() }"; "typed scrape")]
+    #[test_case("y = match x case [a, _] if a > 1 do a case { b: 2 } do null end"; "match arms")]
+    #[test_case("try\nthrow \"e\"\ncatch e do\nprint(e)\nfinally\nx = 1\nend"; "try catch")]
+    #[test_case("crawl link, depth where depth < 2 do\nbreak\nend"; "crawl")]
+    #[test_case("x = 1.7e308 + 0x7fffffffffffffff"; "large numbers")]
+    #[test_case("x = \"a ${y + 1} \\\"b\\\"\""; "interpolated string")]
+    fn test_serde_roundtrip(input: &str) {
+        let prgm = setup_parser(input).parse_program().unwrap();
+        let json = serde_json::to_string(&prgm).unwrap();
+        let loaded: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.stmts, prgm.stmts);
+        // Spans are left out of equality, so compare the dumps too.
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+    }
+
+    #[test]
+    fn test_stmt_and_expr_spans() {
+        let mut p = setup_parser("x = 1\nfoo(a, bar)");
+        let prgm = p.parse_program().unwrap();
+        assert_eq!(prgm.stmts[0].span, Span::new(0, 5, 1, 1));
+        assert_eq!(prgm.stmts[1].span, Span::new(6, 17, 2, 1));
+        match &prgm.stmts[1].kind {
+            StmtKind::Expr(Expr {
+                kind: ExprKind::Call(call),
+                ..
+            }) => assert_eq!(call.args[1].span, Span::new(13, 16, 2, 8)),
+            _ => panic!("expected call stmt"),
+        }
+    }
+
+    #[test_case("if a > 1 x end", Span::new(9, 10, 1, 10); "missing do")]
+    #[test_case("x = (", Span::new(4, 5, 1, 5); "unknown prefix")]
+    #[test_case("x = 0x", Span::new(4, 6, 1, 5); "invalid hex literal")]
+    #[test_case("x = 1e999", Span::new(4, 9, 1, 5); "infinite float literal")]
+    #[test_case(r#"x = "a ${b c}""#, Span::new(11, 12, 1, 12); "unclosed interpolation")]
+    #[test_case("def f(a = 1, b) do end", Span::new(13, 14, 1, 14); "default param before")]
+    #[test_case("def f(*a, b) do end", Span::new(10, 11, 1, 11); "param after rest")]
+    #[test_case("def f(**a, *b) do end", Span::new(11, 12, 1, 12); "rest after kwargs")]
+    #[test_case("x = 1\nbreak", Span::new(6, 11, 2, 1); "break outside loop")]
+    #[test_case("for x in y do\n  def f() do continue end\nend", Span::new(27, 35, 2, 14); "continue in fn inside loop")]
+    #[test_case("match x case (a) do end", Span::new(13, 14, 1, 14); "invalid pattern")]
+    #[test_case("x = if a then b", Span::new(15, 15, 1, 16); "conditional without else")]
+    #[test_case("let x 1", Span::new(6, 7, 1, 7); "let without assign")]
+    #[test_case("type P { a: 1 }", Span::new(12, 13, 1, 13); "type decl without type name")]
+    #[test_case(r#"x = "a\q""#, Span::new(6, 8, 1, 7); "unknown escape")]
+    #[test_case(r#"x = "${y}\u{d800}""#, Span::new(9, 17, 1, 10); "invalid code point")]
+    fn test_parse_error_span(input: &str, exp: Span) {
+        let mut p = setup_parser(input);
+        let errs = p.parse_program().unwrap_err();
+        assert_eq!(errs[0].span(), exp);
+    }
+
+    #[test_case("x = (\ny = 1", 1, 1; "recovers at next line")]
+    #[test_case("try\n  x = )
+catch e do\n  y = 1\nend\nz = 1", 1, 2; "catch binding shares try end")]
+    #[test_case("for x y do\n  try\n  catch e do\n  end\nend\nz = 1", 1, 1; "skips try with catch binding")]
+    #[test_case("x = (\nif a do\n  b = )\n  c = 1\nend\nd = 2", 2, 2; "recovers inside blocks")]
+    #[test_case("for x y do\n  if a do\n    b\n  elif c do\n  end\nend\nz = 1", 1, 1; "skips nested blocks of failed stmt")]
+    #[test_case("def f() do\n  for x in y do\n", 1, 0; "unterminated blocks")]
+    #[test_case("for x y do\n  match a\n  case 1 do\n  case 2 do\n  end\nend\nz = 1", 1, 1; "skips match with many cases")]
+    #[test_case("goto )\ngoto (\nuse )", 3, 0; "every stmt fails")]
+    fn test_parse_recovery(input: &str, n_errors: usize, n_stmts: usize) {
+        let mut p = setup_parser(input);
+        let (prgm, errors) = p.parse_program_partial();
+        assert_eq!(errors.len(), n_errors);
+        assert_eq!(prgm.stmts.len(), n_stmts);
+    }
+
+    #[test]
+    fn test_parse_recovery_keeps_block_contents() {
+        let mut p = setup_parser("if a do\n  b = )\n  c = 1\nend");
+        let (prgm, errors) = p.parse_program_partial();
+        assert_eq!(errors.len(), 1);
+        match &prgm.stmts[0].kind {
+            StmtKind::IfElse(lit) => assert_eq!(
+                lit.if_lit.block,
+                Block::new(vec![StmtKind::Assign(
+                    ExprKind::Ident(Identifier::new("c".into())).into(),
+                    ExprKind::Number(1.).into(),
+                    false,
+                    None
+                )
+                .into()])
+            ),
+            _ => panic!("expected if stmt"),
+        }
+    }
 }
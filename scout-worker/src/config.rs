@@ -1,5 +1,6 @@
-use std::fs;
+use std::{fs, time::Duration};
 
+use scout_interpreter::limits::Limits;
 use serde::Deserialize;
 
 use crate::WorkerError;
@@ -10,6 +11,7 @@ const DFEAULT_CONFIG_FILE: &str = "scout.toml";
 pub struct Config {
     pub inputs: ConfigInputs,
     pub outputs: Option<ConfigOutputs>,
+    pub limits: Option<ConfigLimits>,
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -30,6 +32,7 @@ pub struct ConfigInputHttp {
     pub port: usize,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Deserialize)]
 pub enum OutputMethods {
     POST,
@@ -43,6 +46,26 @@ pub struct ConfigOutputHttp {
     pub method: OutputMethods,
 }
 
+/// Limits on each job, so a runaway script cannot hang the worker.
+#[derive(Debug, Default, Deserialize)]
+pub struct ConfigLimits {
+    pub max_steps: Option<u64>,
+    pub timeout_secs: Option<u64>,
+    pub max_pages: Option<usize>,
+    pub max_results: Option<usize>,
+}
+
+impl ConfigLimits {
+    pub fn to_limits(&self) -> Limits {
+        Limits {
+            max_steps: self.max_steps,
+            timeout: self.timeout_secs.map(Duration::from_secs),
+            max_pages: self.max_pages,
+            max_results: self.max_results,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ConfigRMQ {
     pub addr: String,
@@ -11,7 +11,7 @@ use actix_web::{
     web::{self, Data},
     App, HttpResponse, HttpServer, Responder,
 };
-use scout_interpreter::builder::InterpreterBuilder;
+use scout_interpreter::{builder::InterpreterBuilder, limits::Limits};
 use tracing::info;
 
 use crate::{config::ConfigInputHttp, models::incoming, Output};
@@ -19,14 +19,19 @@ use crate::{config::ConfigInputHttp, models::incoming, Output};
 #[post("/")]
 async fn crawl(
     outputs: Data<Arc<Vec<Output>>>,
+    limits: Data<Limits>,
     body: web::Json<incoming::Incoming>,
 ) -> impl Responder {
     match fs::read_to_string(&body.file) {
         Ok(content) => {
-            let interpreter = InterpreterBuilder::default().build().await.unwrap();
+            let interpreter = InterpreterBuilder::default()
+                .with_limits(**limits)
+                .build()
+                .await
+                .unwrap();
             if let Err(e) = interpreter.eval(&content).await {
                 return HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(format!("interpreter error: {e:?}"));
+                    .body(format!("interpreter error: {e}"));
             }
             let res = interpreter.results();
             let payload = res.lock().await.to_json();
@@ -57,12 +62,15 @@ async fn health() -> impl Responder {
 pub async fn start_http_consumer(
     config: &ConfigInputHttp,
     outputs: Arc<Vec<Output>>,
+    limits: Limits,
 ) -> Result<(), io::Error> {
     info!("starting HTTP server on {}:{}", config.addr, config.port);
     let data = Data::new(outputs.clone());
+    let limits = Data::new(limits);
     HttpServer::new(move || {
         App::new()
             .app_data(data.clone())
+            .app_data(limits.clone())
             .service(crawl)
             .service(health)
     })
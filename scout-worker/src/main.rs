@@ -31,10 +31,11 @@ async fn start(config: Config) -> Result<(), Box<dyn std::error::Error>> {
     }
 
     let aoutputs = Arc::new(outputs);
+    let limits = config.limits.unwrap_or_default().to_limits();
     if let Some(http_config) = config.inputs.http {
-        http::server::start_http_consumer(&http_config, aoutputs.clone()).await?;
+        http::server::start_http_consumer(&http_config, aoutputs.clone(), limits).await?;
     } else if let Some(rmq_config) = config.inputs.rmq {
-        rmq::consumer::Consumer::new(&rmq_config, aoutputs.clone())
+        rmq::consumer::Consumer::new(&rmq_config, aoutputs.clone(), limits)
             .await?
             .start()
             .await?;
@@ -1,5 +1,6 @@
 use crate::{http, rmq};
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum Output {
     RMQ(rmq::producer::Producer),
     HTTP(http::sender::Sender),
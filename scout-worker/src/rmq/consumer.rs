@@ -9,6 +9,7 @@ use lapin::{
 };
 use scout_interpreter::{
     builder::{BuilderError, InterpreterBuilder},
+    limits::Limits,
     Interpreter, InterpreterError,
 };
 use std::{fmt::Display, fs, str, sync::Arc};
@@ -30,10 +31,17 @@ pub struct Consumer {
 }
 
 impl Consumer {
-    pub async fn new(config: &ConfigRMQ, outputs: Arc<Vec<Output>>) -> Result<Self, ConsumerError> {
+    pub async fn new(
+        config: &ConfigRMQ,
+        outputs: Arc<Vec<Output>>,
+        limits: Limits,
+    ) -> Result<Self, ConsumerError> {
         let conn = Connection::connect(&config.addr, ConnectionProperties::default()).await?;
         let chann = conn.create_channel().await?;
-        let interpreter = InterpreterBuilder::default().build().await?;
+        let interpreter = InterpreterBuilder::default()
+            .with_limits(limits)
+            .build()
+            .await?;
 
         chann
             .exchange_declare(
@@ -56,7 +56,7 @@ impl Producer {
                 &self.exchange,
                 &self.out_key,
                 BasicPublishOptions::default(),
-                &payload.as_bytes(),
+                payload.as_bytes(),
                 BasicProperties::default(),
             )
             .await?;
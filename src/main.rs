@@ -1,7 +1,10 @@
-use std::{env, fs};
+use std::{env, fs, process::ExitCode, time::Duration};
 
 use repl::run_repl;
-use scout_interpreter::{builder::InterpreterBuilder, Interpreter};
+use scout_interpreter::{builder::InterpreterBuilder, check::check, limits::Limits, Interpreter};
+use scout_lexer::Lexer;
+use scout_lint::lint;
+use scout_parser::{ast::Program, diagnostic::Diagnostic, fmt, Parser};
 
 mod repl;
 
@@ -12,9 +15,15 @@ async fn run(
     match file {
         None => run_repl(interpreter).await,
         Some(f) => {
-            let contents = fs::read_to_string(f)?;
-            if let Err(e) = interpreter.eval(&contents).await {
-                println!("Interpeter error: {:?}", e);
+            let contents = fs::read_to_string(&f)?;
+            if f.ends_with(".json") {
+                // A dumped AST no longer has the source its spans point
+                // into, so errors are reported without a snippet.
+                if let Err(e) = interpreter.eval_ast_json(&contents).await {
+                    println!("error: {e}");
+                }
+            } else if let Err(e) = interpreter.eval(&contents).await {
+                print!("{}", e.render(&f, &contents));
             }
 
             Ok(())
@@ -22,14 +31,167 @@ async fn run(
     }
 }
 
+/// Runs a static pass over each file without starting a browser, printing
+/// any parse errors or diagnostics it reports. Returns whether every file
+/// was clean.
+fn run_static(
+    cmd: &str,
+    files: &[String],
+    pass: impl Fn(&Program) -> Vec<Diagnostic>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    if files.is_empty() {
+        return Err(format!("usage: scout {cmd} <files...>").into());
+    }
+    let mut clean = true;
+    for file in files {
+        let contents = fs::read_to_string(file)?;
+        let diagnostics = match Parser::new(Lexer::new(&contents)).parse_program() {
+            Ok(program) => pass(&program),
+            Err(errs) => errs.iter().map(|e| e.diagnostic()).collect(),
+        };
+        for diag in &diagnostics {
+            print!("{}", diag.render(file, &contents));
+        }
+        clean &= diagnostics.is_empty();
+    }
+    Ok(clean)
+}
+
+/// Formats each file in place, or with `--check` only reports the files
+/// that are not formatted. Returns whether every file was already
+/// formatted or could be.
+fn run_fmt(args: &[String]) -> Result<bool, Box<dyn std::error::Error>> {
+    let check = args.iter().any(|a| a == "--check");
+    let mut clean = true;
+    for file in args.iter().filter(|a| *a != "--check") {
+        let contents = fs::read_to_string(file)?;
+        match fmt::format(&contents) {
+            Ok(formatted) if formatted == contents => {}
+            Ok(_) if check => {
+                println!("{file} is not formatted");
+                clean = false;
+            }
+            Ok(formatted) => fs::write(file, formatted)?,
+            Err(errs) => {
+                for err in &errs {
+                    print!("{}", err.diagnostic().render(file, &contents));
+                }
+                clean = false;
+            }
+        }
+    }
+    Ok(clean)
+}
+
+/// Prints the parsed form of a file. `--emit ast-json` is the only form,
+/// and dumps the AST as JSON that `scout <file>.json` can run.
+fn run_parse(args: &[String]) -> Result<bool, Box<dyn std::error::Error>> {
+    let [flag, emit, file] = args else {
+        return Err("usage: scout parse --emit ast-json <file>".into());
+    };
+    if flag != "--emit" || emit != "ast-json" {
+        return Err(format!("unsupported parse output `{flag} {emit}`").into());
+    }
+    let contents = fs::read_to_string(file)?;
+    match Parser::new(Lexer::new(&contents)).parse_program() {
+        Ok(program) => {
+            println!("{}", serde_json::to_string_pretty(&program)?);
+            Ok(true)
+        }
+        Err(errs) => {
+            for err in &errs {
+                print!("{}", err.diagnostic().render(file, &contents));
+            }
+            Ok(false)
+        }
+    }
+}
+
+/// Takes the `--max-steps`, `--timeout`, `--max-pages` and
+/// `--max-results` flags and their values out of the args.
+fn take_limits(args: &mut Vec<String>) -> Result<Limits, Box<dyn std::error::Error>> {
+    let mut limits = Limits::default();
+    let mut i = 1;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        if !matches!(
+            flag,
+            "--max-steps" | "--timeout" | "--max-pages" | "--max-results"
+        ) {
+            i += 1;
+            continue;
+        }
+        let Some(value) = args.get(i + 1) else {
+            return Err(format!("missing value for `{flag}`").into());
+        };
+        let invalid = || format!("invalid value `{value}` for `{flag}`");
+        match flag {
+            "--max-steps" => limits.max_steps = Some(value.parse().map_err(|_| invalid())?),
+            "--timeout" => {
+                let timeout = value
+                    .parse()
+                    .ok()
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .ok_or_else(invalid)?;
+                limits.timeout = Some(timeout);
+            }
+            "--max-pages" => limits.max_pages = Some(value.parse().map_err(|_| invalid())?),
+            _ => limits.max_results = Some(value.parse().map_err(|_| invalid())?),
+        }
+        args.drain(i..i + 2);
+    }
+    Ok(limits)
+}
+
+fn lint_diagnostics(program: &Program) -> Vec<Diagnostic> {
+    lint(program).iter().map(|l| l.diagnostic()).collect()
+}
+
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     let args: Vec<String> = env::args().collect();
 
+    let tool = match args.get(1).map(String::as_str) {
+        Some("fmt") => Some(run_fmt(&args[2..])),
+        Some("parse") => Some(run_parse(&args[2..])),
+        Some("check") => Some(run_static("check", &args[2..], check)),
+        Some("lint") => Some(run_static("lint", &args[2..], lint_diagnostics)),
+        _ => None,
+    };
+    if let Some(res) = tool {
+        return match res {
+            Ok(true) => ExitCode::SUCCESS,
+            Ok(false) => ExitCode::FAILURE,
+            Err(e) => {
+                println!("Error: {}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let mut args = args;
+    let limits = match take_limits(&mut args) {
+        Ok(limits) => limits,
+        Err(e) => {
+            println!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
     let interpreter = InterpreterBuilder::default()
+        .with_limits(limits)
         .build()
         .await
         .expect("failed to build interpreter");
+    if args.len() > 1 {
+        // Stop the script rather than the process on ctrl-c, so that what
+        // was scraped so far is still printed and the browser is closed.
+        let cancel = interpreter.cancel_token();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                cancel.cancel();
+            }
+        });
+    }
     if let Err(e) = run(args.get(1).cloned(), &interpreter).await {
         println!("Error: {}", e);
     }
@@ -37,4 +199,5 @@ async fn main() {
     println!("{}", json_results);
 
     interpreter.close().await;
+    ExitCode::SUCCESS
 }
@@ -17,8 +17,8 @@ pub async fn run_repl(interpreter: &Interpreter) -> Result<(), Box<dyn std::erro
                 rl.add_history_entry(line.as_str());
 
                 match interpreter.eval(&line).await {
-                    Ok(o) => println!("{}", o.to_display().await),
-                    Err(e) => println!("Interpeter error: {:?}", e),
+                    Ok(o) => println!("{}", o.to_display()),
+                    Err(e) => print!("{}", e.render("<repl>", &line)),
                 }
             }
             Err(ReadlineError::Interrupted) => {